@@ -0,0 +1,309 @@
+//! Screen ambilight (`ambilight`): the screen is sampled a few times a second,
+//! scaled down to the lighting matrix (see `config::image::sample`), and
+//! streamed through a custom slot, so the keyboard glows along with whatever
+//! is on screen.
+//!
+//! Capturing goes through external tools, since each display server needs its
+//! own protocol: `ffmpeg`'s x11grab (X11 SHM) on X11, and `grim` (wlroots
+//! screencopy) on Wayland.
+
+use std::env;
+use std::io::Read;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use fusion_kbd_daemon::SCRATCH_SLOT;
+use fusion_kbd_protocol::config::{image, MATRIX_COLS, MATRIX_ROWS};
+use fusion_kbd_protocol::correction::Correction;
+use fusion_kbd_protocol::effects::FrameClock;
+use fusion_kbd_protocol::state::{Lighting, State};
+use fusion_kbd_protocol::{self as kbd, CustomConfig, Rgb};
+use log::error;
+
+use crate::exit::Failure;
+use crate::{validate_slot, Globals};
+
+/// x11grab frames are scaled to this many pixels per key (by ffmpeg, which is
+/// much quicker at it), before being sampled
+const X11_CELL: usize = 4;
+
+/// with grim, frames are scaled down by this much before being encoded
+const WAYLAND_SCALE: &str = "0.05";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    X11,
+    Wayland,
+}
+
+impl Backend {
+    /// whichever display server the session is running
+    pub fn detect() -> Option<Backend> {
+        if env::var_os("WAYLAND_DISPLAY").is_some() {
+            Some(Backend::Wayland)
+        } else if env::var_os("DISPLAY").is_some() {
+            Some(Backend::X11)
+        } else {
+            None
+        }
+    }
+}
+
+pub struct Options {
+    pub backend: Backend,
+    /// output to capture (a grim output name, or an X11 display)
+    pub output: Option<String>,
+    pub slot: u8,
+    pub brightness: u8,
+    pub fps: u32,
+}
+
+enum Grabber {
+    /// ffmpeg streaming scaled-down rgb24 frames, with the newest one kept
+    /// around by a background thread
+    X11 {
+        child: Child,
+        latest: Arc<Mutex<Option<Vec<u8>>>>,
+    },
+    Wayland {
+        output: Option<String>,
+    },
+}
+
+impl Grabber {
+    fn start(opts: &Options) -> Result<Grabber, String> {
+        match opts.backend {
+            Backend::Wayland => Ok(Grabber::Wayland {
+                output: opts.output.clone(),
+            }),
+            Backend::X11 => {
+                let display = match opts.output {
+                    Some(ref output) => output.clone(),
+                    None => env::var("DISPLAY").unwrap_or_else(|_| ":0".to_string()),
+                };
+                let (width, height) = (MATRIX_COLS * X11_CELL, MATRIX_ROWS * X11_CELL);
+                let mut child = Command::new("ffmpeg")
+                    .args(["-loglevel", "error", "-f", "x11grab"])
+                    .args(["-framerate", &opts.fps.to_string(), "-i", &display])
+                    .arg("-vf")
+                    .arg(format!("scale={}:{}:flags=area", width, height))
+                    .args(["-f", "rawvideo", "-pix_fmt", "rgb24", "-"])
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::piped())
+                    .spawn()
+                    .map_err(|e| format!("couldn't run ffmpeg (is it installed?): {}", e))?;
+
+                let latest = Arc::new(Mutex::new(None));
+                let mut stdout = child.stdout.take().unwrap();
+                let shared = latest.clone();
+                thread::spawn(move || {
+                    let mut frame = vec![0; width * height * 3];
+                    while stdout.read_exact(&mut frame).is_ok() {
+                        *shared.lock().unwrap() = Some(frame.clone());
+                    }
+                });
+
+                Ok(Grabber::X11 { child, latest })
+            }
+        }
+    }
+
+    /// The newest frame, or `None` if there hasn't been a new one since the
+    /// last call.
+    fn grab(&mut self) -> Result<Option<CustomConfig>, String> {
+        match self {
+            Grabber::X11 { child, latest } => {
+                if let Ok(Some(status)) = child.try_wait() {
+                    return Err(format!("ffmpeg stopped capturing ({})", status));
+                }
+                match latest.lock().unwrap().take() {
+                    Some(frame) => {
+                        let width = MATRIX_COLS * X11_CELL;
+                        let pixel = |x: usize, y: usize| {
+                            let i = (y * width + x) * 3;
+                            Rgb(frame[i], frame[i + 1], frame[i + 2])
+                        };
+                        image::sample(width, MATRIX_ROWS * X11_CELL, pixel).map(Some)
+                    }
+                    None => Ok(None),
+                }
+            }
+            Grabber::Wayland { output } => {
+                let mut cmd = Command::new("grim");
+                cmd.args(["-t", "ppm", "-s", WAYLAND_SCALE]);
+                if let Some(output) = output {
+                    cmd.args(["-o", output]);
+                }
+                let out = cmd
+                    .arg("-")
+                    .stdin(Stdio::null())
+                    .output()
+                    .map_err(|e| format!("couldn't run grim (is it installed?): {}", e))?;
+                if !out.status.success() {
+                    return Err(format!(
+                        "grim failed: {}",
+                        String::from_utf8_lossy(&out.stderr).trim()
+                    ));
+                }
+                from_ppm(&out.stdout).map(Some)
+            }
+        }
+    }
+}
+
+impl Drop for Grabber {
+    fn drop(&mut self) {
+        if let Grabber::X11 { child, .. } = self {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// samples a binary (P6) PPM, with 8 bit channels
+fn from_ppm(ppm: &[u8]) -> Result<CustomConfig, String> {
+    // the header is 4 whitespace separated fields: P6 WIDTH HEIGHT MAXVAL
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    while fields.len() < 4 {
+        while pos < ppm.len() && ppm[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        let start = pos;
+        while pos < ppm.len() && !ppm[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            return Err("truncated PPM header".to_string());
+        }
+        fields.push(String::from_utf8_lossy(&ppm[start..pos]).into_owned());
+    }
+    // exactly one whitespace byte separates the header from the pixels
+    let pixels = &ppm[(pos + 1).min(ppm.len())..];
+
+    let number = |s: &str| {
+        s.parse::<usize>()
+            .map_err(|_| format!("invalid PPM header field '{}'", s))
+    };
+    if fields[0] != "P6" || number(&fields[3])? != 255 {
+        return Err("only 8 bit binary PPMs are supported".to_string());
+    }
+    let (width, height) = (number(&fields[1])?, number(&fields[2])?);
+    if pixels.len() < width * height * 3 {
+        return Err("truncated PPM".to_string());
+    }
+
+    image::sample(width, height, |x, y| {
+        let i = (y * width + x) * 3;
+        Rgb(pixels[i], pixels[i + 1], pixels[i + 2])
+    })
+}
+
+/// Mirrors the screen onto the keyboard until interrupted.
+pub fn run(
+    kbd: &dyn kbd::Keyboard,
+    opts: &Options,
+    correction: &Correction,
+) -> Result<(), libusb::Error> {
+    let mut grabber = match Grabber::start(opts) {
+        Ok(grabber) => grabber,
+        Err(e) => {
+            error!("{}", e);
+            return Err(libusb::Error::Other);
+        }
+    };
+
+    let delay = Duration::from_secs(1) / opts.fps;
+    let mut last: Option<Vec<u8>> = None;
+    let mut clock = FrameClock::new();
+    loop {
+        match grabber.grab() {
+            Ok(Some(cfg)) => {
+                let cfg = correction.apply(&cfg);
+                // a still screen needn't be re-uploaded
+                if last.as_deref() != Some(cfg.as_bytes()) {
+                    kbd.upload_custom(opts.slot, cfg.as_bytes())?;
+                    kbd.set_custom(opts.slot, opts.brightness)?;
+                    last = Some(cfg.as_bytes().to_vec());
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error!("{}", e);
+                return Err(libusb::Error::Other);
+            }
+        }
+
+        clock.wait(delay);
+        while clock.is_late(delay) {
+            clock.skip(delay);
+        }
+    }
+}
+
+#[rustfmt::skip]
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("ambilight")
+        .about("Glow along with what's on screen (captured with ffmpeg on X11, or grim on Wayland)")
+        .arg(Arg::with_name("backend")
+            .takes_value(true)
+            .long("backend")
+            .possible_values(&["x11", "wayland"])
+            .help("How to capture the screen (default: whatever the session is running)"))
+        .arg(Arg::with_name("output")
+            .takes_value(true)
+            .long("output")
+            .help("Output to capture: an output name (Wayland), or a display (X11; default: $DISPLAY)"))
+        .arg(Arg::with_name("slot")
+            .takes_value(true)
+            .short("s")
+            .long("slot")
+            .validator(validate_slot)
+            .help("Custom slot frames are streamed through (default: 4)"))
+        .arg(Arg::with_name("fps")
+            .takes_value(true)
+            .long("fps")
+            .validator(|fstr| {
+                let fval = fstr.parse::<u32>();
+                if fval.is_err() || fval.unwrap() == 0 {
+                    return Err("fps must be a positive number!".to_string())
+                }
+                Ok(())
+            })
+            .help("How often to sample the screen (default: 10)"))
+}
+
+pub fn exec(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let backend = match m.value_of("backend") {
+        Some("x11") => Backend::X11,
+        Some(_) => Backend::Wayland,
+        None => match Backend::detect() {
+            Some(backend) => backend,
+            None => {
+                error!("no display server found (pass --backend)");
+                return Err(libusb::Error::Other.into());
+            }
+        },
+    };
+    let opts = Options {
+        backend,
+        output: m.value_of("output").map(|o| o.to_string()),
+        slot: m
+            .value_of("slot")
+            .map_or(SCRATCH_SLOT, |sstr| sstr.parse::<u8>().unwrap()),
+        brightness: g.brightness(),
+        fps: m
+            .value_of("fps")
+            .map_or(10, |fstr| fstr.parse::<u32>().unwrap()),
+    };
+
+    g.with_keyboard(|kbd| Ok(run(kbd, &opts, &g.correction)?))?;
+    g.remember(State {
+        lighting: Lighting::Custom { slot: opts.slot },
+        brightness: opts.brightness,
+    });
+    Ok(())
+}
@@ -0,0 +1,190 @@
+//! `play` and `render`: animations (built-in ones, JSON animations and GIFs),
+//! streamed through a custom slot, or rendered offline to a GIF of a virtual
+//! keyboard.
+
+use std::fs::File;
+use std::str::FromStr;
+use std::time::Duration;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use fusion_kbd_daemon::SCRATCH_SLOT;
+use fusion_kbd_protocol::state::{Lighting, State};
+use fusion_kbd_protocol::{self as kbd, effects, preview, CustomConfig, Keymap, Rgb};
+use log::error;
+
+use crate::exit::Failure;
+use crate::{unknown_subcommand, validate_slot, Globals, Help};
+
+/// Frames of a built-in animation (see `effects::ANIMATIONS`), of a JSON
+/// animation (see `config::animation`), or of a GIF
+fn load_animation(
+    file: &str,
+    color: Rgb,
+    fps: Option<u32>,
+    keymap: &Keymap,
+) -> Result<Vec<(CustomConfig, Duration)>, String> {
+    let mut frames = match effects::animation(file, color) {
+        Some(frames) => frames,
+        None if file.to_lowercase().ends_with(".json") => {
+            let text = std::fs::read_to_string(file)
+                .map_err(|e| format!("couldn't open '{}': {}", file, e))?;
+            kbd::config::animation::from_json(&text, keymap)
+                .map_err(|e| format!("invalid animation '{}': {}", file, e))?
+        }
+        None => {
+            let f = File::open(file).map_err(|e| format!("couldn't open '{}': {}", file, e))?;
+            kbd::config::image::from_gif(f).map_err(|e| format!("invalid GIF '{}': {}", file, e))?
+        }
+    };
+
+    if let Some(fps) = fps {
+        let delay = Duration::from_secs(1) / fps;
+        for frame in frames.iter_mut() {
+            frame.1 = delay;
+        }
+    }
+
+    Ok(frames)
+}
+
+#[rustfmt::skip]
+pub fn subcommands<'a, 'b>(help: &'b Help) -> Vec<App<'a, 'b>> {
+    vec![
+        SubCommand::with_name("play")
+            .about("Play an animation by streaming frames through a custom slot")
+            .arg(Arg::with_name("file")
+                .required(true)
+                .value_name("ANIMATION")
+                .index(1)
+                .help(&help.animation))
+            .arg(Arg::with_name("color")
+                .takes_value(true)
+                .short("c")
+                .long("color")
+                .validator(|cstr| kbd::Rgb::from_str(&cstr).map(|_| ()))
+                .help("Color used by built-in animations (default: white)"))
+            .arg(Arg::with_name("slot")
+                .takes_value(true)
+                .short("s")
+                .long("slot")
+                .validator(validate_slot)
+                .help("Custom slot frames are streamed through (default: 4)"))
+            .arg(Arg::with_name("fps")
+                .takes_value(true)
+                .long("fps")
+                .validator(|fstr| {
+                    let fval = fstr.parse::<u32>();
+                    if fval.is_err() || fval.unwrap() == 0 {
+                        return Err("fps must be a positive number!".to_string())
+                    }
+                    Ok(())
+                })
+                .help("Override the GIF's frame delays with a fixed frame rate"))
+            .arg(Arg::with_name("loops")
+                .takes_value(true)
+                .short("l")
+                .long("loops")
+                .validator(|lstr| match lstr.parse::<u32>() {
+                    Ok(_) => Ok(()),
+                    Err(_) => Err("loops must be a number!".to_string()),
+                })
+                .help("Number of times to play the animation, 0 = forever (default: 1)")),
+        SubCommand::with_name("render")
+            .about("Render an animation on a virtual keyboard, and save it as a GIF")
+            .arg(Arg::with_name("file")
+                .required(true)
+                .value_name("ANIMATION")
+                .index(1)
+                .help(&help.animation))
+            .arg(Arg::with_name("out")
+                .required(true)
+                .takes_value(true)
+                .short("o")
+                .long("out")
+                .value_name("GIF"))
+            .arg(Arg::with_name("color")
+                .takes_value(true)
+                .short("c")
+                .long("color")
+                .validator(|cstr| kbd::Rgb::from_str(&cstr).map(|_| ()))
+                .help("Color used by built-in animations (default: white)"))
+            .arg(Arg::with_name("fps")
+                .takes_value(true)
+                .long("fps")
+                .validator(|fstr| {
+                    let fval = fstr.parse::<u32>();
+                    if fval.is_err() || fval.unwrap() == 0 {
+                        return Err("fps must be a positive number!".to_string())
+                    }
+                    Ok(())
+                })
+                .help("Override the animation's frame delays with a fixed frame rate"))
+            .arg(Arg::with_name("key-size")
+                .takes_value(true)
+                .long("key-size")
+                .validator(|kstr| match kstr.parse::<usize>() {
+                    Ok(k) if (3..=100).contains(&k) => Ok(()),
+                    _ => Err("key-size must be a number from 3 - 100!".to_string()),
+                })
+                .help("Size of each key, in pixels (default: 24)")),
+    ]
+}
+
+pub fn exec(name: &str, m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let file = m.value_of("file").unwrap();
+    let color = m
+        .value_of("color")
+        .map_or(Rgb(0xff, 0xff, 0xff), |cstr| Rgb::from_str(cstr).unwrap());
+    let fps = m.value_of("fps").map(|fstr| fstr.parse::<u32>().unwrap());
+    let frames = match load_animation(file, color, fps, &g.keymap) {
+        Ok(frames) => frames,
+        Err(e) => {
+            error!("{}", e);
+            return Err(Failure::File);
+        }
+    };
+
+    match name {
+        "play" => {
+            let slot = m
+                .value_of("slot")
+                .map_or(SCRATCH_SLOT, |sstr| sstr.parse::<u8>().unwrap());
+            let loops = m
+                .value_of("loops")
+                .map_or(1, |lstr| lstr.parse::<u32>().unwrap());
+            let brightness = g.brightness();
+
+            let frames: Vec<_> = frames
+                .into_iter()
+                .map(|(cfg, delay)| (g.correction.apply(&cfg), delay))
+                .collect();
+            g.with_keyboard(|kbd| Ok(effects::play(kbd, slot, brightness, &frames, loops)?))?;
+            g.remember(State {
+                lighting: Lighting::Custom { slot },
+                brightness,
+            });
+            Ok(())
+        }
+        // rendering happens entirely offline
+        "render" => {
+            let out = m.value_of("out").unwrap();
+            let key_size = m
+                .value_of("key-size")
+                .map_or(24, |kstr| kstr.parse::<usize>().unwrap());
+            let previews: Vec<_> = frames
+                .iter()
+                .map(|(cfg, delay)| (preview::render(cfg, &g.keymap, key_size), *delay))
+                .collect();
+
+            let written = File::create(out)
+                .map_err(|e| e.to_string())
+                .and_then(|f| preview::write_gif(&previews, f));
+            if let Err(e) = written {
+                error!("couldn't write '{}': {}", out, e);
+                return Err(Failure::File);
+            }
+            Ok(())
+        }
+        name => Err(unknown_subcommand(name)),
+    }
+}
@@ -0,0 +1,106 @@
+//! `backup FILE` / `restore FILE`: every custom slot at once, in a single
+//! `.fkb` file (see `kbd::config::backup`), e.g: to get them back after a
+//! reinstall or a firmware reset.
+
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use fusion_kbd_protocol::config::backup::Backup;
+use fusion_kbd_protocol::{self as kbd, Keymap};
+use log::{error, info, warn};
+
+use crate::exit::Failure;
+use crate::{provision, Globals};
+
+/// Reads every slot into a backup at `file` (which is overwritten). With
+/// `dry_run`, there's nothing real to read back, so nothing gets written.
+pub fn backup(
+    kbd: &dyn kbd::Keyboard,
+    file: &str,
+    keymap: &Keymap,
+    dry_run: bool,
+) -> Result<(), Failure> {
+    let caps = kbd.capabilities();
+    let mut slots = Vec::new();
+    for slot in 0..caps.num_slots {
+        println!("Backing up slot {}...", slot);
+        slots.push((slot, kbd.download_config(slot)?));
+    }
+
+    let backup = Backup {
+        model: caps.model,
+        layout: keymap.layout().to_string(),
+        created: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()),
+        slots,
+    };
+    if dry_run {
+        info!("Not writing '{}' (--dry-run)", file);
+        return Ok(());
+    }
+    if let Err(e) = fs::write(file, backup.to_bytes()) {
+        error!("couldn't write '{}': {}", file, e);
+        return Err(Failure::File);
+    }
+    println!("Backed up {} slot(s) to '{}'", backup.slots.len(), file);
+    Ok(())
+}
+
+/// Writes every slot in the backup at `file` back to the keyboard, reading
+/// each one back to make sure it stuck. Stops at the first one that doesn't.
+pub fn restore(kbd: &dyn kbd::Keyboard, file: &str) -> Result<(), Failure> {
+    let backup = match fs::read(file)
+        .map_err(|e| e.to_string())
+        .and_then(|data| Backup::from_bytes(&data))
+    {
+        Ok(backup) => backup,
+        Err(e) => {
+            error!("couldn't read '{}': {}", file, e);
+            return Err(Failure::File);
+        }
+    };
+
+    let caps = kbd.capabilities();
+    if backup.model != caps.model {
+        warn!(
+            "'{}' was backed up from a {} keyboard (this is a {})",
+            file, backup.model, caps.model
+        );
+    }
+
+    let mut restored = 0;
+    for (slot, cfg) in &backup.slots {
+        if *slot >= caps.num_slots {
+            warn!(
+                "Skipping slot {} (the {} only has {})",
+                slot, caps.model, caps.num_slots
+            );
+            continue;
+        }
+        println!("Restoring slot {}...", slot);
+        if let Err(e) = provision::upload_verified(kbd, *slot, cfg) {
+            error!("slot {}: {}", slot, e);
+            return Err(libusb::Error::Io.into());
+        }
+        restored += 1;
+    }
+    println!("Restored {} slot(s) from '{}'", restored, file);
+    Ok(())
+}
+
+#[rustfmt::skip]
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("backup")
+        .about("Save every custom slot to a single file (e.g: all-slots.fkb), for `restore FILE`")
+        .arg(Arg::with_name("file")
+            .required(true)
+            .value_name("FILE")
+            .index(1))
+}
+
+pub fn exec(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let file = m.value_of("file").unwrap();
+    g.with_keyboard(|kbd| backup(kbd, file, &g.keymap, g.dry_run))
+}
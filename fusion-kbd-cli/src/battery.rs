@@ -0,0 +1,147 @@
+//! Battery indicator (`battery`): the function row becomes a bar showing the
+//! battery's charge, from green when full down to red when empty. The rest of
+//! the slot is left as it was.
+//!
+//! The charge comes from UPower's display device (the combined level of every
+//! battery), via the `upower` CLI, and the bar is redrawn whenever UPower
+//! reports a change.
+
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use fusion_kbd_daemon::SCRATCH_SLOT;
+use fusion_kbd_protocol::correction::Correction;
+use fusion_kbd_protocol::state::{Lighting, State};
+use fusion_kbd_protocol::{self as kbd, effects, zones, CustomConfig, Keymap, Rgb};
+use log::error;
+
+use crate::exit::Failure;
+use crate::{validate_slot, Globals};
+
+const DISPLAY_DEVICE: &str = "/org/freedesktop/UPower/devices/DisplayDevice";
+
+pub struct Options {
+    pub slot: u8,
+    pub brightness: u8,
+}
+
+/// the battery's charge, from 0 - 100
+fn percentage() -> Result<f32, String> {
+    let out = Command::new("upower")
+        .args(["-i", DISPLAY_DEVICE])
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("couldn't run upower (is it installed?): {}", e))?;
+    let text = String::from_utf8_lossy(&out.stdout);
+
+    // "    percentage:          57%"
+    text.lines()
+        .filter_map(|line| line.trim().strip_prefix("percentage:"))
+        .find_map(|p| p.trim().trim_end_matches('%').parse::<f32>().ok())
+        .ok_or_else(|| "UPower doesn't know of any battery".to_string())
+}
+
+/// green when full, through yellow, to red when empty
+fn color(percent: f32) -> Rgb {
+    let (red, yellow, green) = (Rgb(0xff, 0, 0), Rgb(0xff, 0xff, 0), Rgb(0, 0xff, 0));
+    if percent >= 50.0 {
+        effects::blend(yellow, green, (percent - 50.0) / 50.0)
+    } else {
+        effects::blend(red, yellow, percent / 50.0)
+    }
+}
+
+/// `base`, with `row` (left to right) lit up to `percent`
+fn render(base: &CustomConfig, row: &[usize], percent: f32) -> CustomConfig {
+    // at least one key, so an almost empty battery still shows up
+    let lit = ((percent / 100.0 * row.len() as f32).ceil() as usize).clamp(1, row.len());
+
+    let mut cfg = base.clone();
+    for (i, &key) in row.iter().enumerate() {
+        let color = if i < lit {
+            color(percent)
+        } else {
+            Rgb(0, 0, 0)
+        };
+        cfg.set_key(key, color);
+    }
+    cfg
+}
+
+/// Shows the battery level until interrupted.
+pub fn run(
+    kbd: &dyn kbd::Keyboard,
+    opts: &Options,
+    keymap: &Keymap,
+    correction: &Correction,
+) -> Result<(), libusb::Error> {
+    let fail = |e: String| {
+        error!("{}", e);
+        libusb::Error::Other
+    };
+
+    let row = zones::keys("function", keymap).unwrap();
+    if row.is_empty() {
+        return Err(fail("the keymap doesn't have a function row".to_string()));
+    }
+
+    let base = kbd.download_config(opts.slot)?;
+
+    // prints a line whenever a power device changes
+    let mut monitor = Command::new("upower")
+        .arg("--monitor")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| fail(format!("couldn't run upower (is it installed?): {}", e)))?;
+    let mut changes = BufReader::new(monitor.stdout.take().unwrap()).lines();
+
+    let mut shown = None;
+    loop {
+        let percent = percentage().map_err(fail)?;
+        // the bar can't show less than a whole percent anyway
+        if shown != Some(percent.round() as u32) {
+            let cfg = correction.apply(&render(&base, &row, percent));
+            kbd.upload_custom(opts.slot, cfg.as_bytes())?;
+            kbd.set_custom(opts.slot, opts.brightness)?;
+            shown = Some(percent.round() as u32);
+        }
+
+        match changes.next() {
+            Some(Ok(_)) => {}
+            _ => {
+                let _ = monitor.kill();
+                return Err(fail("upower stopped monitoring".to_string()));
+            }
+        }
+    }
+}
+
+#[rustfmt::skip]
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("battery")
+        .about("Show the battery level as a bar across the function row (via UPower)")
+        .arg(Arg::with_name("slot")
+            .takes_value(true)
+            .short("s")
+            .long("slot")
+            .validator(validate_slot)
+            .help("Custom slot to draw the bar on, keeping its other keys (default: 4)"))
+}
+
+pub fn exec(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let opts = Options {
+        slot: m
+            .value_of("slot")
+            .map_or(SCRATCH_SLOT, |sstr| sstr.parse::<u8>().unwrap()),
+        brightness: g.brightness(),
+    };
+
+    g.with_keyboard(|kbd| Ok(run(kbd, &opts, &g.keymap, &g.correction)?))?;
+    g.remember(State {
+        lighting: Lighting::Custom { slot: opts.slot },
+        brightness: opts.brightness,
+    });
+    Ok(())
+}
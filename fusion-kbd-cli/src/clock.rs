@@ -0,0 +1,336 @@
+//! Clock (`clock`) and countdown timer (`timer`) displays, drawn on the
+//! function and number rows (the rest of the slot is left as it was), and
+//! updated once a second.
+//!
+//! Numbers are shown on the number row: the key for the tens digit lights up
+//! in `TENS`, and the key for the ones digit in `ONES` (or `BOTH`, if they're
+//! the same key). The clock shows the hour as one of F1 - F12 (in `AM` or
+//! `PM`), the minutes on the number row, and blinks Esc every second. The
+//! timer shows the time left as a bar across F1 - F12 (green to red), and the
+//! minutes left on the number row, or the seconds left in the last minute.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::Timelike;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use fusion_kbd_daemon::{pomodoro, SCRATCH_SLOT};
+use fusion_kbd_protocol::correction::Correction;
+use fusion_kbd_protocol::state::{Lighting, State};
+use fusion_kbd_protocol::{self as kbd, effects, CustomConfig, Keymap, Rgb};
+use log::error;
+
+use crate::exit::Failure;
+use crate::{unknown_subcommand, validate_slot, Globals};
+
+const TENS: Rgb = Rgb(0xff, 0x80, 0x00);
+const ONES: Rgb = Rgb(0x00, 0xc0, 0xff);
+const BOTH: Rgb = Rgb(0xff, 0xff, 0xff);
+const AM: Rgb = Rgb(0xff, 0xff, 0x00);
+const PM: Rgb = Rgb(0x80, 0x00, 0xff);
+const TICK: Rgb = Rgb(0xff, 0xff, 0xff);
+const DONE: Rgb = Rgb(0xff, 0x00, 0x00);
+
+const FUNCTION_ROW: [&str; 12] = [
+    "f1", "f2", "f3", "f4", "f5", "f6", "f7", "f8", "f9", "f10", "f11", "f12",
+];
+const NUMBER_ROW: [&str; 10] = ["0", "1", "2", "3", "4", "5", "6", "7", "8", "9"];
+
+/// the longest timer the number row can count down
+pub const MAX_TIMER: Duration = Duration::from_secs(99 * 60);
+
+pub enum Display {
+    Clock,
+    Timer(Duration),
+}
+
+pub struct Options {
+    pub display: Display,
+    pub slot: u8,
+    pub brightness: u8,
+}
+
+/// Parses durations like `25m`, `90s`, or `1h30m`. A bare number is minutes.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    if let Ok(minutes) = s.parse::<u64>() {
+        return Ok(Duration::from_secs(minutes * 60));
+    }
+
+    let mut secs = 0;
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(format!("invalid duration '{}' (e.g: 25m, 1h30m)", s)),
+        };
+        let n: u64 = number
+            .parse()
+            .map_err(|_| format!("invalid duration '{}' (e.g: 25m, 1h30m)", s))?;
+        secs += n * unit;
+        number.clear();
+    }
+    if !number.is_empty() {
+        return Err(format!("invalid duration '{}' (e.g: 25m, 1h30m)", s));
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+/// the keys of the two rows, in order
+struct Rows {
+    function: Vec<usize>,
+    number: Vec<usize>,
+}
+
+impl Rows {
+    fn new(keymap: &Keymap) -> Result<Rows, String> {
+        let lookup = |names: &[&str]| -> Result<Vec<usize>, String> {
+            names
+                .iter()
+                .map(|n| {
+                    keymap
+                        .index(n)
+                        .ok_or_else(|| format!("the keymap doesn't have a '{}' key", n))
+                })
+                .collect()
+        };
+        Ok(Rows {
+            function: lookup(&FUNCTION_ROW)?,
+            number: lookup(&NUMBER_ROW)?,
+        })
+    }
+
+    /// `base`, with both rows blacked out
+    fn clear(&self, base: &CustomConfig) -> CustomConfig {
+        let mut cfg = base.clone();
+        for &key in self.function.iter().chain(&self.number) {
+            cfg.set_key(key, Rgb(0, 0, 0));
+        }
+        cfg
+    }
+
+    /// shows `n` (0 - 99) on the number row
+    fn number(&self, cfg: &mut CustomConfig, n: u32) {
+        let (tens, ones) = ((n / 10 % 10) as usize, (n % 10) as usize);
+        if tens == ones {
+            cfg.set_key(self.number[ones], BOTH);
+        } else {
+            cfg.set_key(self.number[tens], TENS);
+            cfg.set_key(self.number[ones], ONES);
+        }
+    }
+}
+
+fn clock(rows: &Rows, base: &CustomConfig, keymap: &Keymap) -> CustomConfig {
+    let now = chrono::Local::now();
+    let mut cfg = rows.clear(base);
+
+    // 0 and 12 are both F12
+    let hour = (now.hour() + 11) % 12;
+    let color = if now.hour() < 12 { AM } else { PM };
+    cfg.set_key(rows.function[hour as usize], color);
+    rows.number(&mut cfg, now.minute());
+
+    if let Some(esc) = keymap.index("esc") {
+        let tick = if now.second().is_multiple_of(2) {
+            TICK
+        } else {
+            base.get_key(esc)
+        };
+        cfg.set_key(esc, tick);
+    }
+    cfg
+}
+
+fn timer(rows: &Rows, base: &CustomConfig, total: Duration, left: Duration) -> CustomConfig {
+    let mut cfg = rows.clear(base);
+
+    let fraction = left.as_secs_f32() / total.as_secs_f32();
+    let lit = (fraction * rows.function.len() as f32).ceil() as usize;
+    let color = effects::blend(DONE, Rgb(0x00, 0xff, 0x00), fraction);
+    for &key in rows.function.iter().take(lit) {
+        cfg.set_key(key, color);
+    }
+
+    // rounded up, so a fresh 25 minute timer shows 25
+    let secs = left.as_secs() as u32 + (left.subsec_nanos() > 0) as u32;
+    if secs >= 60 {
+        rows.number(&mut cfg, secs.div_ceil(60));
+    } else {
+        rows.number(&mut cfg, secs);
+    }
+    cfg
+}
+
+/// Runs the clock until interrupted, or the timer until it's done.
+pub fn run(
+    kbd: &dyn kbd::Keyboard,
+    opts: &Options,
+    keymap: &Keymap,
+    correction: &Correction,
+) -> Result<(), libusb::Error> {
+    let rows = match Rows::new(keymap) {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!("{}", e);
+            return Err(libusb::Error::Other);
+        }
+    };
+
+    let base = kbd.download_config(opts.slot)?;
+
+    let show = |cfg: &CustomConfig| -> Result<(), libusb::Error> {
+        kbd.upload_custom(opts.slot, correction.apply(cfg).as_bytes())?;
+        kbd.set_custom(opts.slot, opts.brightness)
+    };
+
+    let start = Instant::now();
+    let mut shown: Option<CustomConfig> = None;
+    loop {
+        let cfg = match opts.display {
+            Display::Clock => clock(&rows, &base, keymap),
+            Display::Timer(total) => match total.checked_sub(start.elapsed()) {
+                Some(left) if !left.is_zero() => timer(&rows, &base, total, left),
+                _ => break,
+            },
+        };
+        if shown.as_ref().map(CustomConfig::as_bytes) != Some(cfg.as_bytes()) {
+            show(&cfg)?;
+            shown = Some(cfg);
+        }
+
+        // until the next whole second (of the clock, or of the timer)
+        let wait = match opts.display {
+            // nanosecond() goes past 1s during leap seconds
+            Display::Clock => 1_000_000_000 - chrono::Local::now().nanosecond() % 1_000_000_000,
+            Display::Timer(total) => total.saturating_sub(start.elapsed()).subsec_nanos(),
+        };
+        thread::sleep(Duration::from_nanos(wait as u64));
+    }
+
+    // flash both rows, then put the slot back how it was
+    let mut done = rows.clear(&base);
+    for &key in rows.function.iter().chain(&rows.number) {
+        done.set_key(key, DONE);
+    }
+    for _ in 0..3 {
+        show(&done)?;
+        thread::sleep(Duration::from_millis(500));
+        show(&rows.clear(&base))?;
+        thread::sleep(Duration::from_millis(500));
+    }
+    show(&base)
+}
+
+#[rustfmt::skip]
+pub fn subcommands<'a, 'b>() -> Vec<App<'a, 'b>> {
+    vec![
+        SubCommand::with_name("clock")
+            .about("Show the time on the function and number rows")
+            .arg(Arg::with_name("slot")
+                .takes_value(true)
+                .short("s")
+                .long("slot")
+                .validator(validate_slot)
+                .help("Custom slot to draw on, keeping its other keys (default: 4)")),
+        SubCommand::with_name("timer")
+            .about("Count down on the function and number rows, then flash")
+            .arg(Arg::with_name("duration")
+                .required(true)
+                .index(1)
+                .validator(|dstr| {
+                    let duration = parse_duration(&dstr)?;
+                    if duration.as_secs() == 0 || duration > MAX_TIMER {
+                        return Err("duration must be between 1s and 99m!".to_string())
+                    }
+                    Ok(())
+                })
+                .help("How long to count down, e.g: 25m, 90s, 1h30m (plain numbers are minutes)"))
+            .arg(Arg::with_name("slot")
+                .takes_value(true)
+                .short("s")
+                .long("slot")
+                .validator(validate_slot)
+                .help("Custom slot to draw on, keeping its other keys (default: 4)")),
+        SubCommand::with_name("pomodoro")
+            .about("Fill the keyboard with color over each work interval and break, flashing in between")
+            .arg(Arg::with_name("work")
+                .takes_value(true)
+                .long("work")
+                .validator(|wstr| {
+                    if parse_duration(&wstr)?.as_secs() == 0 {
+                        return Err("work must be longer than 0s!".to_string())
+                    }
+                    Ok(())
+                })
+                .help("Length of work intervals, e.g: 25m, 50m (default: 25m)"))
+            .arg(Arg::with_name("break")
+                .takes_value(true)
+                .long("break")
+                .validator(|bstr| {
+                    if parse_duration(&bstr)?.as_secs() == 0 {
+                        return Err("break must be longer than 0s!".to_string())
+                    }
+                    Ok(())
+                })
+                .help("Length of breaks (default: 5m)"))
+            .arg(Arg::with_name("slot")
+                .takes_value(true)
+                .short("s")
+                .long("slot")
+                .validator(validate_slot)
+                .help("Custom slot frames are streamed through (default: 4)")),
+    ]
+}
+
+pub fn exec(name: &str, m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let slot = m
+        .value_of("slot")
+        .map_or(SCRATCH_SLOT, |sstr| sstr.parse::<u8>().unwrap());
+    let brightness = g.brightness();
+
+    match name {
+        "clock" | "timer" => {
+            let display = match m.value_of("duration") {
+                Some(dstr) => Display::Timer(parse_duration(dstr).unwrap()),
+                None => Display::Clock,
+            };
+            let opts = Options {
+                display,
+                slot,
+                brightness,
+            };
+            g.with_keyboard(|kbd| Ok(run(kbd, &opts, &g.keymap, &g.correction)?))?;
+        }
+        "pomodoro" => {
+            let duration = |arg: &str, default: u64| {
+                m.value_of(arg)
+                    .map_or(Duration::from_secs(default * 60), |dstr| {
+                        parse_duration(dstr).unwrap()
+                    })
+            };
+            let pomodoro = pomodoro::Pomodoro::start(pomodoro::Config {
+                work: duration("work", 25),
+                rest: duration("break", 5),
+                slot,
+            });
+            g.with_keyboard(|kbd| {
+                Ok(pomodoro::run(kbd, &pomodoro, &g.correction, &|| {
+                    Some(brightness)
+                })?)
+            })?;
+        }
+        _ => return Err(unknown_subcommand(name)),
+    }
+
+    g.remember(State {
+        lighting: Lighting::Custom { slot },
+        brightness,
+    });
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use std::fs;
+use std::path::Path;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use fusion_kbd_protocol::config::container::{About, Container};
+use fusion_kbd_protocol::config::{self, Format};
+use fusion_kbd_protocol::protocol::BYTES_PER_KEY;
+use fusion_kbd_protocol::Keymap;
+use log::error;
+
+use crate::exit::Failure;
+use crate::Globals;
+
+/// Converts the config in `from` to the format `to`'s extension implies (see
+/// `Format::from_path`). Containers also get `about` (on top of whatever
+/// `from` said about itself, if it's a container too), and say they're for
+/// `model` unless `from` says otherwise.
+pub fn run(
+    from: &str,
+    to: &str,
+    about: &About,
+    model: &str,
+    keymap: &Keymap,
+) -> Result<(), String> {
+    let (from_path, to_path) = (Path::new(from), Path::new(to));
+    if from_path == to_path {
+        return Err(format!("refusing to overwrite '{}' in place", from));
+    }
+
+    let data = fs::read(from_path).map_err(|e| format!("couldn't open '{}': {}", from, e))?;
+    let (from_format, to_format) = (Format::from_path(from_path), Format::from_path(to_path));
+    let cfg = config::decode(&data, from_format, keymap)
+        .map_err(|e| format!("invalid config '{}': {}", from, e))?;
+
+    // only the raw formats keep the first byte of each key
+    let raw = |f: Format| matches!(f, Format::Binary | Format::Container);
+    if raw(from_format)
+        && !raw(to_format)
+        && cfg.as_bytes().chunks(BYTES_PER_KEY).any(|key| key[0] != 0)
+    {
+        println!(
+            "Note: '{}' only keeps colors, so the first byte of each key in '{}' is dropped",
+            to, from
+        );
+    }
+
+    let out = match to_format {
+        Format::Container => {
+            let mut out = match from_format {
+                Format::Container => Container::from_bytes(&data)?,
+                _ => Container {
+                    model: model.to_string(),
+                    layout: keymap.layout().to_string(),
+                    about: About::default(),
+                    config: cfg,
+                },
+            };
+            out.about = out.about.merge(about);
+            out.to_bytes()
+        }
+        _ => config::encode(&cfg, to_format, model, keymap)?,
+    };
+    fs::write(to_path, out).map_err(|e| format!("couldn't write '{}': {}", to, e))?;
+    println!("Wrote '{}'", to);
+    Ok(())
+}
+
+#[rustfmt::skip]
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("convert")
+        .about("Convert a config between formats, picked by extension (binary, .fkp, .json, .toml, .png)")
+        .arg(Arg::with_name("from")
+            .required(true)
+            .value_name("FROM")
+            .index(1))
+        .arg(Arg::with_name("to")
+            .required(true)
+            .value_name("TO")
+            .index(2))
+        .arg(Arg::with_name("name")
+            .takes_value(true)
+            .long("name")
+            .help("What the profile is called (.fkp only)"))
+        .arg(Arg::with_name("author")
+            .takes_value(true)
+            .long("author")
+            .help("Who made it (.fkp only)"))
+        .arg(Arg::with_name("description")
+            .takes_value(true)
+            .long("description")
+            .help("What it looks like, or is for (.fkp only)"))
+}
+
+pub fn exec(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let about = About {
+        name: m.value_of("name").map(str::to_string),
+        author: m.value_of("author").map(str::to_string),
+        description: m.value_of("description").map(str::to_string),
+    };
+    let (from, to) = (m.value_of("from").unwrap(), m.value_of("to").unwrap());
+    if let Err(e) = run(from, to, &about, g.model.name, &g.keymap) {
+        error!("{}", e);
+        return Err(Failure::File);
+    }
+    Ok(())
+}
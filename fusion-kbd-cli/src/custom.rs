@@ -0,0 +1,712 @@
+//! `custom`: showing, uploading and downloading custom slots, and working
+//! with custom configs (drawing, checking, making and changing them).
+
+use std::fs::File;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use fusion_kbd_daemon::themes;
+use fusion_kbd_protocol::state::{Lighting, State};
+use fusion_kbd_protocol::transform::Transform;
+use fusion_kbd_protocol::{self as kbd, CustomConfig, Keymap, Rgb};
+use log::{error, info};
+
+use crate::exit::Failure;
+use crate::{convert, editor, unknown_subcommand, validate_slot, Globals, Help};
+
+/// where a config comes from
+enum Source {
+    File(String),
+    /// an installed or built-in theme, by name (see `themes`)
+    Theme(String),
+}
+
+impl Source {
+    fn load(&self, keymap: &Keymap) -> Result<CustomConfig, String> {
+        match *self {
+            Source::File(ref file) => kbd::config::load(Path::new(file), keymap)
+                .map_err(|e| format!("invalid config '{}': {}", file, e)),
+            Source::Theme(ref name) => themes::load(name, keymap),
+        }
+    }
+}
+
+/// procedurally generated configs (see `custom gen`)
+enum Generator {
+    Sparkle {
+        base: Rgb,
+        accent: Rgb,
+        density: f32,
+        /// picked from the clock if not given
+        seed: Option<u64>,
+    },
+    Rainbow {
+        by_row: bool,
+    },
+}
+
+impl Generator {
+    fn generate(&self, keymap: &Keymap) -> CustomConfig {
+        match *self {
+            Generator::Sparkle {
+                base,
+                accent,
+                density,
+                seed,
+            } => {
+                let seed = seed.unwrap_or_else(|| {
+                    let seed = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map_or(0, |d| d.as_nanos() as u64);
+                    info!("Using seed {}", seed);
+                    seed
+                });
+                kbd::effects::sparkle(base, accent, density, seed, keymap)
+            }
+            Generator::Rainbow { by_row } => kbd::effects::rainbow(by_row, keymap),
+        }
+    }
+}
+
+/// Writes a freshly made config to `out` (which mustn't exist yet), or
+/// prints it as a JSON profile if there's nowhere to write it.
+fn write_new(
+    cfg: &CustomConfig,
+    out: Option<&str>,
+    model: &str,
+    keymap: &Keymap,
+) -> Result<(), Failure> {
+    match out {
+        Some(out) => {
+            if Path::new(out).exists() {
+                error!("refusing to overwrite '{}'", out);
+                return Err(Failure::File);
+            }
+            if let Err(e) = kbd::config::save(Path::new(out), cfg, model, keymap) {
+                error!("{}", e);
+                return Err(Failure::File);
+            }
+            println!("Wrote '{}'", out);
+        }
+        None => print!("{}", kbd::config::json::to_json(cfg, keymap)),
+    }
+    Ok(())
+}
+
+/// Uploads `cfg` to `slot`. With `verify`, the slot is read back afterwards,
+/// and if any key didn't arrive the way it was sent, they're listed and the
+/// upload fails (rather than the corruption going unnoticed).
+fn upload(
+    kbd: &dyn kbd::Keyboard,
+    slot: u8,
+    cfg: &CustomConfig,
+    verify: bool,
+) -> Result<(), Failure> {
+    kbd.upload_custom(slot, cfg.as_bytes())?;
+    if !verify {
+        return Ok(());
+    }
+
+    let readback = kbd.download_config(slot)?;
+    let mismatched = cfg.diff(&readback);
+    if mismatched.is_empty() {
+        println!("Verified slot {}", slot);
+        return Ok(());
+    }
+
+    error!(
+        "slot {} didn't read back the way it was sent ({} key(s)):",
+        slot,
+        mismatched.len()
+    );
+    for key in mismatched {
+        error!(
+            "  key {} (offset {}): sent {}, read back {}",
+            key,
+            kbd::protocol::key_offset(key),
+            cfg.get_key(key),
+            readback.get_key(key)
+        );
+    }
+    Err(Failure::Verify)
+}
+
+/// Refuses a config that says it was made for another model than `kbd` (see
+/// `config::made_for`), rather than uploading it to keys it wasn't meant for.
+pub fn check_model(kbd: &dyn kbd::Keyboard, path: &Path) -> Result<(), String> {
+    let model = kbd.capabilities().model;
+    match kbd::config::made_for(path) {
+        Some(made_for) if made_for != model => Err(format!(
+            "'{}' was made for a {} keyboard, not this {} (`custom convert` it to a \
+             .json profile to upload it anyway)",
+            path.display(),
+            made_for,
+            model
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Lists every key that differs between `a` and `b` (in color, or in its
+/// first, unknown byte)
+fn print_diff(a: &CustomConfig, b: &CustomConfig, keymap: &Keymap) {
+    let (a_bytes, b_bytes) = (a.as_bytes(), b.as_bytes());
+    let mut differ = 0;
+    for key in 0..a.num_keys().min(b.num_keys()) {
+        let name = match keymap.name(key) {
+            Some(name) => format!("{} (key {})", name, key),
+            None => format!("key {}", key),
+        };
+        let (a_color, b_color) = (a.get_key(key), b.get_key(key));
+        let offset = kbd::protocol::key_offset(key);
+        let (a_first, b_first) = (a_bytes[offset], b_bytes[offset]);
+        if a_color == b_color && a_first == b_first {
+            continue;
+        }
+
+        differ += 1;
+        let mut line = format!("{:<18}", name);
+        if a_color != b_color {
+            line.push_str(&format!(" {} -> {}", a_color, b_color));
+        }
+        if a_first != b_first {
+            line.push_str(&format!(
+                " first byte 0x{:02x} -> 0x{:02x}",
+                a_first, b_first
+            ));
+        }
+        println!("{}", line);
+    }
+
+    match differ {
+        0 => println!("no differences"),
+        1 => println!("1 key differs"),
+        n => println!("{} keys differ", n),
+    }
+}
+
+/// `kbd::config::load`, with its errors printed
+fn load(file: &str, keymap: &Keymap) -> Result<CustomConfig, Failure> {
+    kbd::config::load(Path::new(file), keymap).map_err(|e| {
+        error!("invalid config '{}': {}", file, e);
+        Failure::File
+    })
+}
+
+/// Saves a variation of `from` to `out`, for the same model `from` was made
+/// for (or else `model`).
+fn save_variation(
+    cfg: &CustomConfig,
+    from: &str,
+    out: &str,
+    model: &str,
+    keymap: &Keymap,
+) -> Result<(), Failure> {
+    let made_for = kbd::config::made_for(Path::new(from));
+    let made_for = made_for.as_deref().unwrap_or(model);
+    if let Err(e) = kbd::config::save(Path::new(out), cfg, made_for, keymap) {
+        error!("{}", e);
+        return Err(Failure::File);
+    }
+    println!("Wrote '{}'", out);
+    Ok(())
+}
+
+#[rustfmt::skip]
+pub fn subcommand<'a, 'b>(help: &'b Help) -> App<'a, 'b> {
+    SubCommand::with_name("custom")
+        .about("Work with Custom lighting profiles")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(editor::subcommand(help))
+        .subcommand(SubCommand::with_name("render")
+            .about("Draw a custom config in the terminal (needs truecolor support)")
+            .arg(Arg::with_name("file")
+                .required_unless_one(&["slot", "theme"])
+                .conflicts_with_all(&["slot", "theme"])
+                .value_name("FILE")
+                .index(1)
+                .help("Config to draw (binary, .fkp container, .json / .toml / .txt profile, or OpenRGB .orp)"))
+            .arg(Arg::with_name("theme")
+                .conflicts_with("slot")
+                .takes_value(true)
+                .value_name("NAME")
+                .long("theme")
+                .help("Draw a theme instead (see `themes list`)"))
+            .arg(Arg::with_name("slot")
+                .takes_value(true)
+                .short("s")
+                .long("slot")
+                .validator(validate_slot)
+                .help(&help.render_slot)))
+        .subcommand(SubCommand::with_name("validate")
+            .about("Check config files for problems, without uploading them")
+            .arg(Arg::with_name("files")
+                .required(true)
+                .multiple(true)
+                .value_name("FILE")
+                .help("Configs to check (binary, .fkp container, .json / .toml / .txt profile, OpenRGB .orp, or .png)")))
+        .subcommand(convert::subcommand())
+        .subcommand(SubCommand::with_name("new")
+            .about("Write a starter profile from a template, to edit into something else")
+            .arg(Arg::with_name("file")
+                .value_name("FILE")
+                .index(1)
+                .help("Where to write it, in any format (default: print a JSON profile)"))
+            .arg(Arg::with_name("template")
+                .required(true)
+                .takes_value(true)
+                .short("t")
+                .long("template")
+                .possible_values(kbd::effects::TEMPLATES))
+            .arg(Arg::with_name("color")
+                .takes_value(true)
+                .short("c")
+                .long("color")
+                .multiple(true)
+                .number_of_values(1)
+                .validator(|cstr| kbd::Rgb::from_str(&cstr).map(|_| ()))
+                .help("Colors for the template, in order (repeatable; default: depends on the template)")))
+        .subcommand(SubCommand::with_name("gen")
+            .about("Generate a config procedurally")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("sparkle")
+                .about("Scatter randomly picked accent keys over a base color")
+                .arg(Arg::with_name("file")
+                    .value_name("FILE")
+                    .index(1)
+                    .help("Where to write it, in any format (default: print a JSON profile)"))
+                .arg(Arg::with_name("base")
+                    .takes_value(true)
+                    .long("base")
+                    .value_name("COLOR")
+                    .validator(|cstr| kbd::Rgb::from_str(&cstr).map(|_| ()))
+                    .help("Color of most keys (default: #101020)"))
+                .arg(Arg::with_name("accent")
+                    .takes_value(true)
+                    .long("accent")
+                    .value_name("COLOR")
+                    .validator(|cstr| kbd::Rgb::from_str(&cstr).map(|_| ()))
+                    .help("Color of the scattered keys (default: #ffffff)"))
+                .arg(Arg::with_name("density")
+                    .takes_value(true)
+                    .long("density")
+                    .validator(|dstr| match dstr.parse::<f32>() {
+                        Ok(d) if (0.0..=1.0).contains(&d) => Ok(()),
+                        _ => Err("density must be a number from 0 - 1!".to_string()),
+                    })
+                    .help("Fraction of keys to make accents (default: 0.1)"))
+                .arg(Arg::with_name("seed")
+                    .takes_value(true)
+                    .long("seed")
+                    .validator(|sstr| sstr.parse::<u64>().map(|_| ()).map_err(|e| e.to_string()))
+                    .help("Seed for picking keys, to get the same ones again (default: random)")))
+            .subcommand(SubCommand::with_name("rainbow")
+                .about("A still rainbow, from red on the left to violet on the right")
+                .arg(Arg::with_name("file")
+                    .value_name("FILE")
+                    .index(1)
+                    .help("Where to write it, in any format (default: print a JSON profile)"))
+                .arg(Arg::with_name("rows")
+                    .long("rows")
+                    .help("Run the rainbow down the rows (top to bottom) instead"))))
+        .subcommand(SubCommand::with_name("diff")
+            .about("Show which keys differ between two configs, or a config and a slot")
+            .arg(Arg::with_name("a")
+                .required(true)
+                .value_name("A")
+                .index(1))
+            .arg(Arg::with_name("b")
+                .required_unless("slot")
+                .conflicts_with("slot")
+                .value_name("B")
+                .index(2))
+            .arg(Arg::with_name("slot")
+                .takes_value(true)
+                .short("s")
+                .long("slot")
+                .validator(validate_slot)
+                .help(&help.diff_slot)))
+        .subcommand(SubCommand::with_name("blend")
+            .about("Mix two configs key by key, in any format")
+            .arg(Arg::with_name("a")
+                .required(true)
+                .value_name("A")
+                .index(1))
+            .arg(Arg::with_name("b")
+                .required(true)
+                .value_name("B")
+                .index(2))
+            .arg(Arg::with_name("ratio")
+                .takes_value(true)
+                .short("r")
+                .long("ratio")
+                .validator(|rstr| match rstr.parse::<f32>() {
+                    Ok(r) if (0.0..=1.0).contains(&r) => Ok(()),
+                    _ => Err("ratio must be a number from 0 - 1!".to_string()),
+                })
+                .help("How much of B to mix in: 0 is all A, 1 is all B (default: 0.5)"))
+            .arg(Arg::with_name("out")
+                .required(true)
+                .takes_value(true)
+                .short("o")
+                .long("out")
+                .value_name("FILE")))
+        .subcommand(SubCommand::with_name("transform")
+            .about("Make a variation of a config, in any format")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("mirror")
+                .about("Flip it left to right")
+                .arg(Arg::with_name("file")
+                    .required(true)
+                    .value_name("FILE")
+                    .index(1))
+                .arg(Arg::with_name("out")
+                    .required(true)
+                    .takes_value(true)
+                    .short("o")
+                    .long("out")
+                    .value_name("FILE")))
+            .subcommand(SubCommand::with_name("shift")
+                .about("Move colors along each row, wrapping around")
+                .setting(AppSettings::AllowNegativeNumbers)
+                .arg(Arg::with_name("keys")
+                    .required(true)
+                    .value_name("N")
+                    .index(2)
+                    .validator(|nstr| match nstr.parse::<isize>() {
+                        Ok(_) => Ok(()),
+                        Err(_) => Err("N must be a whole number of keys!".to_string()),
+                    })
+                    .help("How many keys to the right (negative for left)"))
+                .arg(Arg::with_name("file")
+                    .required(true)
+                    .value_name("FILE")
+                    .index(1))
+                .arg(Arg::with_name("out")
+                    .required(true)
+                    .takes_value(true)
+                    .short("o")
+                    .long("out")
+                    .value_name("FILE")))
+            .subcommand(SubCommand::with_name("hue")
+                .about("Rotate every key's hue")
+                .setting(AppSettings::AllowNegativeNumbers)
+                .arg(Arg::with_name("degrees")
+                    .required(true)
+                    .value_name("DEGREES")
+                    .index(2)
+                    .validator(|dstr| match dstr.parse::<f32>() {
+                        Ok(_) => Ok(()),
+                        Err(_) => Err("DEGREES must be a number!".to_string()),
+                    }))
+                .arg(Arg::with_name("file")
+                    .required(true)
+                    .value_name("FILE")
+                    .index(1))
+                .arg(Arg::with_name("out")
+                    .required(true)
+                    .takes_value(true)
+                    .short("o")
+                    .long("out")
+                    .value_name("FILE")))
+            .subcommand(SubCommand::with_name("invert")
+                .about("Swap light for dark, keeping each key's hue")
+                .arg(Arg::with_name("file")
+                    .required(true)
+                    .value_name("FILE")
+                    .index(1))
+                .arg(Arg::with_name("out")
+                    .required(true)
+                    .takes_value(true)
+                    .short("o")
+                    .long("out")
+                    .value_name("FILE"))))
+        .arg(Arg::with_name("slot")
+            .required(true)
+            .index(1)
+            .validator(validate_slot)
+            .help(&help.slot))
+        .arg(Arg::with_name("set")
+            .conflicts_with_all(&["get", "set-image", "theme"])
+            .takes_value(true)
+            .value_name("FILE")
+            .long("set")
+            .help("Upload new RGB Configuration to selected slot (binary, .fkp container, .json / .toml / .txt profile, or OpenRGB .orp)"))
+        .arg(Arg::with_name("set-image")
+            .conflicts_with_all(&["get", "set", "theme"])
+            .takes_value(true)
+            .value_name("PNG")
+            .long("set-image")
+            .help("Upload new RGB Configuration to selected slot, sampled from a PNG"))
+        .arg(Arg::with_name("get")
+            .conflicts_with_all(&["set", "set-image", "theme"])
+            .takes_value(true)
+            .value_name("FILE")
+            .long("get")
+            .help("Download RGB Configuration from selected slot (binary, .fkp container, .json / .toml / .txt profile, OpenRGB .orp, or .png)"))
+        .arg(Arg::with_name("theme")
+            .conflicts_with_all(&["set", "set-image", "get"])
+            .takes_value(true)
+            .value_name("NAME")
+            .long("theme")
+            .help("Upload a theme to selected slot (see `themes list`)"))
+        .arg(Arg::with_name("verify")
+            .long("verify")
+            .help("After uploading, read the slot back and check it arrived intact"))
+}
+
+pub fn exec(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    match m.subcommand() {
+        ("edit", Some(m)) => editor::exec(m, g),
+        ("render", Some(m)) => render(m, g),
+        ("validate", Some(m)) => validate(m, g),
+        ("convert", Some(m)) => convert::exec(m, g),
+        ("new", Some(m)) => new(m, g),
+        ("gen", Some(m)) => gen(m, g),
+        ("diff", Some(m)) => diff(m, g),
+        ("blend", Some(m)) => blend(m, g),
+        ("transform", Some(m)) => transform(m, g),
+        ("", None) => slot(m, g),
+        (name, _) => Err(unknown_subcommand(name)),
+    }
+}
+
+/// `custom N`, switching to (and with `--set` / `--theme` / `--set-image`,
+/// uploading to) slot N, or with `--get`, downloading it
+fn slot(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let slot = m.value_of("slot").unwrap().parse::<u8>().unwrap();
+    let brightness = g.brightness();
+    let verify = m.is_present("verify");
+    if verify && g.all_devices {
+        // (see `Broadcast::download_custom`)
+        error!(
+            "--verify can't read a slot back from several keyboards at once. Pick \
+             one with --device instead"
+        );
+        return Err(libusb::Error::InvalidParam.into());
+    }
+
+    if let Some(file) = m.value_of("get") {
+        return g.with_keyboard(|kbd| {
+            let cfg = kbd.download_config(slot)?;
+            let model = kbd.capabilities().model;
+            if let Err(e) = kbd::config::save(Path::new(file), &cfg, &model, &g.keymap) {
+                error!("{}", e);
+                return Err(Failure::File);
+            }
+            Ok(())
+        });
+    }
+
+    let config = match (m.value_of("set"), m.value_of("theme")) {
+        (Some(file), _) => Some(Source::File(file.to_string())),
+        (None, Some(theme)) => Some(Source::Theme(theme.to_string())),
+        (None, None) => None,
+    };
+    g.with_keyboard(|kbd| {
+        if let Some(ref config) = config {
+            let cfg = match config.load(&g.keymap) {
+                Ok(cfg) => cfg,
+                Err(e) => {
+                    error!("{}", e);
+                    return Err(Failure::File);
+                }
+            };
+            if let Source::File(ref file) = *config {
+                if let Err(e) = check_model(kbd, Path::new(file)) {
+                    error!("{}", e);
+                    return Err(libusb::Error::InvalidParam.into());
+                }
+            }
+
+            upload(kbd, slot, &g.correction.apply(&cfg), verify)?;
+        } else if let Some(image) = m.value_of("set-image") {
+            let f = match File::open(image) {
+                Ok(file) => file,
+                Err(e) => {
+                    error!("couldn't open '{}': {}", image, e);
+                    return Err(Failure::File);
+                }
+            };
+
+            let cfg = match kbd::config::image::from_png(f) {
+                Ok(cfg) => cfg,
+                Err(e) => {
+                    error!("invalid image '{}': {}", image, e);
+                    return Err(Failure::File);
+                }
+            };
+
+            upload(kbd, slot, &g.correction.apply(&cfg), verify)?;
+        }
+        Ok(kbd.set_custom(slot, brightness)?)
+    })?;
+    g.remember(State {
+        lighting: Lighting::Custom { slot },
+        brightness,
+    });
+    Ok(())
+}
+
+fn render(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    if let Some(sstr) = m.value_of("slot") {
+        let slot = sstr.parse::<u8>().unwrap();
+        return g.with_keyboard(|kbd| {
+            let cfg = kbd.download_config(slot)?;
+            print!("{}", kbd::preview::ansi(&cfg, &g.keymap, "\n"));
+            Ok(())
+        });
+    }
+
+    // rendering a file happens entirely offline
+    let config = match m.value_of("theme") {
+        Some(theme) => Source::Theme(theme.to_string()),
+        None => Source::File(m.value_of("file").unwrap().to_string()),
+    };
+    match config.load(&g.keymap) {
+        Ok(cfg) => print!("{}", kbd::preview::ansi(&cfg, &g.keymap, "\n")),
+        Err(e) => {
+            error!("{}", e);
+            return Err(Failure::File);
+        }
+    }
+    Ok(())
+}
+
+fn validate(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let mut failed = None;
+    for config in m.values_of("files").unwrap() {
+        let path = Path::new(config);
+        let problems = match std::fs::read(path) {
+            Ok(data) => kbd::config::validate::validate(
+                &data,
+                kbd::config::Format::from_path(path),
+                &g.keymap,
+            ),
+            Err(e) => {
+                error!("{}: couldn't open it: {}", config, e);
+                failed = Some(Failure::File);
+                continue;
+            }
+        };
+
+        if problems.is_empty() {
+            println!("{}: ok", config);
+        }
+        for problem in &problems {
+            println!("{}: {}", config, problem);
+            if problem.severity == kbd::config::validate::Severity::Error {
+                failed = failed.or(Some(Failure::Verify));
+            }
+        }
+    }
+    match failed {
+        Some(failure) => Err(failure),
+        None => Ok(()),
+    }
+}
+
+fn new(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let colors: Vec<Rgb> = m
+        .values_of("color")
+        .map(|colors| colors.map(|c| Rgb::from_str(c).unwrap()).collect())
+        .unwrap_or_default();
+    let template = m.value_of("template").unwrap();
+    let cfg = kbd::effects::template(template, &colors, &g.keymap).unwrap();
+    write_new(&cfg, m.value_of("file"), g.model.name, &g.keymap)
+}
+
+fn gen(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let (generator, m) = match m.subcommand() {
+        ("sparkle", Some(m)) => {
+            let color = |name: &str, default: Rgb| {
+                m.value_of(name)
+                    .map_or(default, |cstr| Rgb::from_str(cstr).unwrap())
+            };
+            (
+                Generator::Sparkle {
+                    base: color("base", Rgb(0x10, 0x10, 0x20)),
+                    accent: color("accent", Rgb(0xff, 0xff, 0xff)),
+                    density: m
+                        .value_of("density")
+                        .map_or(0.1, |dstr| dstr.parse::<f32>().unwrap()),
+                    seed: m.value_of("seed").map(|sstr| sstr.parse::<u64>().unwrap()),
+                },
+                m,
+            )
+        }
+        ("rainbow", Some(m)) => (
+            Generator::Rainbow {
+                by_row: m.is_present("rows"),
+            },
+            m,
+        ),
+        (name, _) => return Err(unknown_subcommand(name)),
+    };
+    write_new(
+        &generator.generate(&g.keymap),
+        m.value_of("file"),
+        g.model.name,
+        &g.keymap,
+    )
+}
+
+fn diff(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let a = m.value_of("a").unwrap();
+    match m.value_of("slot") {
+        Some(sstr) => {
+            let slot = sstr.parse::<u8>().unwrap();
+            let cfg = load(a, &g.keymap)?;
+            g.with_keyboard(|kbd| {
+                // what `--set` would have uploaded
+                print_diff(
+                    &g.correction.apply(&cfg),
+                    &kbd.download_config(slot)?,
+                    &g.keymap,
+                );
+                Ok(())
+            })
+        }
+        None => {
+            let b = m.value_of("b").unwrap();
+            print_diff(&load(a, &g.keymap)?, &load(b, &g.keymap)?, &g.keymap);
+            Ok(())
+        }
+    }
+}
+
+fn blend(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let (a, b) = (m.value_of("a").unwrap(), m.value_of("b").unwrap());
+    let ratio = m
+        .value_of("ratio")
+        .map_or(0.5, |rstr| rstr.parse::<f32>().unwrap());
+    let cfg = kbd::effects::crossfade(&load(a, &g.keymap)?, &load(b, &g.keymap)?, ratio);
+    save_variation(&cfg, a, m.value_of("out").unwrap(), g.model.name, &g.keymap)
+}
+
+fn transform(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let (transform, m) = match m.subcommand() {
+        ("mirror", Some(m)) => (Transform::Mirror, m),
+        ("shift", Some(m)) => (
+            Transform::Shift(m.value_of("keys").unwrap().parse::<isize>().unwrap()),
+            m,
+        ),
+        ("hue", Some(m)) => (
+            Transform::Hue(m.value_of("degrees").unwrap().parse::<f32>().unwrap()),
+            m,
+        ),
+        ("invert", Some(m)) => (Transform::Invert, m),
+        (name, _) => return Err(unknown_subcommand(name)),
+    };
+    let file = m.value_of("file").unwrap();
+    let cfg = transform.apply(&load(file, &g.keymap)?, &g.keymap);
+    save_variation(
+        &cfg,
+        file,
+        m.value_of("out").unwrap(),
+        g.model.name,
+        &g.keymap,
+    )
+}
@@ -0,0 +1,27 @@
+//! `daemon`: holds on to the keyboard, applies the config file's rules, and
+//! takes commands from other invocations (see `fusion_kbd_daemon::service`).
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use fusion_kbd_daemon::service::{self, Options};
+
+use crate::exit::Failure;
+use crate::Globals;
+
+#[rustfmt::skip]
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("daemon")
+        .about("Hold on to the keyboard, apply the config file's rules, and take commands from other invocations")
+        .arg(Arg::with_name("http")
+            .long("http")
+            .takes_value(true)
+            .value_name("ADDR")
+            .validator(|astr| astr.parse::<std::net::SocketAddr>().map(|_| ()).map_err(|e| e.to_string()))
+            .help("Serve the HTTP API on this address, e.g: 127.0.0.1:9123 (unauthenticated!)"))
+}
+
+pub fn exec(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let options = Options {
+        http: m.value_of("http").map(|astr| astr.parse().unwrap()),
+    };
+    Ok(service::run(&g.settings, &options)?)
+}
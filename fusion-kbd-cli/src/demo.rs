@@ -0,0 +1,95 @@
+use std::thread;
+use std::time::Duration;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use fusion_kbd_daemon::saved;
+use fusion_kbd_protocol::{self as kbd, Color, Preset};
+use kbd::state::State;
+
+use crate::exit::Failure;
+use crate::{clock, Globals};
+
+pub struct Options {
+    /// how long to show each combination for
+    pub dwell: Duration,
+    pub speed: u8,
+    pub brightness: u8,
+}
+
+/// Every preset / color combination the keyboard supports. Presets that
+/// bring their own colors (see `Preset::takes_color`) only get shown once.
+fn combinations(caps: &kbd::Capabilities) -> Vec<(Preset, Color)> {
+    let mut combinations = Vec::new();
+    for &preset in caps.presets.iter() {
+        if !preset.takes_color() {
+            combinations.push((preset, Color::Rand));
+            continue;
+        }
+        for &color in caps.preset_colors.iter() {
+            combinations.push((preset, color));
+        }
+    }
+    combinations
+}
+
+/// Steps through every hardware preset, in every color, then puts back
+/// whatever lighting was last set.
+pub fn run(kbd: &dyn kbd::Keyboard, opts: &Options) -> Result<(), libusb::Error> {
+    let combinations = combinations(&kbd.capabilities());
+    for (i, &(preset, color)) in combinations.iter().enumerate() {
+        println!("[{}/{}] {} ({})", i + 1, combinations.len(), preset, color);
+        kbd.set_preset(preset, opts.speed, opts.brightness, color)?;
+        thread::sleep(opts.dwell);
+    }
+
+    if let Some(saved) = saved::load() {
+        let brightness = if saved.off { 0 } else { saved.state.brightness };
+        kbd.set_state(&State {
+            lighting: saved.state.lighting,
+            brightness,
+        })?;
+    }
+    Ok(())
+}
+
+#[rustfmt::skip]
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("demo")
+        .about("Step through every preset in every color, to show off the keyboard (or check all firmware modes still work)")
+        .arg(Arg::with_name("dwell")
+            .takes_value(true)
+            .short("d")
+            .long("dwell")
+            .validator(|dstr| {
+                if clock::parse_duration(&dstr)?.as_secs() == 0 {
+                    return Err("dwell must be longer than 0s!".to_string())
+                }
+                Ok(())
+            })
+            .help("How long to show each one, e.g: 5s, 1m (default: 5s)"))
+        .arg(Arg::with_name("speed")
+            .takes_value(true)
+            .short("s")
+            .long("speed")
+            .validator(|sstr| {
+                let sval = sstr.parse::<u8>();
+                if sval.is_err() || sval.unwrap() > 10 {
+                    return Err("speed must be a number from 0 - 10!".to_string())
+                }
+                Ok(())
+            })
+            .help("effect speed (0 - 10)"))
+}
+
+pub fn exec(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let opts = Options {
+        dwell: m.value_of("dwell").map_or(Duration::from_secs(5), |dstr| {
+            clock::parse_duration(dstr).unwrap()
+        }),
+        speed: m
+            .value_of("speed")
+            .map_or(5, |sstr| sstr.parse::<u8>().unwrap()),
+        brightness: g.brightness(),
+    };
+    g.with_keyboard(|kbd| Ok(run(kbd, &opts)?))
+}
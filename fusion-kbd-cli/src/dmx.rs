@@ -0,0 +1,268 @@
+//! DMX over the network (`dmx`), so lighting consoles and sequencers like
+//! xLights can drive the keyboard as one more RGB fixture.
+//!
+//! Each key takes 3 channels (red, green, blue) of a single universe, starting
+//! at `address`. With `Order::Keys` they follow the lighting matrix's key
+//! numbering (see example-configs/keys.txt), and with `Order::Rows` they go
+//! row by row from the top left, like a matrix model in xLights. Matrix
+//! positions without a key still take up their 3 channels.
+
+use std::io;
+use std::net::{Ipv4Addr, UdpSocket};
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use fusion_kbd_daemon::SCRATCH_SLOT;
+use fusion_kbd_protocol::config::{key_position, MATRIX_COLS, NUM_KEYS};
+use fusion_kbd_protocol::correction::Correction;
+use fusion_kbd_protocol::state::{Lighting, State};
+use fusion_kbd_protocol::{self as kbd, CustomConfig, Rgb};
+use log::error;
+
+use crate::exit::Failure;
+use crate::{validate_slot, Globals};
+
+pub const SACN_PORT: u16 = 5568;
+pub const ARTNET_PORT: u16 = 6454;
+
+/// channels in a DMX universe
+pub const UNIVERSE_SIZE: usize = 512;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// E1.31, over multicast or unicast
+    Sacn,
+    ArtNet,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    Keys,
+    Rows,
+}
+
+pub struct Options {
+    pub protocol: Protocol,
+    pub universe: u16,
+    /// first channel (1 - 512)
+    pub address: usize,
+    pub order: Order,
+    pub slot: u8,
+    pub brightness: u8,
+}
+
+/// the universe and DMX data of an E1.31 data packet
+fn parse_sacn(packet: &[u8]) -> Option<(u16, &[u8])> {
+    let u16_at = |i: usize| u16::from_be_bytes([packet[i], packet[i + 1]]);
+    let u32_at =
+        |i: usize| u32::from_be_bytes([packet[i], packet[i + 1], packet[i + 2], packet[i + 3]]);
+
+    if packet.len() < 126 || &packet[4..16] != b"ASC-E1.17\0\0\0" {
+        return None;
+    }
+    // root layer: data, framing layer: DMX, DMP layer: set property
+    if u32_at(18) != 0x04 || u32_at(40) != 0x02 || packet[117] != 0x02 {
+        return None;
+    }
+    // preview data is meant for visualizers, not fixtures
+    if packet[112] & 0x80 != 0 {
+        return None;
+    }
+    // only the null start code carries levels
+    if packet[125] != 0 {
+        return None;
+    }
+
+    // the property count includes the start code
+    let count = (u16_at(123) as usize).saturating_sub(1);
+    let data = &packet[126..];
+    Some((u16_at(113), &data[..count.min(data.len())]))
+}
+
+/// the universe and DMX data of an ArtDmx packet
+fn parse_artnet(packet: &[u8]) -> Option<(u16, &[u8])> {
+    if packet.len() < 18 || &packet[..8] != b"Art-Net\0" {
+        return None;
+    }
+    // opcodes are little endian, OpDmx is 0x5000
+    if u16::from_le_bytes([packet[8], packet[9]]) != 0x5000 {
+        return None;
+    }
+
+    let universe = u16::from_le_bytes([packet[14], packet[15]]) & 0x7fff;
+    let length = u16::from_be_bytes([packet[16], packet[17]]) as usize;
+    let data = &packet[18..];
+    Some((universe, &data[..length.min(data.len())]))
+}
+
+/// the channel offset (from `address`) of `key`'s red channel
+fn channel(key: usize, order: Order) -> usize {
+    match order {
+        Order::Keys => key * 3,
+        Order::Rows => {
+            let (row, col) = key_position(key);
+            (row * MATRIX_COLS + col) * 3
+        }
+    }
+}
+
+/// keys whose channels fall past the end of `data` (or the universe) are off
+fn to_config(data: &[u8], address: usize, order: Order) -> CustomConfig {
+    let level = |i: usize| data.get(i).copied().unwrap_or(0);
+
+    let mut cfg = CustomConfig::new();
+    for key in 0..NUM_KEYS {
+        let i = address - 1 + channel(key, order);
+        cfg.set_key(key, Rgb(level(i), level(i + 1), level(i + 2)));
+    }
+    cfg
+}
+
+fn bind(opts: &Options) -> io::Result<UdpSocket> {
+    match opts.protocol {
+        Protocol::Sacn => {
+            let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, SACN_PORT))?;
+            // sACN universes are multicast to 239.255.{universe}. Unicast
+            // still works if joining fails (e.g: no multicast route).
+            let [hi, lo] = opts.universe.to_be_bytes();
+            let _ =
+                socket.join_multicast_v4(&Ipv4Addr::new(239, 255, hi, lo), &Ipv4Addr::UNSPECIFIED);
+            Ok(socket)
+        }
+        Protocol::ArtNet => UdpSocket::bind((Ipv4Addr::UNSPECIFIED, ARTNET_PORT)),
+    }
+}
+
+/// the newest levels for our universe, waiting for the next packet if none
+/// have queued up since the last call
+fn next_levels(socket: &UdpSocket, opts: &Options) -> io::Result<Vec<u8>> {
+    let parse = match opts.protocol {
+        Protocol::Sacn => parse_sacn,
+        Protocol::ArtNet => parse_artnet,
+    };
+
+    let mut buf = [0; 1024];
+    let mut levels = None;
+    loop {
+        // block until there's something to show, then drain whatever arrived
+        // while the last frame was uploading, so a slow keyboard drops frames
+        // instead of falling behind
+        socket.set_nonblocking(levels.is_some())?;
+        let len = match socket.recv(&mut buf) {
+            Ok(len) => len,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+            Err(e) => return Err(e),
+        };
+        match parse(&buf[..len]) {
+            Some((universe, data)) if universe == opts.universe => levels = Some(data.to_vec()),
+            _ => {}
+        }
+    }
+    Ok(levels.unwrap())
+}
+
+/// Shows DMX levels received for `opts.universe` until interrupted, by
+/// uploading them to `opts.slot` whenever they change.
+pub fn run(
+    kbd: &dyn kbd::Keyboard,
+    opts: &Options,
+    correction: &Correction,
+) -> Result<(), libusb::Error> {
+    let socket = match bind(opts) {
+        Ok(socket) => socket,
+        Err(e) => {
+            error!("couldn't listen for DMX: {}", e);
+            return Err(libusb::Error::Other);
+        }
+    };
+    println!("Listening for universe {}...", opts.universe);
+
+    let mut last: Option<Vec<u8>> = None;
+    loop {
+        let levels = match next_levels(&socket, opts) {
+            Ok(levels) => levels,
+            Err(e) => {
+                error!("couldn't receive DMX: {}", e);
+                return Err(libusb::Error::Other);
+            }
+        };
+
+        // consoles resend unchanged levels continuously
+        if last.as_ref() == Some(&levels) {
+            continue;
+        }
+        let cfg = correction.apply(&to_config(&levels, opts.address, opts.order));
+        kbd.upload_custom(opts.slot, cfg.as_bytes())?;
+        kbd.set_custom(opts.slot, opts.brightness)?;
+        last = Some(levels);
+    }
+}
+
+#[rustfmt::skip]
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("dmx")
+        .about("Show DMX levels received over sACN (E1.31) or Art-Net, 3 channels (RGB) per key")
+        .arg(Arg::with_name("protocol")
+            .takes_value(true)
+            .long("protocol")
+            .possible_values(&["sacn", "artnet"])
+            .help("What to listen for (default: sacn)"))
+        .arg(Arg::with_name("universe")
+            .takes_value(true)
+            .short("u")
+            .long("universe")
+            .validator(|ustr| match ustr.parse::<u16>() {
+                Ok(u) if u <= 0x7fff => Ok(()),
+                _ => Err("universe must be a number from 0 - 32767!".to_string()),
+            })
+            .help("Universe to show (default: 1)"))
+        .arg(Arg::with_name("address")
+            .takes_value(true)
+            .short("a")
+            .long("address")
+            .validator(|astr| match astr.parse::<usize>() {
+                Ok(a) if (1..=UNIVERSE_SIZE).contains(&a) => Ok(()),
+                _ => Err(format!("address must be a number from 1 - {}!", UNIVERSE_SIZE)),
+            })
+            .help("Channel of the first key's red (default: 1)"))
+        .arg(Arg::with_name("order")
+            .takes_value(true)
+            .long("order")
+            .possible_values(&["keys", "rows"])
+            .help("Channel order: by key number, or row by row from the top left (default: keys)"))
+        .arg(Arg::with_name("slot")
+            .takes_value(true)
+            .short("s")
+            .long("slot")
+            .validator(validate_slot)
+            .help("Custom slot frames are streamed through (default: 4)"))
+}
+
+pub fn exec(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let opts = Options {
+        protocol: match m.value_of("protocol") {
+            Some("artnet") => Protocol::ArtNet,
+            _ => Protocol::Sacn,
+        },
+        universe: m
+            .value_of("universe")
+            .map_or(1, |ustr| ustr.parse::<u16>().unwrap()),
+        address: m
+            .value_of("address")
+            .map_or(1, |astr| astr.parse::<usize>().unwrap()),
+        order: match m.value_of("order") {
+            Some("rows") => Order::Rows,
+            _ => Order::Keys,
+        },
+        slot: m
+            .value_of("slot")
+            .map_or(SCRATCH_SLOT, |sstr| sstr.parse::<u8>().unwrap()),
+        brightness: g.brightness(),
+    };
+
+    g.with_keyboard(|kbd| Ok(run(kbd, &opts, &g.correction)?))?;
+    g.remember(State {
+        lighting: Lighting::Custom { slot: opts.slot },
+        brightness: opts.brightness,
+    });
+    Ok(())
+}
@@ -0,0 +1,295 @@
+use std::fs::{self, OpenOptions};
+use std::io;
+
+use clap::{App, SubCommand};
+use fusion_kbd_daemon::control;
+use fusion_kbd_protocol::{self as kbd, device, device::Ids};
+use log::error;
+use serde_json::{json, Value};
+
+use crate::exit::Failure;
+use crate::init::UDEV_RULE_PATH;
+use crate::{unknown_subcommand, Globals};
+
+/// Prints how a check went, with what to do about it if it failed. Returns
+/// whether it passed.
+fn report(name: &str, result: Result<String, (String, &str)>) -> bool {
+    match result {
+        Ok(detail) => {
+            println!("[ ok ] {}: {}", name, detail);
+            true
+        }
+        Err((problem, fix)) => {
+            println!("[FAIL] {}: {}", name, problem);
+            println!("       -> {}", fix);
+            false
+        }
+    }
+}
+
+/// the keyboard's `(product id, bus, address)`, if it's plugged in
+fn find(context: &libusb::Context, ids: &Ids) -> Result<Option<(u16, u8, u8)>, libusb::Error> {
+    match device::scan(context, ids)?.first() {
+        Some(dev) => Ok(Some((
+            dev.device_descriptor()?.product_id(),
+            dev.bus_number(),
+            dev.address(),
+        ))),
+        None => Ok(None),
+    }
+}
+
+fn check_permissions(bus: u8, address: u8) -> Result<String, (String, &'static str)> {
+    let node = format!("/dev/bus/usb/{:03}/{:03}", bus, address);
+    match OpenOptions::new().read(true).write(true).open(&node) {
+        Ok(_) => Ok(format!("'{}' is writable", node)),
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => Err((
+            format!("no write access to '{}'", node),
+            "install the udev rule (`sudo fusion-kbd-controller setup-udev`), replug \
+             the keyboard or log in again, or run as root",
+        )),
+        Err(e) => Err((
+            format!("couldn't open '{}': {}", node, e),
+            "check that usbfs is mounted at /dev/bus/usb",
+        )),
+    }
+}
+
+fn check_udev_rule(vid: u16, pid: u16) -> Result<String, (String, &'static str)> {
+    let ids = (format!("{:04x}", vid), format!("{:04x}", pid));
+    match fs::read_to_string(UDEV_RULE_PATH) {
+        Ok(rule) if rule.contains(&ids.0) && rule.contains(&ids.1) => {
+            Ok(format!("'{}' is installed", UDEV_RULE_PATH))
+        }
+        Ok(_) => Err((
+            format!("'{}' doesn't mention {}:{}", UDEV_RULE_PATH, ids.0, ids.1),
+            "reinstall it with `sudo fusion-kbd-controller setup-udev`",
+        )),
+        Err(_) => Err((
+            format!("'{}' isn't installed", UDEV_RULE_PATH),
+            "install it with `sudo fusion-kbd-controller setup-udev` (not needed when running as root)",
+        )),
+    }
+}
+
+/// opens the keyboard (detaching the kernel driver, and claiming it), then
+/// sends it a harmless request
+fn check_claim(context: &libusb::Context, ids: &Ids) -> Vec<bool> {
+    device::release_on_exit();
+    let usb = match kbd::FusionKBD::open(context, ids) {
+        Ok(usb) => usb,
+        Err(e) => {
+            let fix = match e {
+                libusb::Error::Access => "fix the permissions above, or run as root",
+                libusb::Error::Busy => {
+                    "something else has the keyboard claimed: stop other instances of \
+                     fusion-kbd-controller, or the vendor's software"
+                }
+                _ => "try replugging the keyboard (or rebooting)",
+            };
+            return vec![report(
+                "detach / claim",
+                Err((format!("couldn't open the keyboard: {}", e), fix)),
+            )];
+        }
+    };
+    let claimed = report(
+        "detach / claim",
+        Ok("kernel driver detached, interfaces claimed".to_string()),
+    );
+
+    let ping = match usb.ping() {
+        Ok(()) => Ok("the keyboard answered a control transfer".to_string()),
+        Err(libusb::Error::Timeout) => Err((
+            "the keyboard didn't answer a control transfer".to_string(),
+            "it may be wedged: try replugging it (or rebooting)",
+        )),
+        Err(e) => Err((
+            format!("control transfer failed: {}", e),
+            "try replugging the keyboard (or rebooting)",
+        )),
+    };
+    vec![claimed, report("control transfer", ping)]
+}
+
+/// Checks everything needed to talk to the keyboard, in order, explaining
+/// how to fix whatever isn't right.
+pub fn run(context: &libusb::Context, ids: &Ids) -> Result<(), libusb::Error> {
+    let mut passed = Vec::new();
+
+    let found = find(context, ids)?;
+    passed.push(report(
+        "keyboard",
+        match found {
+            Some((pid, bus, address)) => Ok(format!(
+                "{:04x}:{:04x} on bus {:03}, device {:03}",
+                ids.vid, pid, bus, address
+            )),
+            None => Err((
+                format!("no {} device on the USB bus", ids),
+                "check `lsusb` (and the BIOS, which can disable it). If the keyboard \
+                 shows up with different ids, try them with --vid / --pid",
+            )),
+        },
+    ));
+
+    if let Some((pid, bus, address)) = found {
+        passed.push(report("permissions", check_permissions(bus, address)));
+        passed.push(report("udev rule", check_udev_rule(ids.vid, pid)));
+
+        if control::Client::connect().is_some() {
+            // it holds the keyboard, so claiming it here would fail
+            report(
+                "detach / claim",
+                Ok("skipped, the daemon is running (and has the keyboard)".to_string()),
+            );
+        } else {
+            passed.extend(check_claim(context, ids));
+        }
+    }
+
+    if passed.iter().all(|&ok| ok) {
+        println!("Everything looks good!");
+        Ok(())
+    } else {
+        Err(libusb::Error::Other)
+    }
+}
+
+/// every keyboard matching `ids`, with its interfaces. The first one is what
+/// gets used unless `--device` picks another.
+fn devices(
+    context: &libusb::Context,
+    ids: &kbd::device::Ids,
+    json: bool,
+) -> Result<(), libusb::Error> {
+    let devices = device::scan(context, ids)?;
+    if devices.is_empty() {
+        error!("No keyboard found! (looked for {})", ids);
+        return Err(libusb::Error::NoDevice);
+    }
+
+    if json {
+        let mut all = Vec::new();
+        for (i, device) in devices.iter().enumerate() {
+            let desc = device.device_descriptor()?;
+            let mut entry = json!({
+                "bus": device.bus_number(),
+                "address": device.address(),
+                "vid": format!("{:04x}", desc.vendor_id()),
+                "pid": format!("{:04x}", desc.product_id()),
+                "default": i == 0,
+            });
+            match device.active_config_descriptor() {
+                Ok(config) => {
+                    let mut interfaces = Vec::new();
+                    for interface in config.interfaces() {
+                        for setting in interface.descriptors() {
+                            interfaces.push(json!({
+                                "interface": setting.interface_number(),
+                                "setting": setting.setting_number(),
+                                "class": setting.class_code(),
+                                "subclass": setting.sub_class_code(),
+                                "protocol": setting.protocol_code(),
+                                "endpoints": setting.num_endpoints(),
+                            }));
+                        }
+                    }
+                    entry["interfaces"] = interfaces.into();
+                }
+                Err(e) => entry["error"] = e.to_string().into(),
+            }
+            all.push(entry);
+        }
+        println!("{}", Value::from(all));
+        return Ok(());
+    }
+
+    for (i, device) in devices.iter().enumerate() {
+        let desc = device.device_descriptor()?;
+        println!(
+            "{:03}:{:03}  {:04x}:{:04x}{}",
+            device.bus_number(),
+            device.address(),
+            desc.vendor_id(),
+            desc.product_id(),
+            if i == 0 { "  (default)" } else { "" }
+        );
+        let config = match device.active_config_descriptor() {
+            Ok(config) => config,
+            Err(e) => {
+                println!("    couldn't read its interfaces: {}", e);
+                continue;
+            }
+        };
+        for interface in config.interfaces() {
+            for setting in interface.descriptors() {
+                println!(
+                    "    interface {}.{}: class {:02x}, subclass {:02x}, protocol {:02x}, {} endpoint(s)",
+                    setting.interface_number(),
+                    setting.setting_number(),
+                    setting.class_code(),
+                    setting.sub_class_code(),
+                    setting.protocol_code(),
+                    setting.num_endpoints()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// what the keyboard supports
+fn info(caps: &kbd::Capabilities, json: bool) {
+    if json {
+        println!("{}", control::capabilities_json(caps));
+        return;
+    }
+
+    let presets: Vec<String> = caps.presets.iter().map(|x| x.to_string()).collect();
+    let colors: Vec<String> = caps.preset_colors.iter().map(|x| x.to_string()).collect();
+
+    println!("model:          {}", caps.model);
+    println!("presets:        {}", presets.join(", "));
+    println!("preset colors:  {}", colors.join(", "));
+    println!(
+        "per-key RGB:    {}",
+        if caps.per_key_rgb { "yes" } else { "no" }
+    );
+    println!("custom slots:   {}", caps.num_slots);
+    println!(
+        "keys:           {} ({}x{} matrix)",
+        caps.num_keys, caps.matrix_cols, caps.matrix_rows
+    );
+    println!("max brightness: {}", caps.max_brightness);
+    println!("max speed:      {}", caps.max_speed);
+}
+
+#[rustfmt::skip]
+pub fn subcommands<'a, 'b>() -> Vec<App<'a, 'b>> {
+    vec![
+        SubCommand::with_name("info")
+            .about("Show what the connected keyboard supports"),
+        SubCommand::with_name("doctor")
+            .about("Check the keyboard can be found, opened and talked to, explaining how to fix what can't"),
+        SubCommand::with_name("devices")
+            .about("List every matching keyboard (for picking one with --device)"),
+    ]
+}
+
+pub fn exec(name: &str, g: &Globals) -> Result<(), Failure> {
+    match name {
+        // the doctor claims the keyboard itself (as one of its checks)
+        "doctor" => Ok(run(&libusb::Context::new()?, &g.settings.usb_ids())?),
+        "devices" => Ok(devices(
+            &libusb::Context::new()?,
+            &g.settings.usb_ids(),
+            g.json,
+        )?),
+        "info" => g.with_keyboard(|kbd| {
+            info(&kbd.capabilities(), g.json);
+            Ok(())
+        }),
+        name => Err(unknown_subcommand(name)),
+    }
+}
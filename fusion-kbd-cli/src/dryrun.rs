@@ -0,0 +1,107 @@
+//! `--dry-run`: a stand-in keyboard that prints what would be sent to the
+//! real one (each control header, decoded, and every interrupt transfer's
+//! payload), without opening anything.
+
+use fusion_kbd_protocol::device::{
+    capabilities_of, valid_config_len, valid_slot, Capabilities, Keyboard,
+};
+use fusion_kbd_protocol::models::Model;
+use fusion_kbd_protocol::protocol::{Direction, Header, Upload};
+use fusion_kbd_protocol::{Color, Preset};
+use log::error;
+
+/// bytes per line of payload hex
+const HEX_WIDTH: usize = 16;
+
+fn hex(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    bytes.join(" ")
+}
+
+pub struct DryRun {
+    model: Model,
+}
+
+impl DryRun {
+    /// pretends to be a keyboard of this `model`
+    pub fn new(model: Model) -> DryRun {
+        DryRun { model }
+    }
+
+    fn control(&self, header: &Header) {
+        println!("control:   {}  ({})", hex(header.as_bytes()), header);
+    }
+}
+
+impl Keyboard for DryRun {
+    fn capabilities(&self) -> Capabilities {
+        capabilities_of(&self.model)
+    }
+
+    fn set_preset(
+        &self,
+        preset: Preset,
+        speed: u8,
+        brightness: u8,
+        color: Color,
+    ) -> Result<(), libusb::Error> {
+        self.control(&Header::preset(preset, speed, brightness, color));
+        Ok(())
+    }
+
+    fn set_preset_direction(
+        &self,
+        preset: Preset,
+        speed: u8,
+        brightness: u8,
+        color: Color,
+        direction: Direction,
+    ) -> Result<(), libusb::Error> {
+        self.control(&Header::preset_direction(
+            preset, speed, brightness, color, direction,
+        ));
+        Ok(())
+    }
+
+    /// there's nothing to read back, so every slot reads as all off
+    fn download_custom(&self, slot: u8, data: &mut [u8]) -> Result<(), libusb::Error> {
+        valid_slot(slot)?;
+        valid_config_len(&self.model, data.len())?;
+        self.control(&Header::read_config(slot));
+        println!(
+            "interrupt: (reading {} packets back, they'd come out blank)",
+            self.model.num_chunks
+        );
+        data.fill(0);
+        Ok(())
+    }
+
+    fn upload_custom(&self, slot: u8, data: &[u8]) -> Result<(), libusb::Error> {
+        // the real keyboard wouldn't get this far either
+        let upload = Upload::new(slot, data, &self.model).map_err(|e| {
+            error!("{}", e);
+            libusb::Error::InvalidParam
+        })?;
+        self.control(&upload.header);
+        for (i, chunk) in upload.chunks.iter().enumerate() {
+            println!("interrupt: {}/{}", i + 1, upload.chunks.len());
+            for (j, line) in chunk.chunks(HEX_WIDTH).enumerate() {
+                println!(
+                    "  {:03x}: {}",
+                    i * self.model.chunk_size + j * HEX_WIDTH,
+                    hex(line)
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn set_custom(&self, slot: u8, brightness: u8) -> Result<(), libusb::Error> {
+        let header = Header::custom(slot, brightness).map_err(|e| {
+            error!("{}", e);
+            libusb::Error::InvalidParam
+        })?;
+        self.control(&header);
+        Ok(())
+    }
+}
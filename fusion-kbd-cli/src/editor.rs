@@ -0,0 +1,531 @@
+//! Interactive editor for custom slots (`custom edit N`): draws the key
+//! matrix in the terminal (in each key's color), and paints on it. Every
+//! change is uploaded to the slot straight away, so the keyboard doubles as
+//! the preview.
+//!
+//! The terminal is put in raw mode with `stty`, and drawn on with ANSI escape
+//! codes (colors need a truecolor terminal).
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use fusion_kbd_protocol::config::{key_position, MATRIX_COLS, MATRIX_ROWS, NUM_KEYS};
+use fusion_kbd_protocol::correction::Correction;
+use fusion_kbd_protocol::preview;
+use fusion_kbd_protocol::state::{Lighting, State};
+use fusion_kbd_protocol::{self as kbd, CustomConfig, Keymap, Rgb};
+use log::error;
+
+use crate::{exit, validate_slot, Globals, Help};
+
+/// colors on the number keys
+const PALETTE: [(u8, Rgb); 10] = [
+    (b'1', Rgb(0xff, 0x00, 0x00)),
+    (b'2', Rgb(0xff, 0x80, 0x00)),
+    (b'3', Rgb(0xff, 0xff, 0x00)),
+    (b'4', Rgb(0x00, 0xff, 0x00)),
+    (b'5', Rgb(0x00, 0xff, 0xff)),
+    (b'6', Rgb(0x00, 0x00, 0xff)),
+    (b'7', Rgb(0x80, 0x00, 0xff)),
+    (b'8', Rgb(0xff, 0x00, 0x80)),
+    (b'9', Rgb(0xff, 0xff, 0xff)),
+    (b'0', Rgb(0x00, 0x00, 0x00)),
+];
+
+const HELP: &str = "arrows/hjkl move  space paint  v select  f fill  x clear  p pick  \
+                    0-9/c color  z undo  r revert  s save  q quit";
+
+pub struct Options {
+    pub slot: u8,
+    /// start from this file rather than the slot, and save back to it
+    pub file: Option<String>,
+    pub brightness: u8,
+}
+
+/// Raw mode on the alternate screen, until dropped.
+struct Terminal {
+    /// `stty -g`, to put things back
+    saved: String,
+}
+
+fn stty(args: &[&str]) -> Result<String, String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("couldn't run stty: {}", e))?;
+    if !output.status.success() {
+        return Err("stdin isn't a terminal".to_string());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+impl Terminal {
+    fn enter() -> Result<Terminal, String> {
+        let saved = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+        print!("\x1b[?1049h\x1b[?25l");
+        let _ = io::stdout().flush();
+        Ok(Terminal { saved })
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        print!("\x1b[0m\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+enum Input {
+    Up,
+    Down,
+    Left,
+    Right,
+    Escape,
+    Byte(u8),
+}
+
+/// Keys pressed, from stdin. A single read can hold several (e.g: a paste),
+/// so whatever isn't used yet is kept for later.
+struct Keys {
+    pending: VecDeque<u8>,
+}
+
+impl Keys {
+    fn new() -> Keys {
+        Keys {
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn next(&mut self) -> io::Result<Input> {
+        if self.pending.is_empty() {
+            let mut buf = [0; 64];
+            let n = io::stdin().read(&mut buf)?;
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.pending.extend(&buf[..n]);
+        }
+
+        let first = self.pending.pop_front().unwrap();
+        if first != 0x1b {
+            return Ok(Input::Byte(first));
+        }
+        // escape sequences come in a single read, so a lone Esc is just Esc
+        let arrow = match (self.pending.front(), self.pending.get(1)) {
+            (Some(b'[' | b'O'), Some(b'A')) => Input::Up,
+            (Some(b'[' | b'O'), Some(b'B')) => Input::Down,
+            (Some(b'[' | b'O'), Some(b'C')) => Input::Right,
+            (Some(b'[' | b'O'), Some(b'D')) => Input::Left,
+            _ => return Ok(Input::Escape),
+        };
+        self.pending.drain(..2);
+        Ok(arrow)
+    }
+}
+
+struct Editor<'a> {
+    keymap: &'a Keymap,
+    /// what files it saves say they were made for (see `Capabilities::model`)
+    model: String,
+    /// which key is at each matrix position
+    grid: Vec<Vec<Option<usize>>>,
+    cfg: CustomConfig,
+    /// what the slot had, for `revert`
+    original: CustomConfig,
+    undo: Vec<CustomConfig>,
+    cursor: (usize, usize),
+    /// the other corner of the selection, if one is being made
+    anchor: Option<(usize, usize)>,
+    color: Rgb,
+    keys: Keys,
+    status: String,
+    /// whether there are changes that haven't been saved to a file
+    dirty: bool,
+}
+
+impl Editor<'_> {
+    fn key(&self) -> Option<usize> {
+        self.grid[self.cursor.0][self.cursor.1]
+    }
+
+    /// the keys a paint / clear applies to
+    fn targets(&self) -> Vec<usize> {
+        let (row, col) = self.cursor;
+        let (anchor_row, anchor_col) = self.anchor.unwrap_or(self.cursor);
+        let rows = row.min(anchor_row)..=row.max(anchor_row);
+        let cols = col.min(anchor_col)..=col.max(anchor_col);
+        (0..NUM_KEYS)
+            .filter(|&key| {
+                let (r, c) = key_position(key);
+                rows.contains(&r) && cols.contains(&c)
+            })
+            .collect()
+    }
+
+    fn paint(&mut self, keys: &[usize], color: Rgb) {
+        if keys.iter().all(|&k| self.cfg.get_key(k) == color) {
+            return;
+        }
+        self.undo.push(self.cfg.clone());
+        for &key in keys {
+            self.cfg.set_key(key, color);
+        }
+        self.dirty = true;
+    }
+
+    /// moves to the nearest key in a direction, if there is one
+    fn step(&mut self, rows: isize, cols: isize) {
+        let (mut row, mut col) = (self.cursor.0 as isize, self.cursor.1 as isize);
+        loop {
+            row += rows;
+            col += cols;
+            if !(0..MATRIX_ROWS as isize).contains(&row)
+                || !(0..MATRIX_COLS as isize).contains(&col)
+            {
+                return;
+            }
+            let row = row as usize;
+            if cols != 0 {
+                if self.grid[row][col as usize].is_some() {
+                    self.cursor = (row, col as usize);
+                    return;
+                }
+                continue;
+            }
+            // rows are ragged, so go to whichever key is closest
+            let nearest = (0..MATRIX_COLS)
+                .filter(|&c| self.grid[row][c].is_some())
+                .min_by_key(|&c| (c as isize - col).abs());
+            if let Some(c) = nearest {
+                self.cursor = (row, c);
+                return;
+            }
+        }
+    }
+
+    fn draw(&self) -> String {
+        let mut out = String::from("\x1b[H\x1b[2J");
+        let selected = self.anchor.map(|_| self.targets()).unwrap_or_default();
+        for row in 0..MATRIX_ROWS {
+            for col in 0..MATRIX_COLS {
+                let key = match self.grid[row][col] {
+                    Some(key) => key,
+                    None => {
+                        out.push_str("    ");
+                        continue;
+                    }
+                };
+                let color = self.cfg.get_key(key);
+                let mut style = preview::ansi_style(color);
+                if (row, col) == self.cursor {
+                    style.push_str("\x1b[7m");
+                } else if selected.contains(&key) {
+                    style.push_str("\x1b[4m");
+                }
+                out.push_str(&format!(
+                    "{}{:^3}\x1b[0m ",
+                    style,
+                    preview::label(self.keymap, key)
+                ));
+            }
+            out.push_str("\r\n\r\n");
+        }
+
+        let under = match self.key() {
+            Some(key) => format!(
+                "{} {}",
+                self.keymap.name(key).unwrap_or("?"),
+                self.cfg.get_key(key)
+            ),
+            None => String::new(),
+        };
+        let c = self.color;
+        out.push_str(&format!(
+            "color \x1b[48;2;{};{};{}m    \x1b[0m {}   key {}{}\r\n",
+            c.0,
+            c.1,
+            c.2,
+            c,
+            under,
+            if self.anchor.is_some() {
+                "   (selecting)"
+            } else {
+                ""
+            }
+        ));
+        out.push_str(&format!("{}\r\n{}", HELP, self.status));
+        out
+    }
+
+    /// reads a line of text at the bottom of the screen. `None` if it's
+    /// cancelled (with Esc).
+    fn prompt(&mut self, question: &str, default: &str) -> io::Result<Option<String>> {
+        let mut answer = String::new();
+        loop {
+            print!(
+                "{}\r\n{} [{}] {}\x1b[?25h",
+                self.draw(),
+                question,
+                default,
+                answer
+            );
+            io::stdout().flush()?;
+            let input = self.keys.next()?;
+            print!("\x1b[?25l");
+            match input {
+                Input::Escape => return Ok(None),
+                Input::Byte(b'\r') | Input::Byte(b'\n') => break,
+                Input::Byte(0x7f) | Input::Byte(0x08) => {
+                    answer.pop();
+                }
+                Input::Byte(b) if (0x20..0x7f).contains(&b) => answer.push(b as char),
+                _ => {}
+            }
+        }
+        Ok(Some(if answer.is_empty() {
+            default.to_string()
+        } else {
+            answer
+        }))
+    }
+}
+
+enum Failure {
+    Terminal(io::Error),
+    Upload(libusb::Error),
+}
+
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Failure {
+        Failure::Terminal(e)
+    }
+}
+
+impl Editor<'_> {
+    /// Handles input until the editor is quit, `upload`ing every change.
+    fn run(
+        &mut self,
+        upload: &dyn Fn(&CustomConfig) -> Result<(), libusb::Error>,
+        default_file: &str,
+    ) -> Result<(), Failure> {
+        loop {
+            print!("{}", self.draw());
+            io::stdout().flush()?;
+
+            let before = self.cfg.clone();
+            self.status.clear();
+            match self.keys.next()? {
+                Input::Up => self.step(-1, 0),
+                Input::Down => self.step(1, 0),
+                Input::Left => self.step(0, -1),
+                Input::Right => self.step(0, 1),
+                Input::Escape => self.anchor = None,
+                Input::Byte(b'k') => self.step(-1, 0),
+                Input::Byte(b'j') => self.step(1, 0),
+                Input::Byte(b'h') => self.step(0, -1),
+                Input::Byte(b'l') => self.step(0, 1),
+                Input::Byte(b' ') | Input::Byte(b'\r') => {
+                    let keys = self.targets();
+                    self.paint(&keys, self.color);
+                    self.anchor = None;
+                }
+                Input::Byte(b'x') => {
+                    let keys = self.targets();
+                    self.paint(&keys, Rgb(0, 0, 0));
+                    self.anchor = None;
+                }
+                Input::Byte(b'f') => {
+                    let keys: Vec<usize> = (0..NUM_KEYS).collect();
+                    self.paint(&keys, self.color);
+                }
+                Input::Byte(b'v') => {
+                    self.anchor = match self.anchor {
+                        Some(_) => None,
+                        None => Some(self.cursor),
+                    }
+                }
+                Input::Byte(b'p') => {
+                    if let Some(key) = self.key() {
+                        self.color = self.cfg.get_key(key);
+                    }
+                }
+                Input::Byte(b'c') => {
+                    let current = self.color.to_string();
+                    if let Some(answer) = self.prompt("color:", &current)? {
+                        match Rgb::from_str(&answer) {
+                            Ok(color) => self.color = color,
+                            Err(e) => self.status = e,
+                        }
+                    }
+                }
+                Input::Byte(b'z') => match self.undo.pop() {
+                    Some(cfg) => self.cfg = cfg,
+                    None => self.status = "nothing to undo".to_string(),
+                },
+                Input::Byte(b'r') => {
+                    // so the revert itself can be undone
+                    self.undo.push(self.cfg.clone());
+                    self.cfg = self.original.clone();
+                    self.dirty = true;
+                }
+                Input::Byte(b's') => {
+                    if let Some(file) = self.prompt("save to:", default_file)? {
+                        self.status = match kbd::config::save(
+                            Path::new(&file),
+                            &self.cfg,
+                            &self.model,
+                            self.keymap,
+                        ) {
+                            Ok(()) => {
+                                self.dirty = false;
+                                format!("saved to {}", file)
+                            }
+                            Err(e) => e,
+                        };
+                    }
+                }
+                // Ctrl-C
+                Input::Byte(0x03) => return Ok(()),
+                Input::Byte(b'q') => {
+                    if !self.dirty {
+                        return Ok(());
+                    }
+                    let answer = self.prompt("unsaved changes, quit anyway? (y/n)", "n")?;
+                    if answer.is_some_and(|a| a.starts_with('y')) {
+                        return Ok(());
+                    }
+                }
+                Input::Byte(b) => {
+                    if let Some(&(_, color)) = PALETTE.iter().find(|&&(k, _)| k == b) {
+                        self.color = color;
+                    }
+                }
+            }
+
+            if self.cfg.as_bytes() != before.as_bytes() {
+                upload(&self.cfg).map_err(Failure::Upload)?;
+            }
+        }
+    }
+}
+
+/// Runs the editor until it's quit.
+pub fn run(
+    kbd: &dyn kbd::Keyboard,
+    opts: &Options,
+    keymap: &Keymap,
+    correction: &Correction,
+) -> Result<(), libusb::Error> {
+    let caps = kbd.capabilities();
+    let cfg = match opts.file {
+        Some(ref file) => match kbd::config::load(Path::new(file), keymap) {
+            Ok(cfg) => cfg,
+            // a new file
+            Err(_) if !Path::new(file).exists() => {
+                CustomConfig::from_bytes(vec![0; caps.config_len])
+            }
+            Err(e) => {
+                error!("invalid config '{}': {}", file, e);
+                return Err(libusb::Error::Other);
+            }
+        },
+        None => kbd.download_config(opts.slot)?,
+    };
+
+    let upload = |cfg: &CustomConfig| -> Result<(), libusb::Error> {
+        kbd.upload_custom(opts.slot, correction.apply(cfg).as_bytes())?;
+        kbd.set_custom(opts.slot, opts.brightness)
+    };
+    upload(&cfg)?;
+
+    let mut grid = vec![vec![None; MATRIX_COLS]; MATRIX_ROWS];
+    for key in (0..NUM_KEYS).filter(|&k| keymap.name(k).is_some()) {
+        let (row, col) = key_position(key);
+        grid[row][col] = Some(key);
+    }
+    let cursor = (0..NUM_KEYS)
+        .filter(|&k| keymap.name(k).is_some())
+        .map(key_position)
+        .min()
+        .unwrap_or((0, 0));
+
+    let mut editor = Editor {
+        keymap,
+        model: caps.model,
+        grid,
+        original: cfg.clone(),
+        cfg,
+        undo: Vec::new(),
+        cursor,
+        anchor: None,
+        color: PALETTE[0].1,
+        keys: Keys::new(),
+        status: String::new(),
+        dirty: false,
+    };
+    let default_file = opts
+        .file
+        .clone()
+        .unwrap_or_else(|| format!("slot-{}.json", opts.slot));
+
+    let terminal = match Terminal::enter() {
+        Ok(terminal) => terminal,
+        Err(e) => {
+            error!("{}", e);
+            return Err(libusb::Error::Other);
+        }
+    };
+    let result = editor.run(&upload, &default_file);
+    // so errors aren't printed on the alternate screen
+    drop(terminal);
+
+    match result {
+        Ok(()) => Ok(()),
+        Err(Failure::Terminal(e)) => {
+            error!("{}", e);
+            Err(libusb::Error::Io)
+        }
+        Err(Failure::Upload(e)) => {
+            error!("couldn't upload to slot {}: {}", opts.slot, e);
+            Err(e)
+        }
+    }
+}
+
+#[rustfmt::skip]
+pub fn subcommand<'a, 'b>(help: &'b Help) -> App<'a, 'b> {
+    SubCommand::with_name("edit")
+        .about("Paint a custom slot in an interactive editor, previewed live on the keyboard")
+        .arg(Arg::with_name("slot")
+            .required(true)
+            .index(1)
+            .validator(validate_slot)
+            .help(&help.slot))
+        .arg(Arg::with_name("file")
+            .takes_value(true)
+            .value_name("FILE")
+            .long("file")
+            .help("Start from FILE (binary, .fkp container, .json / .toml / .txt profile, or OpenRGB .orp) rather than the slot, and save back to it"))
+}
+
+pub fn exec(m: &ArgMatches, g: &Globals) -> Result<(), exit::Failure> {
+    let opts = Options {
+        slot: m.value_of("slot").unwrap().parse::<u8>().unwrap(),
+        file: m.value_of("file").map(|f| f.to_string()),
+        brightness: g.brightness(),
+    };
+    g.with_keyboard(|kbd| Ok(run(kbd, &opts, &g.keymap, &g.correction)?))?;
+    g.remember(State {
+        lighting: Lighting::Custom { slot: opts.slot },
+        brightness: opts.brightness,
+    });
+    Ok(())
+}
@@ -0,0 +1,59 @@
+//! What the CLI exits with, so scripts can tell failures apart without
+//! parsing stderr. `0` means it worked.
+
+/// anything not covered below
+pub const FAILED: i32 = 1;
+/// invalid arguments (what clap rejects, and what it can't check)
+pub const USAGE: i32 = 2;
+/// no keyboard found (or it went away)
+pub const NO_DEVICE: i32 = 3;
+/// not allowed to open the keyboard (see `setup-udev`)
+pub const ACCESS: i32 = 4;
+/// a USB transfer failed, or timed out
+pub const TRANSFER: i32 = 5;
+/// a file couldn't be read, parsed or written (configs, profiles, images...)
+pub const FILE: i32 = 6;
+/// something else (e.g: the daemon) is holding the keyboard
+pub const BUSY: i32 = 7;
+/// a config didn't read back the way it was sent (`--verify`), or didn't pass
+/// `custom validate`
+pub const VERIFY: i32 = 8;
+
+/// Why the CLI failed. Anything from talking to the keyboard stays a
+/// `libusb::Error` (which `?` converts), and so do the CLI's own failures, as
+/// the variant that fits (e.g: `InvalidParam` for bad arguments).
+#[derive(Debug)]
+pub enum Failure {
+    Usb(libusb::Error),
+    /// reading, parsing or writing a file went wrong (and has been logged)
+    File,
+    /// a config didn't check out (and what was wrong has been logged)
+    Verify,
+}
+
+impl From<libusb::Error> for Failure {
+    fn from(e: libusb::Error) -> Failure {
+        Failure::Usb(e)
+    }
+}
+
+impl Failure {
+    pub fn code(&self) -> i32 {
+        match self {
+            Failure::File => FILE,
+            Failure::Verify => VERIFY,
+            Failure::Usb(e) => match e {
+                libusb::Error::InvalidParam => USAGE,
+                libusb::Error::NoDevice | libusb::Error::NotFound => NO_DEVICE,
+                libusb::Error::Access => ACCESS,
+                libusb::Error::Io
+                | libusb::Error::Pipe
+                | libusb::Error::Timeout
+                | libusb::Error::Overflow
+                | libusb::Error::Interrupted => TRANSFER,
+                libusb::Error::Busy => BUSY,
+                _ => FAILED,
+            },
+        }
+    }
+}
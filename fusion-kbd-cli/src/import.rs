@@ -0,0 +1,82 @@
+//! `profile import`: saves the lighting a capture of the Windows Fusion
+//! software shows it setting, as a named profile.
+//!
+//! Fusion's own profile files aren't documented, so they're read by way of
+//! the keyboard: capture the software applying a profile (see `replay`), and
+//! import the capture. Whatever it settled on is what gets saved.
+
+use std::path::PathBuf;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use fusion_kbd_daemon::profile::{self, Profile};
+use fusion_kbd_daemon::settings::parse_location;
+use fusion_kbd_protocol::capture;
+use fusion_kbd_protocol::state::Lighting;
+use fusion_kbd_protocol::Keymap;
+use log::error;
+
+use crate::exit::Failure;
+use crate::{replay, Globals};
+
+pub fn run(
+    file: &str,
+    source: Option<(u8, u8)>,
+    name: &str,
+    keymap: &Keymap,
+) -> Result<PathBuf, String> {
+    let lighting = capture::lighting(&replay::load(file, source)?);
+    let state = lighting
+        .state
+        .ok_or_else(|| format!("'{}' doesn't switch the keyboard's lighting", file))?;
+
+    let config = match state.lighting {
+        Lighting::Preset { .. } => None,
+        Lighting::Custom { slot } => match lighting.configs[slot as usize] {
+            Some(ref cfg) => Some(cfg.clone()),
+            None => {
+                return Err(format!(
+                    "'{}' switches to custom slot {} without uploading its colors (capture \
+                     the profile being applied again, e.g: after switching to another one)",
+                    file, slot
+                ))
+            }
+        },
+    };
+
+    profile::save(name, &Profile { state, config }, keymap)
+}
+
+#[rustfmt::skip]
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("import")
+        .about("Save the lighting a USB capture shows the Windows Fusion software applying, as a named profile")
+        .arg(Arg::with_name("capture")
+            .required(true)
+            .value_name("FILE")
+            .index(1)
+            .help("A pcapng / pcap capture, from USBPcap or usbmon (see `replay`)"))
+        .arg(Arg::with_name("name")
+            .required(true)
+            .index(2))
+        .arg(Arg::with_name("source-device")
+            .takes_value(true)
+            .value_name("BUS:ADDRESS")
+            .long("source-device")
+            .validator(|dstr| parse_location(&dstr).map(|_| ()))
+            .help("Which device in the capture is the keyboard (default: the first sent a lighting header)"))
+}
+
+pub fn exec(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let source = m
+        .value_of("source-device")
+        .map(|dstr| parse_location(dstr).unwrap());
+    let (file, name) = (m.value_of("capture").unwrap(), m.value_of("name").unwrap());
+    match run(file, source, name, &g.keymap) {
+        Ok(path) => println!("Saved '{}'", path.display()),
+        Err(e) => {
+            error!("{}", e);
+            return Err(Failure::File);
+        }
+    }
+    Ok(())
+}
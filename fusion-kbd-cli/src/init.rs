@@ -0,0 +1,245 @@
+use std::env;
+use std::fs;
+use std::process::Command;
+use std::str::FromStr;
+
+use clap::{App, SubCommand};
+use fusion_kbd_daemon::paths;
+use fusion_kbd_protocol::{self as kbd, device, models};
+use log::error;
+
+use crate::exit::Failure;
+use crate::prompt::{ask, confirm};
+use crate::{unknown_subcommand, Globals};
+
+pub static UDEV_RULE_PATH: &str = "/etc/udev/rules.d/70-fusion-kbd.rules";
+static SYSTEMD_UNIT_PATH: &str = "/etc/systemd/system/fusion-kbd.service";
+static RESUME_HOOK_PATH: &str = "/usr/lib/systemd/system-sleep/fusion-kbd";
+
+/// Lets the logged-in user (`uaccess`), and anyone in `plugdev`, open the
+/// keyboard without root
+fn udev_rule() -> String {
+    let mut rule = String::from("# allow fusion-kbd-controller to run without root\n");
+    for model in models::MODELS {
+        rule.push_str(&format!(
+            "SUBSYSTEM==\"usb\", ATTRS{{idVendor}}==\"{:04x}\", ATTRS{{idProduct}}==\"{:04x}\", \
+             MODE=\"0660\", GROUP=\"plugdev\", TAG+=\"uaccess\"\n",
+            device::VID,
+            model.pid
+        ));
+    }
+    rule
+}
+
+/// oneshot unit that applies the default preset at boot
+fn systemd_unit(exe: &str, preset: &str, color: &str, brightness: u8) -> String {
+    format!(
+        "[Unit]\n\
+         Description=Fusion RGB keyboard lighting\n\
+         \n\
+         [Service]\n\
+         Type=oneshot\n\
+         ExecStart={} -b {} preset {} {}\n\
+         \n\
+         [Install]\n\
+         WantedBy=multi-user.target\n",
+        exe, brightness, preset, color
+    )
+}
+
+/// systemd-sleep hook that reapplies the saved state (see `saved`) on resume.
+/// It runs as root, so the current user's `$HOME` / XDG dirs are baked in.
+fn resume_hook(exe: &str) -> String {
+    let env: String = ["HOME", "XDG_CONFIG_HOME", "XDG_STATE_HOME"]
+        .iter()
+        .filter_map(|var| Some(format!("{}='{}' ", var, env::var(var).ok()?)))
+        .collect();
+    format!(
+        "#!/bin/sh\n\
+         # reapplies the keyboard lighting after suspend\n\
+         # (installed by `fusion-kbd-controller install-resume-hook`)\n\
+         case \"$1\" in\n\
+         \x20   post)\n\
+         \x20       # give the keyboard a moment to come back\n\
+         \x20       sleep 1\n\
+         \x20       {}{} restore\n\
+         \x20       ;;\n\
+         esac\n",
+        env, exe
+    )
+}
+
+fn exe() -> String {
+    env::current_exe()
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|_| "fusion-kbd-controller".to_string())
+}
+
+/// installs `udev_rule`, and applies it to the keyboard if it's plugged in
+pub fn install_udev_rule() {
+    let vendor = format!("--attr-match=idVendor={:04x}", device::VID);
+    install(
+        UDEV_RULE_PATH,
+        &udev_rule(),
+        &[
+            &["udevadm", "control", "--reload-rules"],
+            &["udevadm", "trigger", "--subsystem-match=usb", &vendor],
+        ],
+    );
+}
+
+/// installs `resume_hook`
+pub fn install_resume_hook() {
+    install(
+        RESUME_HOOK_PATH,
+        &resume_hook(&exe()),
+        &[&["chmod", "+x", RESUME_HOOK_PATH]],
+    );
+}
+
+/// writes a system file, explaining how to do it by hand if that fails
+fn install(path: &str, contents: &str, then: &[&[&str]]) {
+    if let Err(e) = fs::write(path, contents) {
+        println!(
+            "Couldn't write '{}' ({}). Are you running as root?",
+            path, e
+        );
+        println!(
+            "To install it manually, save the following as '{}':\n",
+            path
+        );
+        println!("{}", contents);
+        return;
+    }
+    println!("Wrote '{}'", path);
+
+    for cmd in then {
+        match Command::new(cmd[0]).args(&cmd[1..]).status() {
+            Ok(status) if status.success() => {}
+            _ => println!(
+                "'{}' failed, you may need to run it yourself",
+                cmd.join(" ")
+            ),
+        }
+    }
+}
+
+/// asks until `parse` accepts the answer
+fn ask_valid<T, F>(question: &str, default: &str, parse: F) -> T
+where
+    F: Fn(&str) -> Option<T>,
+{
+    loop {
+        if let Some(val) = parse(&ask(question, default)) {
+            return val;
+        }
+        println!("Sorry, that's not a valid answer.");
+    }
+}
+
+/// Interactive first-run setup: checks for the keyboard, optionally installs
+/// the udev rule + systemd unit, and writes the initial user config file.
+pub fn run(context: &libusb::Context) -> Result<(), libusb::Error> {
+    println!("Looking for the keyboard...");
+    if kbd::FusionKBD::is_connected(context)? {
+        println!(
+            "Found it! ({:04x}:{:04x})",
+            device::VID,
+            device::PID_AERO_15X
+        );
+    } else {
+        println!("Couldn't find a supported keyboard. You can keep going, but nothing will work.");
+    }
+
+    let layout = ask_valid("Keyboard layout (ansi/iso)?", "ansi", |l| {
+        match l.to_lowercase().as_str() {
+            l @ "ansi" | l @ "iso" => Some(l.to_string()),
+            _ => None,
+        }
+    });
+    let brightness = ask_valid("Default brightness (0 - 50)?", "16", |b| {
+        b.parse::<u8>().ok().filter(|&b| b <= 50)
+    });
+    let preset = ask_valid("Default preset?", "static", |p| {
+        kbd::Preset::from_str(p).ok().map(|p| p.to_string())
+    });
+    let color = ask_valid("Default preset color?", "white", |c| {
+        kbd::Color::lookup(c).ok().map(|c| c.to_string())
+    });
+
+    if confirm("Install a udev rule so the keyboard can be used without root?") {
+        install_udev_rule();
+    }
+
+    if confirm("Install a systemd unit that applies the default preset at boot?") {
+        install(
+            SYSTEMD_UNIT_PATH,
+            &systemd_unit(&exe(), &preset, &color, brightness),
+            &[&["systemctl", "enable", "fusion-kbd.service"]],
+        );
+    }
+
+    if confirm("Install a hook that restores the lighting after suspend?") {
+        install_resume_hook();
+    }
+
+    let path = match paths::config_file() {
+        Some(path) => path,
+        None => {
+            error!("couldn't work out where the config file goes ($HOME isn't set)");
+            return Err(libusb::Error::Other);
+        }
+    };
+
+    let config = format!(
+        "# written by `fusion-kbd-controller init`\n\
+         layout = \"{}\"\n\
+         brightness = {}\n\
+         preset = \"{}\"\n\
+         color = \"{}\"\n",
+        layout, brightness, preset, color
+    );
+
+    if path.exists() && !confirm(&format!("Overwrite '{}'?", path.display())) {
+        return Ok(());
+    }
+    let written = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&path, config));
+    if let Err(e) = written {
+        error!("couldn't write '{}': {}", path.display(), e);
+        return Err(libusb::Error::Other);
+    }
+    println!("Wrote '{}'. All done!", path.display());
+
+    Ok(())
+}
+
+#[rustfmt::skip]
+pub fn subcommands<'a, 'b>() -> Vec<App<'a, 'b>> {
+    vec![
+        SubCommand::with_name("init")
+            .about("Guided first-run setup"),
+        SubCommand::with_name("install-resume-hook")
+            .about("Install a systemd-sleep hook that runs `restore` after suspend (needs root)"),
+        SubCommand::with_name("setup-udev")
+            .about("Install a udev rule so the keyboard can be used without root (needs root)"),
+    ]
+}
+
+pub fn exec(name: &str, _: &Globals) -> Result<(), Failure> {
+    match name {
+        // the wizard shouldn't need the keyboard to be claimed
+        "init" => Ok(run(&libusb::Context::new()?)?),
+        "install-resume-hook" => {
+            install_resume_hook();
+            Ok(())
+        }
+        "setup-udev" => {
+            install_udev_rule();
+            Ok(())
+        }
+        name => Err(unknown_subcommand(name)),
+    }
+}
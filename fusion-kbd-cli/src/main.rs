@@ -0,0 +1,527 @@
+use std::time::Duration;
+
+mod ambilight;
+mod animation;
+mod backup;
+mod battery;
+mod clock;
+mod convert;
+mod custom;
+mod daemon;
+mod demo;
+mod dmx;
+mod doctor;
+mod dryrun;
+mod editor;
+mod exit;
+mod import;
+mod init;
+mod migrate;
+mod nightmode;
+mod paint;
+mod preset;
+mod profile;
+mod progress;
+mod prompt;
+mod provision;
+mod replay;
+mod script;
+mod selftest;
+mod state;
+mod sysload;
+mod themes;
+mod thermal;
+mod visualize;
+
+use clap::{App, Arg};
+use exit::Failure;
+use fusion_kbd_daemon::control;
+use fusion_kbd_daemon::saved::{self, Saved};
+use fusion_kbd_daemon::settings::{parse_location, parse_usb_id, Settings};
+use fusion_kbd_daemon::{events, logging, paths};
+use fusion_kbd_protocol as kbd;
+use kbd::correction::Correction;
+use kbd::models::Model;
+use kbd::state::State;
+use log::error;
+use strum::IntoEnumIterator;
+
+/// Help text (and possible values) worked out at runtime, for the
+/// subcommands' args to borrow (clap 2 only takes `&str`s)
+struct Help {
+    presets: Vec<String>,
+    directions: Vec<String>,
+    /// for preset colors
+    color: String,
+    animation: String,
+    slot: String,
+    render_slot: String,
+    diff_slot: String,
+}
+
+impl Help {
+    fn new() -> Help {
+        let colors: Vec<String> = kbd::Color::iter().map(|x| x.to_string()).collect();
+        let slots = format!("0 - {}", kbd::protocol::NUM_SLOTS - 1);
+        Help {
+            presets: kbd::Preset::iter().map(|x| x.to_string()).collect(),
+            directions: kbd::protocol::Direction::iter()
+                .map(|x| x.to_string())
+                .collect(),
+            color: format!(
+                "preset color ({}). Other colors (#rrggbb, CSS names) are mapped to the nearest one",
+                colors.join(", ")
+            ),
+            animation: format!(
+                "Built-in animation ({}), a JSON animation, or an animated GIF",
+                kbd::effects::ANIMATIONS.join(", ")
+            ),
+            slot: format!("Custom slot ({})", slots),
+            render_slot: format!(
+                "Draw what's on a custom slot instead ({}), read back from the keyboard",
+                slots
+            ),
+            diff_slot: format!(
+                "Compare A with what's on a custom slot ({}), read back from the keyboard",
+                slots
+            ),
+        }
+    }
+}
+
+/// The global flags, on top of the config file and the environment: what
+/// every subcommand's `exec` gets to work with
+struct Globals {
+    settings: Settings,
+    keymap: kbd::Keymap,
+    correction: Correction,
+    /// what's assumed of the keyboard before it's been opened (e.g: which
+    /// model files made without it say they're for)
+    model: Model,
+    /// `-b`, if it was given (see `brightness`)
+    brightness: Option<u8>,
+    default_brightness: u8,
+    /// `--temperature`, if it was given
+    temperature: Option<u32>,
+    json: bool,
+    dry_run: bool,
+    all_devices: bool,
+}
+
+impl Globals {
+    /// `-b`, or the default brightness
+    fn brightness(&self) -> u8 {
+        self.brightness.unwrap_or(self.default_brightness)
+    }
+
+    /// `--slot`, or else `slot` from the config file
+    fn slot(&self, sstr: Option<&str>) -> Result<u8, Failure> {
+        match sstr {
+            Some(sstr) => Ok(sstr.parse::<u8>().unwrap()),
+            None => self.settings.slot.ok_or_else(|| {
+                error!("--slot must be given (or set `slot` in the config file)");
+                libusb::Error::InvalidParam.into()
+            }),
+        }
+    }
+
+    /// Opens the keyboard, and runs `f` on it. That's a stand-in with
+    /// --dry-run, the daemon if it's running (it's holding the keyboard, and
+    /// going through it skips the claim / detach dance), or else the keyboard
+    /// itself (every matching one, with --all-devices).
+    fn with_keyboard<T, F>(&self, f: F) -> Result<T, Failure>
+    where
+        F: FnOnce(&dyn kbd::Keyboard) -> Result<T, Failure>,
+    {
+        let settings = &self.settings;
+        let context = libusb::Context::new()?;
+        let kbd: Box<dyn kbd::Keyboard> = match control::Client::connect() {
+            _ if self.dry_run => Box::new(dryrun::DryRun::new(self.model)),
+            Some(_) if self.all_devices => {
+                error!(
+                    "--all-devices can't go through the daemon (it only holds one \
+                     keyboard). Stop it first"
+                );
+                return Err(libusb::Error::Busy.into());
+            }
+            Some(client) => Box::new(client),
+            None if settings.backend.as_deref() == Some("hidapi") => open_hid(settings)?,
+            None if self.all_devices => {
+                kbd::device::release_on_exit();
+                let ids = settings.usb_ids();
+                let mut kbds = Vec::new();
+                for device in kbd::device::scan(&context, &ids)? {
+                    let mut usb = kbd::FusionKBD::open_device(&device)?;
+                    set_up(&mut usb, settings)?;
+                    kbds.push(usb);
+                }
+                if kbds.is_empty() {
+                    error!("No keyboard found! (looked for {})", ids);
+                    return Err(libusb::Error::NoDevice.into());
+                }
+                Box::new(kbd::device::Broadcast::new(kbds)?)
+            }
+            None => {
+                kbd::device::release_on_exit();
+                let mut usb = kbd::FusionKBD::open(&context, &settings.usb_ids())?;
+                set_up(&mut usb, settings)?;
+                Box::new(usb)
+            }
+        };
+        f(&*kbd)
+    }
+
+    /// Remembers that the keyboard is showing `state` now (for `on` /
+    /// `restore`), and tells anyone who `subscribe`d
+    fn remember(&self, state: State) {
+        self.save(Saved { state, off: false });
+    }
+
+    /// Remembers that the backlight is off, and that `on` brings back `state`
+    fn remember_off(&self, state: State) {
+        self.save(Saved { state, off: true });
+    }
+
+    fn save(&self, saved: Saved) {
+        // nothing actually changed
+        if self.dry_run {
+            return;
+        }
+        if let Err(e) = saved::save(&saved) {
+            error!("{}", e);
+        }
+        let showing = match saved {
+            Saved { state, off: true } => State {
+                brightness: 0,
+                ..state
+            },
+            Saved { state, off: false } => state,
+        };
+        events::publish("cli", &showing);
+    }
+}
+
+/// A subcommand nothing handles. clap only lets through the ones it was told
+/// about, so this is a subcommand that was added without a handler.
+fn unknown_subcommand(name: &str) -> Failure {
+    error!("unknown subcommand '{}'", name);
+    libusb::Error::InvalidParam.into()
+}
+
+/// clap validator for custom slot numbers
+fn validate_slot(sstr: String) -> Result<(), String> {
+    match sstr.parse::<u8>() {
+        Ok(slot) if slot < kbd::protocol::NUM_SLOTS => Ok(()),
+        _ => Err(format!(
+            "slot must be a number from 0 - {}!",
+            kbd::protocol::NUM_SLOTS - 1
+        )),
+    }
+}
+
+/// `Settings::set_up`, with its errors printed, plus a progress bar for
+/// uploads (see `progress`)
+fn set_up(usb: &mut kbd::FusionKBD, settings: &Settings) -> Result<(), Failure> {
+    settings.set_up(usb).map_err(|e| {
+        error!("{}", e);
+        Failure::File
+    })?;
+    if progress::wanted() {
+        let mut bar = progress::Bar::default();
+        usb.set_progress(move |p| bar.draw(p));
+    }
+    Ok(())
+}
+
+/// opens the keyboard through hidapi, if this build has it (the `hid` feature)
+fn open_hid(settings: &Settings) -> Result<Box<dyn kbd::Keyboard>, libusb::Error> {
+    #[cfg(feature = "hid")]
+    {
+        if settings.dump_packets.is_some() {
+            log::warn!("--dump-packets isn't supported by the hidapi backend, ignoring it");
+        }
+        let mut hid = kbd::hid::HidKBD::open(&settings.usb_ids())?;
+        if let Some(timeout) = settings.usb_timeout {
+            hid.set_timeout(timeout);
+        }
+        Ok(Box::new(hid))
+    }
+    #[cfg(not(feature = "hid"))]
+    {
+        let _ = settings;
+        error!("this build doesn't include the hidapi backend (build with `--features hid`)");
+        Err(libusb::Error::NotSupported)
+    }
+}
+
+fn main() {
+    let failure = match run() {
+        Ok(()) => return,
+        Err(failure) => failure,
+    };
+    match failure {
+        Failure::Usb(libusb::Error::Timeout) => error!(
+            "the keyboard isn't responding (a USB transfer timed out). Try \
+             again, or give it longer with --usb-timeout"
+        ),
+        // these were explained where they happened
+        Failure::Usb(libusb::Error::Other)
+        | Failure::Usb(libusb::Error::InvalidParam)
+        | Failure::File
+        | Failure::Verify => {}
+        Failure::Usb(ref e) => error!("{}", e),
+    }
+    std::process::exit(failure.code());
+}
+
+fn run() -> Result<(), Failure> {
+    let help = Help::new();
+    let model_strs: Vec<&str> = kbd::models::names().collect();
+
+    // use clap for arg parsing + validation
+    #[rustfmt::skip]
+    let app_m = App::new("fusion-kbd-controller")
+        .version("0.1")
+        .about("Control Fusion RGB Keyboard on Gigabyte Aero 15X")
+        .arg(Arg::with_name("brightness")
+            .global(true)
+            .takes_value(true)
+            .short("b")
+            .long("brightness")
+            .validator(|bstr| {
+                let bval = bstr.parse::<u8>();
+                if bval.is_err() || bval.unwrap() > 50 {
+                    return Err("brightness must be a number from 0 - 50!".to_string())
+                }
+                Ok(())
+            })
+            .help("keyboard brightness (0 - 50)"))
+        .arg(Arg::with_name("keymap")
+            .global(true)
+            .takes_value(true)
+            .long("keymap")
+            .help("Keyboard layout used for key names: ansi, iso, or a keymap TOML (default: the model's, or ansi)"))
+        .arg(Arg::with_name("model")
+            .global(true)
+            .takes_value(true)
+            .long("model")
+            .possible_values(&model_strs)
+            .help("Laptop model, for its default layout (default: worked out from the DMI product name)"))
+        .arg(Arg::with_name("usb-timeout")
+            .global(true)
+            .takes_value(true)
+            .value_name("MS")
+            .long("usb-timeout")
+            .validator(|tstr| tstr.parse::<u64>().map(|_| ()).map_err(|_| "usb-timeout must be a number of milliseconds!".to_string()))
+            .help("Give up on an unresponsive keyboard after this long (default: 1000, 0 waits forever)"))
+        .arg(Arg::with_name("vid")
+            .global(true)
+            .takes_value(true)
+            .value_name("HEX")
+            .long("vid")
+            .validator(|vstr| parse_usb_id(&vstr).map(|_| ()))
+            .help("USB vendor id to look for (default: 1044)"))
+        .arg(Arg::with_name("pid")
+            .global(true)
+            .takes_value(true)
+            .value_name("HEX")
+            .long("pid")
+            .validator(|pstr| parse_usb_id(&pstr).map(|_| ()))
+            .help("USB product id to look for (default: any known Aero / Aorus keyboard)"))
+        .arg(Arg::with_name("device")
+            .global(true)
+            .takes_value(true)
+            .value_name("BUS:ADDRESS")
+            .long("device")
+            .validator(|dstr| parse_location(&dstr).map(|_| ()))
+            .help("Which keyboard to use, when more than one matches (see `devices`)"))
+        .arg(Arg::with_name("verbose")
+            .global(true)
+            .short("v")
+            .long("verbose")
+            .multiple(true)
+            .help("Say more about what's going on (-vv for every transfer)"))
+        .arg(Arg::with_name("quiet")
+            .global(true)
+            .short("q")
+            .long("quiet")
+            .conflicts_with("verbose")
+            .help("Only print warnings and errors (besides the output asked for)"))
+        .arg(Arg::with_name("format")
+            .global(true)
+            .takes_value(true)
+            .long("format")
+            .possible_values(&["text", "json"])
+            .help("Print `status`, `devices`, `info`, `profile list` and `themes list` (and errors) as JSON, for scripts and status bars (default: text)"))
+        .arg(Arg::with_name("dry-run")
+            .global(true)
+            .long("dry-run")
+            .help("Print what would be sent to the keyboard, without opening it (or saving any state)"))
+        .arg(Arg::with_name("dump-packets")
+            .global(true)
+            .takes_value(true)
+            .value_name("FILE")
+            .long("dump-packets")
+            .help("Log every USB transfer (direction, request, payload, result, duration) to FILE, or to stderr for \"-\""))
+        .arg(Arg::with_name("experimental")
+            .global(true)
+            .long("experimental")
+            .help("Allow features that send the keyboard guessed values (e.g: `preset --direction`)"))
+        .arg(Arg::with_name("all-devices")
+            .global(true)
+            .long("all-devices")
+            .conflicts_with("device")
+            .help("Do the same thing to every matching keyboard (see `devices`)"))
+        .arg(Arg::with_name("temperature")
+            .global(true)
+            .takes_value(true)
+            .long("temperature")
+            .validator(|tstr| kbd::correction::parse_temperature(&tstr).map(|_| ()))
+            .help("Shift custom configs to a warmer / cooler white point, e.g: 3500K"))
+        .subcommand(preset::subcommand(&help))
+        .subcommand(custom::subcommand(&help))
+        .subcommand(themes::subcommand())
+        .subcommand(demo::subcommand())
+        .subcommands(selftest::subcommands())
+        .subcommand(provision::subcommand())
+        .subcommands(paint::subcommands())
+        .subcommand(profile::subcommand(&help))
+        .subcommand(nightmode::subcommand())
+        .subcommands(state::subcommands())
+        .subcommand(backup::subcommand())
+        .subcommands(init::subcommands())
+        .subcommand(daemon::subcommand())
+        .subcommands(doctor::subcommands())
+        .subcommand(replay::subcommand())
+        .subcommands(animation::subcommands(&help))
+        .subcommand(visualize::subcommand())
+        .subcommand(ambilight::subcommand())
+        .subcommand(battery::subcommand())
+        .subcommands(clock::subcommands())
+        .subcommand(thermal::subcommand())
+        .subcommand(sysload::subcommand())
+        .subcommand(dmx::subcommand())
+        .subcommand(script::subcommand())
+        .subcommand(migrate::subcommand())
+        .get_matches_safe()
+        .unwrap_or_else(|e| {
+            // --help and --version come through here too
+            if !e.use_stderr() {
+                e.exit();
+            }
+            eprintln!("{}", e.message);
+            std::process::exit(exit::USAGE);
+        });
+
+    let json = app_m.value_of("format") == Some("json");
+    let level = logging::level(app_m.occurrences_of("verbose"), app_m.is_present("quiet"));
+    if json {
+        logging::init_json(level);
+    } else {
+        logging::init(level);
+    }
+
+    // handle args
+
+    let mut settings = match paths::config_file() {
+        Some(path) => match Settings::load(&path) {
+            Ok(settings) => settings,
+            Err(e) => {
+                error!("invalid config '{}': {}", path.display(), e);
+                return Err(Failure::File);
+            }
+        },
+        None => Settings::default(),
+    };
+    // then the environment, then the flags
+    if let Err(e) = settings.apply_env() {
+        error!("invalid environment variable {}", e);
+        return Err(libusb::Error::InvalidParam.into());
+    }
+
+    // the flags win over the config file and the environment (and are passed
+    // on to the daemon this way, if that's the mode)
+    if let Some(tstr) = app_m.value_of("usb-timeout") {
+        settings.usb_timeout = Some(Duration::from_millis(tstr.parse::<u64>().unwrap()));
+    }
+    if let Some(dstr) = app_m.value_of("dump-packets") {
+        settings.dump_packets = Some(dstr.to_string());
+    }
+    if let Some(mstr) = app_m.value_of("model") {
+        settings.model = Some(mstr.to_string());
+    }
+    if let Some(vstr) = app_m.value_of("vid") {
+        settings.vid = Some(parse_usb_id(vstr).unwrap());
+    }
+    if let Some(pstr) = app_m.value_of("pid") {
+        settings.pid = Some(parse_usb_id(pstr).unwrap());
+    }
+    if let Some(dstr) = app_m.value_of("device") {
+        settings.device = Some(parse_location(dstr).unwrap());
+    }
+
+    let layout = app_m
+        .value_of("keymap")
+        .unwrap_or_else(|| settings.layout());
+    let keymap = match kbd::Keymap::load(layout) {
+        Ok(keymap) => keymap,
+        Err(e) => {
+            error!("invalid keymap: {}", e);
+            return Err(libusb::Error::InvalidParam.into());
+        }
+    };
+
+    let temperature = app_m
+        .value_of("temperature")
+        .map(|tstr| kbd::correction::parse_temperature(tstr).unwrap());
+    let globals = Globals {
+        correction: Correction {
+            temperature: temperature.or_else(nightmode::temperature),
+            ..settings.calibration
+        },
+        model: settings.model().copied().unwrap_or(kbd::models::FALLBACK),
+        brightness: app_m
+            .value_of("brightness")
+            .map(|b| b.parse::<u8>().unwrap()),
+        default_brightness: settings.brightness.unwrap_or(0x50 / 3),
+        temperature,
+        keymap,
+        settings,
+        json,
+        dry_run: app_m.is_present("dry-run"),
+        all_devices: app_m.is_present("all-devices"),
+    };
+    let g = &globals;
+
+    match app_m.subcommand() {
+        ("preset", Some(m)) => preset::exec(m, g),
+        ("custom", Some(m)) => custom::exec(m, g),
+        ("themes", Some(m)) => themes::exec(m, g),
+        ("demo", Some(m)) => demo::exec(m, g),
+        (name @ ("selftest" | "test-leds"), Some(m)) => selftest::exec(name, m, g),
+        ("provision", Some(m)) => provision::exec(m, g),
+        (name @ ("key" | "zone" | "solid"), Some(m)) => paint::exec(name, m, g),
+        ("profile", Some(m)) => profile::exec(m, g),
+        ("night-mode", Some(m)) => nightmode::exec(m, g),
+        (name @ ("off" | "on" | "restore" | "brightness" | "status" | "subscribe"), Some(m)) => {
+            state::exec(name, m, g)
+        }
+        ("backup", Some(m)) => backup::exec(m, g),
+        (name @ ("init" | "install-resume-hook" | "setup-udev"), Some(_)) => init::exec(name, g),
+        ("daemon", Some(m)) => daemon::exec(m, g),
+        (name @ ("doctor" | "devices" | "info"), Some(_)) => doctor::exec(name, g),
+        ("replay", Some(m)) => replay::exec(m, g),
+        (name @ ("play" | "render"), Some(m)) => animation::exec(name, m, g),
+        ("visualize", Some(m)) => visualize::exec(m, g),
+        ("ambilight", Some(m)) => ambilight::exec(m, g),
+        ("battery", Some(m)) => battery::exec(m, g),
+        (name @ ("clock" | "timer" | "pomodoro"), Some(m)) => clock::exec(name, m, g),
+        ("thermal", Some(m)) => thermal::exec(m, g),
+        ("sysload", Some(m)) => sysload::exec(m, g),
+        ("dmx", Some(m)) => dmx::exec(m, g),
+        ("run-script", Some(m)) => script::exec(m, g),
+        ("migrate", Some(m)) => migrate::exec(m, g),
+        ("", None) => state::keep(g),
+        (name, _) => Err(unknown_subcommand(name)),
+    }
+}
@@ -0,0 +1,110 @@
+use std::fs;
+use std::path::Path;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use fusion_kbd_protocol::config::container::Container;
+use fusion_kbd_protocol::config::{self, Format};
+use fusion_kbd_protocol::{CustomConfig, Keymap};
+use log::error;
+
+use crate::exit::Failure;
+use crate::Globals;
+
+fn write(
+    path: &Path,
+    cfg: &CustomConfig,
+    format: Format,
+    model: &str,
+    keymap: &Keymap,
+) -> Result<(), String> {
+    let data = config::encode(cfg, format, model, keymap)?;
+    fs::write(path, data).map_err(|e| format!("couldn't write '{}': {}", path.display(), e))?;
+    println!("Wrote '{}'", path.display());
+    Ok(())
+}
+
+/// Upgrades a bare 512 byte dump (from the original C tool, or early versions
+/// of this one) to a profile container, stamped with `model` and the current
+/// layout. Optionally also writes a JSON profile next to it.
+pub fn run(
+    file: &str,
+    out: Option<&str>,
+    json: bool,
+    model: &str,
+    keymap: &Keymap,
+) -> Result<(), String> {
+    let path = Path::new(file);
+    let data = fs::read(path).map_err(|e| format!("couldn't open '{}': {}", file, e))?;
+
+    let (cfg, model) = if Container::detect(&data) {
+        let existing = Container::from_bytes(&data)?;
+        println!(
+            "'{}' is already a profile container ({}, {} layout)",
+            file, existing.model, existing.layout
+        );
+        if let Some(ref name) = existing.about.name {
+            println!("  name: {}", name);
+        }
+        if let Some(ref author) = existing.about.author {
+            println!("  author: {}", author);
+        }
+        if let Some(ref description) = existing.about.description {
+            println!("  description: {}", description);
+        }
+        (existing.config, existing.model)
+    } else {
+        let cfg = config::decode(&data, Format::Binary, keymap)
+            .map_err(|e| format!("'{}' isn't a legacy dump ({})", file, e))?;
+
+        let out = out.map_or_else(|| path.with_extension("fkp"), |o| o.into());
+        if out == path {
+            return Err(format!("refusing to overwrite '{}' in place", file));
+        }
+        println!(
+            "Wrapping legacy dump for {} ({} layout)",
+            model,
+            keymap.layout()
+        );
+        write(&out, &cfg, Format::Container, model, keymap)?;
+        (cfg, model.to_string())
+    };
+
+    if json {
+        write(
+            &path.with_extension("json"),
+            &cfg,
+            Format::Json,
+            &model,
+            keymap,
+        )?;
+    }
+
+    Ok(())
+}
+
+#[rustfmt::skip]
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("migrate")
+        .about("Upgrade a legacy raw 512 byte dump to a profile container (.fkp)")
+        .arg(Arg::with_name("file")
+            .required(true)
+            .index(1))
+        .arg(Arg::with_name("out")
+            .takes_value(true)
+            .short("o")
+            .long("out")
+            .value_name("FILE")
+            .help("Where to write the container (default: FILE with a .fkp extension)"))
+        .arg(Arg::with_name("json")
+            .long("json")
+            .help("Also write an editable .json profile next to FILE"))
+}
+
+pub fn exec(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let (file, out) = (m.value_of("file").unwrap(), m.value_of("out"));
+    if let Err(e) = run(file, out, m.is_present("json"), g.model.name, &g.keymap) {
+        error!("{}", e);
+        return Err(Failure::File);
+    }
+    Ok(())
+}
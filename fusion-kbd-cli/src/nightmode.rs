@@ -0,0 +1,76 @@
+//! Night mode: a color temperature that's applied to every custom upload until
+//! it's turned off again. Stored as the temperature (in kelvin) in
+//! `state_dir()/night-mode`.
+
+use std::fs;
+use std::path::PathBuf;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use fusion_kbd_daemon::paths;
+use fusion_kbd_protocol::correction::parse_temperature;
+use log::error;
+
+use crate::exit::Failure;
+use crate::Globals;
+
+/// used when turning night mode on without `--temperature`
+pub const DEFAULT_TEMPERATURE: u32 = 3400;
+
+fn path() -> Result<PathBuf, String> {
+    paths::state_dir()
+        .map(|d| d.join("night-mode"))
+        .ok_or_else(|| "couldn't work out where state goes ($HOME isn't set)".to_string())
+}
+
+/// the night mode temperature, if it's on
+pub fn temperature() -> Option<u32> {
+    let text = fs::read_to_string(path().ok()?).ok()?;
+    parse_temperature(text.trim()).ok()
+}
+
+/// turns night mode on at `temperature`, or off if `None`
+pub fn set(temperature: Option<u32>) -> Result<(), String> {
+    let path = path()?;
+    let res = match temperature {
+        Some(kelvin) => path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&path, format!("{}\n", kelvin))),
+        None if path.exists() => fs::remove_file(&path),
+        None => Ok(()),
+    };
+    res.map_err(|e| format!("couldn't update '{}': {}", path.display(), e))
+}
+
+#[rustfmt::skip]
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("night-mode")
+        .about("Warm up every custom upload until turned off (see --temperature)")
+        .arg(Arg::with_name("state")
+            .possible_values(&["on", "off"])
+            .index(1)
+            .help("Turn night mode on or off (default: toggle)"))
+}
+
+pub fn exec(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let on = match m.value_of("state") {
+        Some(state) => state == "on",
+        None => temperature().is_none(),
+    };
+    let temperature = if on {
+        Some(g.temperature.unwrap_or(DEFAULT_TEMPERATURE))
+    } else {
+        None
+    };
+
+    if let Err(e) = set(temperature) {
+        error!("{}", e);
+        return Err(libusb::Error::Other.into());
+    }
+    match temperature {
+        Some(kelvin) => println!("Night mode on ({}K)", kelvin),
+        None => println!("Night mode off"),
+    }
+    println!("(applies to custom configs from the next upload on)");
+    Ok(())
+}
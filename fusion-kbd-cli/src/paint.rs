@@ -0,0 +1,178 @@
+//! `key set`, `zone` and `solid`: recoloring groups of keys in a custom slot.
+
+use std::str::FromStr;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use fusion_kbd_daemon::SCRATCH_SLOT;
+use fusion_kbd_protocol::state::{Lighting, State};
+use fusion_kbd_protocol::{self as kbd, CustomConfig, Rgb};
+use log::error;
+
+use crate::exit::Failure;
+use crate::{unknown_subcommand, validate_slot, Globals};
+
+/// what to paint on a slot
+struct Paint {
+    slot: u8,
+    /// start from all keys off, instead of what's on the slot
+    clear: bool,
+    /// every key this color first (`solid`)
+    fill: Option<Rgb>,
+    keys: Vec<(Vec<usize>, Rgb)>,
+}
+
+#[rustfmt::skip]
+pub fn subcommands<'a, 'b>() -> Vec<App<'a, 'b>> {
+    vec![
+        SubCommand::with_name("key")
+            .about("Work with individual keys")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("set")
+                .about("Recolor keys in a custom slot, leaving the rest of it untouched")
+                .arg(Arg::with_name("keys")
+                    .required(true)
+                    .multiple(true)
+                    .help("Key names (as listed in keys.txt), or raw offsets"))
+                .arg(Arg::with_name("color")
+                    .required(true)
+                    .takes_value(true)
+                    .short("c")
+                    .long("color")
+                    .validator(|cstr| kbd::Rgb::from_str(&cstr).map(|_| ()))
+                    .help("Color as rrggbb hex, or a color name"))
+                .arg(Arg::with_name("slot")
+                    .takes_value(true)
+                    .short("s")
+                    .long("slot")
+                    .validator(validate_slot)
+                    .help("Custom slot to modify (default: `slot` from the config file)"))),
+        SubCommand::with_name("zone")
+            .about("Recolor predefined groups of keys in a custom slot (e.g: `zone wasd red numpad blue`)")
+            .arg(Arg::with_name("zones")
+                .required(true)
+                .multiple(true)
+                .value_name("ZONE COLOR")
+                .help("Pairs of zone + color. Zones: all, function, wasd, arrows, numpad, modifiers, left, center, right"))
+            .arg(Arg::with_name("slot")
+                .takes_value(true)
+                .short("s")
+                .long("slot")
+                .validator(validate_slot)
+                .help("Custom slot to modify (default: `slot` from the config file)"))
+            .arg(Arg::with_name("clear")
+                .long("clear")
+                .help("Turn off all other keys, instead of leaving the rest of the slot as-is")),
+        SubCommand::with_name("solid")
+            .about("Light every key with any RGB color (via a custom slot)")
+            .arg(Arg::with_name("color")
+                .required(true)
+                .index(1)
+                .validator(|cstr| kbd::Rgb::from_str(&cstr).map(|_| ()))
+                .help("Color as rrggbb hex, or a color name"))
+            .arg(Arg::with_name("slot")
+                .takes_value(true)
+                .short("s")
+                .long("slot")
+                .validator(validate_slot)
+                .help("Custom slot to overwrite (default: 4)")),
+    ]
+}
+
+pub fn exec(name: &str, m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let paint = match name {
+        "key" => match m.subcommand() {
+            ("set", Some(m)) => {
+                let mut keys = Vec::new();
+                for name in m.values_of("keys").unwrap() {
+                    match g.keymap.index(name) {
+                        Some(key) => keys.push(key),
+                        None => {
+                            error!("unknown key '{}'", name);
+                            return Err(libusb::Error::InvalidParam.into());
+                        }
+                    }
+                }
+
+                Paint {
+                    slot: g.slot(m.value_of("slot"))?,
+                    clear: false,
+                    fill: None,
+                    keys: vec![(keys, Rgb::from_str(m.value_of("color").unwrap()).unwrap())],
+                }
+            }
+            (name, _) => return Err(unknown_subcommand(name)),
+        },
+        "zone" => {
+            let args: Vec<&str> = m.values_of("zones").unwrap().collect();
+            if !args.len().is_multiple_of(2) {
+                error!("zones and colors must come in pairs (e.g: `wasd red`)");
+                return Err(libusb::Error::InvalidParam.into());
+            }
+
+            let mut keys = Vec::new();
+            for pair in args.chunks(2) {
+                let zone = match kbd::zones::keys(pair[0], &g.keymap) {
+                    Some(zone) => zone,
+                    None => {
+                        error!(
+                            "unknown zone '{}' (expected one of: {})",
+                            pair[0],
+                            kbd::zones::ZONES.join(", ")
+                        );
+                        return Err(libusb::Error::InvalidParam.into());
+                    }
+                };
+                let color = match Rgb::from_str(pair[1]) {
+                    Ok(color) => color,
+                    Err(e) => {
+                        error!("{}", e);
+                        return Err(libusb::Error::InvalidParam.into());
+                    }
+                };
+                keys.push((zone, color));
+            }
+
+            Paint {
+                slot: g.slot(m.value_of("slot"))?,
+                clear: m.is_present("clear"),
+                fill: None,
+                keys,
+            }
+        }
+        "solid" => Paint {
+            slot: m
+                .value_of("slot")
+                .map_or(SCRATCH_SLOT, |sstr| sstr.parse::<u8>().unwrap()),
+            clear: true,
+            fill: Some(Rgb::from_str(m.value_of("color").unwrap()).unwrap()),
+            keys: Vec::new(),
+        },
+        name => return Err(unknown_subcommand(name)),
+    };
+
+    let (slot, brightness) = (paint.slot, g.brightness());
+    g.with_keyboard(|kbd| {
+        let mut cfg = if paint.clear {
+            CustomConfig::from_bytes(vec![0; kbd.capabilities().config_len])
+        } else {
+            kbd.download_config(slot)?
+        };
+        if let Some(color) = paint.fill {
+            cfg.fill(g.correction.rgb(color));
+        }
+
+        for (keys, color) in paint.keys {
+            for key in keys {
+                cfg.set_key(key, g.correction.rgb(color));
+            }
+        }
+
+        kbd.upload_custom(slot, cfg.as_bytes())?;
+        Ok(kbd.set_custom(slot, brightness)?)
+    })?;
+    g.remember(State {
+        lighting: Lighting::Custom { slot },
+        brightness,
+    });
+    Ok(())
+}
@@ -0,0 +1,120 @@
+//! `preset`: one of the keyboard's built-in effects.
+
+use std::str::FromStr;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use fusion_kbd_protocol::protocol::Direction;
+use fusion_kbd_protocol::state::{Lighting, State};
+use fusion_kbd_protocol::{Color, Preset};
+use log::{error, warn};
+
+use crate::exit::Failure;
+use crate::{Globals, Help};
+
+#[rustfmt::skip]
+pub fn subcommand<'a, 'b>(help: &'b Help) -> App<'a, 'b> {
+    let presets: Vec<&str> = help.presets.iter().map(String::as_str).collect();
+    let directions: Vec<&str> = help.directions.iter().map(String::as_str).collect();
+
+    SubCommand::with_name("preset")
+        .about("Work with Preset lighting profiles")
+        .arg(Arg::with_name("preset")
+            .possible_values(&presets)
+            .case_insensitive(true)
+            .index(1)
+            .help("Preset to show (default: `preset` from the config file)"))
+        .arg(Arg::with_name("color")
+            .validator(|cstr| Color::lookup(&cstr.to_lowercase()).map(|_| ()))
+            .index(2)
+            .help(&help.color))
+        .arg(Arg::with_name("speed")
+            .takes_value(true)
+            .short("s")
+            .long("speed")
+            .validator(|sstr| {
+                let sval = sstr.parse::<u8>();
+                if sval.is_err() || sval.unwrap() > 10 {
+                    return Err("speed must be a number from 0 - 10!".to_string())
+                }
+                Ok(())
+            })
+            .help("effect speed (0 - 10)"))
+        .arg(Arg::with_name("direction")
+            .takes_value(true)
+            .long("direction")
+            .possible_values(&directions)
+            .case_insensitive(true)
+            .help("which way wave / marquee / raindrop go (needs --experimental, and isn't saved)"))
+}
+
+pub fn exec(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let preset = match m.value_of("preset") {
+        Some(pstr) => Preset::from_str(&pstr.to_lowercase()).unwrap(),
+        None => match g.settings.preset {
+            Some(preset) => preset,
+            None => {
+                error!("a preset must be given (or set `preset` in the config file)");
+                return Err(libusb::Error::InvalidParam.into());
+            }
+        },
+    };
+
+    let speed = m.value_of("speed").map(|sstr| sstr.parse::<u8>().unwrap());
+    let color = m
+        .value_of("color")
+        .map(|cstr| Color::lookup(&cstr.to_lowercase()).unwrap());
+    for unused in preset.unused_params(speed, color) {
+        warn!("{}, ignoring it", unused);
+    }
+    let speed = speed.unwrap_or(5);
+
+    let mut direction = m
+        .value_of("direction")
+        .map(|dstr| Direction::from_str(&dstr.to_lowercase()).unwrap());
+    if direction.is_some() && !preset.takes_direction() {
+        warn!("`{}` doesn't take a direction, ignoring it", preset);
+        direction = None;
+    }
+    if direction.is_some() {
+        if !m.is_present("experimental") {
+            error!(
+                "--direction is experimental: the values it sends are guesses, from \
+                 similar ITE firmware. Pass --experimental to try it anyway"
+            );
+            return Err(libusb::Error::InvalidParam.into());
+        }
+        warn!(
+            "--direction sends a guessed value, which may do nothing (or something \
+             else) on this keyboard, and isn't saved"
+        );
+    }
+
+    let color = match color.or(g.settings.color) {
+        _ if !preset.takes_color() => Color::Rand,
+        Some(color) => color,
+        None => {
+            error!("Color must be specified for preset `{}`", preset);
+            return Err(libusb::Error::InvalidParam.into());
+        }
+    };
+
+    let brightness = g.brightness();
+    g.with_keyboard(|kbd| {
+        match direction {
+            None => kbd.set_preset(preset, speed, brightness, color)?,
+            Some(direction) => {
+                kbd.set_preset_direction(preset, speed, brightness, color, direction)?
+            }
+        }
+        Ok(())
+    })?;
+    g.remember(State {
+        lighting: Lighting::Preset {
+            preset,
+            color,
+            speed,
+        },
+        brightness,
+    });
+    Ok(())
+}
@@ -0,0 +1,179 @@
+//! `profile`: named lighting profiles (see `fusion_kbd_daemon::profile`),
+//! saved from a preset or a custom slot, and applied again later.
+
+use std::str::FromStr;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use fusion_kbd_daemon::profile::{self, Profile};
+use fusion_kbd_protocol::state::{Lighting, State};
+use fusion_kbd_protocol::{Color, Preset};
+use log::{error, warn};
+use serde_json::Value;
+
+use crate::exit::Failure;
+use crate::{import, unknown_subcommand, validate_slot, Globals, Help};
+
+#[rustfmt::skip]
+pub fn subcommand<'a, 'b>(help: &'b Help) -> App<'a, 'b> {
+    let presets: Vec<&str> = help.presets.iter().map(String::as_str).collect();
+
+    SubCommand::with_name("profile")
+        .about("Save and load named lighting profiles")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(SubCommand::with_name("save")
+            .about("Save a preset, or the contents of a custom slot, as a named profile")
+            .arg(Arg::with_name("name")
+                .required(true)
+                .index(1))
+            .arg(Arg::with_name("slot")
+                .required_unless("preset")
+                .takes_value(true)
+                .short("s")
+                .long("slot")
+                .validator(validate_slot)
+                .help("Custom slot to save"))
+            .arg(Arg::with_name("preset")
+                .conflicts_with("slot")
+                .takes_value(true)
+                .long("preset")
+                .possible_values(&presets)
+                .case_insensitive(true)
+                .help("Preset to save"))
+            .arg(Arg::with_name("color")
+                .requires("preset")
+                .takes_value(true)
+                .short("c")
+                .long("color")
+                .validator(|cstr| Color::lookup(&cstr.to_lowercase()).map(|_| ()))
+                .help(&help.color))
+            .arg(Arg::with_name("speed")
+                .requires("preset")
+                .takes_value(true)
+                .long("speed")
+                .validator(|sstr| {
+                    let sval = sstr.parse::<u8>();
+                    if sval.is_err() || sval.unwrap() > 10 {
+                        return Err("speed must be a number from 0 - 10!".to_string())
+                    }
+                    Ok(())
+                })
+                .help("effect speed (0 - 10)")))
+        .subcommand(SubCommand::with_name("load")
+            .about("Apply a saved profile")
+            .arg(Arg::with_name("name")
+                .required(true)
+                .index(1)))
+        .subcommand(SubCommand::with_name("list")
+            .about("List saved profiles"))
+        .subcommand(SubCommand::with_name("delete")
+            .about("Delete a saved profile")
+            .arg(Arg::with_name("name")
+                .required(true)
+                .index(1)))
+        .subcommand(import::subcommand())
+}
+
+pub fn exec(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    match m.subcommand() {
+        ("save", Some(m)) => save(m, g),
+        ("load", Some(m)) => {
+            let mut profile = match profile::load(m.value_of("name").unwrap(), &g.keymap) {
+                Ok(profile) => profile,
+                Err(e) => {
+                    error!("{}", e);
+                    return Err(Failure::File);
+                }
+            };
+            if let Some(brightness) = g.brightness {
+                profile.state.brightness = brightness;
+            }
+
+            g.with_keyboard(|kbd| {
+                if let (Lighting::Custom { slot }, Some(ref cfg)) =
+                    (&profile.state.lighting, &profile.config)
+                {
+                    kbd.upload_custom(*slot, g.correction.apply(cfg).as_bytes())?;
+                }
+                Ok(kbd.set_state(&profile.state)?)
+            })?;
+            g.remember(profile.state);
+            Ok(())
+        }
+        ("list", Some(_)) => {
+            match profile::list() {
+                Ok(names) if g.json => println!("{}", Value::from(names)),
+                Ok(names) => {
+                    for name in names {
+                        println!("{}", name);
+                    }
+                }
+                Err(e) => {
+                    error!("{}", e);
+                    return Err(Failure::File);
+                }
+            }
+            Ok(())
+        }
+        ("delete", Some(m)) => {
+            if let Err(e) = profile::delete(m.value_of("name").unwrap()) {
+                error!("{}", e);
+                return Err(Failure::File);
+            }
+            Ok(())
+        }
+        ("import", Some(m)) => import::exec(m, g),
+        (name, _) => Err(unknown_subcommand(name)),
+    }
+}
+
+fn save(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let lighting = match m.value_of("preset") {
+        Some(pstr) => {
+            let preset = Preset::from_str(&pstr.to_lowercase()).unwrap();
+            let speed = m.value_of("speed").map(|sstr| sstr.parse::<u8>().unwrap());
+            let color = m
+                .value_of("color")
+                .map(|cstr| Color::lookup(&cstr.to_lowercase()).unwrap());
+            for unused in preset.unused_params(speed, color) {
+                warn!("{}, ignoring it", unused);
+            }
+            if color.is_none() && preset.takes_color() {
+                error!("Color must be specified for preset `{}`", preset);
+                return Err(libusb::Error::InvalidParam.into());
+            }
+
+            Lighting::Preset {
+                preset,
+                color: color
+                    .filter(|_| preset.takes_color())
+                    .unwrap_or(Color::Rand),
+                speed: speed.unwrap_or(5),
+            }
+        }
+        None => Lighting::Custom {
+            slot: m.value_of("slot").unwrap().parse::<u8>().unwrap(),
+        },
+    };
+    let state = State {
+        lighting,
+        brightness: g.brightness(),
+    };
+
+    // a custom slot's colors have to be read back from the keyboard
+    let config = match state.lighting {
+        Lighting::Custom { slot } => Some(g.with_keyboard(|kbd| Ok(kbd.download_config(slot)?))?),
+        Lighting::Preset { .. } => None,
+    };
+    match profile::save(
+        m.value_of("name").unwrap(),
+        &Profile { state, config },
+        &g.keymap,
+    ) {
+        Ok(path) => println!("Saved '{}'", path.display()),
+        Err(e) => {
+            error!("{}", e);
+            return Err(Failure::File);
+        }
+    }
+    Ok(())
+}
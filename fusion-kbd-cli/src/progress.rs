@@ -0,0 +1,70 @@
+//! The progress bar drawn on stderr during uploads (see
+//! `FusionKBD::set_progress`): chunks written, transfer rate, and retries.
+//! Uploads that follow each other, e.g: an animation's frames, share the one
+//! line, which is cleared whenever an upload finishes.
+
+use std::io::{self, IsTerminal, Write};
+
+use fusion_kbd_daemon::logging;
+use fusion_kbd_protocol::device::Progress;
+use log::LevelFilter;
+
+/// in characters, between the brackets
+const WIDTH: usize = 16;
+
+/// whether to draw one: stderr has to be a terminal, and it would get in the
+/// way of `-q` and `--format json`
+pub fn wanted() -> bool {
+    io::stderr().is_terminal() && log::max_level() >= LevelFilter::Info && !logging::json()
+}
+
+#[derive(Default)]
+pub struct Bar {
+    /// uploads started so far
+    uploads: usize,
+    /// retries so far, over every upload
+    retries: u32,
+}
+
+impl Bar {
+    pub fn draw(&mut self, progress: &Progress) {
+        if progress.done == 0 {
+            self.uploads += 1;
+        }
+        let mut stderr = io::stderr();
+        if progress.done == progress.chunks {
+            self.retries += progress.retries;
+            let _ = write!(stderr, "\r\x1b[K");
+            let _ = stderr.flush();
+            return;
+        }
+
+        let filled = WIDTH * progress.done / progress.chunks.max(1);
+        let secs = progress.elapsed.as_secs_f64();
+        let rate = if secs > 0.0 {
+            progress.bytes as f64 / 1024.0 / secs
+        } else {
+            0.0
+        };
+        let mut line = format!(
+            "Uploading slot {} [{}{}] {}/{}  {:.1} KiB/s",
+            progress.slot,
+            "#".repeat(filled),
+            "-".repeat(WIDTH - filled),
+            progress.done,
+            progress.chunks,
+            rate
+        );
+        if self.uploads > 1 {
+            line.push_str(&format!("  (upload {})", self.uploads));
+        }
+        let retries = self.retries + progress.retries;
+        match retries {
+            0 => {}
+            1 => line.push_str("  1 retry"),
+            n => line.push_str(&format!("  {} retries", n)),
+        }
+        let _ = write!(stderr, "\r\x1b[K{}", line);
+        let _ = stderr.flush();
+    }
+}
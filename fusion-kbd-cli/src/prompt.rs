@@ -0,0 +1,21 @@
+use std::io::{self, BufRead, Write};
+
+/// asks a question on stdin, returning `default` if the answer is left blank
+pub fn ask(question: &str, default: &str) -> String {
+    print!("{} [{}] ", question, default);
+    let _ = io::stdout().flush();
+
+    let mut line = String::new();
+    if io::stdin().lock().read_line(&mut line).is_err() {
+        return default.to_string();
+    }
+    match line.trim() {
+        "" => default.to_string(),
+        answer => answer.to_string(),
+    }
+}
+
+/// asks a yes/no question on stdin. Defaults to yes.
+pub fn confirm(question: &str) -> bool {
+    !ask(question, "Y/n").to_lowercase().starts_with('n')
+}
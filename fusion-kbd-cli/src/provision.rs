@@ -0,0 +1,146 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use fusion_kbd_protocol::correction::Correction;
+use fusion_kbd_protocol::{self as kbd, protocol::NUM_SLOTS, CustomConfig, Keymap};
+use log::{error, warn};
+
+use crate::exit::Failure;
+use crate::Globals;
+
+/// Works out which slot a file is meant for. Files are named after their slot,
+/// optionally followed by a description: `0.cfg`, `1-gaming.json`, `2.png`.
+fn slot_for(path: &Path) -> Option<u8> {
+    let stem = path.file_stem()?.to_str()?;
+    let slot = stem.split('-').next()?.parse::<u8>().ok()?;
+    if slot < NUM_SLOTS {
+        Some(slot)
+    } else {
+        None
+    }
+}
+
+/// uploads a config and reads it back to make sure it stuck
+pub fn upload_verified(
+    kbd: &dyn kbd::Keyboard,
+    slot: u8,
+    cfg: &CustomConfig,
+) -> Result<(), String> {
+    kbd.upload_custom(slot, cfg.as_bytes())
+        .map_err(|e| format!("upload failed: {}", e))?;
+
+    let readback = kbd
+        .download_config(slot)
+        .map_err(|e| format!("readback failed: {}", e))?;
+
+    let bad = cfg.diff(&readback);
+    if !bad.is_empty() {
+        return Err(format!("readback mismatch on keys {:?}", bad));
+    }
+
+    Ok(())
+}
+
+/// Uploads every config in `dir` to its slot. All configs are parsed before
+/// touching the keyboard, and the previous contents of every slot are backed up
+/// first: if any upload fails verification, all touched slots are rolled back.
+pub fn run(
+    kbd: &dyn kbd::Keyboard,
+    dir: &str,
+    keymap: &Keymap,
+    correction: &Correction,
+) -> Result<(), libusb::Error> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => {
+            error!("couldn't open '{}'", dir);
+            return Err(libusb::Error::Other);
+        }
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_file())
+        .collect();
+    paths.sort();
+
+    let mut configs: Vec<(u8, PathBuf, CustomConfig)> = Vec::new();
+    for path in paths {
+        let slot = match slot_for(&path) {
+            Some(slot) => slot,
+            None => {
+                warn!("Skipping '{}': not named after a slot", path.display());
+                continue;
+            }
+        };
+
+        if let Some((_, other, _)) = configs.iter().find(|(s, _, _)| *s == slot) {
+            error!(
+                "'{}' and '{}' both target slot {}",
+                other.display(),
+                path.display(),
+                slot
+            );
+            return Err(libusb::Error::Other);
+        }
+
+        if let Err(e) = crate::custom::check_model(kbd, &path) {
+            error!("{}", e);
+            return Err(libusb::Error::InvalidParam);
+        }
+        match kbd::config::load(&path, keymap) {
+            Ok(cfg) => configs.push((slot, path, correction.apply(&cfg))),
+            Err(e) => {
+                error!("invalid config '{}': {}", path.display(), e);
+                return Err(libusb::Error::Other);
+            }
+        }
+    }
+
+    if configs.is_empty() {
+        error!("no slot configs found in '{}'", dir);
+        return Err(libusb::Error::Other);
+    }
+
+    let mut backups = Vec::new();
+    for &(slot, _, _) in configs.iter() {
+        println!("Backing up slot {}...", slot);
+        backups.push((slot, kbd.download_config(slot)?));
+    }
+
+    for (i, (slot, path, cfg)) in configs.iter().enumerate() {
+        println!("Provisioning slot {} from '{}'...", slot, path.display());
+        if let Err(e) = upload_verified(kbd, *slot, cfg) {
+            error!("slot {}: {}", slot, e);
+
+            // roll back everything touched so far, including the failed slot
+            for (slot, backup) in backups.iter().take(i + 1) {
+                println!("Rolling back slot {}...", slot);
+                if let Err(e) = upload_verified(kbd, *slot, backup) {
+                    error!("couldn't roll back slot {}: {}", slot, e);
+                }
+            }
+            return Err(libusb::Error::Other);
+        }
+    }
+
+    println!("Provisioned {} slot(s)", configs.len());
+    Ok(())
+}
+
+#[rustfmt::skip]
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("provision")
+        .about("Upload a directory of configs named after their slots (e.g: 0.cfg, 1-work.json)")
+        .arg(Arg::with_name("dir")
+            .required(true)
+            .value_name("DIR")
+            .index(1))
+}
+
+pub fn exec(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let dir = m.value_of("dir").unwrap();
+    g.with_keyboard(|kbd| Ok(run(kbd, dir, &g.keymap, &g.correction)?))
+}
@@ -0,0 +1,177 @@
+//! `replay`: sends the keyboard what a USB capture (see `kbd::capture`) shows
+//! something else sending it, e.g: the Windows Fusion software using a feature
+//! that isn't implemented here yet. Handy for working out what it does.
+
+use std::fs;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use fusion_kbd_daemon::control;
+use fusion_kbd_daemon::settings::{parse_location, Settings};
+use fusion_kbd_protocol::capture::{self, Transfer};
+use fusion_kbd_protocol::protocol::{KEY_ENDPOINT, LIGHTING_INTERFACE};
+use fusion_kbd_protocol::{self as kbd, device};
+use log::error;
+
+use crate::exit::Failure;
+use crate::Globals;
+
+pub struct Options {
+    pub capture: String,
+    /// the keyboard's `(bus, address)` in the capture (see `keyboard`)
+    pub source: Option<(u8, u8)>,
+    /// print the transfers, instead of sending them
+    pub list: bool,
+}
+
+/// The keyboard's `(bus, address)` in a capture: `source`, or else the first
+/// device that got a class request on the lighting interface.
+fn keyboard(transfers: &[Transfer], source: Option<(u8, u8)>) -> Option<(u16, u16)> {
+    if let Some((bus, address)) = source {
+        return Some((bus as u16, address as u16));
+    }
+    transfers
+        .iter()
+        .find(|t| match t.setup {
+            Some(s) => s.is_class_or_vendor() && s.index == LIGHTING_INTERFACE,
+            None => false,
+        })
+        .map(|t| t.device)
+}
+
+/// whether `t` is something the keyboard was told (or asked), rather than
+/// the USB stack setting it up, or key presses
+fn worth_replaying(t: &Transfer) -> bool {
+    match t.setup {
+        Some(s) => s.is_class_or_vendor(),
+        // key presses are typing, rather than anything worth replaying
+        None => t.endpoint != KEY_ENDPOINT,
+    }
+}
+
+/// The keyboard's transfers in the capture at `path` (see `keyboard`), that
+/// are worth replaying
+pub fn load(path: &str, source: Option<(u8, u8)>) -> Result<Vec<Transfer>, String> {
+    let transfers = fs::read(path)
+        .map_err(|e| e.to_string())
+        .and_then(|bytes| capture::parse(&bytes))
+        .map_err(|e| format!("couldn't read '{}': {}", path, e))?;
+    let source = keyboard(&transfers, source).ok_or_else(|| {
+        format!(
+            "couldn't tell which device in '{}' is the keyboard (pick one with \
+             --source-device)",
+            path
+        )
+    })?;
+    let transfers: Vec<Transfer> = transfers
+        .into_iter()
+        .filter(|t| t.device == source && worth_replaying(t))
+        .collect();
+    if transfers.is_empty() {
+        return Err(format!(
+            "no transfers to {:03}:{:03} in '{}'",
+            source.0, source.1, path
+        ));
+    }
+    Ok(transfers)
+}
+
+fn send(usb: &kbd::FusionKBD, t: &Transfer) -> Result<Vec<u8>, libusb::Error> {
+    let mut data = match t.setup {
+        Some(s) if t.is_in() => vec![0; s.length as usize],
+        // interrupt packets are at most 64 bytes at full speed
+        None if t.is_in() => vec![0; t.data.len().max(64)],
+        _ => t.data.clone(),
+    };
+    let n = match t.setup {
+        Some(s) => usb.control(s.request_type, s.request, s.value, s.index, &mut data)?,
+        None => usb.interrupt(t.endpoint, &mut data)?,
+    };
+    data.truncate(n);
+    Ok(data)
+}
+
+pub fn run(
+    context: &libusb::Context,
+    settings: &Settings,
+    options: &Options,
+    dry_run: bool,
+) -> Result<(), Failure> {
+    let transfers = match load(&options.capture, options.source) {
+        Ok(transfers) => transfers,
+        Err(e) => {
+            error!("{}", e);
+            return Err(Failure::File);
+        }
+    };
+
+    if options.list || dry_run {
+        for t in &transfers {
+            println!("{}", t);
+        }
+        return Ok(());
+    }
+
+    if control::Client::connect().is_some() {
+        error!("the daemon is holding the keyboard. Stop it first");
+        return Err(libusb::Error::Busy.into());
+    }
+    device::release_on_exit();
+    let mut usb = kbd::FusionKBD::open(context, &settings.usb_ids())?;
+    crate::set_up(&mut usb, settings)?;
+
+    let mut failed = 0;
+    for t in &transfers {
+        println!("{}", t);
+        match send(&usb, t) {
+            // worth knowing when reverse engineering, e.g: a status byte
+            Ok(ref data) if t.is_in() && *data != t.data => {
+                let data: Vec<String> = data.iter().map(|b| format!("{:02x}", b)).collect();
+                println!("  -> differs from the capture: [{}]", data.join(" "));
+            }
+            Ok(_) => {}
+            Err(e) => {
+                println!("  -> failed: {}", e);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        error!("{} of {} transfers failed", failed, transfers.len());
+        return Err(libusb::Error::Io.into());
+    }
+    println!("Replayed {} transfers", transfers.len());
+    Ok(())
+}
+
+#[rustfmt::skip]
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("replay")
+        .about("Send the keyboard the transfers in a USB capture (e.g: of the Windows Fusion software)")
+        .arg(Arg::with_name("capture")
+            .required(true)
+            .value_name("FILE")
+            .index(1)
+            .help("A pcapng / pcap capture, from USBPcap or usbmon"))
+        .arg(Arg::with_name("source-device")
+            .takes_value(true)
+            .value_name("BUS:ADDRESS")
+            .long("source-device")
+            .validator(|dstr| parse_location(&dstr).map(|_| ()))
+            .help("Which device in the capture is the keyboard (default: the first sent a lighting header)"))
+        .arg(Arg::with_name("list")
+            .long("list")
+            .help("Print the transfers, instead of sending them"))
+}
+
+pub fn exec(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let options = Options {
+        capture: m.value_of("capture").unwrap().to_string(),
+        source: m
+            .value_of("source-device")
+            .map(|dstr| parse_location(dstr).unwrap()),
+        list: m.is_present("list"),
+    };
+    // raw transfers, so it can't go through `Keyboard` (or the daemon)
+    run(&libusb::Context::new()?, &g.settings, &options, g.dry_run)
+}
@@ -0,0 +1,355 @@
+//! User-scripted effects (`run-script`), written in Rhai (https://rhai.rs).
+//! A script defines `frame(t, sys)`, which is called `fps` times a second to
+//! draw the next frame:
+//!
+//! ```rhai
+//! fn frame(t, sys) {
+//!     for key in keys() {
+//!         set(key, hue(col(key) * 20.0 + t * 90.0));
+//!     }
+//!     if sys.cpu > 0.8 {
+//!         set("esc", "red");
+//!     }
+//! }
+//! ```
+//!
+//! `t` is the time since the script started, in seconds. `sys` holds `cpu`
+//! and `ram` (how busy they are, from 0 - 1, refreshed every `SAMPLE`), and
+//! the local `hour`, `minute` and `second`.
+//!
+//! Each frame starts out as the last one was (all off, at first). The grid is
+//! drawn on with:
+//!
+//! - `set(key, color)`, `get(key)`, `fill(color)`
+//! - `keys()`: every key; `row(key)` / `col(key)`: where a key is on the
+//!   matrix; `key_at(row, col)`: the key there (or -1)
+//!
+//! where keys are indexes, or keymap names (`"esc"`, `"w"`, ...), and colors
+//! are `0xRRGGBB` numbers, or strings (`"red"`, `"#ff8000"`). `rgb(r, g, b)`,
+//! `hue(degrees)` and `blend(a, b, t)` make colors.
+
+use std::cell::RefCell;
+use std::fs;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use chrono::Timelike;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use fusion_kbd_daemon::SCRATCH_SLOT;
+use fusion_kbd_protocol::config::{key_position, MATRIX_COLS, MATRIX_ROWS, NUM_KEYS};
+use fusion_kbd_protocol::correction::Correction;
+use fusion_kbd_protocol::effects::{self, FrameClock};
+use fusion_kbd_protocol::state::{Lighting, State};
+use fusion_kbd_protocol::{self as kbd, CustomConfig, Keymap, Rgb};
+use log::error;
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, Map, Scope, FLOAT, INT};
+
+use crate::exit::Failure;
+use crate::{sysload, validate_slot, Globals};
+
+/// how often `sys.cpu` / `sys.ram` are refreshed
+const SAMPLE: Duration = Duration::from_millis(500);
+
+pub struct Options {
+    pub path: String,
+    pub fps: u32,
+    pub slot: u8,
+    pub brightness: u8,
+}
+
+type Fallible<T> = Result<T, Box<EvalAltResult>>;
+
+fn to_int(Rgb(r, g, b): Rgb) -> INT {
+    (r as INT) << 16 | (g as INT) << 8 | b as INT
+}
+
+fn from_int(color: INT) -> Rgb {
+    Rgb((color >> 16) as u8, (color >> 8) as u8, color as u8)
+}
+
+fn parse_color(color: &str) -> Fallible<Rgb> {
+    Rgb::from_str(color).map_err(|e| e.into())
+}
+
+/// a key index, checked
+fn index(key: INT) -> Fallible<usize> {
+    if (0..NUM_KEYS as INT).contains(&key) {
+        Ok(key as usize)
+    } else {
+        Err(format!("key {} is out of range (0 - {})", key, NUM_KEYS - 1).into())
+    }
+}
+
+/// A script engine drawing on `grid`.
+fn engine(grid: &Rc<RefCell<CustomConfig>>, keymap: &Keymap) -> Engine {
+    let mut engine = Engine::new();
+
+    let named = {
+        let keymap = keymap.clone();
+        move |name: &str| -> Fallible<usize> {
+            keymap
+                .index(name)
+                .ok_or_else(|| format!("the keymap doesn't have a '{}' key", name).into())
+        }
+    };
+    let set = {
+        let grid = grid.clone();
+        move |key: usize, color: Rgb| grid.borrow_mut().set_key(key, color)
+    };
+
+    {
+        let set = set.clone();
+        engine.register_fn("set", move |key: INT, color: INT| -> Fallible<()> {
+            set(index(key)?, from_int(color));
+            Ok(())
+        });
+    }
+    {
+        let set = set.clone();
+        engine.register_fn("set", move |key: INT, color: &str| -> Fallible<()> {
+            set(index(key)?, parse_color(color)?);
+            Ok(())
+        });
+    }
+    {
+        let (set, named) = (set.clone(), named.clone());
+        engine.register_fn("set", move |key: &str, color: INT| -> Fallible<()> {
+            set(named(key)?, from_int(color));
+            Ok(())
+        });
+    }
+    {
+        let (set, named) = (set.clone(), named.clone());
+        engine.register_fn("set", move |key: &str, color: &str| -> Fallible<()> {
+            set(named(key)?, parse_color(color)?);
+            Ok(())
+        });
+    }
+
+    {
+        let grid = grid.clone();
+        engine.register_fn("get", move |key: INT| -> Fallible<INT> {
+            Ok(to_int(grid.borrow().get_key(index(key)?)))
+        });
+    }
+    {
+        let (grid, named) = (grid.clone(), named.clone());
+        engine.register_fn("get", move |key: &str| -> Fallible<INT> {
+            Ok(to_int(grid.borrow().get_key(named(key)?)))
+        });
+    }
+
+    {
+        let grid = grid.clone();
+        engine.register_fn("fill", move |color: INT| {
+            grid.borrow_mut().fill(from_int(color));
+        });
+    }
+    {
+        let grid = grid.clone();
+        engine.register_fn("fill", move |color: &str| -> Fallible<()> {
+            grid.borrow_mut().fill(parse_color(color)?);
+            Ok(())
+        });
+    }
+
+    engine.register_fn("keys", || -> rhai::Array {
+        (0..NUM_KEYS as INT).map(Dynamic::from).collect()
+    });
+    engine.register_fn("row", |key: INT| -> Fallible<INT> {
+        Ok(key_position(index(key)?).0 as INT)
+    });
+    engine.register_fn("col", |key: INT| -> Fallible<INT> {
+        Ok(key_position(index(key)?).1 as INT)
+    });
+    engine.register_fn("key_at", |row: INT, col: INT| -> INT {
+        if !(0..MATRIX_ROWS as INT).contains(&row) || !(0..MATRIX_COLS as INT).contains(&col) {
+            return -1;
+        }
+        (0..NUM_KEYS)
+            .find(|&key| key_position(key) == (row as usize, col as usize))
+            .map_or(-1, |key| key as INT)
+    });
+
+    engine.register_fn("rgb", |r: INT, g: INT, b: INT| -> INT {
+        let clamp = |c: INT| c.clamp(0, 0xff) as u8;
+        to_int(Rgb(clamp(r), clamp(g), clamp(b)))
+    });
+    engine.register_fn("hue", |degrees: FLOAT| -> INT {
+        to_int(effects::hue(degrees as f32))
+    });
+    engine.register_fn("hue", |degrees: INT| -> INT {
+        to_int(effects::hue(degrees as f32))
+    });
+    engine.register_fn("blend", |a: INT, b: INT, t: FLOAT| -> INT {
+        to_int(effects::blend(from_int(a), from_int(b), t as f32))
+    });
+
+    engine
+}
+
+/// Keeps `sys` up to date.
+struct Sampler {
+    cpu_times: Vec<(u64, u64)>,
+    cpu: f32,
+    ram: f32,
+    at: Instant,
+}
+
+impl Sampler {
+    fn new() -> Result<Sampler, String> {
+        Ok(Sampler {
+            cpu_times: sysload::cpu_times()?,
+            cpu: 0.0,
+            ram: sysload::ram_usage()?,
+            at: Instant::now(),
+        })
+    }
+
+    fn sys(&mut self) -> Result<Map, String> {
+        if self.at.elapsed() >= SAMPLE {
+            let now = sysload::cpu_times()?;
+            let (busy, total) = now.iter().zip(&self.cpu_times).fold(
+                (0, 0),
+                |(busy, total), (&(b, t), &(last_b, last_t))| {
+                    (
+                        busy + b.saturating_sub(last_b),
+                        total + t.saturating_sub(last_t),
+                    )
+                },
+            );
+            if total > 0 {
+                self.cpu = busy as f32 / total as f32;
+            }
+            self.cpu_times = now;
+            self.ram = sysload::ram_usage()?;
+            self.at = Instant::now();
+        }
+
+        let time = chrono::Local::now();
+        let mut sys = Map::new();
+        sys.insert("cpu".into(), (self.cpu as FLOAT).into());
+        sys.insert("ram".into(), (self.ram as FLOAT).into());
+        sys.insert("hour".into(), (time.hour() as INT).into());
+        sys.insert("minute".into(), (time.minute() as INT).into());
+        sys.insert("second".into(), (time.second() as INT).into());
+        Ok(sys)
+    }
+}
+
+/// Runs the script until interrupted, or until it fails.
+pub fn run(
+    kbd: &dyn kbd::Keyboard,
+    opts: &Options,
+    keymap: &Keymap,
+    correction: &Correction,
+) -> Result<(), libusb::Error> {
+    let fail = |e: String| {
+        error!("{}", e);
+        libusb::Error::Other
+    };
+
+    let source = fs::read_to_string(&opts.path)
+        .map_err(|e| fail(format!("couldn't read '{}': {}", opts.path, e)))?;
+
+    let grid = Rc::new(RefCell::new(CustomConfig::new()));
+    let engine = engine(&grid, keymap);
+    let ast = engine
+        .compile(&source)
+        .map_err(|e| fail(format!("{}: {}", opts.path, e)))?;
+    if !ast
+        .iter_functions()
+        .any(|f| f.name == "frame" && f.params.len() == 2)
+    {
+        return Err(fail(format!(
+            "{} doesn't define `frame(t, sys)`",
+            opts.path
+        )));
+    }
+
+    // top-level statements run once, up front
+    let mut scope = Scope::new();
+    engine
+        .run_ast_with_scope(&mut scope, &ast)
+        .map_err(|e| fail(format!("{}: {}", opts.path, e)))?;
+
+    let mut sampler = Sampler::new().map_err(fail)?;
+    let frame = Duration::from_secs(1) / opts.fps;
+    let start = Instant::now();
+    let mut clock = FrameClock::new();
+    let mut shown: Option<CustomConfig> = None;
+    loop {
+        let t = start.elapsed().as_secs_f64() as FLOAT;
+        let sys = sampler.sys().map_err(fail)?;
+        // whatever it returns is ignored
+        let _: Dynamic = engine
+            .call_fn_with_options(
+                CallFnOptions::new().eval_ast(false),
+                &mut scope,
+                &ast,
+                "frame",
+                (t, sys),
+            )
+            .map_err(|e| fail(format!("{}: {}", opts.path, e)))?;
+
+        let cfg = correction.apply(&grid.borrow());
+        if shown.as_ref().map(CustomConfig::as_bytes) != Some(cfg.as_bytes()) {
+            kbd.upload_custom(opts.slot, cfg.as_bytes())?;
+            kbd.set_custom(opts.slot, opts.brightness)?;
+            shown = Some(cfg);
+        }
+
+        clock.wait(frame);
+        while clock.is_late(frame) {
+            clock.skip(frame);
+        }
+    }
+}
+
+#[rustfmt::skip]
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("run-script")
+        .about("Run a user-defined effect, scripted in Rhai")
+        .arg(Arg::with_name("file")
+            .required(true)
+            .index(1)
+            .help("Script defining `frame(t, sys)` (e.g: effect.rhai)"))
+        .arg(Arg::with_name("fps")
+            .takes_value(true)
+            .long("fps")
+            .validator(|fstr| {
+                let fval = fstr.parse::<u32>();
+                if fval.is_err() || fval.unwrap() == 0 {
+                    return Err("fps must be a positive number!".to_string())
+                }
+                Ok(())
+            })
+            .help("How many times a second `frame` is called (default: 30)"))
+        .arg(Arg::with_name("slot")
+            .takes_value(true)
+            .short("s")
+            .long("slot")
+            .validator(validate_slot)
+            .help("Custom slot frames are streamed through (default: 4)"))
+}
+
+pub fn exec(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let opts = Options {
+        path: m.value_of("file").unwrap().to_string(),
+        fps: m
+            .value_of("fps")
+            .map_or(30, |fstr| fstr.parse::<u32>().unwrap()),
+        slot: m
+            .value_of("slot")
+            .map_or(SCRATCH_SLOT, |sstr| sstr.parse::<u8>().unwrap()),
+        brightness: g.brightness(),
+    };
+
+    g.with_keyboard(|kbd| Ok(run(kbd, &opts, &g.keymap, &g.correction)?))?;
+    g.remember(State {
+        lighting: Lighting::Custom { slot: opts.slot },
+        brightness: opts.brightness,
+    });
+    Ok(())
+}
@@ -0,0 +1,258 @@
+use std::fs::File;
+use std::io::Write;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use fusion_kbd_daemon::SCRATCH_SLOT;
+use fusion_kbd_protocol::{self as kbd, effects, CustomConfig, Keymap, Rgb};
+use log::error;
+
+use crate::exit::Failure;
+use crate::prompt::confirm;
+use crate::{unknown_subcommand, validate_slot, Globals};
+
+/// brightness used for the test frames
+static FULL_BRIGHTNESS: u8 = 50;
+
+struct Fault {
+    key: usize,
+    reason: String,
+}
+
+/// uploads + switches to a frame, returning any keys that didn't read back
+/// the way they were sent
+fn show(
+    kbd: &dyn kbd::Keyboard,
+    slot: u8,
+    cfg: &CustomConfig,
+) -> Result<Vec<usize>, libusb::Error> {
+    kbd.upload_custom(slot, cfg.as_bytes())?;
+    kbd.set_custom(slot, FULL_BRIGHTNESS)?;
+
+    Ok(cfg.diff(&kbd.download_config(slot)?))
+}
+
+/// Cycles full red / green / blue frames through `slot`, asking the user to
+/// confirm each one. Whenever a frame looks wrong, the matrix is walked column
+/// by column (and then key by key) to narrow down the faulty LEDs.
+///
+/// The original contents of `slot` are restored afterwards, and a report
+/// listing faulty key offsets is written to `report`.
+pub fn run(kbd: &dyn kbd::Keyboard, slot: u8, report: &str) -> Result<(), libusb::Error> {
+    let backup = kbd.download_config(slot)?;
+
+    let channels = [
+        ("red", 'r', Rgb(0xff, 0, 0)),
+        ("green", 'g', Rgb(0, 0xff, 0)),
+        ("blue", 'b', Rgb(0, 0, 0xff)),
+    ];
+
+    let mut faults: Vec<Fault> = Vec::new();
+
+    for &(name, channel, color) in channels.iter() {
+        for key in show(kbd, slot, &effects::solid(color))? {
+            faults.push(Fault {
+                key,
+                reason: format!("{}: readback mismatch", channel),
+            });
+        }
+
+        if confirm(&format!("Are all keys lit solid {}?", name)) {
+            continue;
+        }
+
+        for col in 0..kbd::MATRIX_COLS {
+            show(kbd, slot, &effects::column(col, color))?;
+
+            if confirm(&format!(
+                "Column {}: are all lit keys solid {}, with everything else off?",
+                col, name
+            )) {
+                continue;
+            }
+
+            for key in effects::column_keys(col) {
+                show(kbd, slot, &effects::single_key(key, color))?;
+
+                let (row, col) = kbd::key_position(key);
+                if !confirm(&format!(
+                    "Key {} (row {}, col {}): is exactly one key lit solid {}?",
+                    key, row, col, name
+                )) {
+                    faults.push(Fault {
+                        key,
+                        reason: format!("{}: reported faulty", channel),
+                    });
+                }
+            }
+        }
+    }
+
+    println!("Restoring slot {}...", slot);
+    kbd.upload_custom(slot, backup.as_bytes())?;
+    kbd.set_custom(slot, FULL_BRIGHTNESS)?;
+
+    faults.sort_by_key(|f| f.key);
+
+    let mut f = match File::create(report) {
+        Ok(file) => file,
+        Err(_) => {
+            error!("couldn't open '{}'", report);
+            return Err(libusb::Error::Other);
+        }
+    };
+
+    // one faulty key offset per line, so the report can be used as a mask
+    let mut out = String::from("# fusion-kbd-controller selftest report\n# key reason\n");
+    for fault in faults.iter() {
+        out.push_str(&format!("{} {}\n", fault.key, fault.reason));
+    }
+    if f.write_all(out.as_bytes()).is_err() {
+        error!("couldn't write '{}'", report);
+        return Err(libusb::Error::Other);
+    }
+
+    if faults.is_empty() {
+        println!("No faults found!");
+    } else {
+        println!("Found {} fault(s), see '{}'", faults.len(), report);
+    }
+
+    Ok(())
+}
+
+/// how `leds` steps across the keyboard
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Walk {
+    Key,
+    Row,
+    Column,
+}
+
+/// Lights the keys in `keymap` one at a time (or a row / column at a time)
+/// on `slot`, asking the user to confirm each one, then lists the ones that
+/// didn't light up. The original contents of `slot` are restored afterwards.
+pub fn leds(
+    kbd: &dyn kbd::Keyboard,
+    slot: u8,
+    walk: Walk,
+    keymap: &Keymap,
+) -> Result<(), libusb::Error> {
+    let backup = kbd.download_config(slot)?;
+
+    let mut keys: Vec<(&str, usize)> = keymap.keys().collect();
+    keys.sort_by_key(|&(_, key)| kbd::key_position(key));
+
+    // (what to call it, which keys)
+    let groups: Vec<(String, Vec<usize>)> = match walk {
+        Walk::Key => keys
+            .iter()
+            .map(|&(name, key)| (format!("Key '{}' ({})", name, key), vec![key]))
+            .collect(),
+        Walk::Row => (0..kbd::config::MATRIX_ROWS)
+            .map(|row| {
+                let row_keys = keys.iter().map(|&(_, key)| key);
+                let row_keys = row_keys.filter(|&key| kbd::key_position(key).0 == row);
+                (format!("Row {}", row), row_keys.collect())
+            })
+            .collect(),
+        Walk::Column => (0..kbd::MATRIX_COLS)
+            .map(|col| {
+                let col_keys = keys.iter().map(|&(_, key)| key);
+                let col_keys = col_keys.filter(|&key| kbd::key_position(key).1 == col);
+                (format!("Column {}", col), col_keys.collect())
+            })
+            .collect(),
+    };
+
+    let white = Rgb(0xff, 0xff, 0xff);
+    let mut failed: Vec<String> = Vec::new();
+    for (label, group) in groups.iter().filter(|(_, group)| !group.is_empty()) {
+        let mut cfg = CustomConfig::new();
+        for &key in group {
+            cfg.set_key(key, white);
+        }
+        let mismatched = show(kbd, slot, &cfg)?;
+
+        let question = match walk {
+            Walk::Key => format!("{}: is it lit white, with everything else off?", label),
+            _ => format!(
+                "{}: are all {} of its keys lit white, with everything else off?",
+                label,
+                group.len()
+            ),
+        };
+        if !confirm(&question) {
+            failed.push(label.clone());
+        } else if !mismatched.is_empty() {
+            failed.push(format!("{} (readback mismatch)", label));
+        }
+    }
+
+    println!("Restoring slot {}...", slot);
+    kbd.upload_custom(slot, backup.as_bytes())?;
+    kbd.set_custom(slot, FULL_BRIGHTNESS)?;
+
+    if failed.is_empty() {
+        println!("All LEDs look fine!");
+    } else {
+        println!("{} problem(s) found:", failed.len());
+        for label in failed {
+            println!("  {}", label);
+        }
+    }
+    Ok(())
+}
+
+#[rustfmt::skip]
+pub fn subcommands<'a, 'b>() -> Vec<App<'a, 'b>> {
+    vec![
+        SubCommand::with_name("selftest")
+            .about("Cycle full R/G/B frames to find dead or stuck LEDs")
+            .arg(Arg::with_name("slot")
+                .takes_value(true)
+                .short("s")
+                .long("slot")
+                .validator(validate_slot)
+                .help("Custom slot used for test frames, restored afterwards (default: 4)"))
+            .arg(Arg::with_name("report")
+                .takes_value(true)
+                .value_name("FILE")
+                .short("o")
+                .long("report")
+                .help("Where to write the fault report (default: selftest.txt)")),
+        SubCommand::with_name("test-leds")
+            .about("Light each key (or row / column) in turn, to find dead or miswired LEDs")
+            .arg(Arg::with_name("by")
+                .takes_value(true)
+                .long("by")
+                .possible_values(&["key", "row", "column"])
+                .help("What to light at a time (default: key)"))
+            .arg(Arg::with_name("slot")
+                .takes_value(true)
+                .short("s")
+                .long("slot")
+                .validator(validate_slot)
+                .help("Custom slot used for test frames, restored afterwards (default: 4)")),
+    ]
+}
+
+pub fn exec(name: &str, m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let slot = m
+        .value_of("slot")
+        .map_or(SCRATCH_SLOT, |sstr| sstr.parse::<u8>().unwrap());
+    match name {
+        "selftest" => {
+            let report = m.value_of("report").unwrap_or("selftest.txt");
+            g.with_keyboard(|kbd| Ok(run(kbd, slot, report)?))
+        }
+        "test-leds" => {
+            let walk = match m.value_of("by") {
+                Some("row") => Walk::Row,
+                Some("column") => Walk::Column,
+                _ => Walk::Key,
+            };
+            g.with_keyboard(|kbd| Ok(leds(kbd, slot, walk, &g.keymap)?))
+        }
+        name => Err(unknown_subcommand(name)),
+    }
+}
@@ -0,0 +1,210 @@
+//! Subcommands that work from the last lighting that was set (see
+//! `fusion_kbd_daemon::saved`): `off`, `on`, `restore`, `brightness` and
+//! `status`, plus `subscribe`, which follows it as it changes.
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use fusion_kbd_daemon::saved::{self, Saved};
+use fusion_kbd_daemon::{control, events};
+use fusion_kbd_protocol::protocol::MAX_BRIGHTNESS;
+use fusion_kbd_protocol::state::{Lighting, State};
+use log::error;
+use serde_json::json;
+
+use crate::exit::Failure;
+use crate::{backup, unknown_subcommand, Globals};
+
+#[rustfmt::skip]
+pub fn subcommands<'a, 'b>() -> Vec<App<'a, 'b>> {
+    vec![
+        SubCommand::with_name("off")
+            .about("Turn the backlight off (`on` brings back whatever was showing)"),
+        SubCommand::with_name("on")
+            .about("Bring back the lighting from before `off`"),
+        SubCommand::with_name("restore")
+            .about("Reapply the last lighting that was set (e.g: after a reboot), or with FILE, every slot in a backup")
+            .arg(Arg::with_name("file")
+                .value_name("FILE")
+                .index(1)
+                .help("A backup made with `backup`, to write back to the custom slots")),
+        SubCommand::with_name("brightness")
+            .about("Step the brightness up or down (e.g: from a hotkey)")
+            .arg(Arg::with_name("direction")
+                .required(true)
+                .possible_values(&["up", "down"])
+                .index(1))
+            .arg(Arg::with_name("step")
+                .takes_value(true)
+                .long("step")
+                .validator(|sstr| {
+                    let sval = sstr.parse::<u8>();
+                    if sval.is_err() || sval.unwrap() > 50 {
+                        return Err("step must be a number from 0 - 50!".to_string())
+                    }
+                    Ok(())
+                })
+                .help("How far to step (default: `brightness_step` from the config file, or 5)")),
+        SubCommand::with_name("status")
+            .about("Show the last lighting that was set, and whether the daemon is running"),
+        SubCommand::with_name("subscribe")
+            .about("Print a line of JSON whenever the lighting changes"),
+    ]
+}
+
+/// Switches to `state`
+fn apply(g: &Globals, state: State) -> Result<(), Failure> {
+    g.with_keyboard(|kbd| Ok(kbd.set_state(&state)?))?;
+    g.remember(state);
+    Ok(())
+}
+
+/// Turns the backlight off, remembering `state` for `on`
+fn off(g: &Globals, state: State) -> Result<(), Failure> {
+    let dark = State {
+        brightness: 0,
+        ..state.clone()
+    };
+    g.with_keyboard(|kbd| Ok(kbd.set_state(&dark)?))?;
+    g.remember_off(state);
+    Ok(())
+}
+
+pub fn exec(name: &str, m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    match name {
+        "off" => off(
+            g,
+            match saved::load() {
+                Some(saved) => saved.state,
+                // nothing's been set through the CLI yet, so there's nothing to
+                // remember. Go with a default that `on` can still bring back.
+                None => State {
+                    lighting: Lighting::Custom {
+                        slot: g.settings.slot.unwrap_or(0),
+                    },
+                    brightness: g.default_brightness,
+                },
+            },
+        ),
+        "on" => match saved::load() {
+            Some(saved) => apply(g, saved.state),
+            None => {
+                error!("nothing to turn back on (no lighting has been set yet)");
+                Err(libusb::Error::Other.into())
+            }
+        },
+        "restore" => match m.value_of("file") {
+            Some(file) => g.with_keyboard(|kbd| backup::restore(kbd, file)),
+            None => match saved::load() {
+                Some(Saved { state, off: true }) => off(g, state),
+                Some(Saved { state, off: false }) => apply(g, state),
+                None => {
+                    error!("nothing to restore (no lighting has been set yet)");
+                    Err(libusb::Error::Other.into())
+                }
+            },
+        },
+        "brightness" => {
+            let saved = match saved::load() {
+                Some(saved) => saved,
+                None => {
+                    error!("the current brightness isn't known (no lighting has been set yet)");
+                    return Err(libusb::Error::Other.into());
+                }
+            };
+            let step = match m.value_of("step") {
+                Some(sstr) => sstr.parse::<u8>().unwrap(),
+                None => g.settings.brightness_step,
+            };
+
+            // while off, the keyboard is at 0: `up` turns it back on, and `down`
+            // leaves it off (still remembering what to bring back)
+            let current = if saved.off { 0 } else { saved.state.brightness };
+            match m.value_of("direction").unwrap() {
+                "up" => apply(
+                    g,
+                    State {
+                        brightness: current.saturating_add(step).min(MAX_BRIGHTNESS),
+                        ..saved.state
+                    },
+                ),
+                _ if saved.off => off(g, saved.state),
+                _ => apply(
+                    g,
+                    State {
+                        brightness: current.saturating_sub(step),
+                        ..saved.state
+                    },
+                ),
+            }
+        }
+        "status" => {
+            status(g.json);
+            Ok(())
+        }
+        "subscribe" => {
+            if let Err(e) = events::subscribe() {
+                error!("couldn't listen for events: {}", e);
+                return Err(libusb::Error::Other.into());
+            }
+            Ok(())
+        }
+        name => Err(unknown_subcommand(name)),
+    }
+}
+
+/// No subcommand: with `-b`, keeps showing whatever was last set, at that
+/// brightness. Without it, only opens the keyboard.
+pub fn keep(g: &Globals) -> Result<(), Failure> {
+    let brightness = match g.brightness {
+        Some(brightness) => brightness,
+        None => return g.with_keyboard(|_| Ok(())),
+    };
+    match saved::load() {
+        Some(saved) => apply(
+            g,
+            State {
+                brightness,
+                ..saved.state
+            },
+        ),
+        None => {
+            error!("-b needs a mode to go with it (no lighting has been set yet)");
+            Err(libusb::Error::Other.into())
+        }
+    }
+}
+
+fn status(json: bool) {
+    let saved = saved::load();
+    let daemon = control::Client::connect().is_some();
+    if json {
+        println!(
+            "{}",
+            json!({
+                "state": saved.as_ref().map(|saved| saved.state.to_json()),
+                "off": saved.as_ref().is_some_and(|saved| saved.off),
+                "daemon": daemon,
+            })
+        );
+        return;
+    }
+
+    match saved {
+        Some(saved) => {
+            match saved.state.lighting {
+                Lighting::Preset {
+                    preset,
+                    color,
+                    speed,
+                } => println!("lighting:   {} ({}, speed {})", preset, color, speed),
+                Lighting::Custom { slot } => println!("lighting:   custom slot {}", slot),
+            }
+            println!("brightness: {}", saved.state.brightness);
+            println!("backlight:  {}", if saved.off { "off" } else { "on" });
+        }
+        None => println!("lighting:   (nothing has been set yet)"),
+    }
+    println!(
+        "daemon:     {}",
+        if daemon { "running" } else { "not running" }
+    );
+}
@@ -0,0 +1,222 @@
+//! System load monitor (`sysload`): every CPU core gets a bar (a few columns
+//! wide, or shared with its neighbours on machines with lots of cores)
+//! showing how busy it's been since the last refresh, optionally with a RAM
+//! usage bar on the right. Bars fill from the bottom row up, green to red,
+//! like `visualize`'s.
+//!
+//! Load comes from `/proc/stat`, and RAM usage from `/proc/meminfo`.
+
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use fusion_kbd_daemon::SCRATCH_SLOT;
+use fusion_kbd_protocol::config::{key_position, MATRIX_COLS, MATRIX_ROWS, NUM_KEYS};
+use fusion_kbd_protocol::correction::Correction;
+use fusion_kbd_protocol::state::{Lighting, State};
+use fusion_kbd_protocol::{self as kbd, CustomConfig, Rgb};
+use log::error;
+
+use crate::exit::Failure;
+use crate::{validate_slot, Globals};
+
+/// CPU bar colors, from the bottom row up
+const CPU_COLORS: [Rgb; MATRIX_ROWS] = [
+    Rgb(0x00, 0xff, 0x00),
+    Rgb(0x00, 0xff, 0x00),
+    Rgb(0x80, 0xff, 0x00),
+    Rgb(0xff, 0xff, 0x00),
+    Rgb(0xff, 0x80, 0x00),
+    Rgb(0xff, 0x00, 0x00),
+];
+
+const RAM_COLOR: Rgb = Rgb(0x00, 0x80, 0xff);
+
+/// columns taken up by the RAM bar
+const RAM_COLS: usize = 2;
+
+pub struct Options {
+    /// also show RAM usage
+    pub ram: bool,
+    pub interval: Duration,
+    pub slot: u8,
+    pub brightness: u8,
+}
+
+/// (busy, total) jiffies of each core, since boot
+pub fn cpu_times() -> Result<Vec<(u64, u64)>, String> {
+    let stat =
+        fs::read_to_string("/proc/stat").map_err(|e| format!("couldn't read /proc/stat: {}", e))?;
+
+    // "cpu3 user nice system idle iowait irq softirq steal ..." (the
+    // aggregate "cpu" line has no number)
+    let cores: Vec<(u64, u64)> = stat
+        .lines()
+        .filter(|l| l.starts_with("cpu") && l.as_bytes().get(3).is_some_and(u8::is_ascii_digit))
+        .map(|l| {
+            let fields: Vec<u64> = l
+                .split_whitespace()
+                .skip(1)
+                .filter_map(|f| f.parse().ok())
+                .collect();
+            let total = fields.iter().take(8).sum::<u64>();
+            // idle + iowait
+            let idle = fields.iter().skip(3).take(2).sum::<u64>();
+            (total - idle, total)
+        })
+        .collect();
+
+    if cores.is_empty() {
+        return Err("no CPU cores in /proc/stat".to_string());
+    }
+    Ok(cores)
+}
+
+/// fraction of RAM in use, from 0 - 1
+pub fn ram_usage() -> Result<f32, String> {
+    let meminfo = fs::read_to_string("/proc/meminfo")
+        .map_err(|e| format!("couldn't read /proc/meminfo: {}", e))?;
+
+    // "MemTotal:       16303740 kB"
+    let field = |name: &str| {
+        meminfo
+            .lines()
+            .find_map(|l| l.strip_prefix(name)?.strip_prefix(':'))
+            .and_then(|v| v.trim().trim_end_matches("kB").trim().parse::<f32>().ok())
+    };
+    match (field("MemTotal"), field("MemAvailable")) {
+        (Some(total), Some(available)) if total > 0.0 => Ok(1.0 - available / total),
+        _ => Err("couldn't make sense of /proc/meminfo".to_string()),
+    }
+}
+
+/// `loads` spread out over `cols` columns. Cores sharing a column are
+/// averaged.
+fn columns(loads: &[f32], cols: usize) -> Vec<f32> {
+    let n = loads.len();
+    (0..cols)
+        .map(|col| {
+            let lo = col * n / cols;
+            let hi = ((col + 1) * n / cols).max(lo + 1);
+            loads[lo..hi].iter().sum::<f32>() / (hi - lo) as f32
+        })
+        .collect()
+}
+
+fn render(loads: &[f32], ram: Option<f32>) -> CustomConfig {
+    let cpu_cols = if ram.is_some() {
+        MATRIX_COLS - RAM_COLS
+    } else {
+        MATRIX_COLS
+    };
+    let levels = columns(loads, cpu_cols);
+
+    let mut cfg = CustomConfig::new();
+    for key in 0..NUM_KEYS {
+        let (row, col) = key_position(key);
+        let height = MATRIX_ROWS - row;
+        let (level, color) = match levels.get(col) {
+            Some(&level) => (level, CPU_COLORS[height - 1]),
+            None => (ram.unwrap_or(0.0), RAM_COLOR),
+        };
+        if level * MATRIX_ROWS as f32 >= height as f32 - 0.5 {
+            cfg.set_key(key, color);
+        }
+    }
+    cfg
+}
+
+/// Shows the system load until interrupted.
+pub fn run(
+    kbd: &dyn kbd::Keyboard,
+    opts: &Options,
+    correction: &Correction,
+) -> Result<(), libusb::Error> {
+    let fail = |e: String| {
+        error!("{}", e);
+        libusb::Error::Other
+    };
+
+    let mut last = cpu_times().map_err(fail)?;
+    let mut shown: Option<Vec<u8>> = None;
+    loop {
+        thread::sleep(opts.interval);
+
+        let now = cpu_times().map_err(fail)?;
+        let loads: Vec<f32> = now
+            .iter()
+            .zip(&last)
+            .map(|(&(busy, total), &(last_busy, last_total))| {
+                let total = total.saturating_sub(last_total);
+                if total == 0 {
+                    0.0
+                } else {
+                    busy.saturating_sub(last_busy) as f32 / total as f32
+                }
+            })
+            .collect();
+        last = now;
+
+        let ram = if opts.ram {
+            Some(ram_usage().map_err(fail)?)
+        } else {
+            None
+        };
+
+        let cfg = correction.apply(&render(&loads, ram));
+        // an idle machine needn't be re-uploaded every time
+        if shown.as_deref() != Some(cfg.as_bytes()) {
+            kbd.upload_custom(opts.slot, cfg.as_bytes())?;
+            kbd.set_custom(opts.slot, opts.brightness)?;
+            shown = Some(cfg.as_bytes().to_vec());
+        }
+    }
+}
+
+#[rustfmt::skip]
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("sysload")
+        .about("Show per-core CPU load (and optionally RAM usage) as bar graphs")
+        .arg(Arg::with_name("ram")
+            .long("ram")
+            .help("Add a RAM usage bar on the right"))
+        .arg(Arg::with_name("interval")
+            .takes_value(true)
+            .long("interval")
+            .validator(|istr| {
+                let ival = istr.parse::<u64>();
+                if ival.is_err() || ival.unwrap() == 0 {
+                    return Err("interval must be a positive number of milliseconds!".to_string())
+                }
+                Ok(())
+            })
+            .help("Milliseconds between refreshes (default: 1000)"))
+        .arg(Arg::with_name("slot")
+            .takes_value(true)
+            .short("s")
+            .long("slot")
+            .validator(validate_slot)
+            .help("Custom slot the bars are streamed through (default: 4)"))
+}
+
+pub fn exec(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let opts = Options {
+        ram: m.is_present("ram"),
+        interval: Duration::from_millis(
+            m.value_of("interval")
+                .map_or(1000, |istr| istr.parse::<u64>().unwrap()),
+        ),
+        slot: m
+            .value_of("slot")
+            .map_or(SCRATCH_SLOT, |sstr| sstr.parse::<u8>().unwrap()),
+        brightness: g.brightness(),
+    };
+
+    g.with_keyboard(|kbd| Ok(run(kbd, &opts, &g.correction)?))?;
+    g.remember(State {
+        lighting: Lighting::Custom { slot: opts.slot },
+        brightness: opts.brightness,
+    });
+    Ok(())
+}
@@ -0,0 +1,57 @@
+//! `themes list`: the theme packs installed in the themes directory, and the
+//! built-in ones (see `fusion_kbd_daemon::themes`).
+
+use clap::{App, AppSettings, ArgMatches, SubCommand};
+use fusion_kbd_daemon::themes::{self, Origin};
+use log::error;
+use serde_json::{json, Value};
+
+use crate::exit::Failure;
+use crate::{unknown_subcommand, Globals};
+
+#[rustfmt::skip]
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("themes")
+        .about("Work with theme packs (configs in the themes directory)")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(SubCommand::with_name("list")
+            .about("List themes (installed and built-in)"))
+}
+
+pub fn exec(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    match m.subcommand() {
+        ("list", Some(_)) => list(g.json),
+        (name, _) => Err(unknown_subcommand(name)),
+    }
+}
+
+fn list(json: bool) -> Result<(), Failure> {
+    let all = match themes::list() {
+        Ok(all) => all,
+        Err(e) => {
+            error!("{}", e);
+            return Err(Failure::File);
+        }
+    };
+
+    if json {
+        let all: Vec<Value> = all
+            .into_iter()
+            .map(|(name, origin)| match origin {
+                Origin::Installed(path) => {
+                    json!({"name": name, "path": path.display().to_string()})
+                }
+                Origin::BuiltIn => json!({"name": name, "path": null}),
+            })
+            .collect();
+        println!("{}", Value::from(all));
+        return Ok(());
+    }
+    for (name, origin) in all {
+        match origin {
+            Origin::Installed(path) => println!("{:<16} {}", name, path.display()),
+            Origin::BuiltIn => println!("{:<16} (built-in)", name),
+        }
+    }
+    Ok(())
+}
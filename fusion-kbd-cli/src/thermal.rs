@@ -0,0 +1,198 @@
+//! Temperature monitor (`thermal`): the whole keyboard shifts from blue to red
+//! as the hottest hwmon temperature sensor climbs from `cool` to `hot`.
+//!
+//! Sensors are read from `/sys/class/hwmon`, optionally limited to chips with
+//! the given names (e.g: `coretemp` / `k10temp` for the CPU, `amdgpu` /
+//! `nouveau` for the GPU).
+
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use fusion_kbd_daemon::SCRATCH_SLOT;
+use fusion_kbd_protocol::correction::Correction;
+use fusion_kbd_protocol::state::{Lighting, State};
+use fusion_kbd_protocol::{self as kbd, effects, Rgb};
+use log::error;
+
+use crate::exit::Failure;
+use crate::{validate_slot, Globals};
+
+const HWMON: &str = "/sys/class/hwmon";
+
+/// how often the sensors are read
+const INTERVAL: Duration = Duration::from_secs(1);
+
+const COOL_COLOR: Rgb = Rgb(0x00, 0x00, 0xff);
+const HOT_COLOR: Rgb = Rgb(0xff, 0x00, 0x00);
+
+pub struct Options {
+    /// hwmon chip names to read (every chip, if empty)
+    pub sensors: Vec<String>,
+    /// temperatures (in °C) at which the keyboard is fully blue / red
+    pub cool: f32,
+    pub hot: f32,
+    pub slot: u8,
+    pub brightness: u8,
+}
+
+/// The hottest reading (in °C) of the chosen chips. Errors list the chips
+/// there are, if none of them match.
+fn hottest(sensors: &[String]) -> Result<f32, String> {
+    let entries = fs::read_dir(HWMON).map_err(|e| format!("couldn't read '{}': {}", HWMON, e))?;
+
+    let mut names = Vec::new();
+    let mut hottest: Option<f32> = None;
+    for entry in entries.flatten() {
+        let dir = entry.path();
+        let name = fs::read_to_string(dir.join("name")).unwrap_or_default();
+        let name = name.trim().to_string();
+        if sensors.is_empty() || sensors.contains(&name) {
+            for temp in temperatures(&dir) {
+                hottest = Some(hottest.map_or(temp, |t| t.max(temp)));
+            }
+        }
+        names.push(name);
+    }
+
+    hottest.ok_or_else(|| {
+        names.sort();
+        names.dedup();
+        format!(
+            "no temperature sensors found (chips: {})",
+            if names.is_empty() {
+                "none".to_string()
+            } else {
+                names.join(", ")
+            }
+        )
+    })
+}
+
+/// every `tempN_input` of a hwmon chip, in °C
+fn temperatures(dir: &Path) -> Vec<f32> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries
+        .flatten()
+        .filter(|e| {
+            let file = e.file_name();
+            let file = file.to_string_lossy();
+            file.starts_with("temp") && file.ends_with("_input")
+        })
+        // in millidegrees
+        .filter_map(|e| {
+            fs::read_to_string(e.path())
+                .ok()?
+                .trim()
+                .parse::<i64>()
+                .ok()
+        })
+        .map(|m| m as f32 / 1000.0)
+        .collect()
+}
+
+fn color(opts: &Options, temp: f32) -> Rgb {
+    let t = ((temp - opts.cool) / (opts.hot - opts.cool)).clamp(0.0, 1.0);
+    effects::blend(COOL_COLOR, HOT_COLOR, t)
+}
+
+/// Follows the temperature until interrupted.
+pub fn run(
+    kbd: &dyn kbd::Keyboard,
+    opts: &Options,
+    correction: &Correction,
+) -> Result<(), libusb::Error> {
+    let mut shown = None;
+    loop {
+        let temp = match hottest(&opts.sensors) {
+            Ok(temp) => temp,
+            Err(e) => {
+                error!("{}", e);
+                return Err(libusb::Error::Other);
+            }
+        };
+
+        let color = correction.rgb(color(opts, temp));
+        if shown != Some(color) {
+            kbd.upload_custom(opts.slot, effects::solid(color).as_bytes())?;
+            kbd.set_custom(opts.slot, opts.brightness)?;
+            shown = Some(color);
+        }
+
+        thread::sleep(INTERVAL);
+    }
+}
+
+#[rustfmt::skip]
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("thermal")
+        .about("Shift from blue to red as the hottest hwmon temperature sensor heats up")
+        .arg(Arg::with_name("sensor")
+            .takes_value(true)
+            .long("sensor")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Only read this hwmon chip, e.g: coretemp, k10temp, amdgpu (repeatable; default: every chip)"))
+        .arg(Arg::with_name("cool")
+            .takes_value(true)
+            .long("cool")
+            .validator(|cstr| {
+                if cstr.parse::<f32>().is_err() {
+                    return Err("cool must be a temperature in °C!".to_string())
+                }
+                Ok(())
+            })
+            .help("Temperature (°C) at or below which the keyboard is blue (default: 40)"))
+        .arg(Arg::with_name("hot")
+            .takes_value(true)
+            .long("hot")
+            .validator(|hstr| {
+                if hstr.parse::<f32>().is_err() {
+                    return Err("hot must be a temperature in °C!".to_string())
+                }
+                Ok(())
+            })
+            .help("Temperature (°C) at or above which the keyboard is red (default: 90)"))
+        .arg(Arg::with_name("slot")
+            .takes_value(true)
+            .short("s")
+            .long("slot")
+            .validator(validate_slot)
+            .help("Custom slot the color is shown through (default: 4)"))
+}
+
+pub fn exec(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let cool = m
+        .value_of("cool")
+        .map_or(40.0, |cstr| cstr.parse::<f32>().unwrap());
+    let hot = m
+        .value_of("hot")
+        .map_or(90.0, |hstr| hstr.parse::<f32>().unwrap());
+    if cool >= hot {
+        error!("--cool must be below --hot");
+        return Err(libusb::Error::InvalidParam.into());
+    }
+    let opts = Options {
+        sensors: m
+            .values_of("sensor")
+            .map_or(Vec::new(), |s| s.map(|s| s.to_string()).collect()),
+        cool,
+        hot,
+        slot: m
+            .value_of("slot")
+            .map_or(SCRATCH_SLOT, |sstr| sstr.parse::<u8>().unwrap()),
+        brightness: g.brightness(),
+    };
+
+    g.with_keyboard(|kbd| Ok(run(kbd, &opts, &g.correction)?))?;
+    g.remember(State {
+        lighting: Lighting::Custom { slot: opts.slot },
+        brightness: opts.brightness,
+    });
+    Ok(())
+}
@@ -0,0 +1,402 @@
+//! Audio visualizer (`visualize`). Whatever is playing is captured with
+//! PipeWire's `pw-record`, and rendered to the lighting matrix in one of the
+//! `STYLES`, streamed through a custom slot like `play` does.
+
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::io::Read;
+use std::process::{Child, Command, Stdio};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use fusion_kbd_daemon::SCRATCH_SLOT;
+use fusion_kbd_protocol::config::{key_position, MATRIX_COLS, MATRIX_ROWS, NUM_KEYS};
+use fusion_kbd_protocol::correction::Correction;
+use fusion_kbd_protocol::effects::{self, FrameClock};
+use fusion_kbd_protocol::state::{Lighting, State};
+use fusion_kbd_protocol::{self as kbd, CustomConfig, Rgb};
+use log::error;
+
+use crate::exit::Failure;
+use crate::{validate_slot, Globals};
+
+/// names of each `Style`
+pub const STYLES: &[&str] = &["bars", "pulse", "beat"];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    /// a spectrum analyser, low frequencies on the left
+    Bars,
+    /// every key following the volume, like a VU meter
+    Pulse,
+    /// every key flashing on each beat
+    Beat,
+}
+
+const RATE: u32 = 48000;
+/// samples analysed per frame (a power of 2, for the FFT)
+const WINDOW: usize = 2048;
+/// range covered by the `bars` style
+const LOWEST_HZ: f32 = 50.0;
+const HIGHEST_HZ: f32 = 16000.0;
+
+/// `bars` colors, from the bottom row up
+const BAR_COLORS: [Rgb; MATRIX_ROWS] = [
+    Rgb(0x00, 0xff, 0x00),
+    Rgb(0x00, 0xff, 0x00),
+    Rgb(0x80, 0xff, 0x00),
+    Rgb(0xff, 0xff, 0x00),
+    Rgb(0xff, 0x80, 0x00),
+    Rgb(0xff, 0x00, 0x00),
+];
+
+pub struct Options {
+    pub style: Style,
+    /// PipeWire node to capture, instead of what's playing on the default
+    /// output (e.g: a microphone)
+    pub source: Option<String>,
+    /// scales how strongly the keyboard reacts (1.0 = default)
+    pub sensitivity: f32,
+    /// used by `pulse` and `beat`
+    pub color: Rgb,
+    pub slot: u8,
+    pub brightness: u8,
+    pub fps: u32,
+}
+
+/// The last `WINDOW` samples captured, filled from a background thread. A slow
+/// keyboard just means frames look at fewer of the samples, rather than the
+/// visualizer lagging behind the audio.
+struct Capture {
+    child: Child,
+    samples: Arc<Mutex<VecDeque<f32>>>,
+}
+
+impl Capture {
+    fn start(source: Option<&str>) -> Result<Capture, String> {
+        let mut cmd = Command::new("pw-record");
+        cmd.arg("--format=s16")
+            .arg(format!("--rate={}", RATE))
+            .arg("--channels=1");
+        match source {
+            Some(source) => cmd.arg(format!("--target={}", source)),
+            // record the default output's monitor, i.e: what's playing
+            None => cmd.arg("--properties={ stream.capture.sink = true }"),
+        };
+        let mut child = cmd
+            .arg("-")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("couldn't run pw-record (is PipeWire installed?): {}", e))?;
+
+        let samples = Arc::new(Mutex::new(VecDeque::from(vec![0.0; WINDOW])));
+        let mut stdout = child.stdout.take().unwrap();
+        let shared = samples.clone();
+        thread::spawn(move || {
+            let mut buf = [0; 1024];
+            // s16le, and a read can end half way through a sample, so its
+            // first byte is kept for the next one
+            let mut pending: Option<u8> = None;
+            while let Ok(len) = stdout.read(&mut buf) {
+                if len == 0 {
+                    break;
+                }
+                let mut samples = shared.lock().unwrap();
+                for &byte in &buf[..len] {
+                    match pending.take() {
+                        Some(low) => {
+                            samples.pop_front();
+                            samples.push_back(i16::from_le_bytes([low, byte]) as f32 / 32768.0);
+                        }
+                        None => pending = Some(byte),
+                    }
+                }
+            }
+        });
+
+        Ok(Capture { child, samples })
+    }
+
+    /// `None` once pw-record has exited
+    fn window(&mut self) -> Option<Vec<f32>> {
+        match self.child.try_wait() {
+            Ok(None) => Some(self.samples.lock().unwrap().iter().copied().collect()),
+            _ => None,
+        }
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// in-place radix-2 FFT of (re, im) pairs. `buf.len()` must be a power of 2.
+fn fft(buf: &mut [(f32, f32)]) {
+    let n = buf.len();
+
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            buf.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (wr, wi) = ((angle * k as f32).cos(), (angle * k as f32).sin());
+                let (ar, ai) = buf[start + k];
+                let (br, bi) = buf[start + k + len / 2];
+                let (tr, ti) = (br * wr - bi * wi, br * wi + bi * wr);
+                buf[start + k] = (ar + tr, ai + ti);
+                buf[start + k + len / 2] = (ar - tr, ai - ti);
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// loudness of `MATRIX_COLS` log-spaced frequency bands
+fn spectrum(samples: &[f32]) -> Vec<f32> {
+    let n = samples.len();
+    // Hann window, to keep loud bands from smearing into their neighbours
+    let mut buf: Vec<(f32, f32)> = samples
+        .iter()
+        .enumerate()
+        .map(|(i, s)| {
+            (
+                s * (0.5 - 0.5 * (2.0 * PI * i as f32 / n as f32).cos()),
+                0.0,
+            )
+        })
+        .collect();
+    fft(&mut buf);
+
+    let bin = |hz: f32| ((hz * n as f32 / RATE as f32) as usize).min(n / 2 - 1);
+    (0..MATRIX_COLS)
+        .map(|band| {
+            let edge =
+                |i: usize| LOWEST_HZ * (HIGHEST_HZ / LOWEST_HZ).powf(i as f32 / MATRIX_COLS as f32);
+            let lo = bin(edge(band));
+            let hi = bin(edge(band + 1)).max(lo + 1);
+            buf[lo..hi]
+                .iter()
+                .map(|(re, im)| (re * re + im * im).sqrt())
+                .fold(0.0, f32::max)
+        })
+        .collect()
+}
+
+fn rms(samples: &[f32]) -> f32 {
+    (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+/// Renders audio into frames. Levels are normalized against a slowly decaying
+/// peak, so quiet and loud tracks both use the whole keyboard.
+struct Visualizer {
+    opts: Options,
+    peak: f32,
+    /// what's currently shown (bar heights from 0 - 1, or the flash / pulse)
+    levels: Vec<f32>,
+    /// running average of the bass, for `beat`
+    bass: f32,
+}
+
+impl Visualizer {
+    fn new(opts: Options) -> Visualizer {
+        Visualizer {
+            opts,
+            peak: 1e-3,
+            levels: vec![0.0; MATRIX_COLS],
+            bass: 0.0,
+        }
+    }
+
+    /// `value` relative to the recent peak, from 0 - 1
+    fn normalize(&mut self, value: f32) -> f32 {
+        self.peak = (self.peak * 0.995).max(value).max(1e-3);
+        (value / self.peak * self.opts.sensitivity).min(1.0)
+    }
+
+    fn frame(&mut self, samples: &[f32]) -> CustomConfig {
+        match self.opts.style {
+            Style::Bars => {
+                let bands = spectrum(samples);
+                let loudest = bands.iter().copied().fold(0.0, f32::max);
+                self.normalize(loudest);
+                for (level, band) in self.levels.iter_mut().zip(bands) {
+                    // bars jump up, but fall gradually
+                    let target = (band / self.peak * self.opts.sensitivity).sqrt().min(1.0);
+                    *level = target.max(*level - 0.08);
+                }
+
+                let mut cfg = CustomConfig::new();
+                for key in 0..NUM_KEYS {
+                    let (row, col) = key_position(key);
+                    let height = MATRIX_ROWS - row;
+                    if self.levels[col] * MATRIX_ROWS as f32 >= height as f32 - 0.5 {
+                        cfg.set_key(key, BAR_COLORS[height - 1]);
+                    }
+                }
+                cfg
+            }
+            Style::Pulse => {
+                let level = self.normalize(rms(samples));
+                self.levels[0] = level.max(self.levels[0] * 0.85);
+                effects::solid(effects::blend(
+                    Rgb(0, 0, 0),
+                    self.opts.color,
+                    self.levels[0],
+                ))
+            }
+            Style::Beat => {
+                let bands = spectrum(samples);
+                let bass = bands[..3].iter().sum::<f32>();
+                // a beat is a jump well above the recent average. Higher
+                // sensitivities need less of a jump.
+                let threshold = 1.0 + 0.6 / self.opts.sensitivity.max(0.1);
+                let beat = bass > self.bass * threshold && bass > 0.05;
+                self.bass = self.bass * 0.9 + bass * 0.1;
+
+                self.levels[0] = if beat { 1.0 } else { self.levels[0] * 0.75 };
+                effects::solid(effects::blend(
+                    Rgb(0, 0, 0),
+                    self.opts.color,
+                    self.levels[0],
+                ))
+            }
+        }
+    }
+}
+
+/// Visualizes audio until interrupted (or pw-record exits).
+pub fn run(
+    kbd: &dyn kbd::Keyboard,
+    opts: Options,
+    correction: &Correction,
+) -> Result<(), libusb::Error> {
+    let mut capture = match Capture::start(opts.source.as_deref()) {
+        Ok(capture) => capture,
+        Err(e) => {
+            error!("{}", e);
+            return Err(libusb::Error::Other);
+        }
+    };
+
+    let (slot, brightness) = (opts.slot, opts.brightness);
+    let delay = Duration::from_secs(1) / opts.fps;
+    let mut visualizer = Visualizer::new(opts);
+    let mut clock = FrameClock::new();
+    loop {
+        let samples = match capture.window() {
+            Some(samples) => samples,
+            None => {
+                error!("pw-record stopped capturing");
+                return Err(libusb::Error::Other);
+            }
+        };
+
+        let cfg = correction.apply(&visualizer.frame(&samples));
+        kbd.upload_custom(slot, cfg.as_bytes())?;
+        kbd.set_custom(slot, brightness)?;
+
+        // skip whole frames if the upload took too long
+        clock.wait(delay);
+        while clock.is_late(delay) {
+            clock.skip(delay);
+        }
+    }
+}
+
+#[rustfmt::skip]
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("visualize")
+        .about("React to whatever is playing (captured with PipeWire's pw-record)")
+        .arg(Arg::with_name("style")
+            .index(1)
+            .possible_values(STYLES)
+            .help("bars: spectrum analyser, pulse: follow the volume, beat: flash on beats (default: bars)"))
+        .arg(Arg::with_name("source")
+            .takes_value(true)
+            .long("source")
+            .value_name("NODE")
+            .help("PipeWire node to capture, e.g: a microphone (default: what's playing)"))
+        .arg(Arg::with_name("sensitivity")
+            .takes_value(true)
+            .long("sensitivity")
+            .validator(|sstr| match sstr.parse::<f32>() {
+                Ok(s) if s > 0.0 => Ok(()),
+                _ => Err("sensitivity must be a positive number!".to_string()),
+            })
+            .help("How strongly to react, e.g: 2 for quiet music (default: 1)"))
+        .arg(Arg::with_name("color")
+            .takes_value(true)
+            .short("c")
+            .long("color")
+            .validator(|cstr| kbd::Rgb::from_str(&cstr).map(|_| ()))
+            .help("Color used by pulse and beat (default: white)"))
+        .arg(Arg::with_name("slot")
+            .takes_value(true)
+            .short("s")
+            .long("slot")
+            .validator(validate_slot)
+            .help("Custom slot frames are streamed through (default: 4)"))
+        .arg(Arg::with_name("fps")
+            .takes_value(true)
+            .long("fps")
+            .validator(|fstr| {
+                let fval = fstr.parse::<u32>();
+                if fval.is_err() || fval.unwrap() == 0 {
+                    return Err("fps must be a positive number!".to_string())
+                }
+                Ok(())
+            })
+            .help("Frames per second to aim for (default: 30)"))
+}
+
+pub fn exec(m: &ArgMatches, g: &Globals) -> Result<(), Failure> {
+    let opts = Options {
+        style: match m.value_of("style") {
+            Some("pulse") => Style::Pulse,
+            Some("beat") => Style::Beat,
+            _ => Style::Bars,
+        },
+        source: m.value_of("source").map(|s| s.to_string()),
+        sensitivity: m
+            .value_of("sensitivity")
+            .map_or(1.0, |sstr| sstr.parse::<f32>().unwrap()),
+        color: m
+            .value_of("color")
+            .map_or(Rgb(0xff, 0xff, 0xff), |cstr| Rgb::from_str(cstr).unwrap()),
+        slot: m
+            .value_of("slot")
+            .map_or(SCRATCH_SLOT, |sstr| sstr.parse::<u8>().unwrap()),
+        brightness: g.brightness(),
+        fps: m
+            .value_of("fps")
+            .map_or(30, |fstr| fstr.parse::<u32>().unwrap()),
+    };
+
+    let state = State {
+        lighting: Lighting::Custom { slot: opts.slot },
+        brightness: opts.brightness,
+    };
+    g.with_keyboard(|kbd| Ok(run(kbd, opts, &g.correction)?))?;
+    g.remember(state);
+    Ok(())
+}
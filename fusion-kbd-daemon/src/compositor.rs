@@ -0,0 +1,240 @@
+//! Layers drawn over the custom slots (see `fusion_kbd_protocol::layers`), so
+//! indicators (`locks`) and effects (`reactive`) don't clobber the lighting
+//! they're drawn on.
+//!
+//! Every write the daemon makes goes through a `Compositor`, which remembers
+//! what was last uploaded to each slot (its base), and uploads the base with
+//! the layers on top instead. Downloads get the base back, so e.g: `custom 2
+//! --get` never picks up an indicator. Integrations add and remove layers
+//! through `Layers` (see `Server::layers`), which has the showing slot
+//! redrawn.
+//!
+//! Slots the daemon hasn't uploaded to are downloaded the first time a layer
+//! has to go over them.
+
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::{self, HashMap};
+use std::sync::{Arc, Mutex};
+
+use fusion_kbd_protocol::layers::{self, Layer};
+use fusion_kbd_protocol::protocol::Direction;
+use fusion_kbd_protocol::state::Lighting;
+use fusion_kbd_protocol::{Capabilities, Color, CustomConfig, Keyboard, Preset};
+
+use crate::saved;
+
+struct Entry {
+    name: String,
+    /// `None` for every slot
+    slot: Option<u8>,
+    layer: Layer,
+}
+
+/// The layers over the slots, in stacking order (bottom first). Clones share
+/// the same stack.
+#[derive(Clone)]
+pub struct Layers {
+    entries: Arc<Mutex<Vec<Entry>>>,
+    /// has the showing slot redrawn
+    changed: Arc<dyn Fn() + Send + Sync>,
+}
+
+impl Layers {
+    pub(crate) fn new(changed: impl Fn() + Send + Sync + 'static) -> Layers {
+        Layers {
+            entries: Arc::new(Mutex::new(Vec::new())),
+            changed: Arc::new(changed),
+        }
+    }
+
+    /// Puts `layer` over `slot` (or every slot, if `None`) as `name`. A layer
+    /// that's already there by that name is replaced, keeping its place in
+    /// the stack; otherwise, it goes on top.
+    pub fn set(&self, name: &str, slot: Option<u8>, layer: Layer) {
+        {
+            let mut entries = self.entries.lock().unwrap();
+            match entries.iter_mut().find(|e| e.name == name) {
+                Some(entry) => {
+                    entry.slot = slot;
+                    entry.layer = layer;
+                }
+                None => entries.push(Entry {
+                    name: name.to_string(),
+                    slot,
+                    layer,
+                }),
+            }
+        }
+        (self.changed)();
+    }
+
+    pub fn remove(&self, name: &str) {
+        let removed = {
+            let mut entries = self.entries.lock().unwrap();
+            let before = entries.len();
+            entries.retain(|e| e.name != name);
+            entries.len() != before
+        };
+        if removed {
+            (self.changed)();
+        }
+    }
+
+    /// `base`, with every layer over `slot` on top
+    fn composite(&self, slot: u8, base: &CustomConfig) -> CustomConfig {
+        let entries = self.entries.lock().unwrap();
+        layers::composite(
+            base,
+            entries
+                .iter()
+                .filter(|e| e.slot.is_none_or(|s| s == slot))
+                .map(|e| &e.layer),
+        )
+    }
+
+    fn any_over(&self, slot: u8) -> bool {
+        let entries = self.entries.lock().unwrap();
+        entries.iter().any(|e| e.slot.is_none_or(|s| s == slot))
+    }
+}
+
+/// what's known about one slot
+struct Slot {
+    base: CustomConfig,
+    /// what's actually uploaded (the base, composited)
+    uploaded: CustomConfig,
+}
+
+/// A `Keyboard` that composites `Layers` into every upload to `kbd`.
+pub struct Compositor<'a> {
+    kbd: &'a dyn Keyboard,
+    layers: Layers,
+    slots: RefCell<HashMap<u8, Slot>>,
+    /// the custom slot showing, and its brightness
+    showing: Cell<Option<(u8, u8)>>,
+}
+
+impl<'a> Compositor<'a> {
+    pub fn new(kbd: &'a dyn Keyboard, layers: Layers) -> Compositor<'a> {
+        // probably what the keyboard is showing, if the daemon is restarting
+        let showing = match saved::load() {
+            Some(saved) if !saved.off => match saved.state.lighting {
+                Lighting::Custom { slot } => Some((slot, saved.state.brightness)),
+                Lighting::Preset { .. } => None,
+            },
+            _ => None,
+        };
+        Compositor {
+            kbd,
+            layers,
+            slots: RefCell::new(HashMap::new()),
+            showing: Cell::new(showing),
+        }
+    }
+
+    /// Brings what's uploaded to `slot` up to date with its layers. Returns
+    /// whether anything was uploaded.
+    fn refresh(&self, slot: u8) -> Result<bool, libusb::Error> {
+        let mut slots = self.slots.borrow_mut();
+        let known = match slots.entry(slot) {
+            hash_map::Entry::Occupied(known) => known.into_mut(),
+            // nothing to draw over it, so no need to know what's there
+            hash_map::Entry::Vacant(_) if !self.layers.any_over(slot) => return Ok(false),
+            hash_map::Entry::Vacant(unknown) => {
+                let base = self.kbd.download_config(slot)?;
+                unknown.insert(Slot {
+                    uploaded: base.clone(),
+                    base,
+                })
+            }
+        };
+        let cfg = self.layers.composite(slot, &known.base);
+        if cfg.as_bytes() == known.uploaded.as_bytes() {
+            return Ok(false);
+        }
+        self.kbd.upload_custom(slot, cfg.as_bytes())?;
+        known.uploaded = cfg;
+        Ok(true)
+    }
+
+    /// Redraws the showing slot, after its layers have changed.
+    pub fn redraw(&self) -> Result<(), libusb::Error> {
+        match self.showing.get() {
+            Some((slot, brightness)) if self.refresh(slot)? => {
+                self.kbd.set_custom(slot, brightness)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Keyboard for Compositor<'_> {
+    fn capabilities(&self) -> Capabilities {
+        self.kbd.capabilities()
+    }
+
+    fn set_preset(
+        &self,
+        preset: Preset,
+        speed: u8,
+        brightness: u8,
+        color: Color,
+    ) -> Result<(), libusb::Error> {
+        self.kbd.set_preset(preset, speed, brightness, color)?;
+        self.showing.set(None);
+        Ok(())
+    }
+
+    fn set_preset_direction(
+        &self,
+        preset: Preset,
+        speed: u8,
+        brightness: u8,
+        color: Color,
+        direction: Direction,
+    ) -> Result<(), libusb::Error> {
+        self.kbd
+            .set_preset_direction(preset, speed, brightness, color, direction)?;
+        self.showing.set(None);
+        Ok(())
+    }
+
+    fn download_custom(&self, slot: u8, data: &mut [u8]) -> Result<(), libusb::Error> {
+        match self.slots.borrow().get(&slot) {
+            // the keyboard's the one to complain about the wrong length
+            Some(known) if data.len() == known.base.as_bytes().len() => {
+                data.copy_from_slice(known.base.as_bytes());
+                Ok(())
+            }
+            _ => self.kbd.download_custom(slot, data),
+        }
+    }
+
+    fn upload_custom(&self, slot: u8, data: &[u8]) -> Result<(), libusb::Error> {
+        if data.len() != self.kbd.capabilities().config_len {
+            self.slots.borrow_mut().remove(&slot);
+            return self.kbd.upload_custom(slot, data);
+        }
+        let base = CustomConfig::from_bytes(data.to_vec());
+
+        let cfg = self.layers.composite(slot, &base);
+        // forgotten until it's uploaded, in case the upload fails half way
+        self.slots.borrow_mut().remove(&slot);
+        self.kbd.upload_custom(slot, cfg.as_bytes())?;
+        self.slots.borrow_mut().insert(
+            slot,
+            Slot {
+                base,
+                uploaded: cfg,
+            },
+        );
+        Ok(())
+    }
+
+    fn set_custom(&self, slot: u8, brightness: u8) -> Result<(), libusb::Error> {
+        self.refresh(slot)?;
+        self.kbd.set_custom(slot, brightness)?;
+        self.showing.set(Some((slot, brightness)));
+        Ok(())
+    }
+}
@@ -0,0 +1,560 @@
+//! The daemon's control socket, so CLI invocations can go through the daemon
+//! rather than claiming the device themselves (which is slow, and makes the
+//! keyboard hiccup every time).
+//!
+//! The socket is `runtime_dir()/control.sock`. Each request is a line of JSON
+//! naming a `Keyboard` operation, and is answered by a line of JSON:
+//!
+//! ```text
+//! > {"op":"set_custom","slot":2,"brightness":16}
+//! < {"ok":true}
+//! > {"op":"download_custom","slot":2}
+//! < {"ok":true,"data":"00ff00..."}
+//! > {"op":"set_custom","slot":9,"brightness":16}
+//! < {"error":"InvalidParam","message":"slot must be from 0 - 4"}
+//! ```
+//!
+//! `data` is the config, as hex, and is as long as the keyboard's
+//! `config_len` capability says. Errors are named after
+//! `libusb::Error` variants.
+
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+
+use fusion_kbd_protocol::protocol::{Direction, MAX_BRIGHTNESS, MAX_SPEED, NUM_SLOTS};
+use fusion_kbd_protocol::{Capabilities, Color, Keyboard, Preset};
+use log::{error, warn};
+use serde_json::{json, Value};
+
+use crate::compositor::{Compositor, Layers};
+use crate::paths;
+use crate::scheduler;
+
+fn socket_path() -> io::Result<PathBuf> {
+    Ok(paths::runtime_dir()?.join("control.sock"))
+}
+
+enum Op {
+    Capabilities,
+    SetPreset {
+        preset: Preset,
+        speed: u8,
+        brightness: u8,
+        color: Color,
+        direction: Option<Direction>,
+    },
+    DownloadCustom {
+        slot: u8,
+    },
+    UploadCustom {
+        slot: u8,
+        data: Vec<u8>,
+    },
+    SetCustom {
+        slot: u8,
+        brightness: u8,
+    },
+}
+
+impl Op {
+    fn to_json(&self) -> Value {
+        match *self {
+            Op::Capabilities => json!({"op": "capabilities"}),
+            Op::SetPreset {
+                preset,
+                speed,
+                brightness,
+                color,
+                direction,
+            } => {
+                let mut op = json!({
+                    "op": "set_preset",
+                    "preset": preset.to_string(),
+                    "speed": speed,
+                    "brightness": brightness,
+                    "color": color.to_string(),
+                });
+                if let Some(direction) = direction {
+                    op["direction"] = direction.to_string().into();
+                }
+                op
+            }
+            Op::DownloadCustom { slot } => json!({"op": "download_custom", "slot": slot}),
+            Op::UploadCustom { slot, ref data } => json!({
+                "op": "upload_custom",
+                "slot": slot,
+                "data": to_hex(data),
+            }),
+            Op::SetCustom { slot, brightness } => json!({
+                "op": "set_custom",
+                "slot": slot,
+                "brightness": brightness,
+            }),
+        }
+    }
+
+    /// parses (and range checks, since the driver asserts) a request
+    fn from_json(value: &Value) -> Result<Op, String> {
+        let number = |name: &str, max: u8| -> Result<u8, String> {
+            match value.get(name).and_then(|n| n.as_u64()) {
+                Some(n) if n <= max as u64 => Ok(n as u8),
+                _ => Err(format!("{} must be from 0 - {}", name, max)),
+            }
+        };
+        let string = |name: &str| -> Result<&str, String> {
+            value
+                .get(name)
+                .and_then(|s| s.as_str())
+                .ok_or_else(|| format!("{} must be a string", name))
+        };
+        let slot = || number("slot", NUM_SLOTS - 1);
+
+        match string("op")? {
+            "capabilities" => Ok(Op::Capabilities),
+            "set_preset" => Ok(Op::SetPreset {
+                preset: Preset::from_str(string("preset")?).map_err(|e| e.to_string())?,
+                speed: number("speed", MAX_SPEED)?,
+                brightness: number("brightness", MAX_BRIGHTNESS)?,
+                color: Color::from_str(string("color")?).map_err(|e| e.to_string())?,
+                direction: match value.get("direction") {
+                    Some(_) => {
+                        Some(Direction::from_str(string("direction")?).map_err(|e| e.to_string())?)
+                    }
+                    None => None,
+                },
+            }),
+            "download_custom" => Ok(Op::DownloadCustom { slot: slot()? }),
+            "upload_custom" => {
+                // the length is the keyboard's to check, since it depends on
+                // the model
+                let data = from_hex(string("data")?).ok_or("data must be hex")?;
+                Ok(Op::UploadCustom {
+                    slot: slot()?,
+                    data,
+                })
+            }
+            "set_custom" => Ok(Op::SetCustom {
+                slot: slot()?,
+                brightness: number("brightness", MAX_BRIGHTNESS)?,
+            }),
+            op => Err(format!("unknown op '{}'", op)),
+        }
+    }
+
+    fn perform(&self, kbd: &dyn Keyboard) -> Result<Value, libusb::Error> {
+        let mut response = json!({"ok": true});
+        match *self {
+            Op::Capabilities => response["capabilities"] = capabilities_json(&kbd.capabilities()),
+            Op::SetPreset {
+                preset,
+                speed,
+                brightness,
+                color,
+                direction: None,
+            } => kbd.set_preset(preset, speed, brightness, color)?,
+            Op::SetPreset {
+                preset,
+                speed,
+                brightness,
+                color,
+                direction: Some(direction),
+            } => kbd.set_preset_direction(preset, speed, brightness, color, direction)?,
+            Op::DownloadCustom { slot } => {
+                let cfg = kbd.download_config(slot)?;
+                response["data"] = to_hex(cfg.as_bytes()).into();
+            }
+            Op::UploadCustom { slot, ref data } => kbd.upload_custom(slot, data)?,
+            Op::SetCustom { slot, brightness } => kbd.set_custom(slot, brightness)?,
+        }
+        Ok(response)
+    }
+}
+
+/// how `capabilities` responses (and `info --format json`) describe `caps`
+pub fn capabilities_json(caps: &Capabilities) -> Value {
+    let presets: Vec<String> = caps.presets.iter().map(|p| p.to_string()).collect();
+    let colors: Vec<String> = caps.preset_colors.iter().map(|c| c.to_string()).collect();
+    json!({
+        "model": caps.model,
+        "presets": presets,
+        "preset_colors": colors,
+        "per_key_rgb": caps.per_key_rgb,
+        "num_slots": caps.num_slots,
+        "num_keys": caps.num_keys,
+        "config_len": caps.config_len,
+        "matrix_rows": caps.matrix_rows,
+        "matrix_cols": caps.matrix_cols,
+        "max_brightness": caps.max_brightness,
+        "max_speed": caps.max_speed,
+    })
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn error_response(e: &libusb::Error, message: &str) -> Value {
+    json!({"error": format!("{:?}", e), "message": message})
+}
+
+fn error_from_name(name: &str) -> libusb::Error {
+    match name {
+        "Io" => libusb::Error::Io,
+        "InvalidParam" => libusb::Error::InvalidParam,
+        "Access" => libusb::Error::Access,
+        "NoDevice" => libusb::Error::NoDevice,
+        "NotFound" => libusb::Error::NotFound,
+        "Busy" => libusb::Error::Busy,
+        "Timeout" => libusb::Error::Timeout,
+        "Overflow" => libusb::Error::Overflow,
+        "Pipe" => libusb::Error::Pipe,
+        "Interrupted" => libusb::Error::Interrupted,
+        "NoMem" => libusb::Error::NoMem,
+        "NotSupported" => libusb::Error::NotSupported,
+        _ => libusb::Error::Other,
+    }
+}
+
+/// something for the daemon to get to
+enum Message {
+    /// from a socket client, waiting for a reply
+    Request { op: Op, reply: mpsc::Sender<Value> },
+    /// from one of the daemon's own integrations, for the scheduler
+    Submit {
+        source: String,
+        write: Box<scheduler::Write>,
+    },
+    /// the layers changed, so the showing slot needs redrawing
+    Redraw,
+}
+
+/// The daemon's end of the socket. Clients are handled on their own threads,
+/// but their requests are performed by whichever thread calls `serve_until`
+/// (i.e: the one that owns the device).
+pub struct Server {
+    messages: mpsc::Receiver<Message>,
+    submit: mpsc::Sender<Message>,
+    layers: Layers,
+}
+
+/// Hands writes to the daemon's scheduler from other threads (see
+/// `Server::submitter`).
+#[derive(Clone)]
+pub struct Submitter(mpsc::Sender<Message>);
+
+impl Submitter {
+    pub fn submit(&self, source: &str, write: scheduler::Write) {
+        let _ = self.0.send(Message::Submit {
+            source: source.to_string(),
+            write: Box::new(write),
+        });
+    }
+}
+
+impl Server {
+    /// Starts listening. Fails with `AddrInUse` if another daemon already is.
+    pub fn bind() -> io::Result<Server> {
+        let path = socket_path()?;
+        if UnixStream::connect(&path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("another daemon is listening on '{}'", path.display()),
+            ));
+        }
+        // left behind by a daemon that didn't exit cleanly
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)?;
+
+        let (submit, messages) = mpsc::channel();
+        let tx = submit.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().filter_map(|s| s.ok()) {
+                let tx = tx.clone();
+                thread::spawn(move || handle(stream, tx));
+            }
+        });
+
+        let redraw = submit.clone();
+        let layers = Layers::new(move || {
+            let _ = redraw.send(Message::Redraw);
+        });
+
+        Ok(Server {
+            messages,
+            submit,
+            layers,
+        })
+    }
+
+    pub fn submitter(&self) -> Submitter {
+        Submitter(self.submit.clone())
+    }
+
+    /// The layers composited over the slots by the `Compositor` passed to
+    /// `serve_until`.
+    pub fn layers(&self) -> Layers {
+        self.layers.clone()
+    }
+
+    /// A `Client` for the daemon's own threads, which skips the socket
+    /// (requests are still performed by `serve_until`).
+    pub fn client(&self) -> Client {
+        Client {
+            transport: Transport::Local(self.submit.clone()),
+        }
+    }
+
+    /// Performs client requests as they come in, until `deadline`, or until a
+    /// write is submitted (which is returned, for the scheduler).
+    pub fn serve_until(
+        &self,
+        kbd: &Compositor,
+        deadline: Instant,
+    ) -> Option<(String, scheduler::Write)> {
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match self.messages.recv_timeout(timeout) {
+                Ok(Message::Request { op, reply }) => {
+                    let response = match op.perform(kbd) {
+                        Ok(response) => response,
+                        Err(e) => error_response(&e, e.strerror()),
+                    };
+                    let _ = reply.send(response);
+                }
+                Ok(Message::Submit { source, write }) => return Some((source, *write)),
+                Ok(Message::Redraw) => {
+                    if let Err(e) = kbd.redraw() {
+                        error!("couldn't redraw layers: {}", e);
+                    }
+                }
+                Err(_) => return None,
+            }
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        if let Ok(path) = socket_path() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// reads requests from one client until it hangs up
+fn handle(stream: UnixStream, messages: mpsc::Sender<Message>) {
+    for line in BufReader::new(&stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+
+        let op = serde_json::from_str(&line)
+            .map_err(|e| e.to_string())
+            .and_then(|v| Op::from_json(&v));
+        let response = match op {
+            Ok(op) => {
+                let (reply, response) = mpsc::channel();
+                if messages.send(Message::Request { op, reply }).is_err() {
+                    return;
+                }
+                match response.recv() {
+                    Ok(response) => response,
+                    Err(_) => return,
+                }
+            }
+            Err(e) => error_response(&libusb::Error::InvalidParam, &e),
+        };
+
+        if writeln!(&stream, "{}", response).is_err() {
+            return;
+        }
+    }
+}
+
+enum Transport {
+    Socket {
+        stream: UnixStream,
+        reader: RefCell<BufReader<UnixStream>>,
+    },
+    /// straight to the `Server`, from inside the daemon
+    Local(mpsc::Sender<Message>),
+}
+
+/// A `Keyboard` that forwards everything to a running daemon.
+pub struct Client {
+    transport: Transport,
+}
+
+impl Client {
+    /// connects to the daemon, if one is running
+    pub fn connect() -> Option<Client> {
+        let path = match socket_path() {
+            Ok(path) => path,
+            Err(e) => {
+                warn!("not looking for a daemon: {}", e);
+                return None;
+            }
+        };
+        let stream = UnixStream::connect(path).ok()?;
+        let reader = RefCell::new(BufReader::new(stream.try_clone().ok()?));
+        Some(Client {
+            transport: Transport::Socket { stream, reader },
+        })
+    }
+
+    fn request(&self, op: Op) -> Result<Value, libusb::Error> {
+        let response: Value = match self.transport {
+            Transport::Socket {
+                ref stream,
+                ref reader,
+            } => {
+                writeln!(&*stream, "{}", op.to_json()).map_err(|_| libusb::Error::Io)?;
+
+                let mut line = String::new();
+                match reader.borrow_mut().read_line(&mut line) {
+                    Ok(n) if n > 0 => {}
+                    _ => return Err(libusb::Error::Io),
+                }
+                serde_json::from_str(&line).map_err(|_| libusb::Error::Io)?
+            }
+            Transport::Local(ref messages) => {
+                let (reply, response) = mpsc::channel();
+                messages
+                    .send(Message::Request { op, reply })
+                    .map_err(|_| libusb::Error::Io)?;
+                response.recv().map_err(|_| libusb::Error::Io)?
+            }
+        };
+
+        match response["error"].as_str() {
+            Some(name) => Err(error_from_name(name)),
+            None => Ok(response),
+        }
+    }
+}
+
+impl Keyboard for Client {
+    fn capabilities(&self) -> Capabilities {
+        // `Keyboard::capabilities` can't fail, so all that's left is to say so
+        let response = self.request(Op::Capabilities).unwrap_or_else(|e| {
+            warn!("couldn't ask the daemon what the keyboard supports: {}", e);
+            Value::default()
+        });
+        let caps = &response["capabilities"];
+        let number = |name: &str| caps[name].as_u64().unwrap_or(0);
+        let names = |name: &str| -> Vec<String> {
+            caps[name]
+                .as_array()
+                .map(|a| {
+                    a.iter()
+                        .filter_map(|s| s.as_str())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        Capabilities {
+            model: caps["model"].as_str().unwrap_or("unknown").to_string(),
+            presets: names("presets")
+                .iter()
+                .filter_map(|p| Preset::from_str(p).ok())
+                .collect(),
+            preset_colors: names("preset_colors")
+                .iter()
+                .filter_map(|c| Color::from_str(c).ok())
+                .collect(),
+            per_key_rgb: caps["per_key_rgb"].as_bool().unwrap_or(false),
+            num_slots: number("num_slots") as u8,
+            num_keys: number("num_keys") as usize,
+            config_len: number("config_len") as usize,
+            matrix_rows: number("matrix_rows") as usize,
+            matrix_cols: number("matrix_cols") as usize,
+            max_brightness: number("max_brightness") as u8,
+            max_speed: number("max_speed") as u8,
+        }
+    }
+
+    fn set_preset(
+        &self,
+        preset: Preset,
+        speed: u8,
+        brightness: u8,
+        color: Color,
+    ) -> Result<(), libusb::Error> {
+        self.request(Op::SetPreset {
+            preset,
+            speed,
+            brightness,
+            color,
+            direction: None,
+        })
+        .map(|_| ())
+    }
+
+    fn set_preset_direction(
+        &self,
+        preset: Preset,
+        speed: u8,
+        brightness: u8,
+        color: Color,
+        direction: Direction,
+    ) -> Result<(), libusb::Error> {
+        self.request(Op::SetPreset {
+            preset,
+            speed,
+            brightness,
+            color,
+            direction: Some(direction),
+        })
+        .map(|_| ())
+    }
+
+    fn download_custom(&self, slot: u8, data: &mut [u8]) -> Result<(), libusb::Error> {
+        let response = self.request(Op::DownloadCustom { slot })?;
+        let bytes = response["data"]
+            .as_str()
+            .and_then(from_hex)
+            .ok_or(libusb::Error::Io)?;
+        if bytes.len() != data.len() {
+            error!(
+                "Custom configs for the daemon's keyboard are {} bytes (not {})",
+                bytes.len(),
+                data.len()
+            );
+            return Err(libusb::Error::InvalidParam);
+        }
+        data.copy_from_slice(&bytes);
+        Ok(())
+    }
+
+    fn upload_custom(&self, slot: u8, data: &[u8]) -> Result<(), libusb::Error> {
+        self.request(Op::UploadCustom {
+            slot,
+            data: data.to_vec(),
+        })
+        .map(|_| ())
+    }
+
+    fn set_custom(&self, slot: u8, brightness: u8) -> Result<(), libusb::Error> {
+        self.request(Op::SetCustom { slot, brightness }).map(|_| ())
+    }
+}
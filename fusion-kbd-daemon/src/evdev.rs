@@ -0,0 +1,69 @@
+//! Reading input events straight from `/dev/input` (which needs root, or the
+//! `input` group), for `reactive` and `locks`.
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+
+/// key presses / releases (see linux/input-event-codes.h)
+pub const EV_KEY: u16 = 0x01;
+/// keyboard LEDs turning on / off
+pub const EV_LED: u16 = 0x11;
+
+pub const LED_NUML: u16 = 0x00;
+pub const LED_CAPSL: u16 = 0x01;
+
+/// a `struct input_event`, minus the timestamp
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub kind: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+/// every keyboard udev knows about, de-duplicated
+pub fn keyboards() -> Vec<PathBuf> {
+    let mut devices: Vec<PathBuf> = fs::read_dir("/dev/input/by-path")
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.to_string_lossy().ends_with("-event-kbd"))
+        .filter_map(|p| fs::canonicalize(p).ok())
+        .collect();
+    devices.sort();
+    devices.dedup();
+    devices
+}
+
+/// Forwards every event from `device` to `events`, until the device goes away
+/// (or `events` is hung up). Only opening the device can fail.
+pub fn listen(device: PathBuf, events: mpsc::Sender<Event>) -> Result<(), String> {
+    let mut file =
+        File::open(&device).map_err(|e| format!("couldn't open '{}': {}", device.display(), e))?;
+
+    thread::spawn(move || {
+        // struct input_event: a timeval, then u16 type, u16 code, i32 value
+        let time_len = 2 * std::mem::size_of::<usize>();
+        let mut event = vec![0; time_len + 8];
+        while file.read_exact(&mut event).is_ok() {
+            let field = |i: usize| [event[time_len + i], event[time_len + i + 1]];
+            let event = Event {
+                kind: u16::from_ne_bytes(field(0)),
+                code: u16::from_ne_bytes(field(2)),
+                value: i32::from_ne_bytes([
+                    event[time_len + 4],
+                    event[time_len + 5],
+                    event[time_len + 6],
+                    event[time_len + 7],
+                ]),
+            };
+            if events.send(event).is_err() {
+                return;
+            }
+        }
+    });
+    Ok(())
+}
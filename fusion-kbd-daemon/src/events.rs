@@ -0,0 +1,92 @@
+//! Lighting change notifications.
+//!
+//! Every subscriber binds a datagram socket in `runtime_dir()/subscribers/`.
+//! Whenever the lighting changes, an event is sent to each of them as a single
+//! line of JSON, e.g:
+//!
+//! `{"source":"cli","mode":"custom","slot":2,"brightness":16}`
+
+use std::fs;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use fusion_kbd_protocol::state::State;
+
+use crate::paths;
+
+fn subscribers_dir() -> io::Result<PathBuf> {
+    Ok(paths::runtime_dir()?.join("subscribers"))
+}
+
+/// Notifies all subscribers of a lighting change. Best-effort: failures are
+/// silently ignored, and sockets left behind by dead subscribers are removed.
+pub fn publish(source: &str, state: &State) {
+    let mut event = state.to_json();
+    event["source"] = source.into();
+    let line = event.to_string() + "\n";
+
+    let entries = match subscribers_dir().and_then(fs::read_dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    let sock = match UnixDatagram::unbound() {
+        Ok(sock) => sock,
+        Err(_) => return,
+    };
+
+    for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        if let Err(e) = sock.send_to(line.as_bytes(), &path) {
+            if e.kind() == io::ErrorKind::ConnectionRefused {
+                let _ = fs::remove_file(&path);
+            }
+        }
+    }
+}
+
+/// A bound subscriber socket. It's removed again when dropped.
+pub struct Subscriber {
+    sock: UnixDatagram,
+    path: PathBuf,
+}
+
+impl Subscriber {
+    pub fn bind() -> io::Result<Subscriber> {
+        let dir = subscribers_dir()?;
+        fs::create_dir_all(&dir)?;
+
+        // numbered, since a process can have several
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!("{}-{}.sock", process::id(), n));
+        let _ = fs::remove_file(&path);
+        let sock = UnixDatagram::bind(&path)?;
+        Ok(Subscriber { sock, path })
+    }
+
+    /// waits for the next event, giving up after `timeout` (if given)
+    pub fn recv(&self, timeout: Option<Duration>) -> io::Result<String> {
+        self.sock.set_read_timeout(timeout)?;
+        let mut buf = [0; 4096];
+        let n = self.sock.recv(&mut buf)?;
+        Ok(String::from_utf8_lossy(&buf[..n]).into_owned())
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Prints lighting change events to stdout until killed
+pub fn subscribe() -> io::Result<()> {
+    let subscriber = Subscriber::bind()?;
+    loop {
+        print!("{}", subscriber.recv(None)?);
+    }
+}
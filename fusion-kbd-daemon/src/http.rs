@@ -0,0 +1,416 @@
+//! Optional HTTP API (`--http 127.0.0.1:9123`), so scripts and home automation
+//! on other machines can drive the keyboard. There's no authentication, so
+//! only listen on addresses you trust.
+//!
+//! - `GET /state`: the current lighting (see `saved`)
+//! - `PUT /brightness`: `{"brightness": 20}`
+//! - `PUT /preset`: `{"preset": "wave", "color": "red", "speed": 5}`. `color`,
+//!   `speed`, and `brightness` are optional.
+//! - `POST /custom/{slot}`: upload a config to the slot and switch to it. The
+//!   body is a JSON profile (see `config::json`), a PNG (`image/png`), or a raw
+//!   / `.fkp` config (`application/octet-stream`). An empty body just switches
+//!   to the slot.
+//!
+//! Changes are queued with the scheduler (as source `http`), and answered with
+//! `202 Accepted` and the resulting state. Errors are `{"error": "..."}`.
+
+use std::fmt::Display;
+use std::io::{self, BufRead, BufReader, Read, Write as _};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use fusion_kbd_protocol::config::{self, container::Container, Format};
+use fusion_kbd_protocol::correction::Correction;
+use fusion_kbd_protocol::protocol::{MAX_BRIGHTNESS, MAX_SPEED, NUM_SLOTS};
+use fusion_kbd_protocol::state::{Lighting, State};
+use fusion_kbd_protocol::{Color, Keymap, Preset};
+use serde_json::{json, Value};
+
+use crate::control::Submitter;
+use crate::saved;
+use crate::scheduler::Write;
+
+/// larger bodies are refused (a PNG of the lighting matrix is tiny)
+const MAX_BODY: usize = 64 * 1024;
+/// the request line and headers, together, can't be any longer than this
+const MAX_HEAD: usize = 8 * 1024;
+/// how long a client gets to send its whole request (or read the reply)
+/// before it's hung up on, however slowly it trickles in
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// connections served at once. Any more are turned away, rather than each
+/// getting a thread
+const MAX_CONNECTIONS: usize = 16;
+
+/// status code + JSON body
+type Reply = (u16, Value);
+
+fn error(status: u16, message: impl Display) -> Reply {
+    (status, json!({"error": message.to_string()}))
+}
+
+struct Request {
+    method: String,
+    path: String,
+    content_type: Option<String>,
+    body: Vec<u8>,
+}
+
+pub struct Api {
+    pub submitter: Submitter,
+    /// for JSON profiles
+    pub keymap: Keymap,
+    pub correction: Correction,
+    /// used until something sets the brightness
+    pub default_brightness: u8,
+}
+
+/// Starts serving `api` on `addr` in the background. Only binding can fail.
+pub fn serve(addr: SocketAddr, api: Api) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    let api = Arc::new(api);
+    let connections = Arc::new(AtomicUsize::new(0));
+    thread::spawn(move || {
+        // each connection gets its own thread, so a slow client only holds up
+        // itself
+        for stream in listener.incoming().filter_map(|s| s.ok()) {
+            if stream.set_write_timeout(Some(TIMEOUT)).is_err() {
+                continue;
+            }
+            if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                connections.fetch_sub(1, Ordering::SeqCst);
+                let _ = respond(&stream, error(503, "too many requests at once"));
+                continue;
+            }
+
+            let (api, connections) = (api.clone(), connections.clone());
+            thread::spawn(move || {
+                let reply = match read_request(&stream, Instant::now() + TIMEOUT) {
+                    Ok(req) => api.route(&req),
+                    Err(e) => e,
+                };
+                let _ = respond(&stream, reply);
+                connections.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Reads from a client until `deadline`, however it's paced: before each read,
+/// the timeout is set to whatever time is left.
+struct Deadline<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Read for Deadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if left == Duration::ZERO {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        self.stream.read(buf)
+    }
+}
+
+/// reads a line of the request's head, refusing the request once the head
+/// has gone over `MAX_HEAD`
+fn read_line<R: BufRead>(head: &mut io::Take<R>, line: &mut String) -> Result<usize, Reply> {
+    line.clear();
+    let n = head.read_line(line).map_err(bad)?;
+    if n > 0 && !line.ends_with('\n') {
+        if head.limit() == 0 {
+            return Err(error(
+                431,
+                format!("request heads are limited to {} bytes", MAX_HEAD),
+            ));
+        }
+        return Err(error(400, "the request ended mid-line"));
+    }
+    Ok(n)
+}
+
+fn bad(e: io::Error) -> Reply {
+    match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+            error(408, "timed out waiting for the request")
+        }
+        _ => error(400, e),
+    }
+}
+
+/// reads a request, which has to have all arrived by `deadline`
+fn read_request(stream: &TcpStream, deadline: Instant) -> Result<Request, Reply> {
+    let mut reader = BufReader::new(Deadline { stream, deadline });
+    let mut head = (&mut reader).take(MAX_HEAD as u64);
+
+    let mut line = String::new();
+    read_line(&mut head, &mut line)?;
+    let mut parts = line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Err(error(400, "malformed request line")),
+    };
+
+    let mut content_length = 0;
+    let mut content_type = None;
+    loop {
+        if read_line(&mut head, &mut line)? == 0 {
+            break;
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let (name, value) = match header.split_once(':') {
+            Some((name, value)) => (name.trim().to_lowercase(), value.trim()),
+            None => return Err(error(400, "malformed header")),
+        };
+        match name.as_str() {
+            "content-length" => {
+                content_length = value
+                    .parse::<usize>()
+                    .map_err(|_| error(400, "bad Content-Length"))?
+            }
+            "content-type" => content_type = Some(value.to_lowercase()),
+            _ => {}
+        }
+    }
+
+    if content_length > MAX_BODY {
+        return Err(error(
+            413,
+            format!("bodies are limited to {} bytes", MAX_BODY),
+        ));
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).map_err(bad)?;
+
+    Ok(Request {
+        method,
+        path,
+        content_type,
+        body,
+    })
+}
+
+fn respond(mut stream: &TcpStream, (status, body): Reply) -> io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "",
+    };
+    let body = body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\
+         \r\n\
+         {}",
+        status,
+        reason,
+        body.len(),
+        body
+    )
+}
+
+fn json_body(req: &Request) -> Result<Value, Reply> {
+    serde_json::from_slice(&req.body).map_err(|e| error(400, format!("invalid JSON: {}", e)))
+}
+
+/// an optional number field, from 0 - `max`
+fn number(body: &Value, name: &str, max: u8) -> Result<Option<u8>, Reply> {
+    match body.get(name) {
+        None => Ok(None),
+        Some(n) => match n.as_u64() {
+            Some(n) if n <= max as u64 => Ok(Some(n as u8)),
+            _ => Err(error(
+                400,
+                format!("'{}' must be a number from 0 - {}", name, max),
+            )),
+        },
+    }
+}
+
+impl Api {
+    fn route(&self, req: &Request) -> Reply {
+        let segments: Vec<&str> = req.path.trim_matches('/').split('/').collect();
+        let res = match (req.method.as_str(), segments.as_slice()) {
+            ("GET", ["state"]) => self.state(),
+            ("PUT", ["brightness"]) => self.brightness(req),
+            ("PUT", ["preset"]) => self.preset(req),
+            ("POST", ["custom", slot]) => self.custom(req, slot),
+            (_, ["state"]) | (_, ["brightness"]) | (_, ["preset"]) | (_, ["custom", _]) => {
+                Err(error(405, format!("{} isn't supported here", req.method)))
+            }
+            _ => Err(error(404, format!("no such endpoint '{}'", req.path))),
+        };
+        res.unwrap_or_else(|e| e)
+    }
+
+    fn submit(&self, write: Write) -> Result<Reply, Reply> {
+        let state = write.state.to_json();
+        self.submitter.submit("http", write);
+        Ok((202, state))
+    }
+
+    fn current_brightness(&self) -> u8 {
+        saved::load().map_or(self.default_brightness, |saved| saved.state.brightness)
+    }
+
+    fn state(&self) -> Result<Reply, Reply> {
+        let saved = saved::load().ok_or_else(|| error(404, "no lighting has been set yet"))?;
+        let mut state = saved.state.to_json();
+        if saved.off {
+            state["off"] = true.into();
+        }
+        Ok((200, state))
+    }
+
+    fn brightness(&self, req: &Request) -> Result<Reply, Reply> {
+        let body = json_body(req)?;
+        let brightness = number(&body, "brightness", MAX_BRIGHTNESS)?
+            .ok_or_else(|| error(400, "missing 'brightness'"))?;
+        let saved = saved::load().ok_or_else(|| {
+            error(
+                409,
+                "no lighting has been set yet, so there's nothing to dim",
+            )
+        })?;
+
+        self.submit(Write {
+            state: State {
+                brightness,
+                ..saved.state
+            },
+            upload: None,
+        })
+    }
+
+    fn preset(&self, req: &Request) -> Result<Reply, Reply> {
+        let body = json_body(req)?;
+        let field = |name: &str| body.get(name).and_then(|v| v.as_str());
+
+        let preset = match field("preset") {
+            Some(p) => Preset::from_str(&p.to_lowercase())
+                .map_err(|_| error(400, format!("unknown preset '{}'", p)))?,
+            None => return Err(error(400, "missing 'preset'")),
+        };
+        let color = match field("color") {
+            Some(c) => Some(Color::lookup(c).map_err(|e| error(400, e))?),
+            None => None,
+        };
+        let speed = number(&body, "speed", MAX_SPEED)?;
+        if let Some(unused) = preset.unused_params(speed, color).into_iter().next() {
+            return Err(error(400, unused));
+        }
+
+        self.submit(Write {
+            state: State {
+                lighting: Lighting::Preset {
+                    preset,
+                    color: color.unwrap_or(Color::Rand),
+                    speed: speed.unwrap_or(5),
+                },
+                brightness: match number(&body, "brightness", MAX_BRIGHTNESS)? {
+                    Some(brightness) => brightness,
+                    None => self.current_brightness(),
+                },
+            },
+            upload: None,
+        })
+    }
+
+    fn custom(&self, req: &Request, slot: &str) -> Result<Reply, Reply> {
+        let slot = match slot.parse::<u8>() {
+            Ok(slot) if slot < NUM_SLOTS => slot,
+            _ => return Err(error(404, format!("slots go from 0 - {}", NUM_SLOTS - 1))),
+        };
+
+        let upload = if req.body.is_empty() {
+            None
+        } else {
+            let format = match req.content_type.as_deref() {
+                Some("image/png") => Format::Png,
+                Some("application/toml") => Format::Toml,
+                Some("text/plain") => Format::Text,
+                Some("application/octet-stream") if Container::detect(&req.body) => {
+                    Format::Container
+                }
+                Some("application/octet-stream") => Format::Binary,
+                _ => Format::Json,
+            };
+            let cfg = config::decode(&req.body, format, &self.keymap)
+                .map_err(|e| error(400, format!("invalid config: {}", e)))?;
+            Some(self.correction.apply(&cfg))
+        };
+
+        self.submit(Write {
+            state: State {
+                lighting: Lighting::Custom { slot },
+                brightness: self.current_brightness(),
+            },
+            upload,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// what `read_request` makes of whatever `client` sends, by `deadline`
+    fn read_from(client: impl FnOnce(TcpStream) + Send + 'static, deadline: Duration) -> Reply {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || client(TcpStream::connect(addr).unwrap()));
+        let (stream, _) = listener.accept().unwrap();
+        let reply = match read_request(&stream, Instant::now() + deadline) {
+            Ok(req) => (200, json!({"method": req.method, "path": req.path})),
+            Err(e) => e,
+        };
+        drop(stream);
+        client.join().unwrap();
+        reply
+    }
+
+    #[test]
+    fn whole_requests_are_read() {
+        let send = |mut s: TcpStream| s.write_all(b"GET /state HTTP/1.1\r\n\r\n").unwrap();
+        let (status, req) = read_from(send, Duration::from_secs(5));
+        assert_eq!(status, 200);
+        assert_eq!(req, json!({"method": "GET", "path": "/state"}));
+    }
+
+    #[test]
+    fn trickling_requests_time_out() {
+        // never quiet for long, but never finished either
+        let trickle = |mut s: TcpStream| {
+            for _ in 0..20 {
+                if s.write_all(b"x").is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(20));
+            }
+        };
+        let started = Instant::now();
+        let (status, _) = read_from(trickle, Duration::from_millis(100));
+        assert_eq!(status, 408);
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}
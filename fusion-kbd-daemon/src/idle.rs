@@ -0,0 +1,167 @@
+//! Dimming (and turning off) the backlight while the computer isn't being
+//! used, configured with an `[idle]` table in the config file. Timeouts can be
+//! set separately for when the laptop is on battery:
+//!
+//! ```toml
+//! [idle]
+//! dim_after_min = 5
+//! off_after_min = 15
+//! dim_brightness = 5  # default: 5
+//!
+//! [idle.battery]
+//! dim_after_min = 1
+//! off_after_min = 3
+//! ```
+//!
+//! Either timeout can be left out. Idle time comes from the X server's
+//! XScreenSaver extension (via `xprintidle`) when there's a `DISPLAY`, and
+//! from logind's idle hint otherwise (which the desktop sets after its own
+//! idle timeout, so it can lag behind by that much).
+//!
+//! Dimming doesn't touch the saved state (see `saved`), so any input brings
+//! back exactly what was showing.
+
+use std::env;
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use fusion_kbd_protocol::protocol::MAX_BRIGHTNESS;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Timeouts {
+    pub dim: Option<Duration>,
+    pub off: Option<Duration>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub ac: Timeouts,
+    /// used instead of `ac` while on battery
+    pub battery: Timeouts,
+    pub dim_brightness: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Awake,
+    Dimmed,
+    Off,
+}
+
+fn timeouts(table: &toml::Table, prefix: &str, defaults: Timeouts) -> Result<Timeouts, String> {
+    let minutes = |name: &str| -> Result<Option<Duration>, String> {
+        match table.get(name) {
+            None => Ok(None),
+            Some(toml::Value::Integer(m)) if *m > 0 => {
+                Ok(Some(Duration::from_secs(*m as u64 * 60)))
+            }
+            Some(_) => Err(format!("`{}.{}` must be a positive number", prefix, name)),
+        }
+    };
+    Ok(Timeouts {
+        dim: minutes("dim_after_min")?.or(defaults.dim),
+        off: minutes("off_after_min")?.or(defaults.off),
+    })
+}
+
+impl Config {
+    /// parses the `[idle]` table of the config file
+    pub fn from_toml(table: &toml::Table) -> Result<Config, String> {
+        let ac = timeouts(table, "idle", Timeouts::default())?;
+        // anything not overridden for battery is the same as on AC
+        let battery = match table.get("battery") {
+            None => ac,
+            Some(toml::Value::Table(t)) => timeouts(t, "idle.battery", ac)?,
+            Some(_) => return Err("`idle.battery` must be a table".to_string()),
+        };
+
+        let dim_brightness = match table.get("dim_brightness") {
+            None => 5,
+            Some(toml::Value::Integer(b)) if (0..=MAX_BRIGHTNESS as i64).contains(b) => *b as u8,
+            Some(_) => {
+                return Err(format!(
+                    "`idle.dim_brightness` must be a number from 0 - {}",
+                    MAX_BRIGHTNESS
+                ))
+            }
+        };
+
+        Ok(Config {
+            ac,
+            battery,
+            dim_brightness,
+        })
+    }
+
+    /// what the backlight should be doing after `idle` without input
+    pub fn level(&self, idle: Duration, on_ac: Option<bool>) -> Level {
+        let timeouts = if on_ac == Some(false) {
+            self.battery
+        } else {
+            self.ac
+        };
+        let past = |timeout: Option<Duration>| timeout.is_some_and(|t| idle >= t);
+
+        if past(timeouts.off) {
+            Level::Off
+        } else if past(timeouts.dim) {
+            Level::Dimmed
+        } else {
+            Level::Awake
+        }
+    }
+}
+
+/// stdout of a command, if it succeeded
+fn output(cmd: &mut Command) -> Option<String> {
+    let out = cmd
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    String::from_utf8(out.stdout).ok()
+}
+
+/// how long it's been since the last input, if that can be found out
+pub fn idle_time() -> Option<Duration> {
+    if env::var_os("DISPLAY").is_some() {
+        // milliseconds since the last input
+        let ms = output(&mut Command::new("xprintidle")).and_then(|o| o.trim().parse().ok());
+        if let Some(ms) = ms {
+            return Some(Duration::from_millis(ms));
+        }
+    }
+    logind_idle_time()
+}
+
+/// based on the idle hint of the active session on seat0
+fn logind_idle_time() -> Option<Duration> {
+    let session = output(Command::new("loginctl").args([
+        "show-seat",
+        "seat0",
+        "--property=ActiveSession",
+        "--value",
+    ]))?;
+    let props = output(Command::new("loginctl").args([
+        "show-session",
+        session.trim(),
+        "--property=IdleHint",
+        "--property=IdleSinceHint",
+    ]))?;
+
+    let prop = |name: &str| {
+        props
+            .lines()
+            .find_map(|l| l.strip_prefix(name)?.strip_prefix('='))
+    };
+    if prop("IdleHint")? != "yes" {
+        return Some(Duration::from_secs(0));
+    }
+    // microseconds since the epoch
+    let since = Duration::from_micros(prop("IdleSinceHint")?.parse().ok()?);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+    Some(now.saturating_sub(since))
+}
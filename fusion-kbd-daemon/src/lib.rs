@@ -0,0 +1,52 @@
+//! Pieces shared between the `fusion-kbd-daemon` service and the CLI:
+//!
+//! - `compositor` - layers drawn over the custom slots
+//! - `control` - the daemon's control socket, and a client for it
+//! - `events` - lighting change notifications
+//! - `evdev` - input events, read from `/dev/input`
+//! - `http` - the optional HTTP API
+//! - `idle` - dimming the backlight while idle
+//! - `locks` - Caps Lock / Num Lock indicators
+//! - `logging` - where log messages go
+//! - `mqtt` - the Home Assistant (MQTT) bridge
+//! - `paths` - where config / runtime files live
+//! - `plugins` - third-party effects / indicators, run by the daemon
+//! - `pomodoro` - a pomodoro timer
+//! - `power` - the power source, for rules
+//! - `profile` - named lighting profiles
+//! - `reactive` - key press effects
+//! - `rules` - declarative `when ... then ...` lighting rules
+//! - `saved` - the last lighting state applied
+//! - `scheduler` - coalescing / rate limiting of device writes
+//! - `secrets` - credentials, kept out of the plaintext config
+//! - `service` - the daemon's main loop
+//! - `settings` - the user config file
+//! - `themes` - theme packs, installed in the data directory
+//! - `window` - the focused window, for rules
+
+pub mod compositor;
+pub mod control;
+pub mod evdev;
+pub mod events;
+pub mod http;
+pub mod idle;
+pub mod locks;
+pub mod logging;
+pub mod mqtt;
+pub mod paths;
+pub mod plugins;
+pub mod pomodoro;
+pub mod power;
+pub mod profile;
+pub mod reactive;
+pub mod rules;
+pub mod saved;
+pub mod scheduler;
+pub mod secrets;
+pub mod service;
+pub mod settings;
+pub mod themes;
+pub mod window;
+
+/// Custom slot clobbered by one-off lighting (solid colors, animations, ...)
+pub const SCRATCH_SLOT: u8 = 4;
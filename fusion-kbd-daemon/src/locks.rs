@@ -0,0 +1,171 @@
+//! Lock key indicators: Caps Lock and Num Lock light up in their own color
+//! while those locks are on, on top of whatever custom slot is showing.
+//!
+//! Configured with a `[locks]` table in the config file:
+//!
+//! ```toml
+//! [locks]
+//! caps = "red"
+//! num = "#00ff00"
+//! devices = ["/dev/input/by-path/platform-i8042-serio-0-event-kbd"]
+//! ```
+//!
+//! Either color can be left out, to leave that key alone. `devices` defaults
+//! to every keyboard in `/dev/input/by-path`, like `reactive`'s.
+//!
+//! Lock changes are picked up from the keyboards' LED events. The overlay is a
+//! layer over every slot (see `compositor`), so switching profiles keeps it,
+//! and a key gets its own color back when its lock turns off. Presets can't
+//! be drawn over, so the overlay only shows while a custom slot is.
+
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
+
+use fusion_kbd_protocol::correction::Correction;
+use fusion_kbd_protocol::layers::{Blend, Layer, Mask};
+use fusion_kbd_protocol::{CustomConfig, Keymap, Rgb};
+
+use crate::compositor::Layers;
+use crate::evdev;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub caps: Option<Rgb>,
+    pub num: Option<Rgb>,
+    /// evdev devices to read, or every keyboard if empty
+    pub devices: Vec<PathBuf>,
+}
+
+impl Config {
+    /// parses the `[locks]` table of the config file
+    pub fn from_toml(table: &toml::Table) -> Result<Config, String> {
+        let color = |name: &str| -> Result<Option<Rgb>, String> {
+            match table.get(name) {
+                None => Ok(None),
+                Some(toml::Value::String(c)) => Rgb::from_str(c)
+                    .map(Some)
+                    .map_err(|e| format!("`locks.{}`: {}", name, e)),
+                Some(_) => Err(format!("`locks.{}` must be a color", name)),
+            }
+        };
+
+        let devices = match table.get("devices") {
+            None => Vec::new(),
+            Some(toml::Value::Array(devices)) => devices
+                .iter()
+                .map(|d| d.as_str().map(PathBuf::from))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| "`locks.devices` must be a list of paths".to_string())?,
+            Some(_) => return Err("`locks.devices` must be a list of paths".to_string()),
+        };
+
+        Ok(Config {
+            caps: color("caps")?,
+            num: color("num")?,
+            devices,
+        })
+    }
+}
+
+/// a lock, and the key it lights up
+struct Lock {
+    led: u16,
+    key: usize,
+    color: Rgb,
+    on: bool,
+}
+
+/// whether an LED is on at the moment, per sysfs (e.g:
+/// `/sys/class/leds/input3::capslock`)
+fn led_on(name: &str) -> bool {
+    let suffix = format!("::{}", name);
+    fs::read_dir("/sys/class/leds")
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name().to_string_lossy().ends_with(&suffix))
+        .filter_map(|e| fs::read_to_string(e.path().join("brightness")).ok())
+        .any(|b| b.trim() != "0")
+}
+
+/// the layer showing the locks that are on
+fn layer(locks: &[Lock], correction: &Correction) -> Layer {
+    let mut cfg = CustomConfig::new();
+    let mut mask = Mask::none();
+    for lock in locks.iter().filter(|l| l.on) {
+        // slots hold corrected colors
+        cfg.set_key(lock.key, correction.rgb(lock.color));
+        mask.set(lock.key, 1.0);
+    }
+    Layer {
+        config: cfg,
+        mask,
+        blend: Blend::Normal,
+    }
+}
+
+/// Starts following the lock LEDs in the background, drawing the overlay as
+/// one of `layers`. Only opening the devices can fail.
+pub fn spawn(
+    config: Config,
+    keymap: &Keymap,
+    layers: Layers,
+    correction: Correction,
+) -> Result<(), String> {
+    let mut locks = Vec::new();
+    for &(color, led, name, sysfs) in &[
+        (config.caps, evdev::LED_CAPSL, "caps", "capslock"),
+        (config.num, evdev::LED_NUML, "numlk", "numlock"),
+    ] {
+        if let Some(color) = color {
+            let key = keymap
+                .index(name)
+                .ok_or_else(|| format!("the keymap doesn't have a '{}' key", name))?;
+            locks.push(Lock {
+                led,
+                key,
+                color,
+                on: led_on(sysfs),
+            });
+        }
+    }
+    if locks.is_empty() {
+        return Ok(());
+    }
+
+    let devices = if config.devices.is_empty() {
+        evdev::keyboards()
+    } else {
+        config.devices.clone()
+    };
+    if devices.is_empty() {
+        return Err("no keyboards found in /dev/input/by-path".to_string());
+    }
+
+    let (tx, events) = mpsc::channel();
+    for device in devices {
+        evdev::listen(device, tx.clone())?;
+    }
+
+    thread::spawn(move || {
+        let mut locks = locks;
+        // for locks that were already on
+        layers.set("locks", None, layer(&locks, &correction));
+        for event in events.iter().filter(|e| e.kind == evdev::EV_LED) {
+            let on = event.value != 0;
+            let mut changed = false;
+            for lock in locks.iter_mut().filter(|l| l.led == event.code) {
+                changed |= lock.on != on;
+                lock.on = on;
+            }
+            // the other keyboards echo the same change
+            if changed {
+                layers.set("locks", None, layer(&locks, &correction));
+            }
+        }
+    });
+    Ok(())
+}
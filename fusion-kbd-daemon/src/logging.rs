@@ -0,0 +1,88 @@
+//! Where `log` messages go (for the daemon, and the CLI): stderr, with errors
+//! and warnings called out, and anything chattier than `info` saying which
+//! module it came from. Or, with `--format json`, as one JSON object per
+//! line (see `Json`).
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde_json::json;
+
+struct Stderr;
+
+impl Log for Stderr {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match record.level() {
+            Level::Error => eprintln!("Error: {}", record.args()),
+            Level::Warn => eprintln!("Warning: {}", record.args()),
+            Level::Info => eprintln!("{}", record.args()),
+            level => eprintln!("[{} {}] {}", level, record.target(), record.args()),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// e.g: `{"level": "error", "target": "fusion_kbd_cli", "message": "No
+/// keyboard found! (looked for 1044:*)"}`
+struct Json;
+
+impl Log for Json {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = json!({
+            "level": record.level().as_str().to_lowercase(),
+            "target": record.target(),
+            "message": record.args().to_string(),
+        });
+        eprintln!("{}", line);
+    }
+
+    fn flush(&self) {}
+}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// The level `-v` / `-q` ask for: `Info` by default, `Debug` / `Trace` for
+/// each `-v`, and just warnings and errors with `-q`.
+pub fn level(verbose: u64, quiet: bool) -> LevelFilter {
+    match (quiet, verbose) {
+        (true, _) => LevelFilter::Warn,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    }
+}
+
+/// starts logging at `level` (and up). Only the first call does anything.
+pub fn init(level: LevelFilter) {
+    if log::set_logger(&Stderr).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+/// `init`, but logging JSON lines
+pub fn init_json(level: LevelFilter) {
+    if log::set_logger(&Json).is_ok() {
+        JSON.store(true, Ordering::Relaxed);
+        log::set_max_level(level);
+    }
+}
+
+/// whether messages are going out as JSON (see `init_json`)
+pub fn json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
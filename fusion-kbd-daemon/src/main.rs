@@ -0,0 +1,70 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use clap::{App, Arg};
+use fusion_kbd_daemon::service;
+use fusion_kbd_daemon::settings::Settings;
+use fusion_kbd_daemon::{logging, paths};
+use log::error;
+
+fn main() -> Result<(), libusb::Error> {
+    #[rustfmt::skip]
+    let app_m = App::new("fusion-kbd-daemon")
+        .about("Drives the Fusion RGB keyboard from the rules in the config file")
+        .arg(Arg::with_name("config")
+            .short("c")
+            .long("config")
+            .takes_value(true)
+            .help("config file (defaults to ~/.config/fusion-kbd/config.toml)"))
+        .arg(Arg::with_name("http")
+            .long("http")
+            .takes_value(true)
+            .value_name("ADDR")
+            .validator(|astr| astr.parse::<SocketAddr>().map(|_| ()).map_err(|e| e.to_string()))
+            .help("serve the HTTP API on this address, e.g: 127.0.0.1:9123 (unauthenticated!)"))
+        .arg(Arg::with_name("verbose")
+            .short("v")
+            .long("verbose")
+            .multiple(true)
+            .help("say more about what's going on (-vv for every transfer)"))
+        .arg(Arg::with_name("quiet")
+            .short("q")
+            .long("quiet")
+            .conflicts_with("verbose")
+            .help("only print warnings and errors"))
+        .get_matches();
+
+    logging::init(logging::level(
+        app_m.occurrences_of("verbose"),
+        app_m.is_present("quiet"),
+    ));
+
+    let path = match app_m.value_of("config") {
+        Some(path) => PathBuf::from(path),
+        None => match paths::config_file() {
+            Some(path) => path,
+            None => {
+                error!("couldn't find the config file ($HOME isn't set)");
+                return Err(libusb::Error::Other);
+            }
+        },
+    };
+
+    let mut settings = match Settings::load(&path) {
+        Ok(settings) => settings,
+        Err(e) => {
+            error!("invalid config '{}': {}", path.display(), e);
+            return Err(libusb::Error::Other);
+        }
+    };
+    if let Err(e) = settings.apply_env() {
+        error!("invalid environment variable {}", e);
+        return Err(libusb::Error::InvalidParam);
+    }
+
+    let options = service::Options {
+        http: app_m.value_of("http").map(|astr| astr.parse().unwrap()),
+    };
+
+    service::run(&settings, &options)
+}
@@ -0,0 +1,476 @@
+//! MQTT bridge, exposing the keyboard to Home Assistant as an RGB light (via
+//! MQTT discovery), so it can take part in home automation scenes:
+//!
+//! ```toml
+//! [mqtt]
+//! host = "homeassistant.local"
+//! port = 1883                         # default
+//! username = "fusion"                 # optional
+//! password = "secret:mqtt"            # optional (see `secrets`)
+//! node_id = "aero"                    # default: fusion_kbd
+//! discovery_prefix = "homeassistant"  # default
+//! ```
+//!
+//! The light uses Home Assistant's JSON schema: commands arrive on
+//! `fusion-kbd/NODE/set` (e.g: `{"state": "ON", "brightness": 30, "color":
+//! {"r": 255, "g": 0, "b": 0}}`), and every lighting change (from any source)
+//! is published to `fusion-kbd/NODE/state`. Presets are exposed as effects, and
+//! colors are shown through the scratch slot.
+//!
+//! Only what's needed for that is implemented: MQTT 3.1.1, QoS 0, over plain
+//! TCP.
+
+use std::io::{self, Read, Write as _};
+use std::net::TcpStream;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use fusion_kbd_protocol::correction::Correction;
+use fusion_kbd_protocol::protocol::MAX_BRIGHTNESS;
+use fusion_kbd_protocol::state::{Lighting, State};
+use fusion_kbd_protocol::{Color, CustomConfig, Preset, Rgb};
+use log::error;
+use serde_json::{json, Value};
+use strum::IntoEnumIterator;
+
+use crate::control::Submitter;
+use crate::events::Subscriber;
+use crate::saved;
+use crate::scheduler::Write;
+use crate::SCRATCH_SLOT;
+
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+/// how long to wait before reconnecting after losing the broker
+const RETRY: Duration = Duration::from_secs(10);
+
+/// the `[mqtt]` table of the config file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    /// may be a `"secret:NAME"` reference
+    pub password: Option<String>,
+    pub node_id: String,
+    pub discovery_prefix: String,
+}
+
+impl Config {
+    pub fn from_toml(table: &toml::Table) -> Result<Config, String> {
+        let string = |name: &str| -> Result<Option<String>, String> {
+            match table.get(name) {
+                None => Ok(None),
+                Some(toml::Value::String(s)) => Ok(Some(s.clone())),
+                Some(_) => Err(format!("`mqtt.{}` must be a string", name)),
+            }
+        };
+
+        let port = match table.get("port") {
+            None => 1883,
+            Some(toml::Value::Integer(p)) if (1..=0xffff).contains(p) => *p as u16,
+            Some(_) => return Err("`mqtt.port` must be a port number".to_string()),
+        };
+
+        let node_id = string("node_id")?.unwrap_or_else(|| "fusion_kbd".to_string());
+        if node_id.is_empty()
+            || !node_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err("`mqtt.node_id` may only contain letters, numbers, and _".to_string());
+        }
+
+        Ok(Config {
+            host: string("host")?.ok_or("the mqtt integration needs `mqtt.host`")?,
+            port,
+            username: string("username")?,
+            password: string("password")?,
+            node_id,
+            discovery_prefix: string("discovery_prefix")?
+                .unwrap_or_else(|| "homeassistant".to_string()),
+        })
+    }
+
+    fn topic(&self, name: &str) -> String {
+        format!("fusion-kbd/{}/{}", self.node_id, name)
+    }
+}
+
+pub struct Bridge {
+    pub config: Config,
+    /// `config.password`, with secrets resolved
+    pub password: Option<String>,
+    pub submitter: Submitter,
+    pub correction: Correction,
+    /// used until something sets the brightness
+    pub default_brightness: u8,
+}
+
+/// Runs the bridge in the background, reconnecting whenever the connection to
+/// the broker drops.
+pub fn spawn(bridge: Bridge) {
+    thread::spawn(move || loop {
+        if let Err(e) = bridge.session() {
+            error!(
+                "MQTT connection to {}:{} failed: {}",
+                bridge.config.host, bridge.config.port, e
+            );
+        }
+        thread::sleep(RETRY);
+    });
+}
+
+/// appends an MQTT string (or binary blob): u16 length + bytes
+fn put_str(buf: &mut Vec<u8>, s: &[u8]) {
+    buf.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    buf.extend_from_slice(s);
+}
+
+fn packet(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = vec![kind];
+    // "remaining length", 7 bits at a time
+    let mut len = body.len();
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        out.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+    out.extend_from_slice(body);
+    out
+}
+
+fn read_packet(stream: &mut impl Read) -> io::Result<(u8, Vec<u8>)> {
+    let mut byte = [0; 1];
+    stream.read_exact(&mut byte)?;
+    let kind = byte[0];
+
+    let mut len = 0;
+    for shift in 0..4 {
+        stream.read_exact(&mut byte)?;
+        len |= ((byte[0] & 0x7f) as usize) << (7 * shift);
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+
+    let mut body = vec![0; len];
+    stream.read_exact(&mut body)?;
+    Ok((kind, body))
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// the connection's write half, shared between the bridge's threads
+#[derive(Clone)]
+struct Writer(Arc<Mutex<TcpStream>>);
+
+impl Writer {
+    fn send(&self, packet: &[u8]) -> io::Result<()> {
+        self.0.lock().unwrap().write_all(packet)
+    }
+
+    fn publish(&self, topic: &str, payload: &str, retain: bool) -> io::Result<()> {
+        let mut body = Vec::new();
+        put_str(&mut body, topic.as_bytes());
+        body.extend_from_slice(payload.as_bytes());
+        self.send(&packet(if retain { 0x31 } else { 0x30 }, &body))
+    }
+}
+
+/// a lighting change event (see `events`), as a Home Assistant light state
+fn light_state(event: &Value) -> Value {
+    let brightness = event["brightness"].as_u64().unwrap_or(0);
+    let mut state = json!({
+        "state": if brightness > 0 { "ON" } else { "OFF" },
+        "brightness": brightness,
+    });
+    if brightness > 0 {
+        state["color_mode"] = "rgb".into();
+    }
+    if event["mode"] == "preset" {
+        state["effect"] = event["preset"].clone();
+    }
+    state
+}
+
+impl Bridge {
+    fn session(&self) -> io::Result<()> {
+        let config = &self.config;
+        let mut stream = TcpStream::connect((config.host.as_str(), config.port))?;
+        // the broker pings back at least every KEEP_ALIVE / 2, so silence
+        // for longer than KEEP_ALIVE means the connection's gone
+        stream.set_read_timeout(Some(KEEP_ALIVE))?;
+        let writer = Writer(Arc::new(Mutex::new(stream.try_clone()?)));
+        let availability = config.topic("availability");
+
+        // CONNECT, with a (retained) will marking the light unavailable
+        let mut flags = 0x02 | 0x04 | 0x20;
+        let mut body = Vec::new();
+        put_str(&mut body, b"MQTT");
+        body.push(4);
+        let flags_at = body.len();
+        body.push(0);
+        body.extend_from_slice(&(KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+        put_str(
+            &mut body,
+            format!("fusion-kbd-{}", config.node_id).as_bytes(),
+        );
+        put_str(&mut body, availability.as_bytes());
+        put_str(&mut body, b"offline");
+        if let Some(ref username) = config.username {
+            flags |= 0x80;
+            put_str(&mut body, username.as_bytes());
+        }
+        if let Some(ref password) = self.password {
+            flags |= 0x40;
+            put_str(&mut body, password.as_bytes());
+        }
+        body[flags_at] = flags;
+        writer.send(&packet(0x10, &body))?;
+
+        match read_packet(&mut stream)? {
+            (0x20, ack) if ack.len() == 2 && ack[1] == 0 => {}
+            (0x20, ack) if ack.len() == 2 => {
+                return Err(invalid(match ack[1] {
+                    4 => "bad username or password",
+                    5 => "not authorized",
+                    _ => "connection refused",
+                }))
+            }
+            _ => return Err(invalid("expected CONNACK")),
+        }
+
+        let command_topic = config.topic("set");
+        let mut body = vec![0, 1];
+        put_str(&mut body, command_topic.as_bytes());
+        body.push(0);
+        writer.send(&packet(0x82, &body))?;
+
+        writer.publish(
+            &format!(
+                "{}/light/{}/config",
+                config.discovery_prefix, config.node_id
+            ),
+            &self.discovery().to_string(),
+            true,
+        )?;
+        writer.publish(&availability, "online", true)?;
+
+        let alive = Arc::new(AtomicBool::new(true));
+        self.spawn_pinger(writer.clone(), alive.clone());
+        self.spawn_state_publisher(writer.clone(), alive.clone())?;
+
+        let res = self.serve(&mut stream, &command_topic);
+        alive.store(false, Ordering::Relaxed);
+        let _ = stream.shutdown(std::net::Shutdown::Both);
+        res
+    }
+
+    /// the Home Assistant discovery payload
+    fn discovery(&self) -> Value {
+        let config = &self.config;
+        let effects: Vec<String> = Preset::iter().map(|p| p.to_string()).collect();
+        json!({
+            "name": "Keyboard backlight",
+            "unique_id": format!("fusion_kbd_{}", config.node_id),
+            "schema": "json",
+            "command_topic": config.topic("set"),
+            "state_topic": config.topic("state"),
+            "availability_topic": config.topic("availability"),
+            "brightness": true,
+            "brightness_scale": MAX_BRIGHTNESS,
+            "supported_color_modes": ["rgb"],
+            "effect": true,
+            "effect_list": effects,
+            "device": {
+                "identifiers": [format!("fusion_kbd_{}", config.node_id)],
+                "name": "Fusion RGB keyboard",
+                "manufacturer": "Gigabyte",
+            },
+        })
+    }
+
+    fn spawn_pinger(&self, writer: Writer, alive: Arc<AtomicBool>) {
+        thread::spawn(move || {
+            while alive.load(Ordering::Relaxed) {
+                thread::sleep(KEEP_ALIVE / 2);
+                if writer.send(&packet(0xc0, &[])).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+
+    /// mirrors every lighting change to the state topic
+    fn spawn_state_publisher(&self, writer: Writer, alive: Arc<AtomicBool>) -> io::Result<()> {
+        let subscriber = Subscriber::bind()?;
+        let topic = self.config.topic("state");
+
+        if let Some(saved) = saved::load() {
+            let mut event = saved.state.to_json();
+            if saved.off {
+                event["brightness"] = 0.into();
+            }
+            writer.publish(&topic, &light_state(&event).to_string(), true)?;
+        }
+
+        thread::spawn(move || {
+            while alive.load(Ordering::Relaxed) {
+                let event = match subscriber.recv(Some(Duration::from_secs(1))) {
+                    Ok(event) => event,
+                    Err(_) => continue,
+                };
+                let event: Value = match serde_json::from_str(&event) {
+                    Ok(event) => event,
+                    Err(_) => continue,
+                };
+                if writer
+                    .publish(&topic, &light_state(&event).to_string(), true)
+                    .is_err()
+                {
+                    return;
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// handles incoming publishes until the connection drops
+    fn serve(&self, stream: &mut TcpStream, command_topic: &str) -> io::Result<()> {
+        // what "ON" goes back to after an "OFF"
+        let mut on_brightness = saved::load()
+            .map(|saved| saved.state.brightness)
+            .filter(|&b| b > 0)
+            .unwrap_or(self.default_brightness);
+
+        loop {
+            let (kind, body) = read_packet(stream)?;
+            if kind & 0xf0 != 0x30 {
+                // SUBACK, PINGRESP, ...
+                continue;
+            }
+
+            if body.len() < 2 {
+                return Err(invalid("truncated PUBLISH"));
+            }
+            let topic_len = u16::from_be_bytes([body[0], body[1]]) as usize;
+            let mut payload_at = 2 + topic_len;
+            if kind & 0x06 != 0 {
+                // QoS 1 / 2 have a packet id. We only subscribe at QoS 0, so
+                // these shouldn't happen, but skip it just in case.
+                payload_at += 2;
+            }
+            if body.len() < payload_at {
+                return Err(invalid("truncated PUBLISH"));
+            }
+            if &body[2..2 + topic_len] != command_topic.as_bytes() {
+                continue;
+            }
+
+            match serde_json::from_slice(&body[payload_at..]) {
+                Ok(cmd) => self.command(&cmd, &mut on_brightness),
+                Err(e) => error!("invalid MQTT command: {}", e),
+            }
+        }
+    }
+
+    /// turns a Home Assistant light command into a write
+    fn command(&self, cmd: &Value, on_brightness: &mut u8) {
+        let current = saved::load().map_or(
+            State {
+                lighting: Lighting::Preset {
+                    preset: Preset::Static,
+                    color: Color::White,
+                    speed: 5,
+                },
+                brightness: 0,
+            },
+            |saved| saved.state,
+        );
+
+        if cmd["state"] == "OFF" {
+            if current.brightness > 0 {
+                *on_brightness = current.brightness;
+            }
+            self.submitter.submit(
+                "mqtt",
+                Write {
+                    state: State {
+                        brightness: 0,
+                        ..current
+                    },
+                    upload: None,
+                },
+            );
+            return;
+        }
+
+        let brightness = match cmd["brightness"].as_u64() {
+            Some(b) => b.min(MAX_BRIGHTNESS as u64) as u8,
+            None if current.brightness == 0 => *on_brightness,
+            None => current.brightness,
+        };
+        if brightness > 0 {
+            *on_brightness = brightness;
+        }
+
+        let channel = |name: &str| cmd["color"][name].as_u64().map(|c| c.min(255) as u8);
+        let color = match (channel("r"), channel("g"), channel("b")) {
+            (Some(r), Some(g), Some(b)) => Some(Rgb(r, g, b)),
+            _ => None,
+        };
+        let effect = cmd["effect"]
+            .as_str()
+            .and_then(|e| Preset::from_str(e).ok());
+
+        let mut upload = None;
+        let lighting = match (color, effect) {
+            (Some(color), _) => {
+                let mut cfg = CustomConfig::new();
+                cfg.fill(self.correction.rgb(color));
+                upload = Some(cfg);
+                Lighting::Custom { slot: SCRATCH_SLOT }
+            }
+            (None, Some(preset)) => match current.lighting {
+                // keep the color / speed when re-selecting the same preset
+                Lighting::Preset {
+                    preset: p,
+                    color,
+                    speed,
+                } if p == preset => Lighting::Preset {
+                    preset,
+                    color,
+                    speed,
+                },
+                _ => Lighting::Preset {
+                    preset,
+                    color: Color::Rand,
+                    speed: 5,
+                },
+            },
+            (None, None) => current.lighting,
+        };
+
+        self.submitter.submit(
+            "mqtt",
+            Write {
+                state: State {
+                    lighting,
+                    brightness,
+                },
+                upload,
+            },
+        );
+    }
+}
@@ -0,0 +1,104 @@
+use std::env;
+use std::fs::{self, DirBuilder};
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+/// `$XDG_CONFIG_HOME/fusion-kbd`, falling back to `~/.config/fusion-kbd`
+pub fn config_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config").map(|d| d.join("fusion-kbd"))
+}
+
+/// the user config file
+pub fn config_file() -> Option<PathBuf> {
+    config_dir().map(|d| d.join("config.toml"))
+}
+
+/// `$XDG_DATA_HOME/fusion-kbd`, falling back to `~/.local/share/fusion-kbd`
+pub fn data_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_DATA_HOME", ".local/share").map(|d| d.join("fusion-kbd"))
+}
+
+/// `$XDG_STATE_HOME/fusion-kbd`, falling back to `~/.local/state/fusion-kbd`
+pub fn state_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_STATE_HOME", ".local/state").map(|d| d.join("fusion-kbd"))
+}
+
+fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    match env::var_os(var) {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(fallback)),
+    }
+}
+
+/// `$XDG_RUNTIME_DIR/fusion-kbd`, created if it isn't there yet. Without
+/// `XDG_RUNTIME_DIR` (e.g: under `sudo`, since it clears the environment),
+/// root uses `/run/fusion-kbd` and everyone else `/tmp/fusion-kbd-$UID`.
+///
+/// Anyone could create the latter first, and plant a socket for the CLI to
+/// talk to instead of the daemon, so it's refused (with `PermissionDenied`)
+/// unless it's a directory of ours that nobody else can get into.
+pub fn runtime_dir() -> io::Result<PathBuf> {
+    let uid = unsafe { libc::geteuid() };
+    let dir = match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("fusion-kbd"),
+        _ if uid == 0 => PathBuf::from("/run/fusion-kbd"),
+        _ => env::temp_dir().join(format!("fusion-kbd-{}", uid)),
+    };
+    match DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(()) => {}
+        Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e),
+    }
+    check_private(&dir, uid)?;
+    Ok(dir)
+}
+
+/// `PermissionDenied` unless `dir` is a directory (not a symlink to one)
+/// owned by `uid`, with no permissions for anyone else
+fn check_private(dir: &Path, uid: u32) -> io::Result<()> {
+    let meta = fs::symlink_metadata(dir)?;
+    let problem = if !meta.is_dir() {
+        "isn't a directory"
+    } else if meta.uid() != uid {
+        "belongs to someone else"
+    } else if meta.mode() & 0o077 != 0 {
+        "can be used by other users"
+    } else {
+        return Ok(());
+    };
+    Err(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("'{}' {}, so it's not safe to use", dir.display(), problem),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn only_private_dirs_are_used() {
+        let dir = env::temp_dir().join(format!("fusion-kbd-paths-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        DirBuilder::new().mode(0o700).create(&dir).unwrap();
+        let uid = unsafe { libc::geteuid() };
+
+        assert!(check_private(&dir, uid).is_ok());
+        let e = check_private(&dir, uid + 1).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        assert!(e.to_string().contains("belongs to someone else"), "{}", e);
+
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+        let e = check_private(&dir, uid).unwrap_err();
+        assert!(e.to_string().contains("other users"), "{}", e);
+
+        let file = dir.join("file");
+        fs::write(&file, b"").unwrap();
+        let e = check_private(&file, uid).unwrap_err();
+        assert!(e.to_string().contains("isn't a directory"), "{}", e);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
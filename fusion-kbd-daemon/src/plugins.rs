@@ -0,0 +1,216 @@
+//! Effect / indicator plugins: programs the daemon runs at startup, which
+//! draw on the keyboard as layers (see `compositor`). Every executable in
+//! `data_dir()/plugins` is a plugin, and they're started in name order.
+//!
+//! Plugins talk to the daemon with lines of JSON. The daemon sends a single
+//! line on the plugin's stdin when it starts:
+//!
+//! ```text
+//! {"version":1,"layout":"ansi"}
+//! ```
+//!
+//! and the plugin sends messages on its stdout, one per line:
+//!
+//! ```text
+//! {"layer":{"keys":{"esc":"#ff0000","f1":"green"},"blend":"normal","opacity":1.0}}
+//! {"layer":{"fill":"#000040","keys":{"space":"white"},"slot":4}}
+//! {"clear":true}
+//! ```
+//!
+//! `layer` replaces the plugin's layer. `keys` (like a JSON profile) is drawn
+//! over whatever's under it, and `fill` (if given) over every other key.
+//! `blend` is one of `layers::BLENDS` (default: normal), `opacity` is 0 - 1
+//! (default: 1), and `slot` limits the layer to one custom slot (default:
+//! every slot). `clear` takes the layer away. Anything the plugin prints on
+//! stderr goes to the daemon's.
+//!
+//! When a plugin exits, its layer goes with it. Plugins aren't restarted.
+
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::thread;
+
+use fusion_kbd_protocol::correction::Correction;
+use fusion_kbd_protocol::layers::{Blend, Layer, Mask};
+use fusion_kbd_protocol::protocol::NUM_SLOTS;
+use fusion_kbd_protocol::{CustomConfig, Keymap, Rgb, NUM_KEYS};
+use log::{error, info};
+use serde_json::{json, Value};
+
+use crate::compositor::Layers;
+use crate::paths;
+
+/// bumped whenever the messages change incompatibly
+const VERSION: u64 = 1;
+
+pub fn dir() -> Option<PathBuf> {
+    paths::data_dir().map(|d| d.join("plugins"))
+}
+
+/// every plugin in `dir()`, in name order
+pub fn discover() -> Vec<PathBuf> {
+    let mut plugins: Vec<PathBuf> = dir()
+        .and_then(|d| fs::read_dir(d).ok())
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+        .filter(|e| {
+            e.metadata()
+                .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        })
+        .map(|e| e.path())
+        .collect();
+    plugins.sort();
+    plugins
+}
+
+fn name(plugin: &Path) -> String {
+    plugin
+        .file_name()
+        .map_or_else(String::new, |n| n.to_string_lossy().into_owned())
+}
+
+enum Message {
+    Layer { slot: Option<u8>, layer: Box<Layer> },
+    Clear,
+}
+
+impl Message {
+    fn from_json(
+        value: &Value,
+        keymap: &Keymap,
+        correction: &Correction,
+    ) -> Result<Message, String> {
+        if value["clear"].as_bool() == Some(true) {
+            return Ok(Message::Clear);
+        }
+        let value = match value["layer"] {
+            Value::Object(_) => &value["layer"],
+            _ => return Err("expected a `layer` or `clear` message".to_string()),
+        };
+
+        let opacity = match value["opacity"] {
+            Value::Null => 1.0,
+            ref o => match o.as_f64() {
+                Some(o) if (0.0..=1.0).contains(&o) => o as f32,
+                _ => return Err("`opacity` must be a number from 0 - 1".to_string()),
+            },
+        };
+
+        // slots hold corrected colors
+        let color = |value: &Value, what: &str| -> Result<Rgb, String> {
+            let color = value
+                .as_str()
+                .ok_or_else(|| format!("{} must be a color", what))?;
+            Ok(correction.rgb(Rgb::from_str(color)?))
+        };
+
+        let mut cfg = CustomConfig::new();
+        let mut mask = Mask::none();
+        if !value["fill"].is_null() {
+            cfg.fill(color(&value["fill"], "`fill`")?);
+            for key in 0..NUM_KEYS {
+                mask.set(key, opacity);
+            }
+        }
+        match value["keys"] {
+            Value::Null => {}
+            Value::Object(ref keys) => {
+                for (name, c) in keys {
+                    let key = keymap
+                        .index(name)
+                        .ok_or_else(|| format!("unknown key '{}'", name))?;
+                    cfg.set_key(key, color(c, &format!("the color for '{}'", name))?);
+                    mask.set(key, opacity);
+                }
+            }
+            _ => return Err("`keys` must be an object of key names to colors".to_string()),
+        }
+
+        let blend = match value["blend"] {
+            Value::Null => Blend::Normal,
+            Value::String(ref b) => Blend::from_str(b)?,
+            _ => return Err("`blend` must be a string".to_string()),
+        };
+
+        let slot = match value["slot"] {
+            Value::Null => None,
+            ref s => match s.as_u64() {
+                Some(s) if s < NUM_SLOTS as u64 => Some(s as u8),
+                _ => {
+                    return Err(format!(
+                        "`slot` must be a number from 0 - {}",
+                        NUM_SLOTS - 1
+                    ))
+                }
+            },
+        };
+
+        Ok(Message::Layer {
+            slot,
+            layer: Box::new(Layer {
+                config: cfg,
+                mask,
+                blend,
+            }),
+        })
+    }
+}
+
+/// Starts `plugin`, drawing its layer in `layers` until it exits. Only
+/// starting it can fail.
+pub fn spawn(
+    plugin: &Path,
+    layout: &str,
+    keymap: Keymap,
+    layers: Layers,
+    correction: Correction,
+) -> Result<(), String> {
+    let name = name(plugin);
+    let mut child = Command::new(plugin)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("couldn't start '{}': {}", plugin.display(), e))?;
+
+    let mut stdin = child.stdin.take().unwrap();
+    let stdout = child.stdout.take().unwrap();
+    let hello = json!({"version": VERSION, "layout": layout});
+    // a plugin that doesn't care can ignore (or close) its stdin
+    let _ = writeln!(stdin, "{}", hello);
+
+    thread::spawn(move || {
+        // kept open for as long as the plugin runs
+        let _stdin = stdin;
+        let id = format!("plugin:{}", name);
+        for line in BufReader::new(stdout).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let message = serde_json::from_str(&line)
+                .map_err(|e| e.to_string())
+                .and_then(|v| Message::from_json(&v, &keymap, &correction));
+            match message {
+                Ok(Message::Layer { slot, layer }) => layers.set(&id, slot, *layer),
+                Ok(Message::Clear) => layers.remove(&id),
+                Err(e) => error!("plugin '{}': {}", name, e),
+            }
+        }
+
+        layers.remove(&id);
+        match child.wait() {
+            Ok(status) if status.success() => info!("plugin '{}' exited", name),
+            Ok(status) => error!("plugin '{}' exited ({})", name, status),
+            Err(e) => error!("plugin '{}': {}", name, e),
+        }
+    });
+    Ok(())
+}
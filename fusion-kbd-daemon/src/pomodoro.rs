@@ -0,0 +1,167 @@
+//! Pomodoro timer: the keyboard slowly fills with color, left to right, over
+//! each work interval (in `WORK`) and break (in `BREAK`), and flashes the new
+//! color when one ends and the other begins.
+//!
+//! Runs standalone (`fusion-kbd-controller pomodoro`), or inside the daemon
+//! with a `[pomodoro]` table in the config file:
+//!
+//! ```toml
+//! [pomodoro]
+//! work_min = 25  # default: 25
+//! break_min = 5  # default: 5
+//! slot = 4
+//! ```
+//!
+//! Inside the daemon, it keeps time from when the daemon started, and (like
+//! `reactive`) is only drawn while the keyboard is showing `slot`.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use fusion_kbd_protocol::config::{key_position, MATRIX_COLS, NUM_KEYS};
+use fusion_kbd_protocol::correction::Correction;
+use fusion_kbd_protocol::protocol::NUM_SLOTS;
+use fusion_kbd_protocol::{effects, CustomConfig, Keyboard, Rgb};
+use log::error;
+
+use crate::control::Client;
+use crate::{saved, SCRATCH_SLOT};
+
+const WORK: Rgb = Rgb(0xff, 0x30, 0x00);
+const BREAK: Rgb = Rgb(0x00, 0xff, 0x60);
+
+/// how long the keyboard flashes for at the start of each interval
+const FLASH: Duration = Duration::from_secs(3);
+const FLASH_PERIOD: Duration = Duration::from_millis(500);
+
+/// how often the keyboard is redrawn (often enough to flash)
+const FRAME: Duration = Duration::from_millis(125);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub work: Duration,
+    /// how long breaks last
+    pub rest: Duration,
+    pub slot: u8,
+}
+
+impl Config {
+    /// parses the `[pomodoro]` table of the config file
+    pub fn from_toml(table: &toml::Table) -> Result<Config, String> {
+        let minutes = |name: &str, default: u64| -> Result<Duration, String> {
+            match table.get(name) {
+                None => Ok(Duration::from_secs(default * 60)),
+                Some(toml::Value::Integer(m)) if *m > 0 => Ok(Duration::from_secs(*m as u64 * 60)),
+                Some(_) => Err(format!("`pomodoro.{}` must be a positive number", name)),
+            }
+        };
+
+        let slot = match table.get("slot") {
+            None => SCRATCH_SLOT,
+            Some(toml::Value::Integer(s)) if (0..NUM_SLOTS as i64).contains(s) => *s as u8,
+            Some(_) => {
+                return Err(format!(
+                    "`pomodoro.slot` must be a number from 0 - {}",
+                    NUM_SLOTS - 1
+                ))
+            }
+        };
+
+        Ok(Config {
+            work: minutes("work_min", 25)?,
+            rest: minutes("break_min", 5)?,
+            slot,
+        })
+    }
+}
+
+/// A running pomodoro. What it shows only depends on how long it's been
+/// running, so drawing can stop and pick up again without losing time.
+pub struct Pomodoro {
+    config: Config,
+    start: Instant,
+}
+
+impl Pomodoro {
+    pub fn start(config: Config) -> Pomodoro {
+        Pomodoro {
+            config,
+            start: Instant::now(),
+        }
+    }
+
+    /// what the keyboard shows at `now`
+    pub fn frame(&self, now: Instant) -> CustomConfig {
+        let (work, rest) = (self.config.work, self.config.rest);
+        let cycle = work + rest;
+        let elapsed = now.saturating_duration_since(self.start);
+        let into_cycle = Duration::from_nanos((elapsed.as_nanos() % cycle.as_nanos()) as u64);
+
+        let (color, into, length) = if into_cycle < work {
+            (WORK, into_cycle, work)
+        } else {
+            (BREAK, into_cycle - work, rest)
+        };
+
+        // not when first starting, only at transitions
+        if elapsed >= work && into < FLASH {
+            let on = (into.as_millis() / (FLASH_PERIOD.as_millis() / 2)).is_multiple_of(2);
+            return effects::solid(if on { color } else { Rgb(0, 0, 0) });
+        }
+
+        // the front column fades in, rather than jumping on
+        let filled = into.as_secs_f32() / length.as_secs_f32() * MATRIX_COLS as f32;
+        let mut cfg = CustomConfig::new();
+        for key in 0..NUM_KEYS {
+            let (_, col) = key_position(key);
+            let level = (filled - col as f32).clamp(0.0, 1.0);
+            cfg.set_key(key, effects::blend(Rgb(0, 0, 0), color, level));
+        }
+        cfg
+    }
+}
+
+/// Draws `pomodoro` through `kbd` until a write fails. While `brightness`
+/// returns `None`, nothing is drawn.
+pub fn run(
+    kbd: &dyn Keyboard,
+    pomodoro: &Pomodoro,
+    correction: &Correction,
+    brightness: &dyn Fn() -> Option<u8>,
+) -> Result<(), libusb::Error> {
+    let slot = pomodoro.config.slot;
+    let mut shown: Option<(CustomConfig, u8)> = None;
+    loop {
+        match brightness() {
+            Some(brightness) => {
+                let cfg = correction.apply(&pomodoro.frame(Instant::now()));
+                let unchanged = shown
+                    .as_ref()
+                    .is_some_and(|(c, b)| c.as_bytes() == cfg.as_bytes() && *b == brightness);
+                if !unchanged {
+                    kbd.upload_custom(slot, cfg.as_bytes())?;
+                    kbd.set_custom(slot, brightness)?;
+                    shown = Some((cfg, brightness));
+                }
+            }
+            // so it's drawn straight away when the slot is shown again
+            None => shown = None,
+        }
+        thread::sleep(FRAME);
+    }
+}
+
+/// Starts a pomodoro in the background, drawn through `kbd` while its slot is
+/// showing.
+pub fn spawn(config: Config, kbd: Client, correction: Correction) {
+    thread::spawn(move || {
+        let slot = config.slot;
+        let pomodoro = Pomodoro::start(config);
+        loop {
+            if let Err(e) = run(&kbd, &pomodoro, &correction, &|| saved::showing(slot)) {
+                error!("couldn't draw the pomodoro: {}", e);
+                thread::sleep(Duration::from_secs(1));
+            }
+        }
+    });
+}
@@ -0,0 +1,18 @@
+//! The power source, for `power` rules.
+
+use std::fs;
+
+/// `Some(true)` when plugged in, or `None` if there's no AC adapter to ask
+/// (e.g: a desktop).
+pub fn on_ac() -> Option<bool> {
+    let mut adapters = fs::read_dir("/sys/class/power_supply")
+        .ok()?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| fs::read_to_string(p.join("type")).is_ok_and(|t| t.trim() == "Mains"))
+        .peekable();
+    adapters.peek()?;
+
+    // any adapter being online counts
+    Some(adapters.any(|p| fs::read_to_string(p.join("online")).is_ok_and(|o| o.trim() == "1")))
+}
@@ -0,0 +1,89 @@
+//! Named profiles, stored as `data_dir()/profiles/NAME.json`.
+//!
+//! A profile is a lighting state (see `state::State`), plus the colors to
+//! upload for custom profiles:
+//!
+//! ```json
+//! {"mode": "custom", "slot": 2, "brightness": 16, "keys": {"esc": "#ff0000"}}
+//! ```
+
+use std::fs;
+use std::path::PathBuf;
+
+use fusion_kbd_protocol::config::json;
+use fusion_kbd_protocol::state::{Lighting, State};
+use fusion_kbd_protocol::{CustomConfig, Keymap};
+
+use crate::paths;
+
+pub struct Profile {
+    pub state: State,
+    /// uploaded to the slot in `state` when loading a custom profile
+    pub config: Option<CustomConfig>,
+}
+
+fn dir() -> Result<PathBuf, String> {
+    paths::data_dir()
+        .map(|d| d.join("profiles"))
+        .ok_or_else(|| "couldn't work out where profiles go ($HOME isn't set)".to_string())
+}
+
+fn path(name: &str) -> Result<PathBuf, String> {
+    if name.is_empty() || name.starts_with('.') || name.contains('/') {
+        return Err(format!("'{}' isn't a valid profile name", name));
+    }
+    Ok(dir()?.join(format!("{}.json", name)))
+}
+
+pub fn save(name: &str, profile: &Profile, keymap: &Keymap) -> Result<PathBuf, String> {
+    let mut value = profile.state.to_json();
+    if let Some(ref cfg) = profile.config {
+        value["keys"] = serde_json::from_str(&json::to_json(cfg, keymap)).unwrap();
+    }
+
+    let path = path(name)?;
+    fs::create_dir_all(dir()?)
+        .and_then(|_| fs::write(&path, format!("{:#}\n", value)))
+        .map_err(|e| format!("couldn't write '{}': {}", path.display(), e))?;
+    Ok(path)
+}
+
+pub fn load(name: &str, keymap: &Keymap) -> Result<Profile, String> {
+    let path = path(name)?;
+    let text = fs::read_to_string(&path).map_err(|_| format!("no profile named '{}'", name))?;
+    let invalid = |e: String| format!("invalid profile '{}': {}", path.display(), e);
+
+    let value: serde_json::Value =
+        serde_json::from_str(&text).map_err(|e| invalid(e.to_string()))?;
+    let state = State::from_json(&value).map_err(invalid)?;
+
+    let config = match (&state.lighting, value.get("keys")) {
+        (Lighting::Custom { .. }, Some(keys)) => {
+            Some(json::from_json(&keys.to_string(), keymap).map_err(invalid)?)
+        }
+        _ => None,
+    };
+
+    Ok(Profile { state, config })
+}
+
+/// names of all saved profiles, sorted
+pub fn list() -> Result<Vec<String>, String> {
+    let entries = match fs::read_dir(dir()?) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(Vec::new()),
+    };
+
+    let mut names: Vec<String> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|e| e == "json"))
+        .filter_map(|p| Some(p.file_stem()?.to_str()?.to_string()))
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+pub fn delete(name: &str) -> Result<(), String> {
+    fs::remove_file(path(name)?).map_err(|_| format!("no profile named '{}'", name))
+}
@@ -0,0 +1,415 @@
+//! Software reactive effects: key presses are read straight from `/dev/input`,
+//! and rendered as effects starting at the key that was actually pressed,
+//! which the firmware's reactive presets can't do.
+//!
+//! Configured with a `[reactive]` table in the config file:
+//!
+//! ```toml
+//! [reactive]
+//! effect = "ripple"   # or "fade", "trail"
+//! color = "#00ffff"
+//! background = "black"
+//! blend = "normal"    # or "add", "multiply", "screen"
+//! duration_ms = 600
+//! slot = 4
+//! devices = ["/dev/input/by-path/platform-i8042-serio-0-event-kbd"]
+//! ```
+//!
+//! Every key but `effect` is optional. `devices` defaults to every keyboard in
+//! `/dev/input/by-path`.
+//!
+//! `background` is uploaded to `slot` at startup, and effects are drawn as a
+//! layer over it (see `compositor`), combined with it per `blend`. Anything
+//! uploaded to `slot` later (e.g: a profile) replaces the background, and
+//! effects go over that instead. They're only drawn while the keyboard is
+//! showing `slot` (e.g: after `custom 4`, or a `when startup then custom 4`
+//! rule), so they don't trample other lighting.
+
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use fusion_kbd_protocol::config::{key_position, MATRIX_COLS, NUM_KEYS};
+use fusion_kbd_protocol::correction::Correction;
+use fusion_kbd_protocol::effects::{self, FrameClock};
+use fusion_kbd_protocol::layers::{Blend, Layer, Mask};
+use fusion_kbd_protocol::protocol::NUM_SLOTS;
+use fusion_kbd_protocol::{Keyboard, Keymap, Rgb};
+use log::error;
+
+use crate::compositor::Layers;
+use crate::control::Client;
+use crate::SCRATCH_SLOT;
+use crate::{evdev, saved};
+
+pub const EFFECTS: &[&str] = &["fade", "ripple", "trail"];
+
+const FRAME: Duration = Duration::from_millis(33);
+
+/// how far a ripple travels over its duration, in keys
+const RIPPLE_RADIUS: f32 = 8.0;
+/// how long a trail's fading tail is, in keys (the head reaches the end of
+/// the row)
+const TRAIL_LENGTH: f32 = 3.0;
+
+/// Linux `KEY_*` codes (see linux/input-event-codes.h), and the keymap names
+/// they're looked up as. Keys with no LED (or no keymap name) are left out.
+static KEYCODES: &[(u16, &str)] = &[
+    (1, "esc"),
+    (2, "1"),
+    (3, "2"),
+    (4, "3"),
+    (5, "4"),
+    (6, "5"),
+    (7, "6"),
+    (8, "7"),
+    (9, "8"),
+    (10, "9"),
+    (11, "0"),
+    (12, "-"),
+    (13, "="),
+    (14, "backspace"),
+    (15, "tab"),
+    (16, "q"),
+    (17, "w"),
+    (18, "e"),
+    (19, "r"),
+    (20, "t"),
+    (21, "y"),
+    (22, "u"),
+    (23, "i"),
+    (24, "o"),
+    (25, "p"),
+    (26, "["),
+    (27, "]"),
+    (28, "enter"),
+    (29, "lctrl"),
+    (30, "a"),
+    (31, "s"),
+    (32, "d"),
+    (33, "f"),
+    (34, "g"),
+    (35, "h"),
+    (36, "j"),
+    (37, "k"),
+    (38, "l"),
+    (39, ";"),
+    (40, "'"),
+    (41, "`"),
+    (42, "shift"),
+    // the `#` key on ISO layouts reports itself as backslash
+    (43, "\\"),
+    (43, "#"),
+    (44, "z"),
+    (45, "x"),
+    (46, "c"),
+    (47, "v"),
+    (48, "b"),
+    (49, "n"),
+    (50, "m"),
+    (51, ","),
+    (52, "."),
+    (53, "/"),
+    (54, "rshift"),
+    (55, "num*"),
+    (56, "lalt"),
+    (57, "space"),
+    (58, "caps"),
+    (59, "f1"),
+    (60, "f2"),
+    (61, "f3"),
+    (62, "f4"),
+    (63, "f5"),
+    (64, "f6"),
+    (65, "f7"),
+    (66, "f8"),
+    (67, "f9"),
+    (68, "f10"),
+    (69, "numlk"),
+    (71, "num7"),
+    (72, "num8"),
+    (73, "num9"),
+    (74, "num-"),
+    (75, "num4"),
+    (76, "num5"),
+    (77, "num6"),
+    (78, "num+"),
+    (79, "num1"),
+    (80, "num2"),
+    (81, "num3"),
+    (82, "num0"),
+    (83, "num."),
+    (86, "iso\\"),
+    (87, "f11"),
+    (88, "f12"),
+    (96, "numenter"),
+    (97, "rctrl"),
+    (98, "num/"),
+    (100, "ralt"),
+    (102, "home"),
+    (103, "up"),
+    (104, "pgup"),
+    (105, "left"),
+    (106, "right"),
+    (107, "end"),
+    (108, "down"),
+    (109, "pgdn"),
+    (111, "del"),
+    (119, "pause"),
+    (125, "win"),
+    (127, "menu"),
+];
+
+/// the key a `KEY_*` code lights up on `keymap`
+fn key_for(code: u16, keymap: &Keymap) -> Option<usize> {
+    KEYCODES
+        .iter()
+        .filter(|&&(c, _)| c == code)
+        .find_map(|&(_, name)| keymap.index(name))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    /// the pressed key lights up, then fades out
+    Fade,
+    /// a ring spreading out from the pressed key
+    Ripple,
+    /// a streak running out along the pressed key's row, with a fading tail
+    Trail,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub effect: Effect,
+    pub color: Rgb,
+    pub background: Rgb,
+    /// how effects combine with what's under them
+    pub blend: Blend,
+    pub duration: Duration,
+    pub slot: u8,
+    /// evdev devices to read, or every keyboard if empty
+    pub devices: Vec<PathBuf>,
+}
+
+impl Config {
+    /// parses the `[reactive]` table of the config file
+    pub fn from_toml(table: &toml::Table) -> Result<Config, String> {
+        let color = |name: &str, default: Rgb| -> Result<Rgb, String> {
+            match table.get(name) {
+                None => Ok(default),
+                Some(toml::Value::String(c)) => {
+                    Rgb::from_str(c).map_err(|e| format!("`reactive.{}`: {}", name, e))
+                }
+                Some(_) => Err(format!("`reactive.{}` must be a color", name)),
+            }
+        };
+
+        let effect = match table.get("effect").and_then(|e| e.as_str()) {
+            Some("fade") => Effect::Fade,
+            Some("ripple") => Effect::Ripple,
+            Some("trail") => Effect::Trail,
+            _ => {
+                return Err(format!(
+                    "`reactive.effect` must be one of: {}",
+                    EFFECTS.join(", ")
+                ))
+            }
+        };
+
+        let blend = match table.get("blend") {
+            None => Blend::Normal,
+            Some(toml::Value::String(b)) => {
+                Blend::from_str(b).map_err(|e| format!("`reactive.blend`: {}", e))?
+            }
+            Some(_) => return Err("`reactive.blend` must be a string".to_string()),
+        };
+
+        let duration = match table.get("duration_ms") {
+            None => Duration::from_millis(600),
+            Some(toml::Value::Integer(ms)) if *ms > 0 => Duration::from_millis(*ms as u64),
+            Some(_) => return Err("`reactive.duration_ms` must be a positive number".to_string()),
+        };
+
+        let slot = match table.get("slot") {
+            None => SCRATCH_SLOT,
+            Some(toml::Value::Integer(s)) if (0..NUM_SLOTS as i64).contains(s) => *s as u8,
+            Some(_) => {
+                return Err(format!(
+                    "`reactive.slot` must be a number from 0 - {}",
+                    NUM_SLOTS - 1
+                ))
+            }
+        };
+
+        let devices = match table.get("devices") {
+            None => Vec::new(),
+            Some(toml::Value::Array(devices)) => devices
+                .iter()
+                .map(|d| d.as_str().map(PathBuf::from))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| "`reactive.devices` must be a list of paths".to_string())?,
+            Some(_) => return Err("`reactive.devices` must be a list of paths".to_string()),
+        };
+
+        Ok(Config {
+            effect,
+            color: color("color", Rgb(0xff, 0xff, 0xff))?,
+            background: color("background", Rgb(0, 0, 0))?,
+            blend,
+            duration,
+            slot,
+            devices,
+        })
+    }
+}
+
+/// the `KEY_*` code of a key press (releases and autorepeats don't count)
+fn pressed(event: evdev::Event) -> Option<u16> {
+    if event.kind == evdev::EV_KEY && event.value == 1 {
+        Some(event.code)
+    } else {
+        None
+    }
+}
+
+/// How lit (0 - 1) `key` is, `t` of the way (0 - 1) through an effect started
+/// by `pressed`.
+fn intensity(effect: Effect, pressed: usize, key: usize, t: f32) -> f32 {
+    let (row, col) = key_position(key);
+    let (prow, pcol) = key_position(pressed);
+    let fading = 1.0 - t;
+
+    match effect {
+        Effect::Fade if key == pressed => fading,
+        Effect::Fade => 0.0,
+        Effect::Ripple => {
+            let (dx, dy) = (col as f32 - pcol as f32, row as f32 - prow as f32);
+            let distance = (dx * dx + dy * dy).sqrt();
+            let radius = t * RIPPLE_RADIUS;
+            (1.0 - (distance - radius).abs()).max(0.0) * fading
+        }
+        Effect::Trail if row == prow => {
+            let distance = (col as f32 - pcol as f32).abs();
+            let head = t * MATRIX_COLS as f32;
+            if distance > head {
+                0.0
+            } else {
+                (1.0 - (head - distance) / TRAIL_LENGTH).max(0.0) * fading
+            }
+        }
+        Effect::Trail => 0.0,
+    }
+}
+
+/// The effects of every recent press, as a layer.
+struct Renderer {
+    config: Config,
+    /// corrected, like the slot's
+    color: Rgb,
+    /// key, and when it was pressed
+    presses: Vec<(usize, Instant)>,
+}
+
+impl Renderer {
+    fn render(&mut self, now: Instant) -> Layer {
+        let duration = self.config.duration;
+        self.presses
+            .retain(|&(_, at)| now.saturating_duration_since(at) < duration);
+
+        let mut mask = Mask::none();
+        for key in 0..NUM_KEYS {
+            let level = self
+                .presses
+                .iter()
+                .map(|&(pressed, at)| {
+                    let t =
+                        now.saturating_duration_since(at).as_secs_f32() / duration.as_secs_f32();
+                    intensity(self.config.effect, pressed, key, t)
+                })
+                .fold(0.0, f32::max);
+            mask.set(key, level);
+        }
+        Layer {
+            config: effects::solid(self.color),
+            mask,
+            blend: self.config.blend,
+        }
+    }
+}
+
+/// Starts listening for key presses in the background, uploading the
+/// background through `kbd` and drawing effects as one of `layers`. Only
+/// opening the devices can fail.
+pub fn spawn(
+    config: Config,
+    keymap: Keymap,
+    kbd: Client,
+    layers: Layers,
+    correction: Correction,
+) -> Result<(), String> {
+    let devices = if config.devices.is_empty() {
+        evdev::keyboards()
+    } else {
+        config.devices.clone()
+    };
+    if devices.is_empty() {
+        return Err("no keyboards found in /dev/input/by-path".to_string());
+    }
+
+    let (tx, events) = mpsc::channel();
+    for device in devices {
+        evdev::listen(device, tx.clone())?;
+    }
+
+    thread::spawn(move || {
+        let slot = config.slot;
+        // so switching to the slot shows the background right away
+        let background = correction.apply(&effects::solid(config.background));
+        if let Err(e) = kbd.upload_custom(slot, background.as_bytes()) {
+            error!("couldn't upload the reactive background: {}", e);
+        }
+
+        let mut renderer = Renderer {
+            color: correction.rgb(config.color),
+            config,
+            presses: Vec::new(),
+        };
+        // wait for a press, then animate until every effect has finished
+        while let Ok(event) = events.recv() {
+            let code = match pressed(event) {
+                Some(code) => code,
+                None => continue,
+            };
+            if saved::showing(slot).is_none() {
+                continue;
+            }
+
+            let mut clock = FrameClock::new();
+            let mut code = Some(code);
+            loop {
+                let now = Instant::now();
+                let more = events.try_iter().filter_map(pressed);
+                for c in code.take().into_iter().chain(more) {
+                    if let Some(key) = key_for(c, &keymap) {
+                        renderer.presses.push((key, now));
+                    }
+                }
+
+                let layer = renderer.render(now);
+                if renderer.presses.is_empty() {
+                    layers.remove("reactive");
+                    break;
+                }
+                layers.set("reactive", Some(slot), layer);
+
+                clock.wait(FRAME);
+                while clock.is_late(FRAME) {
+                    clock.skip(FRAME);
+                }
+            }
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,621 @@
+//! Declarative lighting rules.
+//!
+//! Rules are listed in the `rules` array of the config file, one per string:
+//!
+//! ```toml
+//! rules = [
+//!     "when startup then preset static white",
+//!     "when time 22:00-07:00 then brightness 5",
+//!     "when time 09:00-17:00 and day mon-fri then custom 1",
+//!     "when power battery then brightness 5",
+//!     "when not window class steam_app_* then profile default",
+//!     "when window class kitty then solid red",
+//!     "when window class steam_app_* then profile gaming",
+//! ]
+//! ```
+//!
+//! The grammar is `when <trigger> [and <condition>]... then <action>`.
+//! Triggers and conditions are both predicates: a rule fires when its trigger
+//! *becomes* true (i.e: it was false the last time rules were evaluated), as
+//! long as all of its conditions hold at that moment. When several rules fire
+//! at once, their actions are applied in the order the rules are listed.
+//!
+//! Predicates:
+//!
+//! - `startup` - true from the moment the daemon starts
+//! - `time HH:MM-HH:MM` - wall-clock window, which may wrap past midnight
+//! - `day <days>` - comma separated weekdays and/or ranges (`mon-fri,sun`)
+//! - `power ac` / `power battery` - the power source (see `power`). Neither
+//!   is ever true without an AC adapter.
+//! - `window class <pattern>` / `window title <pattern>` - the focused window
+//!   (see `window`). Patterns are case-insensitive, and `*` matches anything.
+//! - `not <predicate>`
+//!
+//! Actions:
+//!
+//! - `preset <preset> [color] [speed N]`
+//! - `custom <slot>`
+//! - `solid <color>` (uploaded to the scratch slot)
+//! - `brightness <N>`
+//! - `profile <name>` (see `profile`)
+
+use std::str::FromStr;
+
+use chrono::{Datelike, Local, Timelike};
+use fusion_kbd_protocol::protocol::{MAX_BRIGHTNESS, MAX_SPEED, NUM_SLOTS};
+use fusion_kbd_protocol::{Color, Preset, Rgb};
+
+use crate::power;
+use crate::window::Window;
+
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// Everything a predicate can be evaluated against
+#[derive(Debug, Clone)]
+pub struct Facts {
+    /// minutes since local midnight
+    pub minute_of_day: u32,
+    /// 0 = monday
+    pub weekday: u32,
+    /// whether we're plugged in, if that can be told
+    pub on_ac: Option<bool>,
+    /// the focused window, if known
+    pub window: Option<Window>,
+}
+
+impl Facts {
+    /// The current time and power source. Looking up the focused window is comparatively slow,
+    /// so `window` is left for the caller to fill in (see
+    /// `Engine::needs_window`).
+    pub fn now() -> Facts {
+        let now = Local::now();
+        Facts {
+            minute_of_day: now.hour() * 60 + now.minute(),
+            weekday: now.weekday().num_days_from_monday(),
+            on_ac: power::on_ac(),
+            window: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowField {
+    Class,
+    Title,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Predicate {
+    Startup,
+    /// [start, end) in minutes since midnight. `start > end` wraps midnight.
+    Time {
+        start: u32,
+        end: u32,
+    },
+    /// indexed by `Facts::weekday`
+    Day([bool; 7]),
+    /// true = on AC
+    Power(bool),
+    /// `pattern` is lowercase
+    Window {
+        field: WindowField,
+        pattern: String,
+    },
+    Not(Box<Predicate>),
+}
+
+impl Predicate {
+    pub fn eval(&self, facts: &Facts) -> bool {
+        match *self {
+            Predicate::Startup => true,
+            Predicate::Window { field, ref pattern } => match facts.window {
+                Some(ref window) => {
+                    let value = match field {
+                        WindowField::Class => &window.class,
+                        WindowField::Title => &window.title,
+                    };
+                    glob(pattern, &value.to_lowercase())
+                }
+                None => false,
+            },
+            Predicate::Power(ac) => facts.on_ac == Some(ac),
+            Predicate::Not(ref p) => !p.eval(facts),
+            Predicate::Time { start, end } => {
+                let now = facts.minute_of_day;
+                if start <= end {
+                    start <= now && now < end
+                } else {
+                    now >= start || now < end
+                }
+            }
+            Predicate::Day(days) => days[facts.weekday as usize],
+        }
+    }
+
+    fn uses_window(&self) -> bool {
+        match *self {
+            Predicate::Window { .. } => true,
+            Predicate::Not(ref p) => p.uses_window(),
+            _ => false,
+        }
+    }
+
+    fn parse(words: &[&str]) -> Result<Predicate, String> {
+        match words {
+            ["startup"] => Ok(Predicate::Startup),
+            ["time", window] => {
+                let (start, end) = split_pair(window, "time window")?;
+                Ok(Predicate::Time {
+                    start: parse_time(start)?,
+                    end: parse_time(end)?,
+                })
+            }
+            ["day", list] => {
+                let mut days = [false; 7];
+                for item in list.split(',') {
+                    let (first, last) = match item.find('-') {
+                        Some(_) => split_pair(item, "day range")?,
+                        None => (item, item),
+                    };
+                    let (first, last) = (parse_day(first)?, parse_day(last)?);
+                    // ranges may wrap past sunday (e.g: `fri-mon`)
+                    let mut day = first;
+                    loop {
+                        days[day] = true;
+                        if day == last {
+                            break;
+                        }
+                        day = (day + 1) % 7;
+                    }
+                }
+                Ok(Predicate::Day(days))
+            }
+            ["power", "ac"] => Ok(Predicate::Power(true)),
+            ["power", "battery"] => Ok(Predicate::Power(false)),
+            ["window", field, pattern @ ..] if !pattern.is_empty() => {
+                let field = match *field {
+                    "class" => WindowField::Class,
+                    "title" => WindowField::Title,
+                    _ => return Err(format!("'{}' isn't `class` or `title`", field)),
+                };
+                Ok(Predicate::Window {
+                    field,
+                    pattern: pattern.join(" ").to_lowercase(),
+                })
+            }
+            ["not", rest @ ..] => Ok(Predicate::Not(Box::new(Predicate::parse(rest)?))),
+            _ => Err(format!("unknown predicate '{}'", words.join(" "))),
+        }
+    }
+}
+
+/// `*` matches any run of characters, everything else matches itself
+fn glob(pattern: &str, s: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == s,
+        Some((prefix, rest)) => {
+            let s = match s.strip_prefix(prefix) {
+                Some(s) => s,
+                None => return false,
+            };
+            // try every possible length for the `*`
+            s.char_indices()
+                .map(|(i, _)| i)
+                .chain(Some(s.len()))
+                .any(|i| glob(rest, &s[i..]))
+        }
+    }
+}
+
+fn split_pair<'a>(s: &'a str, what: &str) -> Result<(&'a str, &'a str), String> {
+    let mut parts = s.splitn(2, '-');
+    match (parts.next(), parts.next()) {
+        (Some(a), Some(b)) => Ok((a, b)),
+        _ => Err(format!("'{}' is not a valid {}", s, what)),
+    }
+}
+
+/// `HH:MM` -> minutes since midnight
+fn parse_time(s: &str) -> Result<u32, String> {
+    let err = || format!("'{}' is not a valid time (expected HH:MM)", s);
+
+    let mut parts = s.splitn(2, ':');
+    let hour: u32 = parts.next().and_then(|h| h.parse().ok()).ok_or_else(err)?;
+    let minute: u32 = parts.next().and_then(|m| m.parse().ok()).ok_or_else(err)?;
+    if hour > 24 || minute > 59 || (hour == 24 && minute != 0) {
+        return Err(err());
+    }
+    Ok(hour * 60 + minute)
+}
+
+fn parse_day(s: &str) -> Result<usize, String> {
+    DAYS.iter()
+        .position(|&d| d == s)
+        .ok_or_else(|| format!("'{}' is not a day (expected one of {})", s, DAYS.join(", ")))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Preset {
+        preset: Preset,
+        color: Color,
+        speed: u8,
+    },
+    Custom {
+        slot: u8,
+    },
+    Solid(Rgb),
+    Brightness(u8),
+    Profile(String),
+}
+
+impl Action {
+    fn parse(words: &[&str]) -> Result<Action, String> {
+        let number = |s: &str, max: u8, what: &str| -> Result<u8, String> {
+            match s.parse::<u8>() {
+                Ok(n) if n <= max => Ok(n),
+                _ => Err(format!("{} must be a number from 0 - {}", what, max)),
+            }
+        };
+
+        match words {
+            ["preset", preset, rest @ ..] => {
+                let preset =
+                    Preset::from_str(preset).map_err(|_| format!("unknown preset '{}'", preset))?;
+
+                let (color, rest) = match rest {
+                    [color, rest @ ..] if *color != "speed" => (Some(Color::lookup(color)?), rest),
+                    _ => (None, rest),
+                };
+                let speed = match rest {
+                    [] => None,
+                    ["speed", n] => Some(number(n, MAX_SPEED, "speed")?),
+                    _ => return Err(format!("unexpected '{}'", rest.join(" "))),
+                };
+                if let Some(unused) = preset.unused_params(speed, color).into_iter().next() {
+                    return Err(unused);
+                }
+                let (color, speed) = (color.unwrap_or(Color::Rand), speed.unwrap_or(5));
+
+                Ok(Action::Preset {
+                    preset,
+                    color,
+                    speed,
+                })
+            }
+            ["custom", slot] => Ok(Action::Custom {
+                slot: number(slot, NUM_SLOTS - 1, "slot")?,
+            }),
+            ["solid", color] => Ok(Action::Solid(Rgb::from_str(color)?)),
+            ["brightness", n] => Ok(Action::Brightness(number(n, MAX_BRIGHTNESS, "brightness")?)),
+            ["profile", name] => Ok(Action::Profile(name.to_string())),
+            _ => Err(format!("unknown action '{}'", words.join(" "))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub trigger: Predicate,
+    pub conditions: Vec<Predicate>,
+    pub action: Action,
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Rule, String> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+
+        let body = match words.split_first() {
+            Some((&"when", body)) => body,
+            _ => return Err("rules must start with `when`".to_string()),
+        };
+        let then = body
+            .iter()
+            .position(|&w| w == "then")
+            .ok_or_else(|| "rule is missing `then <action>`".to_string())?;
+
+        let mut predicates = body[..then]
+            .split(|&w| w == "and")
+            .map(Predicate::parse)
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter();
+
+        Ok(Rule {
+            trigger: predicates.next().unwrap(),
+            conditions: predicates.collect(),
+            action: Action::parse(&body[then + 1..])?,
+        })
+    }
+}
+
+/// Tracks trigger edges across evaluations
+pub struct Engine {
+    rules: Vec<Rule>,
+    armed: Vec<bool>,
+}
+
+impl Engine {
+    pub fn new(rules: Vec<Rule>) -> Engine {
+        let armed = vec![true; rules.len()];
+        Engine { rules, armed }
+    }
+
+    /// parses the `rules` array of a config file
+    pub fn from_strings(rules: &[String]) -> Result<Engine, String> {
+        let rules = rules
+            .iter()
+            .map(|r| {
+                r.parse()
+                    .map_err(|e| format!("invalid rule '{}': {}", r, e))
+            })
+            .collect::<Result<Vec<Rule>, String>>()?;
+        Ok(Engine::new(rules))
+    }
+
+    /// whether evaluating needs `Facts::window`
+    pub fn needs_window(&self) -> bool {
+        self.rules
+            .iter()
+            .any(|r| r.trigger.uses_window() || r.conditions.iter().any(Predicate::uses_window))
+    }
+
+    /// actions of every rule that just fired, in order
+    pub fn evaluate(&mut self, facts: &Facts) -> Vec<Action> {
+        let mut fired = Vec::new();
+        for (rule, armed) in self.rules.iter().zip(self.armed.iter_mut()) {
+            let triggered = rule.trigger.eval(facts);
+            if triggered && *armed && rule.conditions.iter().all(|c| c.eval(facts)) {
+                fired.push(rule.action.clone());
+            }
+            // re-arm once the trigger goes false again
+            *armed = !triggered;
+        }
+        fired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a monday, at `hh:mm`, on AC, with no window
+    fn at(hour: u32, minute: u32) -> Facts {
+        Facts {
+            minute_of_day: hour * 60 + minute,
+            weekday: 0,
+            on_ac: Some(true),
+            window: None,
+        }
+    }
+
+    fn engine(rules: &[&str]) -> Engine {
+        let rules: Vec<String> = rules.iter().map(|r| r.to_string()).collect();
+        Engine::from_strings(&rules).unwrap()
+    }
+
+    fn parse_err(rule: &str) -> String {
+        rule.parse::<Rule>().unwrap_err()
+    }
+
+    #[test]
+    fn parses_triggers_conditions_and_actions() {
+        let rule: Rule =
+            "when time 22:00-07:00 and day mon-fri,sun and not power battery then brightness 5"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            rule.trigger,
+            Predicate::Time {
+                start: 22 * 60,
+                end: 7 * 60
+            }
+        );
+        assert_eq!(
+            rule.conditions,
+            vec![
+                Predicate::Day([true, true, true, true, true, false, true]),
+                Predicate::Not(Box::new(Predicate::Power(false))),
+            ]
+        );
+        assert_eq!(rule.action, Action::Brightness(5));
+
+        let rule: Rule = "when window class Steam_App_* then preset wave speed 3"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            rule.trigger,
+            Predicate::Window {
+                field: WindowField::Class,
+                pattern: "steam_app_*".to_string()
+            }
+        );
+        assert_eq!(
+            rule.action,
+            Action::Preset {
+                preset: Preset::Wave,
+                color: Color::Rand,
+                speed: 3
+            }
+        );
+    }
+
+    #[test]
+    fn day_ranges_wrap_past_sunday() {
+        let rule: Rule = "when day fri-mon then custom 1".parse().unwrap();
+        assert_eq!(
+            rule.trigger,
+            Predicate::Day([true, false, false, false, true, true, true])
+        );
+    }
+
+    #[test]
+    fn malformed_rules_say_what_is_wrong() {
+        assert_eq!(
+            parse_err("if startup then custom 1"),
+            "rules must start with `when`"
+        );
+        assert_eq!(parse_err("when startup"), "rule is missing `then <action>`");
+        assert_eq!(
+            parse_err("when dusk then custom 1"),
+            "unknown predicate 'dusk'"
+        );
+        assert_eq!(
+            parse_err("when startup and then custom 1"),
+            "unknown predicate ''"
+        );
+        assert_eq!(
+            parse_err("when time 25:00-07:00 then custom 1"),
+            "'25:00' is not a valid time (expected HH:MM)"
+        );
+        assert_eq!(
+            parse_err("when time 22:00 then custom 1"),
+            "'22:00' is not a valid time window"
+        );
+        assert!(parse_err("when day someday then custom 1").starts_with("'someday' is not a day"));
+        assert_eq!(
+            parse_err("when window size big then custom 1"),
+            "'size' isn't `class` or `title`"
+        );
+    }
+
+    #[test]
+    fn malformed_actions_say_what_is_wrong() {
+        assert_eq!(
+            parse_err("when startup then custom 5"),
+            "slot must be a number from 0 - 4"
+        );
+        assert_eq!(
+            parse_err("when startup then brightness 51"),
+            "brightness must be a number from 0 - 50"
+        );
+        assert_eq!(
+            parse_err("when startup then preset wave speed 11"),
+            "speed must be a number from 0 - 10"
+        );
+        assert_eq!(
+            parse_err("when startup then preset sparkle"),
+            "unknown preset 'sparkle'"
+        );
+        assert_eq!(
+            parse_err("when startup then preset static white speed 3"),
+            "`static` doesn't take a speed (it doesn't move)"
+        );
+        assert_eq!(
+            parse_err("when startup then preset wave red"),
+            "`wave` doesn't take a color (it brings its own)"
+        );
+        assert_eq!(
+            parse_err("when startup then preset breathing red fast"),
+            "unexpected 'fast'"
+        );
+        assert_eq!(
+            parse_err("when startup then dance"),
+            "unknown action 'dance'"
+        );
+    }
+
+    #[test]
+    fn engine_errors_name_the_rule() {
+        let rules = vec!["when startup then custom 1".to_string(), "when".to_string()];
+        assert_eq!(
+            Engine::from_strings(&rules).err().unwrap(),
+            "invalid rule 'when': rule is missing `then <action>`"
+        );
+    }
+
+    #[test]
+    fn rules_fire_when_their_trigger_becomes_true() {
+        let mut engine = engine(&["when time 22:00-07:00 then brightness 5"]);
+        assert!(engine.evaluate(&at(21, 59)).is_empty());
+        assert_eq!(engine.evaluate(&at(22, 0)), vec![Action::Brightness(5)]);
+        // still true, so it doesn't fire again (past midnight, either)
+        assert!(engine.evaluate(&at(23, 30)).is_empty());
+        assert!(engine.evaluate(&at(3, 0)).is_empty());
+        // false, then true the next night
+        assert!(engine.evaluate(&at(7, 0)).is_empty());
+        assert_eq!(engine.evaluate(&at(22, 15)), vec![Action::Brightness(5)]);
+    }
+
+    #[test]
+    fn startup_fires_once() {
+        let mut engine = engine(&["when startup then custom 2"]);
+        assert_eq!(
+            engine.evaluate(&at(12, 0)),
+            vec![Action::Custom { slot: 2 }]
+        );
+        assert!(engine.evaluate(&at(12, 1)).is_empty());
+    }
+
+    #[test]
+    fn conditions_gate_but_dont_trigger() {
+        let mut engine = engine(&["when time 09:00-17:00 and power battery then brightness 5"]);
+        // the trigger becomes true while the condition doesn't hold: nothing
+        assert!(engine.evaluate(&at(9, 0)).is_empty());
+        // the condition becoming true later doesn't fire it either
+        let unplugged = Facts {
+            on_ac: Some(false),
+            ..at(10, 0)
+        };
+        assert!(engine.evaluate(&unplugged).is_empty());
+        // only the trigger's next edge does
+        assert!(engine.evaluate(&at(18, 0)).is_empty());
+        let unplugged = Facts {
+            on_ac: Some(false),
+            ..at(9, 30)
+        };
+        assert_eq!(engine.evaluate(&unplugged), vec![Action::Brightness(5)]);
+    }
+
+    #[test]
+    fn rules_that_fire_together_apply_in_order() {
+        let mut engine = engine(&[
+            "when startup then preset static white",
+            "when power ac then brightness 20",
+            "when power battery then brightness 5",
+        ]);
+        assert_eq!(
+            engine.evaluate(&at(8, 0)),
+            vec![
+                Action::Preset {
+                    preset: Preset::Static,
+                    color: Color::White,
+                    speed: 5
+                },
+                Action::Brightness(20),
+            ]
+        );
+    }
+
+    #[test]
+    fn window_predicates_match_case_insensitive_globs() {
+        assert!(!engine(&["when startup then custom 1"]).needs_window());
+
+        let mut engine = engine(&["when window title *- Mozilla Firefox then custom 3"]);
+        assert!(engine.needs_window());
+        assert!(engine.evaluate(&at(12, 0)).is_empty());
+
+        let browsing = Facts {
+            window: Some(Window {
+                class: "firefox".to_string(),
+                title: "Rust - MOZILLA FIREFOX".to_string(),
+            }),
+            ..at(12, 0)
+        };
+        assert_eq!(engine.evaluate(&browsing), vec![Action::Custom { slot: 3 }]);
+    }
+
+    #[test]
+    fn power_is_never_known_without_an_adapter() {
+        let mut engine = engine(&[
+            "when power ac then custom 1",
+            "when power battery then custom 2",
+        ]);
+        let desktop = Facts {
+            on_ac: None,
+            ..at(12, 0)
+        };
+        assert!(engine.evaluate(&desktop).is_empty());
+    }
+}
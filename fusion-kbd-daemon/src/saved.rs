@@ -0,0 +1,56 @@
+//! The last lighting state that was applied (by the CLI, or the daemon), so
+//! `restore` can bring it back after a reboot. Stored as JSON in
+//! `state_dir()/state.json` (see `State::to_json`), plus `"off": true` while
+//! the backlight is turned off.
+
+use std::fs;
+use std::path::PathBuf;
+
+use fusion_kbd_protocol::state::{Lighting, State};
+
+use crate::paths;
+
+pub struct Saved {
+    /// while `off`, this is what `on` brings back, not what's showing
+    pub state: State,
+    pub off: bool,
+}
+
+fn path() -> Result<PathBuf, String> {
+    paths::state_dir()
+        .map(|d| d.join("state.json"))
+        .ok_or_else(|| "couldn't work out where state goes ($HOME isn't set)".to_string())
+}
+
+/// the last saved state, if there is one (and it's readable)
+pub fn load() -> Option<Saved> {
+    let text = fs::read_to_string(path().ok()?).ok()?;
+    let value: serde_json::Value = serde_json::from_str(&text).ok()?;
+    Some(Saved {
+        state: State::from_json(&value).ok()?,
+        off: value["off"].as_bool().unwrap_or(false),
+    })
+}
+
+/// the brightness to draw at, if the keyboard is currently showing `slot`
+pub fn showing(slot: u8) -> Option<u8> {
+    match load() {
+        Some(saved) if !saved.off && saved.state.lighting == Lighting::Custom { slot } => {
+            Some(saved.state.brightness)
+        }
+        _ => None,
+    }
+}
+
+pub fn save(saved: &Saved) -> Result<(), String> {
+    let mut value = saved.state.to_json();
+    if saved.off {
+        value["off"] = true.into();
+    }
+
+    let path = path()?;
+    path.parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&path, format!("{}\n", value)))
+        .map_err(|e| format!("couldn't write '{}': {}", path.display(), e))
+}
@@ -0,0 +1,137 @@
+//! Central queue for device writes.
+//!
+//! Bursty sources (volume keys, notification storms, ...) can submit updates
+//! faster than the controller can take them, which shows up as glitches. The
+//! scheduler coalesces them instead:
+//!
+//! - each source has at most one pending write; submitting another replaces it
+//! - a pending upload to a slot supersedes older pending uploads to that slot
+//! - writes from the same source, and uploads to the same slot, are spaced at
+//!   least `min_interval` apart
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use fusion_kbd_protocol::state::{Lighting, State};
+use fusion_kbd_protocol::{CustomConfig, Keyboard};
+
+use crate::events;
+use crate::saved::{self, Saved};
+
+/// what a source wants the keyboard to show
+#[derive(Clone)]
+pub struct Write {
+    pub state: State,
+    /// uploaded to the custom slot in `state` before switching to it
+    pub upload: Option<CustomConfig>,
+}
+
+impl Write {
+    fn upload_slot(&self) -> Option<u8> {
+        match (&self.upload, &self.state.lighting) {
+            (Some(_), Lighting::Custom { slot }) => Some(*slot),
+            _ => None,
+        }
+    }
+}
+
+pub struct Scheduler {
+    min_interval: Duration,
+    /// in submission order, at most one per source
+    pending: Vec<(String, Write)>,
+    last_write: HashMap<String, Instant>,
+    last_upload: HashMap<u8, Instant>,
+}
+
+impl Scheduler {
+    pub fn new(min_interval: Duration) -> Scheduler {
+        Scheduler {
+            min_interval,
+            pending: Vec::new(),
+            last_write: HashMap::new(),
+            last_upload: HashMap::new(),
+        }
+    }
+
+    pub fn submit(&mut self, source: &str, mut write: Write) {
+        // switching to a slot whose (still pending) upload is being replaced
+        // mustn't lose the upload
+        if let (None, Lighting::Custom { slot }) = (&write.upload, &write.state.lighting) {
+            let superseded = self
+                .pending
+                .iter()
+                .find(|(s, w)| s == source && w.upload_slot() == Some(*slot));
+            if let Some((_, old)) = superseded {
+                write.upload = old.upload.clone();
+            }
+        }
+
+        let slot = write.upload_slot();
+        self.pending
+            .retain(|(s, w)| s != source && (slot.is_none() || w.upload_slot() != slot));
+        self.pending.push((source.to_string(), write));
+    }
+
+    /// earliest moment `write` from `source` may go out
+    fn ready_at(&self, source: &str, write: &Write) -> Option<Instant> {
+        let after = |last: Option<&Instant>| last.map(|&t| t + self.min_interval);
+        let by_source = after(self.last_write.get(source));
+        let by_slot = write
+            .upload_slot()
+            .and_then(|slot| after(self.last_upload.get(&slot)));
+        by_source.max(by_slot)
+    }
+
+    /// when the next pending write becomes ready, if there are any
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.pending
+            .iter()
+            .map(|(s, w)| self.ready_at(s, w).unwrap_or_else(Instant::now))
+            .min()
+    }
+
+    /// Performs every pending write that's ready, notifying subscribers of (and
+    /// saving) each one that succeeds. Returns the writes that failed.
+    pub fn flush(&mut self, kbd: &dyn Keyboard, now: Instant) -> Vec<(String, libusb::Error)> {
+        let mut errors = Vec::new();
+
+        let mut i = 0;
+        while i < self.pending.len() {
+            let ready = {
+                let (source, write) = &self.pending[i];
+                self.ready_at(source, write).is_none_or(|t| t <= now)
+            };
+            if !ready {
+                i += 1;
+                continue;
+            }
+
+            let (source, write) = self.pending.remove(i);
+            self.last_write.insert(source.clone(), now);
+            if let Some(slot) = write.upload_slot() {
+                self.last_upload.insert(slot, now);
+            }
+
+            match perform(kbd, &write) {
+                Ok(()) => {
+                    events::publish(&source, &write.state);
+                    // best-effort, like the notification
+                    let _ = saved::save(&Saved {
+                        state: write.state,
+                        off: false,
+                    });
+                }
+                Err(e) => errors.push((source, e)),
+            }
+        }
+
+        errors
+    }
+}
+
+fn perform(kbd: &dyn Keyboard, write: &Write) -> Result<(), libusb::Error> {
+    if let (Some(slot), Some(cfg)) = (write.upload_slot(), &write.upload) {
+        kbd.upload_custom(slot, cfg.as_bytes())?;
+    }
+    kbd.set_state(&write.state)
+}
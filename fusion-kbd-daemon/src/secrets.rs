@@ -0,0 +1,137 @@
+//! Credentials for network integrations, kept out of the plaintext config.
+//!
+//! Any config value of the form `"secret:NAME"` is looked up in the configured
+//! backend instead of being used as-is:
+//!
+//! ```toml
+//! [secrets]
+//! backend = "age"                           # or "secret-service" (default)
+//! file = "~/.config/fusion-kbd/secrets.age" # age only
+//! identity = "~/.config/fusion-kbd/key.txt" # age only
+//!
+//! [mqtt]
+//! password = "secret:mqtt"
+//! ```
+//!
+//! - `secret-service`: the desktop keyring (GNOME Keyring, KWallet, ...), via
+//!   `secret-tool`. Store a secret with `secret-tool store --label fusion-kbd
+//!   service fusion-kbd key NAME`.
+//! - `age`: an [age](https://age-encryption.org) encrypted TOML file of
+//!   `NAME = "value"` pairs, decrypted once when the store is opened.
+
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+const PREFIX: &str = "secret:";
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Backend {
+    #[default]
+    SecretService,
+    Age {
+        file: PathBuf,
+        identity: PathBuf,
+    },
+}
+
+impl Backend {
+    /// parses the `[secrets]` table of the config file
+    pub fn from_toml(table: &toml::Table) -> Result<Backend, String> {
+        let path = |name: &str| -> Result<PathBuf, String> {
+            match table.get(name).and_then(|v| v.as_str()) {
+                Some(p) => Ok(expand_home(p)),
+                None => Err(format!("the age backend needs `secrets.{}`", name)),
+            }
+        };
+
+        match table.get("backend").map(|b| b.as_str()) {
+            None | Some(Some("secret-service")) => Ok(Backend::SecretService),
+            Some(Some("age")) => Ok(Backend::Age {
+                file: path("file")?,
+                identity: path("identity")?,
+            }),
+            Some(_) => Err("`secrets.backend` must be \"secret-service\" or \"age\"".to_string()),
+        }
+    }
+
+    /// Opens the store. For `age`, this decrypts the whole file up-front, so a
+    /// bad identity is caught at startup rather than on first use.
+    pub fn open(&self) -> Result<Secrets, String> {
+        let decrypted = match self {
+            Backend::SecretService => None,
+            Backend::Age { file, identity } => {
+                let out = run(Command::new("age")
+                    .arg("--decrypt")
+                    .arg("--identity")
+                    .arg(identity)
+                    .arg(file))?;
+                let table: toml::Table = out
+                    .parse()
+                    .map_err(|e: toml::de::Error| format!("'{}': {}", file.display(), e))?;
+                Some(table)
+            }
+        };
+        Ok(Secrets { decrypted })
+    }
+}
+
+pub struct Secrets {
+    /// contents of the age file, if that's the backend
+    decrypted: Option<toml::Table>,
+}
+
+impl Secrets {
+    /// looks up a secret by name
+    pub fn get(&self, name: &str) -> Result<String, String> {
+        match self.decrypted {
+            Some(ref table) => match table.get(name).and_then(|v| v.as_str()) {
+                Some(value) => Ok(value.to_string()),
+                None => Err(format!("no secret named '{}'", name)),
+            },
+            None => {
+                let value = run(Command::new("secret-tool").args([
+                    "lookup",
+                    "service",
+                    "fusion-kbd",
+                    "key",
+                    name,
+                ]))?;
+                Ok(value.trim_end_matches('\n').to_string())
+            }
+        }
+    }
+
+    /// `"secret:NAME"` is looked up, anything else is returned as-is
+    pub fn resolve(&self, value: &str) -> Result<String, String> {
+        match value.strip_prefix(PREFIX) {
+            Some(name) => self.get(name),
+            None => Ok(value.to_string()),
+        }
+    }
+}
+
+/// runs a helper, returning its stdout
+fn run(cmd: &mut Command) -> Result<String, String> {
+    let name = cmd.get_program().to_string_lossy().into_owned();
+    let out = cmd
+        .stdin(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|e| format!("couldn't run `{}`: {}", name, e))?;
+
+    if !out.status.success() {
+        return Err(format!(
+            "`{}` failed: {}",
+            name,
+            String::from_utf8_lossy(&out.stderr).trim()
+        ));
+    }
+    String::from_utf8(out.stdout).map_err(|e| e.to_string())
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
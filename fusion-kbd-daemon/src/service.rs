@@ -0,0 +1,356 @@
+//! The daemon itself: applies rules, performs scheduled writes, and serves
+//! `control` requests, all while holding on to the device (and reclaiming it
+//! if it's unplugged, or reset, and comes back).
+
+use std::net::SocketAddr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use fusion_kbd_protocol as kbd;
+use kbd::correction::Correction;
+use kbd::state::{Lighting, State};
+use kbd::Keyboard;
+use log::{error, info};
+
+use crate::compositor::Compositor;
+use crate::control::Server;
+use crate::idle::{self, Level};
+use crate::profile;
+use crate::rules::{Action, Engine, Facts};
+use crate::saved::Saved;
+use crate::scheduler::{Scheduler, Write};
+use crate::settings::Settings;
+use crate::SCRATCH_SLOT;
+use crate::{events, http, locks, mqtt, plugins, pomodoro, reactive, saved, window};
+
+/// how often rules are re-evaluated
+const TICK: Duration = Duration::from_secs(1);
+
+/// Works out the write a rule's action boils down to, updating the current
+/// `lighting` / `brightness` to match.
+///
+/// `lighting` is `None` until the first lighting action, since there's no way
+/// of reading back what the keyboard is currently showing.
+fn plan(
+    correction: &Correction,
+    keymap: &kbd::Keymap,
+    action: &Action,
+    lighting: &mut Option<Lighting>,
+    brightness: &mut u8,
+) -> Option<Write> {
+    let mut upload = None;
+    let next = match *action {
+        Action::Preset {
+            preset,
+            color,
+            speed,
+        } => Lighting::Preset {
+            preset,
+            color,
+            speed,
+        },
+        Action::Custom { slot } => Lighting::Custom { slot },
+        Action::Solid(color) => {
+            let mut cfg = kbd::CustomConfig::new();
+            cfg.fill(correction.rgb(color));
+            upload = Some(cfg);
+            Lighting::Custom { slot: SCRATCH_SLOT }
+        }
+        Action::Brightness(b) => {
+            *brightness = b;
+            lighting.clone()?
+        }
+        Action::Profile(ref name) => {
+            let profile = match profile::load(name, keymap) {
+                Ok(profile) => profile,
+                Err(e) => {
+                    error!("{}", e);
+                    return None;
+                }
+            };
+            *brightness = profile.state.brightness;
+            upload = profile.config.map(|cfg| correction.apply(&cfg));
+            profile.state.lighting
+        }
+    };
+
+    *lighting = Some(next.clone());
+    Some(Write {
+        state: State {
+            lighting: next,
+            brightness: *brightness,
+        },
+        upload,
+    })
+}
+
+/// Shows the saved state at the brightness `level` calls for. The saved state
+/// itself is left alone, so waking back up restores it exactly.
+fn dim(kbd: &dyn Keyboard, config: &idle::Config, level: Level) -> Result<(), libusb::Error> {
+    // nothing to dim (or bring back) while the backlight is off anyway
+    let mut state = match saved::load() {
+        Some(saved) if !saved.off => saved.state,
+        _ => return Ok(()),
+    };
+    state.brightness = match level {
+        Level::Awake => state.brightness,
+        Level::Dimmed => state.brightness.min(config.dim_brightness),
+        Level::Off => 0,
+    };
+    kbd.set_state(&state)?;
+    events::publish("idle", &state);
+    Ok(())
+}
+
+/// optional integrations, from the command line
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// serve the HTTP API here
+    pub http: Option<SocketAddr>,
+}
+
+/// Whether the keyboard opened at `location` is still there. libusb 0.3 has
+/// no hotplug callbacks, so this is polled (every `TICK`).
+fn plugged_in(context: &libusb::Context, ids: &kbd::device::Ids, location: (u8, u8)) -> bool {
+    match kbd::device::scan(context, ids) {
+        Ok(found) => found
+            .iter()
+            .any(|d| (d.bus_number(), d.address()) == location),
+        // can't tell, so don't go dropping the keyboard over it
+        Err(_) => true,
+    }
+}
+
+/// Brings back the saved state, e.g: for a keyboard that's just been
+/// replugged (and has forgotten it).
+fn restore(kbd: &dyn Keyboard) -> Result<(), libusb::Error> {
+    match saved::load() {
+        Some(Saved { mut state, off }) => {
+            if off {
+                state.brightness = 0;
+            }
+            kbd.set_state(&state)
+        }
+        None => Ok(()),
+    }
+}
+
+/// Runs the daemon until something goes badly wrong. Problems along the way
+/// (e.g: a failed write) are reported on stderr.
+pub fn run(settings: &Settings, options: &Options) -> Result<(), libusb::Error> {
+    let mut engine = match Engine::from_strings(&settings.rules) {
+        Ok(engine) => engine,
+        Err(e) => {
+            error!("{}", e);
+            return Err(libusb::Error::Other);
+        }
+    };
+
+    let server = match Server::bind() {
+        Ok(server) => server,
+        Err(e) => {
+            error!("couldn't open the control socket: {}", e);
+            return Err(libusb::Error::Other);
+        }
+    };
+
+    let keymap = match kbd::Keymap::load(settings.layout()) {
+        Ok(keymap) => keymap,
+        Err(e) => {
+            error!("invalid keymap: {}", e);
+            return Err(libusb::Error::InvalidParam);
+        }
+    };
+
+    if let Some(addr) = options.http {
+        let api = http::Api {
+            submitter: server.submitter(),
+            keymap: keymap.clone(),
+            correction: settings.calibration.clone(),
+            default_brightness: settings.brightness.unwrap_or(0x50 / 3),
+        };
+        if let Err(e) = http::serve(addr, api) {
+            error!("couldn't listen on {}: {}", addr, e);
+            return Err(libusb::Error::Other);
+        }
+    }
+
+    if let Some(ref config) = settings.mqtt {
+        let password = match config.password {
+            Some(ref password) => match settings.secrets.open().and_then(|s| s.resolve(password)) {
+                Ok(password) => Some(password),
+                Err(e) => {
+                    error!("couldn't get the MQTT password: {}", e);
+                    return Err(libusb::Error::Other);
+                }
+            },
+            None => None,
+        };
+        mqtt::spawn(mqtt::Bridge {
+            config: config.clone(),
+            password,
+            submitter: server.submitter(),
+            correction: settings.calibration.clone(),
+            default_brightness: settings.brightness.unwrap_or(0x50 / 3),
+        });
+    }
+
+    if let Some(ref config) = settings.reactive {
+        let spawned = reactive::spawn(
+            config.clone(),
+            keymap.clone(),
+            server.client(),
+            server.layers(),
+            settings.calibration.clone(),
+        );
+        if let Err(e) = spawned {
+            error!("couldn't start reactive effects: {}", e);
+            return Err(libusb::Error::Other);
+        }
+    }
+
+    if let Some(ref config) = settings.locks {
+        let spawned = locks::spawn(
+            config.clone(),
+            &keymap,
+            server.layers(),
+            settings.calibration.clone(),
+        );
+        if let Err(e) = spawned {
+            error!("couldn't start the lock key overlay: {}", e);
+            return Err(libusb::Error::Other);
+        }
+    }
+
+    if let Some(ref config) = settings.pomodoro {
+        pomodoro::spawn(
+            config.clone(),
+            server.client(),
+            settings.calibration.clone(),
+        );
+    }
+
+    // one broken plugin shouldn't take the rest down with it
+    for plugin in plugins::discover() {
+        let spawned = plugins::spawn(
+            &plugin,
+            settings.layout(),
+            keymap.clone(),
+            server.layers(),
+            settings.calibration.clone(),
+        );
+        if let Err(e) = spawned {
+            error!("{}", e);
+        }
+    }
+
+    if settings.backend.as_deref() == Some("hidapi") {
+        error!("the daemon only supports the libusb backend");
+        return Err(libusb::Error::NotSupported);
+    }
+    let context = libusb::Context::new()?;
+    kbd::device::release_on_exit();
+    let ids = settings.usb_ids();
+
+    let mut lighting = None;
+    let mut brightness = settings.brightness.unwrap_or(0x50 / 3);
+    let mut idle_level = Level::Awake;
+
+    let mut scheduler = Scheduler::new(settings.write_interval);
+    // the first time round, a missing keyboard is an error. After that, it's
+    // been unplugged (or reset), and gets waited for.
+    let mut replugged = false;
+    loop {
+        let found = match kbd::device::scan(&context, &ids)?.into_iter().next() {
+            Some(found) => found,
+            None if replugged => {
+                thread::sleep(TICK);
+                continue;
+            }
+            None => {
+                error!("No keyboard found! (looked for {})", ids);
+                return Err(libusb::Error::NoDevice);
+            }
+        };
+        // a reset keyboard comes back at a new address, so this tells the two
+        // apart too
+        let location = (found.bus_number(), found.address());
+        let mut device = match kbd::FusionKBD::open_device(&found) {
+            Ok(device) => device,
+            // e.g: udev hasn't caught up with the permissions yet
+            Err(e) if replugged => {
+                error!("couldn't reopen the keyboard: {}", e);
+                thread::sleep(TICK);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        if let Err(e) = settings.set_up(&mut device) {
+            error!("{}", e);
+            return Err(libusb::Error::Io);
+        }
+        let kbd = Compositor::new(&device, server.layers());
+
+        if replugged {
+            info!("Keyboard reconnected, restoring its lighting");
+            if let Err(e) = restore(&kbd) {
+                error!("couldn't restore the lighting: {}", e);
+            }
+            // re-dimmed on the next tick, if it's still idle
+            idle_level = Level::Awake;
+        }
+
+        let mut next_tick = Instant::now();
+        loop {
+            let now = Instant::now();
+            if now >= next_tick {
+                if !plugged_in(&context, &ids, location) {
+                    break;
+                }
+
+                let mut facts = Facts::now();
+                if engine.needs_window() {
+                    facts.window = window::focused();
+                }
+                for action in engine.evaluate(&facts) {
+                    let write = plan(
+                        &settings.calibration,
+                        &keymap,
+                        &action,
+                        &mut lighting,
+                        &mut brightness,
+                    );
+                    if let Some(write) = write {
+                        scheduler.submit("rules", write);
+                    }
+                }
+                if let Some(ref config) = settings.idle {
+                    if let Some(idle) = idle::idle_time() {
+                        let level = config.level(idle, facts.on_ac);
+                        if level != idle_level {
+                            if let Err(e) = dim(&kbd, config, level) {
+                                error!("couldn't dim the backlight: {}", e);
+                            }
+                            idle_level = level;
+                        }
+                    }
+                }
+                next_tick = now + TICK;
+            }
+
+            for (source, e) in scheduler.flush(&kbd, now) {
+                error!("couldn't apply update from {}: {}", source, e);
+            }
+
+            let wake = scheduler
+                .next_deadline()
+                .map_or(next_tick, |t| t.min(next_tick));
+            if let Some((source, write)) = server.serve_until(&kbd, wake) {
+                scheduler.submit(&source, write);
+            }
+        }
+
+        info!("Keyboard disconnected, waiting for it to come back");
+        replugged = true;
+    }
+}
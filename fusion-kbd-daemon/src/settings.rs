@@ -0,0 +1,462 @@
+//! The user config file (`paths::config_file()`), e.g:
+//!
+//! ```toml
+//! layout = "ansi"     # or "iso", or a keymap TOML (default: the model's)
+//! model = "aero-15x"  # instead of working it out from the DMI product name
+//! brightness = 16
+//! brightness_step = 5 # for `brightness up` / `down`
+//! preset = "static"   # used by `preset` when no preset is given
+//! color = "white"
+//! slot = 0            # used by `key set` / `zone` when no --slot is given
+//! usb_timeout_ms = 1000
+//! vid = 0x1044        # look for a keyboard with these USB ids, rather than
+//! pid = 0x7a39        # the known ones
+//! backend = "libusb"   # or "hidapi" (the CLI, built with the `hid` feature)
+//! write_interval_ms = 100
+//! rules = [
+//!     "when time 22:00-07:00 then brightness 5",
+//! ]
+//!
+//! [calibration]
+//! gamma = 2.2
+//! green = 0.8
+//! ```
+//!
+//! Environment variables (see `ENV`) override the file, e.g:
+//! `FUSION_KBD_BRIGHTNESS=10`, and the CLI's flags override both.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use fusion_kbd_protocol::correction::Correction;
+use fusion_kbd_protocol::device::{FusionKBD, Ids, PacketLog};
+use fusion_kbd_protocol::models::{self, Model};
+use fusion_kbd_protocol::protocol::{MAX_BRIGHTNESS, NUM_SLOTS};
+use fusion_kbd_protocol::{Color, Preset};
+
+use crate::{idle, locks, mqtt, pomodoro, reactive, secrets};
+
+/// ways of talking to the keyboard
+pub const BACKENDS: &[&str] = &["libusb", "hidapi"];
+
+/// see `Settings::brightness_step`
+pub const DEFAULT_BRIGHTNESS_STEP: u8 = 5;
+
+/// see `Settings::write_interval`
+pub const DEFAULT_WRITE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// the environment variables `apply_env` looks at, and the settings they stand
+/// in for. `vid` / `pid` are in hex and `device` is `BUS:ADDRESS`, like the
+/// flags, and `dump_packets` is a path (there's no config file equivalent).
+pub const ENV: &[(&str, &str)] = &[
+    ("FUSION_KBD_LAYOUT", "layout"),
+    ("FUSION_KBD_MODEL", "model"),
+    ("FUSION_KBD_BRIGHTNESS", "brightness"),
+    ("FUSION_KBD_BRIGHTNESS_STEP", "brightness_step"),
+    ("FUSION_KBD_PRESET", "preset"),
+    ("FUSION_KBD_COLOR", "color"),
+    ("FUSION_KBD_SLOT", "slot"),
+    ("FUSION_KBD_USB_TIMEOUT_MS", "usb_timeout_ms"),
+    ("FUSION_KBD_VID", "vid"),
+    ("FUSION_KBD_PID", "pid"),
+    ("FUSION_KBD_DEVICE", "device"),
+    ("FUSION_KBD_DUMP_PACKETS", "dump_packets"),
+    ("FUSION_KBD_BACKEND", "backend"),
+    ("FUSION_KBD_WRITE_INTERVAL_MS", "write_interval_ms"),
+];
+
+/// the keys `from_table` wants numbers for (everything else in `ENV` is a
+/// string), e.g: so `FUSION_KBD_COLOR=112233` stays a color
+const NUMBERS: &[&str] = &[
+    "brightness",
+    "brightness_step",
+    "slot",
+    "usb_timeout_ms",
+    "vid",
+    "pid",
+    "write_interval_ms",
+];
+
+/// a USB vendor / product id, in hex (with or without a leading `0x`)
+pub fn parse_usb_id(s: &str) -> Result<u16, String> {
+    u16::from_str_radix(s.trim_start_matches("0x"), 16)
+        .map_err(|_| format!("'{}' isn't a USB id (4 hex digits, e.g: 7a39)", s))
+}
+
+/// a `--device`, as `BUS:ADDRESS` (e.g: `3:5`, or `003:005` as `lsusb` puts it)
+pub fn parse_location(s: &str) -> Result<(u8, u8), String> {
+    let (bus, address) = s
+        .split_once(':')
+        .ok_or_else(|| format!("'{}' isn't BUS:ADDRESS (e.g: 3:5)", s))?;
+    match (bus.parse::<u8>(), address.parse::<u8>()) {
+        (Ok(bus), Ok(address)) => Ok((bus, address)),
+        _ => Err(format!("'{}' isn't BUS:ADDRESS (e.g: 3:5)", s)),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// keymap name or path (see `Keymap::load`)
+    pub layout: Option<String>,
+    /// one of `models::MODELS`, by name (see `model`)
+    pub model: Option<String>,
+    pub brightness: Option<u8>,
+    /// how far `brightness up` / `down` go at a time
+    pub brightness_step: u8,
+    pub preset: Option<Preset>,
+    pub color: Option<Color>,
+    pub slot: Option<u8>,
+    /// instead of `device::DEFAULT_TIMEOUT` (zero waits forever)
+    pub usb_timeout: Option<Duration>,
+    /// USB vendor / product id overrides (see `usb_ids`)
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    /// the keyboard's `(bus, address)`. Only ever set by `--device`, since the
+    /// address changes whenever the keyboard is replugged.
+    pub device: Option<(u8, u8)>,
+    /// where to log every USB transfer to ("-" for stderr). Only ever set by
+    /// `--dump-packets`.
+    pub dump_packets: Option<String>,
+    /// one of `BACKENDS`
+    pub backend: Option<String>,
+    /// minimum time between the daemon's writes to the same slot (see
+    /// `scheduler`)
+    pub write_interval: Duration,
+    /// see `rules`
+    pub rules: Vec<String>,
+    /// gamma / per-channel scaling applied to every custom upload (the
+    /// temperature is always `None`, it isn't a calibration setting)
+    pub calibration: Correction,
+    /// where `"secret:NAME"` values are looked up (see `secrets`)
+    pub secrets: secrets::Backend,
+    /// the Home Assistant bridge, if the config has an `[mqtt]` table
+    pub mqtt: Option<mqtt::Config>,
+    /// key press effects, if the config has a `[reactive]` table
+    pub reactive: Option<reactive::Config>,
+    /// idle dimming, if the config has an `[idle]` table
+    pub idle: Option<idle::Config>,
+    /// lock key indicators, if the config has a `[locks]` table
+    pub locks: Option<locks::Config>,
+    /// a pomodoro timer, if the config has a `[pomodoro]` table
+    pub pomodoro: Option<pomodoro::Config>,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            layout: None,
+            model: None,
+            brightness: None,
+            brightness_step: DEFAULT_BRIGHTNESS_STEP,
+            preset: None,
+            color: None,
+            slot: None,
+            usb_timeout: None,
+            vid: None,
+            pid: None,
+            device: None,
+            dump_packets: None,
+            backend: None,
+            write_interval: DEFAULT_WRITE_INTERVAL,
+            rules: Vec::new(),
+            calibration: Correction::default(),
+            secrets: secrets::Backend::default(),
+            mqtt: None,
+            reactive: None,
+            idle: None,
+            locks: None,
+            pomodoro: None,
+        }
+    }
+}
+
+impl Settings {
+    /// The laptop's model: `model` if it's set, or else whatever the DMI
+    /// product name says (see `models::detect`).
+    pub fn model(&self) -> Option<&'static Model> {
+        match self.model {
+            Some(ref name) => models::by_name(name),
+            None => models::detect(),
+        }
+    }
+
+    /// `layout`, or else the model's usual one, or else ANSI
+    pub fn layout(&self) -> &str {
+        self.layout
+            .as_deref()
+            .or_else(|| self.model().map(|m| m.layout))
+            .unwrap_or("ansi")
+    }
+
+    /// The keyboards to look for: every known one, unless `vid` / `pid` (or
+    /// `device`) are set.
+    pub fn usb_ids(&self) -> Ids {
+        let mut ids = Ids::default();
+        if let Some(vid) = self.vid {
+            ids.vid = vid;
+        }
+        if let Some(pid) = self.pid {
+            ids.pids = vec![pid];
+        }
+        ids.at = self.device;
+        ids
+    }
+
+    /// applies `usb_timeout` and `dump_packets` to a freshly opened keyboard
+    pub fn set_up(&self, usb: &mut FusionKBD) -> Result<(), String> {
+        if let Some(timeout) = self.usb_timeout {
+            usb.set_timeout(timeout);
+        }
+        if let Some(ref path) = self.dump_packets {
+            let log =
+                PacketLog::open(path).map_err(|e| format!("couldn't open '{}': {}", path, e))?;
+            usb.set_packet_log(log);
+        }
+        Ok(())
+    }
+
+    /// Overrides whatever's set in `ENV`. Values are checked the same way as
+    /// in the config file, and errors say which variable was wrong.
+    pub fn apply_env(&mut self) -> Result<(), String> {
+        for &(var, key) in ENV {
+            let value = match env::var(var) {
+                Ok(value) => value,
+                Err(_) => continue,
+            };
+            let err = |e: String| format!("{}: {}", var, e);
+            match key {
+                "vid" => self.vid = Some(parse_usb_id(&value).map_err(err)?),
+                "pid" => self.pid = Some(parse_usb_id(&value).map_err(err)?),
+                "device" => self.device = Some(parse_location(&value).map_err(err)?),
+                "dump_packets" => self.dump_packets = Some(value),
+                _ => {
+                    // as if it were the only line in a config file
+                    let mut table = toml::Table::new();
+                    let value = match value.parse::<i64>() {
+                        Ok(n) if NUMBERS.contains(&key) => toml::Value::Integer(n),
+                        _ => toml::Value::String(value),
+                    };
+                    table.insert(key.to_string(), value);
+                    let env = Settings::from_table(&table).map_err(err)?;
+                    match key {
+                        "layout" => self.layout = env.layout,
+                        "model" => self.model = env.model,
+                        "brightness" => self.brightness = env.brightness,
+                        "brightness_step" => self.brightness_step = env.brightness_step,
+                        "preset" => self.preset = env.preset,
+                        "color" => self.color = env.color,
+                        "slot" => self.slot = env.slot,
+                        "usb_timeout_ms" => self.usb_timeout = env.usb_timeout,
+                        "backend" => self.backend = env.backend,
+                        "write_interval_ms" => self.write_interval = env.write_interval,
+                        _ => unreachable!("{} isn't in `ENV`", key),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn from_toml(text: &str) -> Result<Settings, String> {
+        let table: toml::Table = text.parse().map_err(|e: toml::de::Error| e.to_string())?;
+        Settings::from_table(&table)
+    }
+
+    fn from_table(table: &toml::Table) -> Result<Settings, String> {
+        let string = |name: &str| -> Result<Option<&str>, String> {
+            match table.get(name) {
+                None => Ok(None),
+                Some(toml::Value::String(s)) => Ok(Some(s)),
+                Some(_) => Err(format!("`{}` must be a string", name)),
+            }
+        };
+        let number = |name: &str, max: i64| -> Result<Option<i64>, String> {
+            debug_assert!(NUMBERS.contains(&name), "`{}` isn't in `NUMBERS`", name);
+            match table.get(name) {
+                None => Ok(None),
+                Some(toml::Value::Integer(n)) if (0..=max).contains(n) => Ok(Some(*n)),
+                Some(_) => Err(format!("`{}` must be a number from 0 - {}", name, max)),
+            }
+        };
+
+        let preset = match string("preset")? {
+            None => None,
+            Some(p) => Some(Preset::from_str(p).map_err(|_| format!("unknown preset '{}'", p))?),
+        };
+        let color = match string("color")? {
+            None => None,
+            Some(c) => Some(Color::lookup(c)?),
+        };
+
+        let model = string("model")?.map(str::to_string);
+        if let Some(ref m) = model {
+            if models::by_name(m).is_none() {
+                let names: Vec<&str> = models::names().collect();
+                return Err(format!("`model` must be one of: {}", names.join(", ")));
+            }
+        }
+
+        let backend = string("backend")?.map(str::to_string);
+        if let Some(ref b) = backend {
+            if !BACKENDS.contains(&b.as_str()) {
+                return Err(format!("`backend` must be one of: {}", BACKENDS.join(", ")));
+            }
+        }
+
+        let brightness = number("brightness", MAX_BRIGHTNESS as i64)?.map(|b| b as u8);
+        let brightness_step = number("brightness_step", MAX_BRIGHTNESS as i64)?
+            .map_or(DEFAULT_BRIGHTNESS_STEP, |s| s as u8);
+        let slot = number("slot", NUM_SLOTS as i64 - 1)?.map(|s| s as u8);
+        let usb_timeout =
+            number("usb_timeout_ms", i64::MAX)?.map(|ms| Duration::from_millis(ms as u64));
+        let vid = number("vid", u16::MAX as i64)?.map(|id| id as u16);
+        let pid = number("pid", u16::MAX as i64)?.map(|id| id as u16);
+        let write_interval = number("write_interval_ms", i64::MAX)?
+            .map_or(DEFAULT_WRITE_INTERVAL, |ms| {
+                Duration::from_millis(ms as u64)
+            });
+
+        let rules = match table.get("rules") {
+            None => Vec::new(),
+            Some(toml::Value::Array(rules)) => rules
+                .iter()
+                .map(|r| r.as_str().map(str::to_string))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| "`rules` must be a list of strings".to_string())?,
+            Some(_) => return Err("`rules` must be a list of strings".to_string()),
+        };
+
+        let calibration = match table.get("calibration") {
+            None => Correction::default(),
+            Some(toml::Value::Table(t)) => calibration(t)?,
+            Some(_) => return Err("`calibration` must be a table".to_string()),
+        };
+
+        let secrets = match table.get("secrets") {
+            None => secrets::Backend::default(),
+            Some(toml::Value::Table(t)) => secrets::Backend::from_toml(t)?,
+            Some(_) => return Err("`secrets` must be a table".to_string()),
+        };
+
+        let mqtt = match table.get("mqtt") {
+            None => None,
+            Some(toml::Value::Table(t)) => Some(mqtt::Config::from_toml(t)?),
+            Some(_) => return Err("`mqtt` must be a table".to_string()),
+        };
+
+        let reactive = match table.get("reactive") {
+            None => None,
+            Some(toml::Value::Table(t)) => Some(reactive::Config::from_toml(t)?),
+            Some(_) => return Err("`reactive` must be a table".to_string()),
+        };
+
+        let idle = match table.get("idle") {
+            None => None,
+            Some(toml::Value::Table(t)) => Some(idle::Config::from_toml(t)?),
+            Some(_) => return Err("`idle` must be a table".to_string()),
+        };
+
+        let locks = match table.get("locks") {
+            None => None,
+            Some(toml::Value::Table(t)) => Some(locks::Config::from_toml(t)?),
+            Some(_) => return Err("`locks` must be a table".to_string()),
+        };
+
+        let pomodoro = match table.get("pomodoro") {
+            None => None,
+            Some(toml::Value::Table(t)) => Some(pomodoro::Config::from_toml(t)?),
+            Some(_) => return Err("`pomodoro` must be a table".to_string()),
+        };
+
+        Ok(Settings {
+            layout: string("layout")?.map(str::to_string),
+            model,
+            brightness,
+            brightness_step,
+            preset,
+            color,
+            slot,
+            usb_timeout,
+            vid,
+            pid,
+            device: None,
+            dump_packets: None,
+            backend,
+            write_interval,
+            rules,
+            calibration,
+            secrets,
+            mqtt,
+            reactive,
+            idle,
+            locks,
+            pomodoro,
+        })
+    }
+
+    /// a missing file is the same as an empty one
+    pub fn load(path: &Path) -> Result<Settings, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => Settings::from_toml(&text),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Settings::default()),
+            Err(e) => Err(format!("couldn't open '{}': {}", path.display(), e)),
+        }
+    }
+}
+
+/// `gamma`, and `red` / `green` / `blue` scale factors, all optional
+fn calibration(table: &toml::Table) -> Result<Correction, String> {
+    let number = |name: &str, default: f32| -> Result<f32, String> {
+        let value = match table.get(name) {
+            None => return Ok(default),
+            Some(toml::Value::Float(f)) => *f as f32,
+            Some(toml::Value::Integer(i)) => *i as f32,
+            Some(_) => return Err(format!("`calibration.{}` must be a number", name)),
+        };
+        if value <= 0.0 {
+            return Err(format!("`calibration.{}` must be positive", name));
+        }
+        Ok(value)
+    };
+
+    Ok(Correction {
+        temperature: None,
+        gamma: number("gamma", 1.0)?,
+        scale: (
+            number("red", 1.0)?,
+            number("green", 1.0)?,
+            number("blue", 1.0)?,
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fusion_kbd_protocol::Rgb;
+
+    #[test]
+    fn env_values_get_the_type_their_key_wants() {
+        // the only test that sets these
+        env::set_var("FUSION_KBD_COLOR", "112233");
+        env::set_var("FUSION_KBD_BRIGHTNESS", "30");
+        let mut settings = Settings::default();
+        settings.apply_env().unwrap();
+        assert_eq!(settings.color, Some(Color::nearest(Rgb(0x11, 0x22, 0x33))));
+        assert_eq!(settings.brightness, Some(30));
+
+        env::set_var("FUSION_KBD_BRIGHTNESS", "bright");
+        let e = Settings::default().apply_env().unwrap_err();
+        assert_eq!(
+            e,
+            "FUSION_KBD_BRIGHTNESS: `brightness` must be a number from 0 - 50"
+        );
+
+        env::remove_var("FUSION_KBD_COLOR");
+        env::remove_var("FUSION_KBD_BRIGHTNESS");
+    }
+}
@@ -0,0 +1,86 @@
+//! Theme packs: custom configs dropped into `data_dir()/themes`, and referred
+//! to by name (e.g: `--theme nord` for `themes/nord.json`). Themes can be in
+//! any format `config::load` understands (.json / .toml / .txt profiles, .fkp
+//! containers, OpenRGB .orp profiles, .png images, or raw binaries).
+//!
+//! The built-in themes (see `fusion_kbd_protocol::themes`) are available by
+//! name too, unless an installed theme has the same name.
+
+use std::fs;
+use std::path::PathBuf;
+
+use fusion_kbd_protocol::{config, themes as builtin};
+use fusion_kbd_protocol::{CustomConfig, Keymap};
+
+use crate::paths;
+
+/// where a theme comes from
+#[derive(Debug, Clone)]
+pub enum Origin {
+    Installed(PathBuf),
+    BuiltIn,
+}
+
+pub fn dir() -> Result<PathBuf, String> {
+    paths::data_dir()
+        .map(|d| d.join("themes"))
+        .ok_or_else(|| "couldn't work out where themes go ($HOME isn't set)".to_string())
+}
+
+/// every installed theme's name, and its file, sorted by name. If a name
+/// has more than one file (`nord.json` and `nord.png`), the first one (by
+/// file name) wins.
+pub fn installed() -> Result<Vec<(String, PathBuf)>, String> {
+    let entries = match fs::read_dir(dir()?) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(Vec::new()),
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_file()))
+        .map(|e| e.path())
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| !n.starts_with('.'))
+        })
+        .collect();
+    paths.sort();
+
+    let mut themes: Vec<(String, PathBuf)> = Vec::new();
+    for path in paths {
+        let name = match path.file_stem().and_then(|s| s.to_str()) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        if !themes.iter().any(|(n, _)| *n == name) {
+            themes.push((name, path));
+        }
+    }
+    themes.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(themes)
+}
+
+/// every theme, installed or built in, sorted by name
+pub fn list() -> Result<Vec<(String, Origin)>, String> {
+    let mut themes: Vec<(String, Origin)> = installed()?
+        .into_iter()
+        .map(|(name, path)| (name, Origin::Installed(path)))
+        .collect();
+    for name in builtin::names() {
+        if !themes.iter().any(|(n, _)| n == name) {
+            themes.push((name.to_string(), Origin::BuiltIn));
+        }
+    }
+    themes.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(themes)
+}
+
+pub fn load(name: &str, keymap: &Keymap) -> Result<CustomConfig, String> {
+    if let Some((_, path)) = installed()?.into_iter().find(|(n, _)| n == name) {
+        return config::load(&path, keymap)
+            .map_err(|e| format!("invalid theme '{}': {}", path.display(), e));
+    }
+    builtin::theme(name, keymap).ok_or_else(|| format!("no theme named '{}'", name))
+}
@@ -0,0 +1,102 @@
+//! The focused window, for `window` rules.
+//!
+//! On X11, this follows EWMH's `_NET_ACTIVE_WINDOW` (via `xprop`). On Wayland,
+//! it asks the compositor's wlr-foreign-toplevel list (via `lswt`), which most
+//! wlroots-based compositors (sway, Hyprland, river, ...) support. GNOME and
+//! KDE don't expose the focused window to other clients at all.
+//!
+//! Either way, the daemon needs the session's `DISPLAY` / `WAYLAND_DISPLAY`
+//! (and `XDG_RUNTIME_DIR`) in its environment.
+
+use std::env;
+use std::process::{Command, Stdio};
+
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Window {
+    /// the X11 `WM_CLASS` class, or the Wayland app ID
+    pub class: String,
+    pub title: String,
+}
+
+/// the focused window, if there is one (and it can be found out)
+pub fn focused() -> Option<Window> {
+    if env::var_os("WAYLAND_DISPLAY").is_some() {
+        wayland()
+    } else if env::var_os("DISPLAY").is_some() {
+        x11()
+    } else {
+        None
+    }
+}
+
+/// stdout of a command, if it succeeded
+fn output(cmd: &mut Command) -> Option<String> {
+    let out = cmd
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    String::from_utf8(out.stdout).ok()
+}
+
+fn wayland() -> Option<Window> {
+    let out = output(Command::new("lswt").arg("--json"))?;
+    let list: Value = serde_json::from_str(&out).ok()?;
+    let field = |toplevel: &Value, name: &str| toplevel[name].as_str().unwrap_or("").to_string();
+
+    list["toplevels"]
+        .as_array()?
+        .iter()
+        .find(|t| t["activated"].as_bool() == Some(true))
+        .map(|t| Window {
+            class: field(t, "app-id"),
+            title: field(t, "title"),
+        })
+}
+
+fn x11() -> Option<Window> {
+    // _NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007
+    let active = output(Command::new("xprop").args(["-root", "_NET_ACTIVE_WINDOW"]))?;
+    let id = active.split_whitespace().last()?;
+    if id == "0x0" {
+        return None;
+    }
+
+    // WM_CLASS(STRING) = "kitty", "kitty"
+    // _NET_WM_NAME(UTF8_STRING) = "~/src"
+    let props = output(Command::new("xprop").args(["-id", id, "WM_CLASS", "_NET_WM_NAME"]))?;
+    let mut window = Window::default();
+    for line in props.lines() {
+        let strings = quoted(line);
+        if line.starts_with("WM_CLASS") {
+            // the instance name comes first, then the class
+            window.class = strings.last().cloned().unwrap_or_default();
+        } else if line.starts_with("_NET_WM_NAME") {
+            window.title = strings.first().cloned().unwrap_or_default();
+        }
+    }
+    Some(window)
+}
+
+/// the `"..."` strings in a line of xprop output
+fn quoted(line: &str) -> Vec<String> {
+    let mut strings = Vec::new();
+    let mut chars = line.chars();
+    while chars.any(|c| c == '"') {
+        let mut s = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => break,
+                '\\' => s.extend(chars.next()),
+                c => s.push(c),
+            }
+        }
+        strings.push(s);
+    }
+    strings
+}
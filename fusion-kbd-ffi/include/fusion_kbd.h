@@ -0,0 +1,163 @@
+/* C bindings for the Fusion RGB keyboard on Gigabyte Aero laptops */
+
+/* Generated by cbindgen from src/lib.rs. Don't edit by hand. */
+
+#ifndef FUSION_KBD_H
+#define FUSION_KBD_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define FUSION_KBD_OK 0
+
+#define FUSION_KBD_ERROR_IO -1
+
+#define FUSION_KBD_ERROR_INVALID_PARAM -2
+
+#define FUSION_KBD_ERROR_ACCESS -3
+
+#define FUSION_KBD_ERROR_NO_DEVICE -4
+
+#define FUSION_KBD_ERROR_NOT_FOUND -5
+
+#define FUSION_KBD_ERROR_BUSY -6
+
+#define FUSION_KBD_ERROR_TIMEOUT -7
+
+#define FUSION_KBD_ERROR_OVERFLOW -8
+
+#define FUSION_KBD_ERROR_PIPE -9
+
+#define FUSION_KBD_ERROR_INTERRUPTED -10
+
+#define FUSION_KBD_ERROR_NO_MEM -11
+
+#define FUSION_KBD_ERROR_NOT_SUPPORTED -12
+
+#define FUSION_KBD_ERROR_OTHER -99
+
+/**
+ * An open keyboard. The libusb context it came from lives (and dies) with
+ * it, since `FusionKBD` borrows one.
+ */
+typedef struct FusionKbd FusionKbd;
+
+/**
+ * Opens (and claims) the first known keyboard, or returns NULL (see
+ * `fusion_kbd_last_error`). Close it with `fusion_kbd_close`.
+ */
+FusionKbd *fusion_kbd_open(void);
+
+/**
+ * Like `fusion_kbd_open`, but looks for the keyboard with these USB ids. A
+ * `pid` of zero means any known one.
+ */
+FusionKbd *fusion_kbd_open_ids(uint16_t vid, uint16_t pid);
+
+/**
+ * Releases the keyboard (handing it back to the kernel driver).
+ *
+ * # Safety
+ *
+ * `kbd` must have come from `fusion_kbd_open` (or be NULL), and mustn't be
+ * used afterwards.
+ */
+void fusion_kbd_close(FusionKbd *kbd);
+
+/**
+ * Switches to a built-in preset, by name (`"wave"`, `"static"`, ...), in a
+ * color by name (`"red"`, ...). `color` can be NULL for presets that don't
+ * take one. `speed` goes from 0 - 10, `brightness` from 0 - 50.
+ *
+ * # Safety
+ *
+ * `kbd` must be an open keyboard, and `preset` / `color` NUL-terminated
+ * strings (or NULL).
+ */
+int fusion_kbd_set_preset(FusionKbd *kbd,
+                          const char *preset,
+                          uint8_t speed,
+                          uint8_t brightness,
+                          const char *color);
+
+/**
+ * Like `fusion_kbd_set_preset`, going in `direction` (`"left"`, `"right"`,
+ * `"up"` or `"down"`). Which presets take one is experimental; keyboards
+ * that can't pass one on give `FUSION_KBD_ERROR_NOT_SUPPORTED`.
+ *
+ * # Safety
+ *
+ * Same as `fusion_kbd_set_preset`, and `direction` must be a NUL-terminated
+ * string.
+ */
+int fusion_kbd_set_preset_direction(FusionKbd *kbd,
+                                    const char *preset,
+                                    uint8_t speed,
+                                    uint8_t brightness,
+                                    const char *color,
+                                    const char *direction);
+
+/**
+ * Shows custom slot `slot` (0 - 4), at `brightness` (0 - 50).
+ *
+ * # Safety
+ *
+ * `kbd` must be an open keyboard.
+ */
+int fusion_kbd_set_custom(FusionKbd *kbd, uint8_t slot, uint8_t brightness);
+
+/**
+ * How many bytes `kbd`'s custom configs are (four per key), for
+ * `fusion_kbd_upload_custom` and friends. 0 if `kbd` is NULL.
+ *
+ * # Safety
+ *
+ * `kbd` must be an open keyboard, or NULL.
+ */
+size_t fusion_kbd_config_len(FusionKbd *kbd);
+
+/**
+ * Uploads a custom config (`fusion_kbd_config_len` bytes, four per key) to
+ * slot `slot`. It isn't shown until `fusion_kbd_set_custom`.
+ *
+ * # Safety
+ *
+ * `kbd` must be an open keyboard, and `data` point to `len` bytes.
+ */
+int fusion_kbd_upload_custom(FusionKbd *kbd, uint8_t slot, const uint8_t *data, size_t len);
+
+/**
+ * Reads the custom config in slot `slot` back into `data`
+ * (`fusion_kbd_config_len` bytes).
+ *
+ * # Safety
+ *
+ * `kbd` must be an open keyboard, and `data` point to `len` writable bytes.
+ */
+int fusion_kbd_download_custom(FusionKbd *kbd, uint8_t slot, uint8_t *data, size_t len);
+
+/**
+ * Loads a config file in any format the CLI reads (a raw dump, .fkp, .json,
+ * .toml, .txt, .orp or .png, going by its extension) into `data`
+ * (`len` bytes, which has to be as long as the config turns out to be),
+ * ready for `fusion_kbd_upload_custom`. `layout` is what key names mean
+ * (`"ansi"`, `"iso"`, or a keymap TOML), or NULL for `"ansi"`. Doesn't need
+ * a keyboard.
+ *
+ * # Safety
+ *
+ * `path` / `layout` must be NUL-terminated strings (`layout` can be NULL),
+ * and `data` point to `len` writable bytes.
+ */
+int fusion_kbd_load_config(const char *path, const char *layout, uint8_t *data, size_t len);
+
+/**
+ * What the last call that failed (on this thread) went wrong with. Owned by
+ * the library, and valid until the next call that fails.
+ */
+const char *fusion_kbd_last_error(void);
+
+#endif /* FUSION_KBD_H */
@@ -0,0 +1,405 @@
+//! C bindings for `fusion_kbd_protocol`, built as `libfusion_kbd.so` /
+//! `libfusion_kbd.a`, so C / C++ utilities and desktop applets can talk to
+//! the keyboard directly. `include/fusion_kbd.h` is generated from this file
+//! (see `cbindgen.toml`).
+//!
+//! Functions that can fail return `FUSION_KBD_OK` (zero), or one of the
+//! (negative) `FUSION_KBD_ERROR_*` codes, which are libusb's. What went wrong
+//! can then be read with `fusion_kbd_last_error`.
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::str::FromStr;
+
+use fusion_kbd_protocol::device::{Ids, VID};
+use fusion_kbd_protocol::protocol::{Direction, MAX_BRIGHTNESS, MAX_SPEED};
+use fusion_kbd_protocol::{Color, CustomConfig, FusionKBD, Keyboard, Keymap, Preset};
+
+pub const FUSION_KBD_OK: c_int = 0;
+pub const FUSION_KBD_ERROR_IO: c_int = -1;
+pub const FUSION_KBD_ERROR_INVALID_PARAM: c_int = -2;
+pub const FUSION_KBD_ERROR_ACCESS: c_int = -3;
+pub const FUSION_KBD_ERROR_NO_DEVICE: c_int = -4;
+pub const FUSION_KBD_ERROR_NOT_FOUND: c_int = -5;
+pub const FUSION_KBD_ERROR_BUSY: c_int = -6;
+pub const FUSION_KBD_ERROR_TIMEOUT: c_int = -7;
+pub const FUSION_KBD_ERROR_OVERFLOW: c_int = -8;
+pub const FUSION_KBD_ERROR_PIPE: c_int = -9;
+pub const FUSION_KBD_ERROR_INTERRUPTED: c_int = -10;
+pub const FUSION_KBD_ERROR_NO_MEM: c_int = -11;
+pub const FUSION_KBD_ERROR_NOT_SUPPORTED: c_int = -12;
+pub const FUSION_KBD_ERROR_OTHER: c_int = -99;
+
+/// An open keyboard. The libusb context it came from lives (and dies) with
+/// it, since `FusionKBD` borrows one.
+pub struct Handle {
+    // dropped before `context`, which it borrows
+    kbd: Option<FusionKBD<'static>>,
+    context: *mut libusb::Context,
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.kbd = None;
+        // safe: made by `Box::into_raw` in `open`, and nothing borrows it now
+        unsafe { drop(Box::from_raw(self.context)) };
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn set_error(message: String) {
+    let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = message);
+}
+
+fn code(e: &libusb::Error) -> c_int {
+    match e {
+        libusb::Error::Io => FUSION_KBD_ERROR_IO,
+        libusb::Error::InvalidParam => FUSION_KBD_ERROR_INVALID_PARAM,
+        libusb::Error::Access => FUSION_KBD_ERROR_ACCESS,
+        libusb::Error::NoDevice => FUSION_KBD_ERROR_NO_DEVICE,
+        libusb::Error::NotFound => FUSION_KBD_ERROR_NOT_FOUND,
+        libusb::Error::Busy => FUSION_KBD_ERROR_BUSY,
+        libusb::Error::Timeout => FUSION_KBD_ERROR_TIMEOUT,
+        libusb::Error::Overflow => FUSION_KBD_ERROR_OVERFLOW,
+        libusb::Error::Pipe => FUSION_KBD_ERROR_PIPE,
+        libusb::Error::Interrupted => FUSION_KBD_ERROR_INTERRUPTED,
+        libusb::Error::NoMem => FUSION_KBD_ERROR_NO_MEM,
+        libusb::Error::NotSupported => FUSION_KBD_ERROR_NOT_SUPPORTED,
+        libusb::Error::Success | libusb::Error::Other => FUSION_KBD_ERROR_OTHER,
+    }
+}
+
+/// Runs `f`, turning its error (or a panic, which mustn't unwind into C) into
+/// an error code, and `fusion_kbd_last_error`'s message.
+fn guard(f: impl FnOnce() -> Result<(), (c_int, String)>) -> c_int {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => FUSION_KBD_OK,
+        Ok(Err((code, message))) => {
+            set_error(message);
+            code
+        }
+        Err(_) => {
+            set_error("panicked (this is a bug)".to_string());
+            FUSION_KBD_ERROR_OTHER
+        }
+    }
+}
+
+fn usb_error(e: libusb::Error) -> (c_int, String) {
+    (code(&e), e.to_string())
+}
+
+fn invalid(message: String) -> (c_int, String) {
+    (FUSION_KBD_ERROR_INVALID_PARAM, message)
+}
+
+/// `value` isn't valid if it's over `max`
+fn at_most(value: u8, max: u8, what: &str) -> Result<(), (c_int, String)> {
+    if value > max {
+        return Err(invalid(format!(
+            "{} must be from 0 - {} (not {})",
+            what, max, value
+        )));
+    }
+    Ok(())
+}
+
+/// `s` as a `&str`, or `what` isn't valid if it can't be
+unsafe fn string<'a>(s: *const c_char, what: &str) -> Result<&'a str, (c_int, String)> {
+    if s.is_null() {
+        return Err(invalid(format!("{} is NULL", what)));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| invalid(format!("{} isn't UTF-8", what)))
+}
+
+unsafe fn keyboard<'a>(kbd: *mut Handle) -> Result<&'a FusionKBD<'static>, (c_int, String)> {
+    match kbd.as_ref().and_then(|h| h.kbd.as_ref()) {
+        Some(kbd) => Ok(kbd),
+        None => Err(invalid("the keyboard is NULL".to_string())),
+    }
+}
+
+/// checks `fusion_kbd_set_preset`'s arguments, and looks up the names
+unsafe fn preset_args(
+    preset: *const c_char,
+    speed: u8,
+    brightness: u8,
+    color: *const c_char,
+) -> Result<(Preset, Color), (c_int, String)> {
+    at_most(speed, MAX_SPEED, "speed")?;
+    at_most(brightness, MAX_BRIGHTNESS, "brightness")?;
+    let name = string(preset, "the preset")?;
+    let preset =
+        Preset::from_str(name).map_err(|_| invalid(format!("unknown preset '{}'", name)))?;
+    let color = match color.is_null() {
+        true => Color::White,
+        false => {
+            let name = string(color, "the color")?;
+            Color::from_str(name).map_err(|_| invalid(format!("unknown color '{}'", name)))?
+        }
+    };
+    Ok((preset, color))
+}
+
+fn open(ids: &Ids) -> Result<Handle, (c_int, String)> {
+    let context = Box::into_raw(Box::new(libusb::Context::new().map_err(usb_error)?));
+    // safe: `Handle` frees the context only after dropping the keyboard
+    let kbd = FusionKBD::open(unsafe { &*context }, ids);
+    let handle = Handle { kbd: None, context };
+    match kbd {
+        Ok(kbd) => Ok(Handle {
+            kbd: Some(kbd),
+            ..handle
+        }),
+        Err(libusb::Error::NoDevice) => Err((
+            FUSION_KBD_ERROR_NO_DEVICE,
+            format!("no keyboard found (looked for {})", ids),
+        )),
+        Err(e) => Err(usb_error(e)),
+    }
+}
+
+/// Opens (and claims) the first known keyboard, or returns NULL (see
+/// `fusion_kbd_last_error`). Close it with `fusion_kbd_close`.
+#[no_mangle]
+pub extern "C" fn fusion_kbd_open() -> *mut Handle {
+    fusion_kbd_open_ids(VID, 0)
+}
+
+/// Like `fusion_kbd_open`, but looks for the keyboard with these USB ids. A
+/// `pid` of zero means any known one.
+#[no_mangle]
+pub extern "C" fn fusion_kbd_open_ids(vid: u16, pid: u16) -> *mut Handle {
+    let mut ids = Ids {
+        vid,
+        ..Ids::default()
+    };
+    if pid != 0 {
+        ids.pids = vec![pid];
+    }
+    let mut handle = None;
+    guard(|| {
+        handle = Some(open(&ids)?);
+        Ok(())
+    });
+    match handle {
+        Some(handle) => Box::into_raw(Box::new(handle)),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Releases the keyboard (handing it back to the kernel driver).
+///
+/// # Safety
+///
+/// `kbd` must have come from `fusion_kbd_open` (or be NULL), and mustn't be
+/// used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn fusion_kbd_close(kbd: *mut Handle) {
+    if !kbd.is_null() {
+        drop(Box::from_raw(kbd));
+    }
+}
+
+/// Switches to a built-in preset, by name (`"wave"`, `"static"`, ...), in a
+/// color by name (`"red"`, ...). `color` can be NULL for presets that don't
+/// take one. `speed` goes from 0 - 10, `brightness` from 0 - 50.
+///
+/// # Safety
+///
+/// `kbd` must be an open keyboard, and `preset` / `color` NUL-terminated
+/// strings (or NULL).
+#[no_mangle]
+pub unsafe extern "C" fn fusion_kbd_set_preset(
+    kbd: *mut Handle,
+    preset: *const c_char,
+    speed: u8,
+    brightness: u8,
+    color: *const c_char,
+) -> c_int {
+    guard(|| {
+        let kbd = keyboard(kbd)?;
+        let (preset, color) = preset_args(preset, speed, brightness, color)?;
+        kbd.set_preset(preset, speed, brightness, color)
+            .map_err(usb_error)
+    })
+}
+
+/// Like `fusion_kbd_set_preset`, going in `direction` (`"left"`, `"right"`,
+/// `"up"` or `"down"`). Which presets take one is experimental; keyboards
+/// that can't pass one on give `FUSION_KBD_ERROR_NOT_SUPPORTED`.
+///
+/// # Safety
+///
+/// Same as `fusion_kbd_set_preset`, and `direction` must be a NUL-terminated
+/// string.
+#[no_mangle]
+pub unsafe extern "C" fn fusion_kbd_set_preset_direction(
+    kbd: *mut Handle,
+    preset: *const c_char,
+    speed: u8,
+    brightness: u8,
+    color: *const c_char,
+    direction: *const c_char,
+) -> c_int {
+    guard(|| {
+        let kbd = keyboard(kbd)?;
+        let (preset, color) = preset_args(preset, speed, brightness, color)?;
+        let name = string(direction, "the direction")?;
+        let direction = Direction::from_str(name)
+            .map_err(|_| invalid(format!("unknown direction '{}'", name)))?;
+        kbd.set_preset_direction(preset, speed, brightness, color, direction)
+            .map_err(usb_error)
+    })
+}
+
+/// Shows custom slot `slot` (0 - 4), at `brightness` (0 - 50).
+///
+/// # Safety
+///
+/// `kbd` must be an open keyboard.
+#[no_mangle]
+pub unsafe extern "C" fn fusion_kbd_set_custom(
+    kbd: *mut Handle,
+    slot: u8,
+    brightness: u8,
+) -> c_int {
+    guard(|| {
+        let kbd = keyboard(kbd)?;
+        at_most(brightness, MAX_BRIGHTNESS, "brightness")?;
+        kbd.set_custom(slot, brightness).map_err(usb_error)
+    })
+}
+
+/// How many bytes `kbd`'s custom configs are (four per key), for
+/// `fusion_kbd_upload_custom` and friends. 0 if `kbd` is NULL.
+///
+/// # Safety
+///
+/// `kbd` must be an open keyboard, or NULL.
+#[no_mangle]
+pub unsafe extern "C" fn fusion_kbd_config_len(kbd: *mut Handle) -> usize {
+    match keyboard(kbd) {
+        Ok(kbd) => kbd.capabilities().config_len,
+        Err((_, message)) => {
+            set_error(message);
+            0
+        }
+    }
+}
+
+/// the error for a config buffer of `len` bytes, if it's NULL or `kbd` doesn't
+/// take that many
+fn config_buffer(kbd: &FusionKBD, is_null: bool, len: usize) -> Result<(), (c_int, String)> {
+    let config_len = kbd.capabilities().config_len;
+    if is_null || len != config_len {
+        return Err(invalid(format!(
+            "custom configs are {} bytes (not {})",
+            config_len, len
+        )));
+    }
+    Ok(())
+}
+
+/// Uploads a custom config (`fusion_kbd_config_len` bytes, four per key) to
+/// slot `slot`. It isn't shown until `fusion_kbd_set_custom`.
+///
+/// # Safety
+///
+/// `kbd` must be an open keyboard, and `data` point to `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn fusion_kbd_upload_custom(
+    kbd: *mut Handle,
+    slot: u8,
+    data: *const u8,
+    len: usize,
+) -> c_int {
+    guard(|| {
+        let kbd = keyboard(kbd)?;
+        config_buffer(kbd, data.is_null(), len)?;
+        kbd.upload_custom(slot, slice::from_raw_parts(data, len))
+            .map_err(usb_error)
+    })
+}
+
+/// Reads the custom config in slot `slot` back into `data`
+/// (`fusion_kbd_config_len` bytes).
+///
+/// # Safety
+///
+/// `kbd` must be an open keyboard, and `data` point to `len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn fusion_kbd_download_custom(
+    kbd: *mut Handle,
+    slot: u8,
+    data: *mut u8,
+    len: usize,
+) -> c_int {
+    guard(|| {
+        let kbd = keyboard(kbd)?;
+        config_buffer(kbd, data.is_null(), len)?;
+        kbd.download_custom(slot, slice::from_raw_parts_mut(data, len))
+            .map_err(usb_error)
+    })
+}
+
+/// Loads a config file in any format the CLI reads (a raw dump, .fkp, .json,
+/// .toml, .txt, .orp or .png, going by its extension) into `data`
+/// (`len` bytes, which has to be as long as the config turns out to be),
+/// ready for `fusion_kbd_upload_custom`. `layout` is what key names mean
+/// (`"ansi"`, `"iso"`, or a keymap TOML), or NULL for `"ansi"`. Doesn't need
+/// a keyboard.
+///
+/// # Safety
+///
+/// `path` / `layout` must be NUL-terminated strings (`layout` can be NULL),
+/// and `data` point to `len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn fusion_kbd_load_config(
+    path: *const c_char,
+    layout: *const c_char,
+    data: *mut u8,
+    len: usize,
+) -> c_int {
+    guard(|| {
+        let path = string(path, "the path")?;
+        let layout = match layout.is_null() {
+            true => "ansi",
+            false => string(layout, "the layout")?,
+        };
+        if data.is_null() {
+            return Err(invalid("the config buffer is NULL".to_string()));
+        }
+        let keymap = Keymap::load(layout).map_err(invalid)?;
+        let cfg: CustomConfig = fusion_kbd_protocol::config::load(Path::new(path), &keymap)
+            .map_err(|e| (FUSION_KBD_ERROR_OTHER, format!("'{}': {}", path, e)))?;
+        if cfg.as_bytes().len() != len {
+            return Err(invalid(format!(
+                "'{}' is a {} byte config (not {})",
+                path,
+                cfg.as_bytes().len(),
+                len
+            )));
+        }
+        slice::from_raw_parts_mut(data, len).copy_from_slice(cfg.as_bytes());
+        Ok(())
+    })
+}
+
+/// What the last call that failed (on this thread) went wrong with. Owned by
+/// the library, and valid until the next call that fails.
+#[no_mangle]
+pub extern "C" fn fusion_kbd_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ptr())
+}
@@ -0,0 +1,12 @@
+#![no_main]
+
+use fusion_kbd_protocol::config::{animation, image};
+use fusion_kbd_protocol::Keymap;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = image::from_gif(data);
+    if let Ok(text) = std::str::from_utf8(data) {
+        let _ = animation::from_json(text, &Keymap::ansi());
+    }
+});
@@ -0,0 +1,16 @@
+#![no_main]
+
+use fusion_kbd_protocol::config::backup::Backup;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // whatever is accepted has to survive being written back out
+    if let Ok(backup) = Backup::from_bytes(data) {
+        let again = Backup::from_bytes(&backup.to_bytes()).expect("re-reading");
+        assert_eq!(again.slots.len(), backup.slots.len());
+        for ((a, x), (b, y)) in again.slots.iter().zip(&backup.slots) {
+            assert_eq!(a, b);
+            assert_eq!(&x.as_bytes()[..], &y.as_bytes()[..]);
+        }
+    }
+});
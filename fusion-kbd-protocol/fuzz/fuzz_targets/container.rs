@@ -0,0 +1,16 @@
+#![no_main]
+
+use fusion_kbd_protocol::config::container::Container;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // whatever is accepted has to survive being written back out
+    if let Ok(container) = Container::from_bytes(data) {
+        let again = Container::from_bytes(&container.to_bytes()).expect("re-reading");
+        assert_eq!(
+            &again.config.as_bytes()[..],
+            &container.config.as_bytes()[..]
+        );
+        assert_eq!(again.about, container.about);
+    }
+});
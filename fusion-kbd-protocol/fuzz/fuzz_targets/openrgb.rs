@@ -0,0 +1,9 @@
+#![no_main]
+
+use fusion_kbd_protocol::config::openrgb;
+use fusion_kbd_protocol::Keymap;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = openrgb::from_orp(data, &Keymap::ansi());
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use fusion_kbd_protocol::config::image;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = image::from_png(data);
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use fusion_kbd_protocol::config::toml;
+use fusion_kbd_protocol::Keymap;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(text) = std::str::from_utf8(data) {
+        let _ = toml::from_toml(text, &Keymap::ansi());
+    }
+});
@@ -0,0 +1,21 @@
+#![no_main]
+
+use fusion_kbd_protocol::config::{validate, Format};
+use fusion_kbd_protocol::Keymap;
+use libfuzzer_sys::fuzz_target;
+
+// `custom validate` runs on files before they're known to parse at all
+fuzz_target!(|data: &[u8]| {
+    let keymap = Keymap::ansi();
+    for format in &[
+        Format::Binary,
+        Format::Container,
+        Format::Json,
+        Format::Toml,
+        Format::Png,
+        Format::OpenRgb,
+        Format::Text,
+    ] {
+        let _ = validate::validate(data, *format, &keymap);
+    }
+});
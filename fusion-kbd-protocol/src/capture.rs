@@ -0,0 +1,692 @@
+//! Reading USB captures, e.g: of the Windows Fusion software doing something
+//! this crate can't yet, so its transfers can be studied (or replayed).
+//!
+//! Captures can be pcapng (what Wireshark saves) or classic pcap, of either
+//! USBPcap (Windows) or usbmon (Linux) traffic. Only control and interrupt
+//! transfers are kept, which is all the keyboard uses.
+//!
+//! `lighting` then works out what the keyboard was set to.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::config::CustomConfig;
+use crate::models;
+use crate::protocol::{Header, NUM_SLOTS};
+use crate::state::State;
+
+/// link types of USB traffic
+const LINKTYPE_USB_LINUX: u32 = 189;
+const LINKTYPE_USB_LINUX_MMAPPED: u32 = 220;
+const LINKTYPE_USBPCAP: u32 = 249;
+
+/// pcapng block types
+const SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const INTERFACE_DESCRIPTION: u32 = 1;
+const SIMPLE_PACKET: u32 = 3;
+const ENHANCED_PACKET: u32 = 6;
+
+/// A control transfer's setup packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Setup {
+    /// `0x80` set for IN transfers
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    /// bytes to send / read
+    pub length: u16,
+}
+
+impl Setup {
+    fn parse(b: &[u8]) -> Setup {
+        Setup {
+            request_type: b[0],
+            request: b[1],
+            value: u16::from_le_bytes([b[2], b[3]]),
+            index: u16::from_le_bytes([b[4], b[5]]),
+            length: u16::from_le_bytes([b[6], b[7]]),
+        }
+    }
+
+    /// anything but a standard request (those belong to the USB stack, e.g:
+    /// enumerating the device)
+    pub fn is_class_or_vendor(&self) -> bool {
+        self.request_type & 0x60 != 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transfer {
+    /// the `(bus, address)` it went to
+    pub device: (u16, u16),
+    /// `0x80` set for IN transfers
+    pub endpoint: u8,
+    /// `None` for interrupt transfers
+    pub setup: Option<Setup>,
+    /// what was sent (for OUT transfers), or what came back (for IN ones)
+    pub data: Vec<u8>,
+}
+
+impl Transfer {
+    pub fn is_in(&self) -> bool {
+        match self.setup {
+            Some(setup) => setup.request_type & 0x80 != 0,
+            None => self.endpoint & 0x80 != 0,
+        }
+    }
+}
+
+impl fmt::Display for Transfer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let data: Vec<String> = self.data.iter().map(|b| format!("{:02x}", b)).collect();
+        write!(
+            f,
+            "{:03}:{:03} {:<3} ",
+            self.device.0,
+            self.device.1,
+            if self.is_in() { "IN" } else { "OUT" }
+        )?;
+        match self.setup {
+            Some(s) => write!(
+                f,
+                "control {:02x} {:02x} {:04x} {:04x} ({} bytes)",
+                s.request_type, s.request, s.value, s.index, s.length
+            )?,
+            None => write!(f, "interrupt {:#04x}", self.endpoint)?,
+        }
+        write!(f, ": [{}]", data.join(" "))
+    }
+}
+
+/// one end of a transfer, as captured
+struct Event {
+    id: u64,
+    /// whether it's the completion (rather than the submission)
+    completion: bool,
+    device: (u16, u16),
+    endpoint: u8,
+    control: bool,
+    setup: Option<Setup>,
+    data: Vec<u8>,
+}
+
+/// bounds-checked little / big endian reads
+struct Reader<'a> {
+    bytes: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn slice(&self, at: usize, len: usize) -> Result<&'a [u8], String> {
+        at.checked_add(len)
+            .and_then(|end| self.bytes.get(at..end))
+            .ok_or_else(|| format!("truncated capture (wanted {} bytes at {})", len, at))
+    }
+
+    fn u16(&self, at: usize) -> Result<u16, String> {
+        let b = self.slice(at, 2)?;
+        let b = [b[0], b[1]];
+        Ok(if self.big_endian {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        })
+    }
+
+    fn u32(&self, at: usize) -> Result<u32, String> {
+        let b = self.slice(at, 4)?;
+        let b = [b[0], b[1], b[2], b[3]];
+        Ok(if self.big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        })
+    }
+
+    fn u64(&self, at: usize) -> Result<u64, String> {
+        Ok(if self.big_endian {
+            (self.u32(at)? as u64) << 32 | self.u32(at + 4)? as u64
+        } else {
+            (self.u32(at + 4)? as u64) << 32 | self.u32(at)? as u64
+        })
+    }
+}
+
+/// A USBPcap packet: a (little endian) `USBPCAP_BUFFER_PACKET_HEADER`, and
+/// then the data. The setup packet of a control transfer comes first in its
+/// data.
+fn usbpcap(packet: &[u8]) -> Result<Option<Event>, String> {
+    let r = Reader {
+        bytes: packet,
+        big_endian: false,
+    };
+    let header_len = r.u16(0)? as usize;
+    let id = r.u64(2)?;
+    let completion = r.slice(16, 1)?[0] & 1 != 0;
+    let device = (r.u16(17)?, r.u16(19)?);
+    let endpoint = r.slice(21, 1)?[0];
+    let control = match r.slice(22, 1)?[0] {
+        1 => false,
+        2 => true,
+        _ => return Ok(None), // isochronous / bulk
+    };
+    let data_len = r.u32(23)? as usize;
+    let mut data = r.slice(header_len, data_len)?;
+
+    let mut setup = None;
+    // the setup stage (of a submission)
+    if control && !completion && r.slice(27, 1)?[0] == 0 && data.len() >= 8 {
+        setup = Some(Setup::parse(data));
+        data = &data[8..];
+    }
+    Ok(Some(Event {
+        id,
+        completion,
+        device,
+        endpoint,
+        control,
+        setup,
+        data: data.to_vec(),
+    }))
+}
+
+/// A usbmon packet: a (host endian, assumed little) `usbmon_packet`, and then
+/// the data. `mmapped` captures have 16 more bytes of header.
+fn usbmon(packet: &[u8], mmapped: bool) -> Result<Option<Event>, String> {
+    let r = Reader {
+        bytes: packet,
+        big_endian: false,
+    };
+    let id = r.u64(0)?;
+    let completion = match r.slice(8, 1)?[0] {
+        b'S' => false,
+        b'C' | b'E' => true,
+        _ => return Ok(None),
+    };
+    let control = match r.slice(9, 1)?[0] {
+        1 => false,
+        2 => true,
+        _ => return Ok(None),
+    };
+    let endpoint = r.slice(10, 1)?[0];
+    let device = (r.u16(12)?, r.slice(11, 1)?[0] as u16);
+    // zero if there's a setup packet
+    let setup = if control && r.slice(14, 1)?[0] == 0 {
+        Some(Setup::parse(r.slice(40, 8)?))
+    } else {
+        None
+    };
+    let len_cap = r.u32(36)? as usize;
+    let header_len = if mmapped { 64 } else { 48 };
+    Ok(Some(Event {
+        id,
+        completion,
+        device,
+        endpoint,
+        control,
+        setup,
+        data: r.slice(header_len, len_cap)?.to_vec(),
+    }))
+}
+
+/// pairs up submissions and completions into transfers
+#[derive(Default)]
+struct Transfers {
+    done: Vec<Transfer>,
+    /// submitted, but not completed yet (by id)
+    pending: HashMap<u64, usize>,
+}
+
+impl Transfers {
+    fn add(&mut self, linktype: u32, packet: &[u8]) -> Result<(), String> {
+        let event = match linktype {
+            LINKTYPE_USBPCAP => usbpcap(packet)?,
+            LINKTYPE_USB_LINUX => usbmon(packet, false)?,
+            LINKTYPE_USB_LINUX_MMAPPED => usbmon(packet, true)?,
+            _ => None,
+        };
+        let event = match event {
+            Some(event) => event,
+            None => return Ok(()),
+        };
+
+        match self.pending.get(&event.id) {
+            None if event.completion => {} // submitted before the capture started
+            None => {
+                // a control transfer without a setup packet is one of
+                // USBPcap's later stages, of a transfer that's been dropped
+                if event.control && event.setup.is_none() {
+                    return Ok(());
+                }
+                self.pending.insert(event.id, self.done.len());
+                self.done.push(Transfer {
+                    device: event.device,
+                    endpoint: event.endpoint,
+                    setup: event.setup,
+                    data: event.data,
+                });
+            }
+            Some(&i) => {
+                let transfer = &mut self.done[i];
+                // OUT data is sent on submission (sometimes in its own USBPcap
+                // packet), IN data comes back on completion
+                if transfer.is_in() == event.completion {
+                    transfer.data.extend_from_slice(&event.data);
+                }
+                if event.completion {
+                    self.pending.remove(&event.id);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// the transfers in a classic pcap file, after its magic number
+fn pcap(r: &Reader, transfers: &mut Transfers) -> Result<(), String> {
+    let linktype = r.u32(20)?;
+    let mut at = 24;
+    while at < r.bytes.len() {
+        let caplen = r.u32(at + 8)? as usize;
+        transfers.add(linktype, r.slice(at + 16, caplen)?)?;
+        at += 16 + caplen;
+    }
+    Ok(())
+}
+
+/// the transfers in a pcapng file, section by section
+fn pcapng(bytes: &[u8], transfers: &mut Transfers) -> Result<(), String> {
+    let mut r = Reader {
+        bytes,
+        big_endian: false,
+    };
+    // each section's, by interface id
+    let mut linktypes = Vec::new();
+    let mut at = 0;
+    while at < bytes.len() {
+        if r.u32(at)? == SECTION_HEADER {
+            r.big_endian = match r.slice(at + 8, 4)? {
+                [0x1a, 0x2b, 0x3c, 0x4d] => true,
+                [0x4d, 0x3c, 0x2b, 0x1a] => false,
+                _ => return Err("bad pcapng byte order magic".to_string()),
+            };
+            linktypes.clear();
+        }
+        let kind = r.u32(at)?;
+        let len = r.u32(at + 4)? as usize;
+        if len < 12 || !len.is_multiple_of(4) {
+            return Err(format!("bad pcapng block length {} at {}", len, at));
+        }
+        let body = at + 8;
+        match kind {
+            INTERFACE_DESCRIPTION => linktypes.push(r.u16(body)? as u32),
+            ENHANCED_PACKET => {
+                let interface = r.u32(body)? as usize;
+                let caplen = r.u32(body + 12)? as usize;
+                let linktype = *linktypes
+                    .get(interface)
+                    .ok_or_else(|| format!("packet from unknown interface {}", interface))?;
+                transfers.add(linktype, r.slice(body + 20, caplen)?)?;
+            }
+            SIMPLE_PACKET => {
+                let linktype = *linktypes
+                    .first()
+                    .ok_or_else(|| "packet from unknown interface 0".to_string())?;
+                let caplen = (r.u32(body)? as usize).min(len.saturating_sub(16));
+                transfers.add(linktype, r.slice(body + 4, caplen)?)?;
+            }
+            _ => {}
+        }
+        at += len;
+    }
+    Ok(())
+}
+
+/// Every control / interrupt transfer in a pcapng (or pcap) capture, in the
+/// order they were submitted. Transfers that were already in flight when the
+/// capture started are left out.
+pub fn parse(bytes: &[u8]) -> Result<Vec<Transfer>, String> {
+    let mut transfers = Transfers::default();
+    match bytes.get(..4) {
+        Some([0x0a, 0x0d, 0x0d, 0x0a]) => pcapng(bytes, &mut transfers)?,
+        // microsecond and nanosecond timestamps, both byte orders
+        Some([0xd4, 0xc3, 0xb2, 0xa1]) | Some([0x4d, 0x3c, 0xb2, 0xa1]) => {
+            let r = Reader {
+                bytes,
+                big_endian: false,
+            };
+            pcap(&r, &mut transfers)?
+        }
+        Some([0xa1, 0xb2, 0xc3, 0xd4]) | Some([0xa1, 0xb2, 0x3c, 0x4d]) => {
+            let r = Reader {
+                bytes,
+                big_endian: true,
+            };
+            pcap(&r, &mut transfers)?
+        }
+        _ => return Err("not a pcapng or pcap file".to_string()),
+    }
+    Ok(transfers.done)
+}
+
+/// What a capture shows the keyboard being set to
+#[derive(Default)]
+pub struct Lighting {
+    /// the last lighting it was switched to
+    pub state: Option<State>,
+    /// the last config uploaded to each custom slot
+    pub configs: [Option<CustomConfig>; NUM_SLOTS as usize],
+}
+
+/// Decodes the headers (and custom configs) in `transfers`, which should all
+/// be the keyboard's. Anything else, including configs that aren't the length
+/// of any known model's, is skipped.
+pub fn lighting(transfers: &[Transfer]) -> Lighting {
+    let mut lighting = Lighting::default();
+    let mut transfers = transfers.iter().filter(|t| !t.is_in());
+    while let Some(t) = transfers.next() {
+        let header = match t.setup.and_then(|_| Header::from_bytes(&t.data)) {
+            Some(header) => header,
+            None => continue,
+        };
+        if let Some(state) = header.state() {
+            lighting.state = Some(state);
+        }
+        if let Some((slot, num_chunks)) = header.custom_config_slot() {
+            let data: Vec<u8> = transfers
+                .by_ref()
+                .take(num_chunks)
+                .filter(|t| t.setup.is_none())
+                .flat_map(|t| t.data.iter().copied())
+                .collect();
+            let known = models::MODELS
+                .iter()
+                .any(|m| m.num_chunks == num_chunks && m.config_len() == data.len());
+            if known {
+                lighting.configs[slot as usize] = Some(CustomConfig::from_bytes(data));
+            }
+        }
+    }
+    lighting
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{CONFIG_OUT_ENDPOINT, REPORT_VALUE, REQUEST_SET_REPORT};
+    use crate::state::Lighting as LightingState;
+
+    /// the keyboard, at 001:003
+    const BUS: u16 = 1;
+    const ADDRESS: u8 = 3;
+
+    fn set_report(header: &Header) -> (Option<Setup>, Vec<u8>) {
+        let setup = Setup {
+            request_type: 0x21,
+            request: REQUEST_SET_REPORT,
+            value: REPORT_VALUE,
+            index: 3,
+            length: 8,
+        };
+        (Some(setup), header.as_bytes().to_vec())
+    }
+
+    fn setup_bytes(s: &Setup) -> Vec<u8> {
+        let mut b = vec![s.request_type, s.request];
+        b.extend_from_slice(&s.value.to_le_bytes());
+        b.extend_from_slice(&s.index.to_le_bytes());
+        b.extend_from_slice(&s.length.to_le_bytes());
+        b
+    }
+
+    /// a usbmon packet: `kind` is `b'S'` or `b'C'`
+    fn usbmon_packet(
+        id: u64,
+        kind: u8,
+        endpoint: u8,
+        setup: Option<Setup>,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut p = id.to_le_bytes().to_vec();
+        p.push(kind);
+        p.push(if setup.is_some() || endpoint & 0x7f == 0 {
+            2
+        } else {
+            1
+        });
+        p.push(endpoint);
+        p.push(ADDRESS);
+        p.extend_from_slice(&BUS.to_le_bytes());
+        p.push(if setup.is_some() { 0 } else { b'-' });
+        p.push(0);
+        p.extend_from_slice(&[0; 16]); // timestamp, status
+        p.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        p.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        match setup {
+            Some(s) => p.extend_from_slice(&setup_bytes(&s)),
+            None => p.extend_from_slice(&[0; 8]),
+        }
+        p.extend_from_slice(data);
+        p
+    }
+
+    /// an OUT transfer's submission (with its data) and completion
+    fn usbmon_out(id: u64, endpoint: u8, setup: Option<Setup>, data: &[u8]) -> Vec<Vec<u8>> {
+        vec![
+            usbmon_packet(id, b'S', endpoint, setup, data),
+            usbmon_packet(id, b'C', endpoint, None, &[]),
+        ]
+    }
+
+    /// a little endian classic pcap file of usbmon `packets`
+    fn pcap_file(packets: &[Vec<u8>]) -> Vec<u8> {
+        let mut f = vec![0xd4, 0xc3, 0xb2, 0xa1];
+        f.extend_from_slice(&[2, 0, 4, 0]); // version
+        f.extend_from_slice(&[0; 8]); // timezone, accuracy
+        f.extend_from_slice(&0xffffu32.to_le_bytes());
+        f.extend_from_slice(&LINKTYPE_USB_LINUX.to_le_bytes());
+        for p in packets {
+            f.extend_from_slice(&[0; 8]); // timestamp
+            f.extend_from_slice(&(p.len() as u32).to_le_bytes());
+            f.extend_from_slice(&(p.len() as u32).to_le_bytes());
+            f.extend_from_slice(p);
+        }
+        f
+    }
+
+    fn block(f: &mut Vec<u8>, kind: u32, body: &[u8]) {
+        let mut body = body.to_vec();
+        while !body.len().is_multiple_of(4) {
+            body.push(0);
+        }
+        let len = (body.len() + 12) as u32;
+        f.extend_from_slice(&kind.to_le_bytes());
+        f.extend_from_slice(&len.to_le_bytes());
+        f.extend_from_slice(&body);
+        f.extend_from_slice(&len.to_le_bytes());
+    }
+
+    /// a little endian pcapng file with one interface, of `linktype`
+    fn pcapng_file(linktype: u32, packets: &[Vec<u8>]) -> Vec<u8> {
+        let mut f = Vec::new();
+        let mut shb = vec![0x4d, 0x3c, 0x2b, 0x1a, 1, 0, 0, 0];
+        shb.extend_from_slice(&u64::MAX.to_le_bytes());
+        block(&mut f, SECTION_HEADER, &shb);
+        let mut idb = (linktype as u16).to_le_bytes().to_vec();
+        idb.extend_from_slice(&[0, 0]);
+        idb.extend_from_slice(&0xffffu32.to_le_bytes());
+        block(&mut f, INTERFACE_DESCRIPTION, &idb);
+        for p in packets {
+            let mut epb = vec![0; 12]; // interface 0, timestamp
+            epb.extend_from_slice(&(p.len() as u32).to_le_bytes());
+            epb.extend_from_slice(&(p.len() as u32).to_le_bytes());
+            epb.extend_from_slice(p);
+            block(&mut f, ENHANCED_PACKET, &epb);
+        }
+        f
+    }
+
+    /// a USBPcap packet
+    fn usbpcap_packet(id: u64, completion: bool, stage: Option<u8>, data: &[u8]) -> Vec<u8> {
+        let header_len: u16 = if stage.is_some() { 28 } else { 27 };
+        let mut p = header_len.to_le_bytes().to_vec();
+        p.extend_from_slice(&id.to_le_bytes());
+        p.extend_from_slice(&[0; 6]); // status, function
+        p.push(completion as u8);
+        p.extend_from_slice(&BUS.to_le_bytes());
+        p.extend_from_slice(&(ADDRESS as u16).to_le_bytes());
+        p.push(if stage.is_some() {
+            0
+        } else {
+            CONFIG_OUT_ENDPOINT
+        });
+        p.push(if stage.is_some() { 2 } else { 1 });
+        p.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        if let Some(stage) = stage {
+            p.push(stage);
+        }
+        p.extend_from_slice(data);
+        p
+    }
+
+    /// set_custom's transfers: the config upload, and switching to it
+    fn upload(slot: u8, config: &[u8], brightness: u8) -> Vec<Vec<u8>> {
+        let mut packets = Vec::new();
+        let (setup, data) = set_report(&Header::custom_config(slot, 8));
+        packets.extend(usbmon_out(1, 0, setup, &data));
+        for (i, chunk) in config.chunks(64).enumerate() {
+            packets.extend(usbmon_out(2 + i as u64, CONFIG_OUT_ENDPOINT, None, chunk));
+        }
+        let (setup, data) = set_report(&Header::custom(slot, brightness).unwrap());
+        packets.extend(usbmon_out(10, 0, setup, &data));
+        packets
+    }
+
+    #[test]
+    fn reads_usbmon_pcaps() {
+        let (setup, data) = set_report(&Header::custom(2, 30).unwrap());
+        let mut packets = usbmon_out(7, 0, setup, &data);
+        // an IN transfer, whose data comes back on completion
+        packets.push(usbmon_packet(8, b'S', 0x81, None, &[]));
+        packets.push(usbmon_packet(8, b'C', 0x81, None, &[1, 2, 3]));
+        // the completion of something submitted before the capture started
+        packets.push(usbmon_packet(99, b'C', 0x81, None, &[4, 5]));
+
+        let transfers = parse(&pcap_file(&packets)).unwrap();
+        assert_eq!(
+            transfers,
+            vec![
+                Transfer {
+                    device: (BUS, ADDRESS as u16),
+                    endpoint: 0,
+                    setup,
+                    data: data.clone(),
+                },
+                Transfer {
+                    device: (BUS, ADDRESS as u16),
+                    endpoint: 0x81,
+                    setup: None,
+                    data: vec![1, 2, 3],
+                },
+            ]
+        );
+        assert!(transfers[1].is_in());
+        assert!(setup.unwrap().is_class_or_vendor());
+        assert_eq!(
+            transfers[0].to_string(),
+            "001:003 OUT control 21 09 0300 0003 (8 bytes): [08 00 35 00 1e 00 00 a4]"
+        );
+    }
+
+    #[test]
+    fn works_out_what_the_keyboard_was_set_to() {
+        let config: Vec<u8> = (0..512).map(|i| i as u8).collect();
+        let transfers = parse(&pcap_file(&upload(1, &config, 30))).unwrap();
+        let lighting = lighting(&transfers);
+        assert_eq!(
+            lighting.state,
+            Some(State {
+                lighting: LightingState::Custom { slot: 1 },
+                brightness: 30,
+            })
+        );
+        assert_eq!(
+            lighting.configs[1].as_ref().map(|c| c.as_bytes().to_vec()),
+            Some(config)
+        );
+        assert!(lighting.configs[0].is_none());
+    }
+
+    #[test]
+    fn short_uploads_are_skipped() {
+        let config = vec![0xaa; 512];
+        let mut packets = upload(4, &config, 10);
+        // drop the last chunk's submission and completion
+        packets.drain(16..18);
+        let transfers = parse(&pcap_file(&packets)).unwrap();
+        let lighting = lighting(&transfers);
+        assert!(lighting.configs[4].is_none());
+    }
+
+    #[test]
+    fn reads_usbpcap_pcapngs() {
+        let (setup, data) = set_report(&Header::custom(0, 50).unwrap());
+        let mut setup_stage = setup_bytes(&setup.unwrap());
+        setup_stage.extend_from_slice(&data);
+        let packets = vec![
+            usbpcap_packet(1, false, Some(0), &setup_stage),
+            usbpcap_packet(1, true, Some(3), &[]),
+            usbpcap_packet(2, false, None, &[0x11; 64]),
+            usbpcap_packet(2, true, None, &[]),
+            // a later stage of a control transfer that wasn't seen starting
+            usbpcap_packet(3, false, Some(1), &[0; 8]),
+        ];
+        let transfers = parse(&pcapng_file(LINKTYPE_USBPCAP, &packets)).unwrap();
+        assert_eq!(transfers.len(), 2);
+        assert_eq!(transfers[0].setup, setup);
+        assert_eq!(transfers[0].data, data);
+        assert_eq!(transfers[1].endpoint, CONFIG_OUT_ENDPOINT);
+        assert_eq!(transfers[1].data, vec![0x11; 64]);
+        assert_eq!(
+            lighting(&transfers).state,
+            Some(State {
+                lighting: LightingState::Custom { slot: 0 },
+                brightness: 50,
+            })
+        );
+    }
+
+    #[test]
+    fn refuses_what_it_cant_read() {
+        assert_eq!(
+            parse(b"\x89PNG").err().unwrap(),
+            "not a pcapng or pcap file"
+        );
+        assert_eq!(parse(&[]).err().unwrap(), "not a pcapng or pcap file");
+
+        let file = pcap_file(&upload(0, &[0; 512], 50));
+        for len in [10, 30, file.len() - 1] {
+            assert!(parse(&file[..len]).is_err(), "{} bytes", len);
+        }
+
+        let mut file = pcapng_file(LINKTYPE_USB_LINUX, &[]);
+        // byte order magic
+        file[8] = 0;
+        assert_eq!(parse(&file).err().unwrap(), "bad pcapng byte order magic");
+
+        let mut file = pcapng_file(LINKTYPE_USB_LINUX, &[]);
+        file[4..8].copy_from_slice(&13u32.to_le_bytes());
+        assert_eq!(
+            parse(&file).err().unwrap(),
+            "bad pcapng block length 13 at 0"
+        );
+    }
+
+    #[test]
+    fn other_link_types_are_ignored() {
+        // ethernet
+        let file = pcapng_file(1, &[vec![0; 60]]);
+        assert!(parse(&file).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,152 @@
+//! JSON animations, for `play`. An animation is either a list of frames, each
+//! shown for its own duration:
+//!
+//! ```json
+//! {
+//!     "frames": [
+//!         { "duration_ms": 200, "fill": "red", "keys": { "esc": "white" } },
+//!         { "duration_ms": 200, "fill": "blue" }
+//!     ]
+//! }
+//! ```
+//!
+//! or a list of keyframes, which are crossfaded between at `fps` (default:
+//! 30). The animation ends at the last keyframe:
+//!
+//! ```json
+//! {
+//!     "fps": 30,
+//!     "keyframes": [
+//!         { "at_ms": 0, "fill": "red" },
+//!         { "at_ms": 1000, "fill": "blue", "keys": { "w": "white" } },
+//!         { "at_ms": 2000, "fill": "red" }
+//!     ]
+//! }
+//! ```
+//!
+//! Every frame starts out as `fill` (default: off), with `keys` (like a JSON
+//! profile, see `json`) on top.
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde_json::Value;
+
+use super::{json, CustomConfig, Rgb};
+use crate::effects;
+use crate::keymap::Keymap;
+
+const DEFAULT_FPS: u64 = 30;
+
+/// the picture a frame / keyframe describes
+fn picture(value: &Value, keymap: &Keymap) -> Result<CustomConfig, String> {
+    let mut cfg = match value["keys"] {
+        Value::Null => CustomConfig::new(),
+        ref keys => json::from_json(&keys.to_string(), keymap)?,
+    };
+
+    match value["fill"] {
+        Value::Null => {}
+        Value::String(ref fill) => {
+            let fill = Rgb::from_str(fill)?;
+            let keys = value["keys"].as_object();
+            for key in 0..super::NUM_KEYS {
+                let named = keymap
+                    .name(key)
+                    .is_some_and(|n| keys.is_some_and(|k| k.contains_key(n)));
+                if !named {
+                    cfg.set_key(key, fill);
+                }
+            }
+        }
+        _ => return Err("`fill` must be a color".to_string()),
+    }
+
+    Ok(cfg)
+}
+
+/// a whole number of milliseconds
+fn millis(value: &Value, field: &str) -> Result<u64, String> {
+    value[field]
+        .as_u64()
+        .ok_or_else(|| format!("`{}` must be a whole number", field))
+}
+
+/// Compiles a JSON animation to frames (and how long each is shown for).
+pub fn from_json(text: &str, keymap: &Keymap) -> Result<Vec<(CustomConfig, Duration)>, String> {
+    let value: Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+
+    let frames = match (&value["frames"], &value["keyframes"]) {
+        (Value::Array(frames), Value::Null) => frames
+            .iter()
+            .enumerate()
+            .map(|(i, frame)| {
+                let duration = millis(frame, "duration_ms").map(Duration::from_millis);
+                let cfg = duration.and_then(|d| Ok((picture(frame, keymap)?, d)));
+                cfg.map_err(|e| format!("frame {}: {}", i, e))
+            })
+            .collect::<Result<Vec<_>, String>>()?,
+        (Value::Null, Value::Array(keyframes)) => {
+            let fps = match value["fps"] {
+                Value::Null => DEFAULT_FPS,
+                ref fps => fps
+                    .as_u64()
+                    .filter(|&f| f > 0)
+                    .ok_or_else(|| "`fps` must be a positive number".to_string())?,
+            };
+            interpolate(keyframes, fps, keymap)?
+        }
+        _ => return Err("an animation needs either `frames` or `keyframes`".to_string()),
+    };
+
+    if frames.is_empty() {
+        return Err("an animation needs at least one frame".to_string());
+    }
+    Ok(frames)
+}
+
+/// `fps` frames a second, crossfading between each pair of keyframes
+fn interpolate(
+    keyframes: &[Value],
+    fps: u64,
+    keymap: &Keymap,
+) -> Result<Vec<(CustomConfig, Duration)>, String> {
+    if keyframes.len() < 2 {
+        return Err("an animation needs at least two keyframes".to_string());
+    }
+
+    let mut keys: Vec<(u64, CustomConfig)> = Vec::new();
+    for (i, keyframe) in keyframes.iter().enumerate() {
+        let at = millis(keyframe, "at_ms").map_err(|e| format!("keyframe {}: {}", i, e))?;
+        if keys.last().is_some_and(|&(last, _)| at <= last) {
+            return Err(format!(
+                "keyframe {}: `at_ms` must be after the last one's",
+                i
+            ));
+        }
+        let cfg = picture(keyframe, keymap).map_err(|e| format!("keyframe {}: {}", i, e))?;
+        keys.push((at, cfg));
+    }
+
+    let (start, end) = (keys[0].0, keys[keys.len() - 1].0);
+    let (step, delay) = (1000.0 / fps as f64, Duration::from_secs(1) / fps as u32);
+    let mut frames = Vec::new();
+    // before the first keyframe, it's held
+    if start > 0 {
+        frames.push((keys[0].1.clone(), Duration::from_millis(start)));
+    }
+
+    let mut t = start as f64;
+    let mut segment = 0;
+    while t < end as f64 {
+        while keys[segment + 1].0 as f64 <= t {
+            segment += 1;
+        }
+        let (a_at, a) = &keys[segment];
+        let (b_at, b) = &keys[segment + 1];
+        let progress = (t - *a_at as f64) / (b_at - a_at) as f64;
+        frames.push((effects::crossfade(a, b, progress as f32), delay));
+        t += step;
+    }
+    Ok(frames)
+}
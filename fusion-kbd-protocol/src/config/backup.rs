@@ -0,0 +1,137 @@
+//! Backup of every custom slot (`.fkb`), so they can be put back after a
+//! reinstall or a firmware reset:
+//!
+//! ```text
+//! "FKBB" | version: u8 | metadata length: u16 LE | metadata | config per slot
+//! ```
+//!
+//! Like a container's (see `container`), the metadata is a TOML table. It
+//! says which slot each config is for, in order:
+//!
+//! ```toml
+//! model = "aero-15x"
+//! layout = "ansi"
+//! created = 1760400000 # unix time
+//! slots = [0, 1, 2, 3, 4]
+//! ```
+//!
+//! Each config is as long as the model's (see `Model::config_len`), and
+//! models this version doesn't know are assumed to be like `FALLBACK`.
+
+use super::CustomConfig;
+use crate::models::{self, FALLBACK};
+use crate::protocol::NUM_SLOTS;
+
+pub const MAGIC: &[u8; 4] = b"FKBB";
+pub const VERSION: u8 = 1;
+
+#[derive(Clone)]
+pub struct Backup {
+    /// keyboard model the slots were read from
+    pub model: String,
+    /// keymap layout in use when they were (see `Keymap::layout`)
+    pub layout: String,
+    /// when, in seconds since the unix epoch
+    pub created: u64,
+    pub slots: Vec<(u8, CustomConfig)>,
+}
+
+impl Backup {
+    /// true if `data` starts with the backup magic
+    pub fn detect(data: &[u8]) -> bool {
+        data.starts_with(MAGIC)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Backup, String> {
+        if !Backup::detect(data) {
+            return Err("not a slot backup (bad magic)".to_string());
+        }
+        if data.len() < 7 {
+            return Err("truncated backup header".to_string());
+        }
+        if data[4] != VERSION {
+            return Err(format!("unsupported backup version {}", data[4]));
+        }
+
+        let meta_len = u16::from_le_bytes([data[5], data[6]]) as usize;
+        let body = &data[7..];
+        if body.len() < meta_len {
+            return Err("truncated backup metadata".to_string());
+        }
+        let meta = std::str::from_utf8(&body[..meta_len]).map_err(|e| e.to_string())?;
+        let meta: toml::Table = meta.parse().map_err(|e: toml::de::Error| e.to_string())?;
+        let string = |name: &str| -> Result<String, String> {
+            match meta.get(name) {
+                Some(toml::Value::String(s)) => Ok(s.clone()),
+                Some(_) => Err(format!("`{}` must be a string", name)),
+                None => Err(format!("missing `{}`", name)),
+            }
+        };
+        let created = match meta.get("created") {
+            Some(toml::Value::Integer(n)) if *n >= 0 => *n as u64,
+            Some(_) => return Err("`created` must be a unix time".to_string()),
+            None => return Err("missing `created`".to_string()),
+        };
+        let slots = match meta.get("slots") {
+            Some(toml::Value::Array(slots)) => slots
+                .iter()
+                .map(|s| match s.as_integer() {
+                    Some(s) if (0..NUM_SLOTS as i64).contains(&s) => Some(s as u8),
+                    _ => None,
+                })
+                .collect::<Option<Vec<u8>>>()
+                .ok_or_else(|| format!("`slots` must be slots from 0 - {}", NUM_SLOTS - 1))?,
+            Some(_) => return Err("`slots` must be a list of slots".to_string()),
+            None => return Err("missing `slots`".to_string()),
+        };
+
+        let model = string("model")?;
+        let config_len = models::by_name(&model).unwrap_or(&FALLBACK).config_len();
+        let configs = &body[meta_len..];
+        if configs.len() != slots.len() * config_len {
+            return Err(format!(
+                "expected {} bytes of configs (for {} slots), got {}",
+                slots.len() * config_len,
+                slots.len(),
+                configs.len()
+            ));
+        }
+        let slots = slots
+            .into_iter()
+            .zip(configs.chunks(config_len))
+            .map(|(slot, data)| (slot, CustomConfig::from_bytes(data.to_vec())))
+            .collect();
+
+        Ok(Backup {
+            model,
+            layout: string("layout")?,
+            created,
+            slots,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let slots: Vec<toml::Value> = self
+            .slots
+            .iter()
+            .map(|&(slot, _)| (slot as i64).into())
+            .collect();
+        let mut meta = toml::Table::new();
+        meta.insert("model".to_string(), self.model.clone().into());
+        meta.insert("layout".to_string(), self.layout.clone().into());
+        meta.insert("created".to_string(), (self.created as i64).into());
+        meta.insert("slots".to_string(), slots.into());
+        let meta = meta.to_string();
+
+        let configs: usize = self.slots.iter().map(|(_, cfg)| cfg.as_bytes().len()).sum();
+        let mut data = Vec::with_capacity(7 + meta.len() + configs);
+        data.extend_from_slice(MAGIC);
+        data.push(VERSION);
+        data.extend_from_slice(&(meta.len() as u16).to_le_bytes());
+        data.extend_from_slice(meta.as_bytes());
+        for (_, cfg) in &self.slots {
+            data.extend_from_slice(cfg.as_bytes());
+        }
+        data
+    }
+}
@@ -0,0 +1,378 @@
+//! Versioned profile container (`.fkp`), which wraps a raw custom config with
+//! metadata describing what it was made for, and (optionally) what it is:
+//!
+//! ```text
+//! "FKBP" | version: u8 | metadata length: u16 LE | metadata | config | CRC-32 LE
+//! ```
+//!
+//! The config is as long as the `model` it's for needs (see
+//! `Model::config_len`), or `models::FALLBACK`'s for models this version
+//! doesn't know.
+//!
+//! The metadata is a TOML table, so new fields can be added without bumping
+//! the version:
+//!
+//! ```toml
+//! model = "aero-15x"
+//! layout = "ansi"
+//! name = "Sunset"              # the rest are optional
+//! author = "someone"
+//! description = "warm colors, fading into purple"
+//! ```
+//!
+//! The CRC-32 covers everything before it. Version 1 containers didn't have
+//! one (or a name, author or description), and are still read.
+
+use super::CustomConfig;
+use crate::models::{self, FALLBACK};
+use crate::protocol::{check_config_len, BYTES_PER_KEY};
+
+pub const MAGIC: &[u8; 4] = b"FKBP";
+pub const VERSION: u8 = 2;
+
+/// What a shared profile says about itself. All of it is optional.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct About {
+    pub name: Option<String>,
+    pub author: Option<String>,
+    pub description: Option<String>,
+}
+
+impl About {
+    /// `self`, with whatever `other` sets replacing it
+    pub fn merge(&self, other: &About) -> About {
+        About {
+            name: other.name.clone().or_else(|| self.name.clone()),
+            author: other.author.clone().or_else(|| self.author.clone()),
+            description: other
+                .description
+                .clone()
+                .or_else(|| self.description.clone()),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Container {
+    /// keyboard model the config was made for
+    pub model: String,
+    /// keymap layout the config was made with (see `Keymap::layout`)
+    pub layout: String,
+    pub about: About,
+    pub config: CustomConfig,
+}
+
+/// CRC-32 (the IEEE one, as zip and png use)
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+impl Container {
+    /// true if `data` starts with the container magic
+    pub fn detect(data: &[u8]) -> bool {
+        data.starts_with(MAGIC)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Container, String> {
+        if !Container::detect(data) {
+            return Err("not a profile container (bad magic)".to_string());
+        }
+        if data.len() < 7 {
+            return Err("truncated container header".to_string());
+        }
+        let version = data[4];
+        let checksum_len = match version {
+            1 => 0,
+            VERSION => 4,
+            _ => return Err(format!("unsupported container version {}", version)),
+        };
+
+        let meta_len = u16::from_le_bytes([data[5], data[6]]) as usize;
+        let body = &data[7..];
+        let config_len = body
+            .len()
+            .checked_sub(meta_len + checksum_len)
+            .filter(|&len| len > 0 && len % BYTES_PER_KEY == 0)
+            .ok_or_else(|| {
+                format!(
+                    "bad metadata length {} ({} bytes after the header)",
+                    meta_len,
+                    body.len()
+                )
+            })?;
+        if checksum_len > 0 {
+            let (checked, checksum) = data.split_at(data.len() - 4);
+            let checksum = u32::from_le_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]);
+            if crc32(checked) != checksum {
+                return Err("checksum mismatch (the container is corrupted)".to_string());
+            }
+        }
+
+        let meta = std::str::from_utf8(&body[..meta_len]).map_err(|e| e.to_string())?;
+        let meta: toml::Table = meta.parse().map_err(|e: toml::de::Error| e.to_string())?;
+        let string = |name: &str| -> Result<String, String> {
+            match meta.get(name) {
+                Some(toml::Value::String(s)) => Ok(s.clone()),
+                Some(_) => Err(format!("`{}` must be a string", name)),
+                None => Err(format!("missing `{}`", name)),
+            }
+        };
+        let optional = |name: &str| -> Result<Option<String>, String> {
+            match meta.get(name) {
+                None => Ok(None),
+                Some(_) => string(name).map(Some),
+            }
+        };
+
+        let model = string("model")?;
+        check_config_len(models::by_name(&model).unwrap_or(&FALLBACK), config_len)?;
+        let config = body[meta_len..meta_len + config_len].to_vec();
+
+        Ok(Container {
+            model,
+            layout: string("layout")?,
+            about: About {
+                name: optional("name")?,
+                author: optional("author")?,
+                description: optional("description")?,
+            },
+            config: CustomConfig::from_bytes(config),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut meta = toml::Table::new();
+        meta.insert("model".to_string(), self.model.clone().into());
+        meta.insert("layout".to_string(), self.layout.clone().into());
+        let about = [
+            ("name", &self.about.name),
+            ("author", &self.about.author),
+            ("description", &self.about.description),
+        ];
+        for (name, value) in about {
+            if let Some(value) = value {
+                meta.insert(name.to_string(), value.clone().into());
+            }
+        }
+        let meta = meta.to_string();
+
+        let config = self.config.as_bytes();
+        let mut data = Vec::with_capacity(7 + meta.len() + config.len() + 4);
+        data.extend_from_slice(MAGIC);
+        data.push(VERSION);
+        data.extend_from_slice(&(meta.len() as u16).to_le_bytes());
+        data.extend_from_slice(meta.as_bytes());
+        data.extend_from_slice(config);
+        let checksum = crc32(&data);
+        data.extend_from_slice(&checksum.to_le_bytes());
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Rgb;
+
+    fn container() -> Container {
+        let mut config = CustomConfig::new();
+        config.set_key(0, Rgb(0xff, 0x80, 0x00));
+        config.set_key(100, Rgb(0x12, 0x34, 0x56));
+        Container {
+            model: FALLBACK.name.to_string(),
+            layout: "ansi".to_string(),
+            about: About {
+                name: Some("Sunset".to_string()),
+                author: None,
+                description: Some("warm colors".to_string()),
+            },
+            config,
+        }
+    }
+
+    /// a container with `meta` as its metadata, and a CRC if it's version 2
+    fn raw(version: u8, meta: &str) -> Vec<u8> {
+        raw_config(version, meta, container().config.as_bytes())
+    }
+
+    fn raw_config(version: u8, meta: &str, config: &[u8]) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.push(version);
+        data.extend_from_slice(&(meta.len() as u16).to_le_bytes());
+        data.extend_from_slice(meta.as_bytes());
+        data.extend_from_slice(config);
+        if version == VERSION {
+            let checksum = crc32(&data);
+            data.extend_from_slice(&checksum.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn round_trips() {
+        let original = container();
+        let data = original.to_bytes();
+        assert!(Container::detect(&data));
+
+        let parsed = Container::from_bytes(&data).unwrap();
+        assert_eq!(parsed.model, original.model);
+        assert_eq!(parsed.layout, original.layout);
+        assert_eq!(parsed.about, original.about);
+        assert!(parsed.config.diff(&original.config).is_empty());
+        assert_eq!(parsed.to_bytes(), data);
+    }
+
+    #[test]
+    fn version_1_containers_have_no_crc() {
+        let data = raw(1, "model = \"aero-15x\"\nlayout = \"iso\"\n");
+        let parsed = Container::from_bytes(&data).unwrap();
+        assert_eq!(parsed.layout, "iso");
+        assert_eq!(parsed.about, About::default());
+        assert!(parsed.config.diff(&container().config).is_empty());
+
+        // ...so one with a CRC tacked on is the wrong size
+        let mut data = data;
+        data.extend_from_slice(&[0; 4]);
+        assert!(Container::from_bytes(&data).is_err());
+    }
+
+    #[test]
+    fn corruption_is_caught_by_the_crc() {
+        let data = container().to_bytes();
+        for at in [5, 20, data.len() - 100, data.len() - 1] {
+            let mut corrupted = data.clone();
+            corrupted[at] ^= 0x01;
+            assert!(Container::from_bytes(&corrupted).is_err(), "byte {}", at);
+        }
+
+        let mut corrupted = data;
+        let config_at = corrupted.len() - 4 - 512;
+        corrupted[config_at] ^= 0x80;
+        assert_eq!(
+            Container::from_bytes(&corrupted).err().unwrap(),
+            "checksum mismatch (the container is corrupted)"
+        );
+    }
+
+    #[test]
+    fn bad_headers_are_refused() {
+        assert_eq!(
+            Container::from_bytes(b"PNG\x00").err().unwrap(),
+            "not a profile container (bad magic)"
+        );
+        assert_eq!(
+            Container::from_bytes(b"FKBP\x02\x00").err().unwrap(),
+            "truncated container header"
+        );
+
+        let mut data = container().to_bytes();
+        data[4] = 3;
+        assert_eq!(
+            Container::from_bytes(&data).err().unwrap(),
+            "unsupported container version 3"
+        );
+    }
+
+    #[test]
+    fn bad_metadata_lengths_are_refused() {
+        // version 1, so there's no CRC to catch it first
+        let meta = "model = \"aero-15x\"\nlayout = \"ansi\"\n";
+        let data = raw(1, meta);
+        let body_len = data.len() - 7;
+        for wrong in [meta.len() - 1, meta.len() + 1, 0xffff] {
+            let mut data = data.clone();
+            data[5..7].copy_from_slice(&(wrong as u16).to_le_bytes());
+            assert_eq!(
+                Container::from_bytes(&data).err().unwrap(),
+                format!(
+                    "bad metadata length {} ({} bytes after the header)",
+                    wrong, body_len
+                )
+            );
+        }
+        let mut data = data;
+        data[5..7].copy_from_slice(&0u16.to_le_bytes());
+        assert!(Container::from_bytes(&data).is_err());
+
+        // with one, the CRC no longer matches either
+        let data = container().to_bytes();
+        let mut wrong = data.clone();
+        wrong[5] ^= 0x01;
+        assert!(Container::from_bytes(&wrong).is_err());
+        assert!(Container::from_bytes(&data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn configs_are_sized_for_their_model() {
+        let meta = "model = \"aero-15x\"\nlayout = \"ansi\"\n";
+        assert_eq!(
+            Container::from_bytes(&raw_config(VERSION, meta, &[0; 516]))
+                .err()
+                .unwrap(),
+            "Custom configs for the aero-15x are 512 bytes (not 516)"
+        );
+
+        // models this version doesn't know are assumed to be like FALLBACK
+        let meta = "model = \"aero-99\"\nlayout = \"ansi\"\n";
+        let parsed = Container::from_bytes(&raw(VERSION, meta)).unwrap();
+        assert_eq!(parsed.model, "aero-99");
+        assert_eq!(parsed.config.as_bytes().len(), FALLBACK.config_len());
+        assert!(Container::from_bytes(&raw_config(VERSION, meta, &[0; 256])).is_err());
+    }
+
+    #[test]
+    fn metadata_must_have_a_model_and_layout() {
+        assert_eq!(
+            Container::from_bytes(&raw(VERSION, "layout = \"ansi\"\n"))
+                .err()
+                .unwrap(),
+            "missing `model`"
+        );
+        assert_eq!(
+            Container::from_bytes(&raw(VERSION, "model = \"aero-15x\"\n"))
+                .err()
+                .unwrap(),
+            "missing `layout`"
+        );
+        assert_eq!(
+            Container::from_bytes(&raw(VERSION, "model = 15\nlayout = \"ansi\"\n"))
+                .err()
+                .unwrap(),
+            "`model` must be a string"
+        );
+        assert_eq!(
+            Container::from_bytes(&raw(
+                VERSION,
+                "model = \"aero-15x\"\nlayout = \"ansi\"\nname = []\n"
+            ))
+            .err()
+            .unwrap(),
+            "`name` must be a string"
+        );
+        assert!(Container::from_bytes(&raw(VERSION, "model = ")).is_err());
+    }
+
+    #[test]
+    fn about_fields_override_in_merge() {
+        let base = container().about;
+        let merged = base.merge(&About {
+            name: Some("Dusk".to_string()),
+            author: Some("someone".to_string()),
+            description: None,
+        });
+        assert_eq!(merged.name.as_deref(), Some("Dusk"));
+        assert_eq!(merged.author.as_deref(), Some("someone"));
+        assert_eq!(merged.description, base.description);
+    }
+}
@@ -0,0 +1,168 @@
+use std::io::Read;
+use std::time::Duration;
+
+use super::{key_position, CustomConfig, Rgb, MATRIX_COLS, MATRIX_ROWS, NUM_KEYS};
+
+/// Samples an image against the lighting matrix. The image is split into a
+/// grid of `MATRIX_COLS` x `MATRIX_ROWS` cells, and each key takes the average
+/// color of the cell at its position.
+///
+/// `pixel(x, y)` returns the color of a single pixel.
+pub fn sample<F>(width: usize, height: usize, pixel: F) -> Result<CustomConfig, String>
+where
+    F: Fn(usize, usize) -> Rgb,
+{
+    if width < MATRIX_COLS || height < MATRIX_ROWS {
+        return Err(format!(
+            "image must be at least {}x{} pixels",
+            MATRIX_COLS, MATRIX_ROWS
+        ));
+    }
+
+    let mut cfg = CustomConfig::new();
+    for key in 0..NUM_KEYS {
+        let (row, col) = key_position(key);
+        let (x0, x1) = (col * width / MATRIX_COLS, (col + 1) * width / MATRIX_COLS);
+        let (y0, y1) = (row * height / MATRIX_ROWS, (row + 1) * height / MATRIX_ROWS);
+
+        let mut sum = [0usize; 3];
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let Rgb(r, g, b) = pixel(x, y);
+                sum[0] += r as usize;
+                sum[1] += g as usize;
+                sum[2] += b as usize;
+            }
+        }
+
+        let n = (x1 - x0) * (y1 - y0);
+        cfg.set_key(
+            key,
+            Rgb((sum[0] / n) as u8, (sum[1] / n) as u8, (sum[2] / n) as u8),
+        );
+    }
+
+    Ok(cfg)
+}
+
+/// Builds a custom config from a PNG (see `sample`)
+pub fn from_png<R: Read>(png: R) -> Result<CustomConfig, String> {
+    let mut decoder = png::Decoder::new(png);
+    // expand palettes / low bit depths, and strip 16 bit channels down to 8
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(|e| e.to_string())?;
+
+    let samples = info.color_type.samples();
+    sample(info.width as usize, info.height as usize, |x, y| {
+        let p = &buf[y * info.line_size + x * samples..];
+        match samples {
+            // grayscale (+ alpha)
+            1 | 2 => Rgb(p[0], p[0], p[0]),
+            // rgb (+ alpha)
+            _ => Rgb(p[0], p[1], p[2]),
+        }
+    })
+}
+
+/// size of each key's cell in `to_png` images, in pixels
+pub const PNG_KEY_SIZE: usize = 10;
+
+/// Draws a custom config as a PNG, with each key a `PNG_KEY_SIZE` square at
+/// its place in the lighting matrix (so `from_png` reads it back exactly).
+/// Places without a key are left black.
+pub fn to_png(cfg: &CustomConfig) -> Result<Vec<u8>, String> {
+    let (width, height) = (MATRIX_COLS * PNG_KEY_SIZE, MATRIX_ROWS * PNG_KEY_SIZE);
+    let mut pixels = vec![0; width * height * 3];
+    for key in 0..NUM_KEYS {
+        let (row, col) = key_position(key);
+        let Rgb(r, g, b) = cfg.get_key(key);
+        for y in row * PNG_KEY_SIZE..(row + 1) * PNG_KEY_SIZE {
+            for x in col * PNG_KEY_SIZE..(col + 1) * PNG_KEY_SIZE {
+                let i = (y * width + x) * 3;
+                pixels[i..i + 3].copy_from_slice(&[r, g, b]);
+            }
+        }
+    }
+
+    let mut png = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut png, width as u32, height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+        writer
+            .write_image_data(&pixels)
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(png)
+}
+
+/// GIFs without a frame delay are played at 10fps, like most browsers do
+const DEFAULT_GIF_DELAY: Duration = Duration::from_millis(100);
+
+/// Decodes an animated GIF into a list of custom configs, along with how long
+/// each frame should be shown for (see `sample`)
+pub fn from_gif<R: Read>(gif: R) -> Result<Vec<(CustomConfig, Duration)>, String> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::RGBA);
+    let mut decoder = options.read_info(gif).map_err(|e| e.to_string())?;
+
+    let (width, height) = (decoder.width() as usize, decoder.height() as usize);
+
+    // frames may only cover part of the image, so they're drawn onto a canvas
+    let mut canvas = vec![0u8; width * height * 4];
+    let mut frames = Vec::new();
+
+    while let Some(frame) = decoder.read_next_frame().map_err(|e| e.to_string())? {
+        let (left, top) = (frame.left as usize, frame.top as usize);
+        let (fw, fh) = (frame.width as usize, frame.height as usize);
+        let previous = canvas.clone();
+
+        for y in 0..fh {
+            for x in 0..fw {
+                let (cx, cy) = (left + x, top + y);
+                if cx >= width || cy >= height {
+                    continue;
+                }
+                let src = &frame.buffer[(y * fw + x) * 4..(y * fw + x) * 4 + 4];
+                // fully transparent pixels leave the canvas as-is
+                if src[3] != 0 {
+                    let dst = (cy * width + cx) * 4;
+                    canvas[dst..dst + 4].copy_from_slice(src);
+                }
+            }
+        }
+
+        let cfg = sample(width, height, |x, y| {
+            let p = &canvas[(y * width + x) * 4..];
+            Rgb(p[0], p[1], p[2])
+        })?;
+        let delay = match frame.delay {
+            0 => DEFAULT_GIF_DELAY,
+            cs => Duration::from_millis(cs as u64 * 10),
+        };
+        frames.push((cfg, delay));
+
+        match frame.dispose {
+            gif::DisposalMethod::Background => {
+                for y in top..(top + fh).min(height) {
+                    for x in left..(left + fw).min(width) {
+                        let dst = (y * width + x) * 4;
+                        canvas[dst..dst + 4].copy_from_slice(&[0; 4]);
+                    }
+                }
+            }
+            gif::DisposalMethod::Previous => canvas = previous,
+            _ => {}
+        }
+    }
+
+    if frames.is_empty() {
+        return Err("GIF doesn't contain any frames".to_string());
+    }
+
+    Ok(frames)
+}
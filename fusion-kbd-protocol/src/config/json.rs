@@ -0,0 +1,49 @@
+use std::str::FromStr;
+
+use super::{CustomConfig, Rgb, NUM_KEYS};
+use crate::keymap::Keymap;
+
+/// Compiles a JSON profile to a custom config. Profiles are a single object
+/// mapping key names to `#rrggbb` colors, e.g: `{ "esc": "#ff0000" }`.
+/// Keys that aren't mentioned are left off.
+pub fn from_json(json: &str, keymap: &Keymap) -> Result<CustomConfig, String> {
+    let value: serde_json::Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+
+    let keys = match value.as_object() {
+        Some(keys) => keys,
+        None => return Err("profile must be a JSON object".to_string()),
+    };
+
+    let mut cfg = CustomConfig::new();
+    for (name, color) in keys.iter() {
+        let key = match keymap.index(name) {
+            Some(key) => key,
+            None => return Err(format!("unknown key '{}'", name)),
+        };
+        let color = match color.as_str() {
+            Some(color) => Rgb::from_str(color)?,
+            None => return Err(format!("color for '{}' must be a string", name)),
+        };
+        cfg.set_key(key, color);
+    }
+
+    Ok(cfg)
+}
+
+/// Emits a JSON profile, in config order. Keys that are off are skipped, and
+/// keys without a name in `keymap` are written as raw offsets.
+pub fn to_json(cfg: &CustomConfig, keymap: &Keymap) -> String {
+    let mut keys = serde_json::Map::new();
+    for key in 0..NUM_KEYS {
+        let color = cfg.get_key(key);
+        if color != Rgb(0, 0, 0) {
+            let name = match keymap.name(key) {
+                Some(name) => name.to_string(),
+                None => key.to_string(),
+            };
+            keys.insert(name, color.to_string().into());
+        }
+    }
+
+    serde_json::to_string_pretty(&serde_json::Value::Object(keys)).unwrap() + "\n"
+}
@@ -0,0 +1,286 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::colors;
+use crate::keymap::Keymap;
+use crate::models::{self, Model};
+use crate::protocol::{key_offset, Color, BYTES_PER_KEY};
+
+pub mod animation;
+pub mod backup;
+pub mod container;
+pub mod image;
+pub mod json;
+pub mod openrgb;
+pub mod text;
+pub mod toml;
+pub mod validate;
+
+/// number of per-key entries in a custom config
+pub const NUM_KEYS: usize = 128;
+/// keys are laid out column-major, 6 rows per column (see example-configs/keys.txt)
+pub const MATRIX_ROWS: usize = 6;
+pub const MATRIX_COLS: usize = NUM_KEYS.div_ceil(MATRIX_ROWS);
+
+/// (row, col) of a key in the lighting matrix. Row 0 is the function row.
+pub fn key_position(key: usize) -> (usize, usize) {
+    (MATRIX_ROWS - 1 - key % MATRIX_ROWS, key / MATRIX_ROWS)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl FromStr for Rgb {
+    type Err = String;
+
+    /// parses `#rrggbb` (the leading `#` is optional), the name of one of the
+    /// preset colors (`red`, `white`, ...), or a CSS color name (`teal`,
+    /// `hotpink`, ...). Preset names win over CSS names, since they describe
+    /// what the LEDs actually show.
+    fn from_str(s: &str) -> Result<Rgb, String> {
+        if let Some(rgb) = Color::from_str(s).ok().and_then(|c| c.to_rgb()) {
+            return Ok(rgb);
+        }
+        if let Some(rgb) = colors::lookup(s) {
+            return Ok(rgb);
+        }
+
+        let hex = s.trim_start_matches('#');
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!(
+                "'{}' is not a valid color (expected #rrggbb, or a color name)",
+                s
+            ));
+        }
+
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+        Ok(Rgb(channel(0), channel(2), channel(4)))
+    }
+}
+
+impl fmt::Display for Rgb {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.0, self.1, self.2)
+    }
+}
+
+/// Custom lighting config, as many bytes as its model's `config_len` (512
+/// for the AERO 15X). Each key takes 4 bytes: [?, R, G, B]
+#[derive(Clone)]
+pub struct CustomConfig {
+    data: Vec<u8>,
+}
+
+impl CustomConfig {
+    /// all keys off, sized for `models::FALLBACK` (which is what configs
+    /// that don't say which model they're for are assumed to be for)
+    pub fn new() -> CustomConfig {
+        CustomConfig::for_model(&models::FALLBACK)
+    }
+
+    /// all keys off, sized for `model`
+    pub fn for_model(model: &Model) -> CustomConfig {
+        CustomConfig {
+            data: vec![0; model.config_len()],
+        }
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> CustomConfig {
+        CustomConfig { data }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// entries in the config (some of which may not be wired to a key)
+    pub fn num_keys(&self) -> usize {
+        self.data.len() / BYTES_PER_KEY
+    }
+
+    /// Keys past the end of the config (that its model doesn't have) are
+    /// always off.
+    pub fn get_key(&self, key: usize) -> Rgb {
+        match self
+            .data
+            .get(key_offset(key)..key_offset(key) + BYTES_PER_KEY)
+        {
+            Some(k) => Rgb(k[1], k[2], k[3]),
+            None => Rgb(0, 0, 0),
+        }
+    }
+
+    /// Keys past the end of the config (that its model doesn't have) are
+    /// left alone.
+    pub fn set_key(&mut self, key: usize, color: Rgb) {
+        if let Some(k) = self
+            .data
+            .get_mut(key_offset(key)..key_offset(key) + BYTES_PER_KEY)
+        {
+            k[1] = color.0;
+            k[2] = color.1;
+            k[3] = color.2;
+        }
+    }
+
+    pub fn fill(&mut self, color: Rgb) {
+        for key in 0..self.num_keys() {
+            self.set_key(key, color);
+        }
+    }
+
+    /// keys whose color differs between `self` and `other`
+    pub fn diff(&self, other: &CustomConfig) -> Vec<usize> {
+        (0..self.num_keys().max(other.num_keys()))
+            .filter(|&key| self.get_key(key) != other.get_key(key))
+            .collect()
+    }
+}
+
+impl Default for CustomConfig {
+    fn default() -> CustomConfig {
+        CustomConfig::new()
+    }
+}
+
+/// On-disk formats for custom configs. Only `Binary` and `Container` keep the
+/// first (unknown) byte of each key, the rest just keep its color.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// raw blob, as sent over the wire
+    Binary,
+    /// raw config wrapped with model / layout metadata (see `container`)
+    Container,
+    /// named-key JSON profile (see `json`)
+    Json,
+    /// named-key TOML profile (see `toml`)
+    Toml,
+    /// image sampled against the lighting matrix (see `image`)
+    Png,
+    /// OpenRGB profile (see `openrgb`)
+    OpenRgb,
+    /// `key r g b` lines (see `text`)
+    Text,
+}
+
+impl Format {
+    /// guesses the format from a file's extension, defaulting to `Binary`
+    pub fn from_path(path: &Path) -> Format {
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+        match ext.as_deref() {
+            Some("fkp") => Format::Container,
+            Some("json") => Format::Json,
+            Some("toml") => Format::Toml,
+            Some("png") => Format::Png,
+            Some("orp") => Format::OpenRgb,
+            Some("txt") => Format::Text,
+            _ => Format::Binary,
+        }
+    }
+}
+
+/// A raw config. Raw files don't say which model they're for, so they're
+/// taken to be for whichever has a config that size, or zero-padded (so the
+/// rest of the keys are off) if they're short of `models::FALLBACK`'s. Any
+/// other size is an error, rather than dropping whatever doesn't fit.
+pub(crate) fn from_raw(data: &[u8]) -> Result<CustomConfig, String> {
+    if models::MODELS.iter().any(|m| m.config_len() == data.len()) {
+        return Ok(CustomConfig::from_bytes(data.to_vec()));
+    }
+    let len = models::FALLBACK.config_len();
+    if data.len() > len {
+        return Err(format!(
+            "too big for a custom config: expected at most {} bytes, got {}",
+            len,
+            data.len()
+        ));
+    }
+    let mut bytes = vec![0; len];
+    bytes[..data.len()].copy_from_slice(data);
+    Ok(CustomConfig::from_bytes(bytes))
+}
+
+/// parses a custom config from an in-memory file
+pub fn decode(data: &[u8], format: Format, keymap: &Keymap) -> Result<CustomConfig, String> {
+    match format {
+        Format::Binary => from_raw(data),
+        Format::Container => Ok(container::Container::from_bytes(data)?.config),
+        Format::Json => {
+            let text = std::str::from_utf8(data).map_err(|e| e.to_string())?;
+            json::from_json(text, keymap)
+        }
+        Format::Toml => {
+            let text = std::str::from_utf8(data).map_err(|e| e.to_string())?;
+            toml::from_toml(text, keymap)
+        }
+        Format::Png => image::from_png(data),
+        Format::OpenRgb => openrgb::from_orp(data, keymap),
+        Format::Text => {
+            let text = std::str::from_utf8(data).map_err(|e| e.to_string())?;
+            text::from_text(text, keymap)
+        }
+    }
+}
+
+/// serializes a custom config to an in-memory file. Containers say it was made
+/// for `model` (see `Model::name`).
+pub fn encode(
+    cfg: &CustomConfig,
+    format: Format,
+    model: &str,
+    keymap: &Keymap,
+) -> Result<Vec<u8>, String> {
+    match format {
+        Format::Binary => Ok(cfg.as_bytes().to_vec()),
+        Format::Container => Ok(container::Container {
+            model: model.to_string(),
+            layout: keymap.layout().to_string(),
+            about: container::About::default(),
+            config: cfg.clone(),
+        }
+        .to_bytes()),
+        Format::Json => Ok(json::to_json(cfg, keymap).into_bytes()),
+        Format::Toml => Ok(toml::to_toml(cfg, keymap).into_bytes()),
+        Format::Png => image::to_png(cfg),
+        Format::OpenRgb => Ok(openrgb::to_orp(cfg, keymap)),
+        Format::Text => Ok(text::to_text(cfg, keymap).into_bytes()),
+    }
+}
+
+/// loads a custom config from disk, in whatever format its extension implies
+pub fn load(path: &Path, keymap: &Keymap) -> Result<CustomConfig, String> {
+    let mut f =
+        File::open(path).map_err(|e| format!("couldn't open '{}': {}", path.display(), e))?;
+
+    let mut data = Vec::new();
+    f.read_to_end(&mut data).map_err(|e| e.to_string())?;
+    decode(&data, Format::from_path(path), keymap)
+}
+
+/// The model the config at `path` says it was made for, if it says (only
+/// containers do)
+pub fn made_for(path: &Path) -> Option<String> {
+    let data = std::fs::read(path).ok()?;
+    if !container::Container::detect(&data) {
+        return None;
+    }
+    container::Container::from_bytes(&data)
+        .ok()
+        .map(|c| c.model)
+}
+
+/// saves a custom config to disk, in whatever format its extension implies
+/// (made for `model`, see `encode`)
+pub fn save(path: &Path, cfg: &CustomConfig, model: &str, keymap: &Keymap) -> Result<(), String> {
+    let data = encode(cfg, Format::from_path(path), model, keymap)?;
+
+    let mut f =
+        File::create(path).map_err(|e| format!("couldn't open '{}': {}", path.display(), e))?;
+    f.write_all(&data).map_err(|e| e.to_string())
+}
@@ -0,0 +1,493 @@
+//! OpenRGB profiles (`.orp`), so per-key colors can be shared with OpenRGB.
+//!
+//! A profile is a header, and then each device's description, the way the
+//! OpenRGB SDK sends them (little endian, strings with a u16 length that
+//! counts their trailing NUL):
+//!
+//! ```text
+//! "OPENRGB_PROFILE\0" | version: u32 | device descriptions...
+//! ```
+//!
+//! Every description ends with its LEDs' names and colors. Reading one takes
+//! the keyboard (the device with the most LEDs named after keys in the
+//! keymap), and colors keys by name. Writing makes a single keyboard device,
+//! with one LED per named key, laid out like the lighting matrix.
+//!
+//! Profile versions up to 4 (OpenRGB 0.9's) are understood.
+
+use super::{key_position, CustomConfig, Rgb, MATRIX_COLS, MATRIX_ROWS};
+use crate::keymap::Keymap;
+
+pub const MAGIC: &[u8; 16] = b"OPENRGB_PROFILE\0";
+/// what's written (the last version without zone segments)
+pub const VERSION: u32 = 3;
+const MAX_VERSION: u32 = 4;
+
+/// OpenRGB's `DEVICE_TYPE_KEYBOARD`
+const DEVICE_TYPE_KEYBOARD: i32 = 5;
+/// `ZONE_TYPE_MATRIX`
+const ZONE_TYPE_MATRIX: i32 = 2;
+/// `MODE_FLAG_HAS_PER_LED_COLOR`, `MODE_COLORS_PER_LED`
+const MODE_FLAG_HAS_PER_LED_COLOR: u32 = 1 << 5;
+const MODE_COLORS_PER_LED: u32 = 1;
+/// empty spots in a zone's matrix map
+const NO_LED: u32 = 0xffff_ffff;
+
+/// key names -> OpenRGB's names for them (without the "Key: " prefix)
+static NAMES: &[(&str, &str)] = &[
+    ("esc", "Escape"),
+    ("`", "`"),
+    ("-", "-"),
+    ("=", "="),
+    ("backspace", "Backspace"),
+    ("tab", "Tab"),
+    ("[", "["),
+    ("]", "]"),
+    ("\\", "\\ (ANSI)"),
+    ("caps", "Caps Lock"),
+    (";", ";"),
+    ("'", "'"),
+    ("#", "#"),
+    ("enter", "Enter"),
+    ("shift", "Left Shift"),
+    ("iso\\", "\\ (ISO)"),
+    (",", ","),
+    (".", "."),
+    ("/", "/"),
+    ("rshift", "Right Shift"),
+    ("lctrl", "Left Control"),
+    ("fn", "Left Fn"),
+    ("win", "Left Windows"),
+    ("lalt", "Left Alt"),
+    ("space", "Space"),
+    ("ralt", "Right Alt"),
+    ("menu", "Menu"),
+    ("rctrl", "Right Control"),
+    ("pause", "Pause/Break"),
+    ("del", "Delete"),
+    ("home", "Home"),
+    ("end", "End"),
+    ("pgup", "Page Up"),
+    ("pgdn", "Page Down"),
+    ("up", "Up Arrow"),
+    ("down", "Down Arrow"),
+    ("left", "Left Arrow"),
+    ("right", "Right Arrow"),
+    ("numlk", "Num Lock"),
+    ("num/", "Number Pad /"),
+    ("num*", "Number Pad *"),
+    ("num-", "Number Pad -"),
+    ("num+", "Number Pad +"),
+    ("numenter", "Number Pad Enter"),
+    ("num.", "Number Pad ."),
+];
+
+/// OpenRGB's name for a key, e.g: "Key: Escape", "Key: F1", "Key: A"
+fn openrgb_name(name: &str) -> String {
+    if let Some(&(_, openrgb)) = NAMES.iter().find(|&&(n, _)| n == name) {
+        return format!("Key: {}", openrgb);
+    }
+    if let Some(n) = name.strip_prefix("num") {
+        if n.len() == 1 && n.chars().all(|c| c.is_ascii_digit()) {
+            return format!("Key: Number Pad {}", n);
+        }
+    }
+    format!("Key: {}", name.to_uppercase())
+}
+
+/// the keymap's name for an OpenRGB LED, if it's a key
+fn key_name<'a>(keymap: &'a Keymap, led: &str) -> Option<&'a str> {
+    keymap
+        .keys()
+        .map(|(name, _)| name)
+        .find(|name| openrgb_name(name) == led)
+}
+
+/// bounds-checked reads
+struct Reader<'a> {
+    data: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .data
+            .get(self.at..self.at.saturating_add(len))
+            .ok_or_else(|| "truncated OpenRGB profile".to_string())?;
+        self.at += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u16()? as usize;
+        let bytes = self.bytes(len)?;
+        let bytes = bytes.strip_suffix(&[0]).unwrap_or(bytes);
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    /// skips `n` u32s
+    fn skip(&mut self, n: usize) -> Result<(), String> {
+        self.bytes(n * 4).map(|_| ())
+    }
+}
+
+/// A device's name, and its LEDs' names and colors (everything else in the
+/// description is skipped)
+struct Device {
+    name: String,
+    leds: Vec<(String, Rgb)>,
+}
+
+fn read_device(r: &mut Reader, version: u32) -> Result<Device, String> {
+    let _device_type = r.u32()?;
+    let name = r.string()?;
+    // vendor (from version 1), description, version, serial, location
+    for _ in 0..if version >= 1 { 5 } else { 4 } {
+        r.string()?;
+    }
+
+    let num_modes = r.u16()?;
+    let _active_mode = r.u32()?;
+    for _ in 0..num_modes {
+        r.string()?;
+        // value, flags, speed min / max, (brightness min / max), colors min /
+        // max, speed, (brightness), direction, color mode
+        r.skip(if version >= 3 { 12 } else { 9 })?;
+        let num_colors = r.u16()? as usize;
+        r.skip(num_colors)?;
+    }
+
+    let num_zones = r.u16()?;
+    for _ in 0..num_zones {
+        r.string()?;
+        // type, leds min / max / count
+        r.skip(4)?;
+        let matrix_len = r.u16()? as usize;
+        r.bytes(matrix_len)?;
+        if version >= 4 {
+            let num_segments = r.u16()?;
+            for _ in 0..num_segments {
+                r.string()?;
+                // type, start index, leds count
+                r.skip(3)?;
+            }
+        }
+    }
+
+    let num_leds = r.u16()? as usize;
+    let mut names = Vec::with_capacity(num_leds);
+    for _ in 0..num_leds {
+        names.push(r.string()?);
+        let _value = r.u32()?;
+    }
+    let num_colors = r.u16()? as usize;
+    let mut leds = Vec::with_capacity(num_leds);
+    for name in names.into_iter().take(num_colors) {
+        // 0x00BBGGRR
+        let c = r.u32()?.to_le_bytes();
+        leds.push((name, Rgb(c[0], c[1], c[2])));
+    }
+    // colors without a name
+    r.skip(num_colors.saturating_sub(leds.len()))?;
+
+    Ok(Device { name, leds })
+}
+
+/// Reads the keyboard's colors out of a profile. Also returns the names of its
+/// LEDs that aren't keys in `keymap` (e.g: logos, or keys this keyboard
+/// doesn't have), which are skipped.
+pub fn from_orp_checked(
+    data: &[u8],
+    keymap: &Keymap,
+) -> Result<(CustomConfig, Vec<String>), String> {
+    if !data.starts_with(MAGIC) {
+        return Err("not an OpenRGB profile (bad magic)".to_string());
+    }
+    let mut r = Reader { data, at: 16 };
+    let version = r.u32()?;
+    if version > MAX_VERSION {
+        return Err(format!("unsupported OpenRGB profile version {}", version));
+    }
+
+    let mut devices = Vec::new();
+    while r.at < data.len() {
+        let size = r.u32()? as usize;
+        if size < 4 {
+            return Err(format!("bad device description size {}", size));
+        }
+        let mut device = Reader {
+            data: r.bytes(size - 4)?,
+            at: 0,
+        };
+        devices.push(read_device(&mut device, version)?);
+    }
+
+    let matched = |d: &Device| {
+        d.leds
+            .iter()
+            .filter(|(led, _)| key_name(keymap, led).is_some())
+            .count()
+    };
+    let device = devices
+        .iter()
+        .filter(|d| matched(d) > 0)
+        .max_by_key(|d| matched(d))
+        .ok_or_else(|| "no keyboard in the OpenRGB profile".to_string())?;
+
+    let mut cfg = CustomConfig::new();
+    let mut skipped = Vec::new();
+    for (led, color) in &device.leds {
+        match key_name(keymap, led).and_then(|name| keymap.index(name)) {
+            Some(key) => cfg.set_key(key, *color),
+            None => skipped.push(format!("{} (on '{}')", led, device.name)),
+        }
+    }
+    Ok((cfg, skipped))
+}
+
+/// Reads the keyboard's colors out of a profile (see `from_orp_checked`)
+pub fn from_orp(data: &[u8], keymap: &Keymap) -> Result<CustomConfig, String> {
+    from_orp_checked(data, keymap).map(|(cfg, _)| cfg)
+}
+
+fn put_u16(out: &mut Vec<u8>, n: u16) {
+    out.extend_from_slice(&n.to_le_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, n: u32) {
+    out.extend_from_slice(&n.to_le_bytes());
+}
+
+fn put_string(out: &mut Vec<u8>, s: &str) {
+    put_u16(out, s.len() as u16 + 1);
+    out.extend_from_slice(s.as_bytes());
+    out.push(0);
+}
+
+/// Writes a profile with one keyboard in it, one LED per key in `keymap`
+pub fn to_orp(cfg: &CustomConfig, keymap: &Keymap) -> Vec<u8> {
+    let keys: Vec<(&str, usize)> = keymap.keys().collect();
+
+    let mut d = Vec::new();
+    put_u32(&mut d, DEVICE_TYPE_KEYBOARD as u32);
+    put_string(&mut d, "Gigabyte Fusion Keyboard");
+    put_string(&mut d, "Gigabyte"); // vendor
+    put_string(&mut d, "Per-key RGB keyboard (fusion-kbd-controller)");
+    put_string(&mut d, ""); // version
+    put_string(&mut d, ""); // serial
+    put_string(&mut d, ""); // location
+
+    // a single "Direct" mode, with per-LED colors
+    put_u16(&mut d, 1);
+    put_u32(&mut d, 0); // active mode
+    put_string(&mut d, "Direct");
+    put_u32(&mut d, 0); // value
+    put_u32(&mut d, MODE_FLAG_HAS_PER_LED_COLOR);
+    // speed min / max, brightness min / max, colors min / max, speed,
+    // brightness, direction
+    for _ in 0..9 {
+        put_u32(&mut d, 0);
+    }
+    put_u32(&mut d, MODE_COLORS_PER_LED);
+    put_u16(&mut d, 0); // mode colors
+
+    // a single matrix zone, mapping positions to LED indices
+    put_u16(&mut d, 1);
+    put_string(&mut d, "Keyboard");
+    put_u32(&mut d, ZONE_TYPE_MATRIX as u32);
+    for _ in 0..3 {
+        put_u32(&mut d, keys.len() as u32); // leds min / max / count
+    }
+    let mut map = vec![NO_LED; MATRIX_ROWS * MATRIX_COLS];
+    for (i, &(_, key)) in keys.iter().enumerate() {
+        let (row, col) = key_position(key);
+        map[row * MATRIX_COLS + col] = i as u32;
+    }
+    put_u16(&mut d, ((map.len() + 2) * 4) as u16);
+    put_u32(&mut d, MATRIX_ROWS as u32);
+    put_u32(&mut d, MATRIX_COLS as u32);
+    for i in map {
+        put_u32(&mut d, i);
+    }
+
+    put_u16(&mut d, keys.len() as u16);
+    for &(name, key) in &keys {
+        put_string(&mut d, &openrgb_name(name));
+        put_u32(&mut d, key as u32);
+    }
+    put_u16(&mut d, keys.len() as u16);
+    for &(_, key) in &keys {
+        let Rgb(r, g, b) = cfg.get_key(key);
+        put_u32(&mut d, u32::from_le_bytes([r, g, b, 0]));
+    }
+
+    let mut out = MAGIC.to_vec();
+    put_u32(&mut out, VERSION);
+    // the description's size counts itself
+    put_u32(&mut out, d.len() as u32 + 4);
+    out.extend_from_slice(&d);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a device description the way OpenRGB `version` writes one, with no
+    /// modes, an empty zone, and `leds`
+    fn device(version: u32, name: &str, leds: &[(&str, Rgb)]) -> Vec<u8> {
+        let mut d = Vec::new();
+        put_u32(&mut d, DEVICE_TYPE_KEYBOARD as u32);
+        put_string(&mut d, name);
+        let strings = if version >= 1 { 5 } else { 4 };
+        for _ in 0..strings {
+            put_string(&mut d, "");
+        }
+        put_u16(&mut d, 0); // modes
+        put_u32(&mut d, 0); // active mode
+        put_u16(&mut d, 1); // zones
+        put_string(&mut d, "Keyboard");
+        for _ in 0..4 {
+            put_u32(&mut d, 0);
+        }
+        put_u16(&mut d, 0); // matrix map
+        if version >= 4 {
+            put_u16(&mut d, 1);
+            put_string(&mut d, "segment");
+            for _ in 0..3 {
+                put_u32(&mut d, 0);
+            }
+        }
+        put_u16(&mut d, leds.len() as u16);
+        for (led, _) in leds {
+            put_string(&mut d, led);
+            put_u32(&mut d, 0);
+        }
+        put_u16(&mut d, leds.len() as u16);
+        for &(_, Rgb(r, g, b)) in leds {
+            put_u32(&mut d, u32::from_le_bytes([r, g, b, 0]));
+        }
+
+        let mut out = Vec::new();
+        put_u32(&mut out, d.len() as u32 + 4);
+        out.extend_from_slice(&d);
+        out
+    }
+
+    fn profile(version: u32, devices: &[Vec<u8>]) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        put_u32(&mut out, version);
+        for d in devices {
+            out.extend_from_slice(d);
+        }
+        out
+    }
+
+    #[test]
+    fn names_keys_the_way_openrgb_does() {
+        assert_eq!(openrgb_name("esc"), "Key: Escape");
+        assert_eq!(openrgb_name("f1"), "Key: F1");
+        assert_eq!(openrgb_name("a"), "Key: A");
+        assert_eq!(openrgb_name("num5"), "Key: Number Pad 5");
+        assert_eq!(openrgb_name("num/"), "Key: Number Pad /");
+        assert_eq!(key_name(&Keymap::ansi(), "Key: Escape"), Some("esc"));
+        assert_eq!(key_name(&Keymap::ansi(), "Logo"), None);
+    }
+
+    #[test]
+    fn round_trips() {
+        let keymap = Keymap::ansi();
+        let mut cfg = CustomConfig::new();
+        for (i, (_, key)) in keymap.keys().enumerate() {
+            cfg.set_key(key, Rgb(i as u8, 0x80, 255 - i as u8));
+        }
+
+        let data = to_orp(&cfg, &keymap);
+        let (parsed, skipped) = from_orp_checked(&data, &keymap).unwrap();
+        assert!(skipped.is_empty(), "{:?}", skipped);
+        for (name, key) in keymap.keys() {
+            assert_eq!(parsed.get_key(key), cfg.get_key(key), "{}", name);
+        }
+    }
+
+    #[test]
+    fn reads_every_known_version() {
+        let keymap = Keymap::ansi();
+        let leds = [
+            ("Key: Escape", Rgb(0xff, 0, 0)),
+            ("Key: A", Rgb(0, 0xff, 0)),
+            ("Logo", Rgb(0, 0, 0xff)),
+        ];
+        for version in 0..=MAX_VERSION {
+            let data = profile(version, &[device(version, "Board", &leds)]);
+            let (cfg, skipped) = from_orp_checked(&data, &keymap).unwrap();
+            assert_eq!(cfg.get_key(keymap.index("esc").unwrap()), leds[0].1);
+            assert_eq!(cfg.get_key(keymap.index("a").unwrap()), leds[1].1);
+            assert_eq!(skipped, vec!["Logo (on 'Board')".to_string()]);
+        }
+    }
+
+    #[test]
+    fn picks_the_device_with_the_most_keys() {
+        let keymap = Keymap::ansi();
+        let mouse = device(VERSION, "Mouse", &[("Key: A", Rgb(1, 1, 1))]);
+        let keyboard = device(
+            VERSION,
+            "Board",
+            &[("Key: A", Rgb(2, 2, 2)), ("Key: B", Rgb(3, 3, 3))],
+        );
+        let data = profile(VERSION, &[mouse, keyboard]);
+        let cfg = from_orp(&data, &keymap).unwrap();
+        assert_eq!(cfg.get_key(keymap.index("a").unwrap()), Rgb(2, 2, 2));
+    }
+
+    #[test]
+    fn refuses_what_it_cant_read() {
+        let keymap = Keymap::ansi();
+        assert_eq!(
+            from_orp(b"OPENRGB_PROFILE", &keymap).err().unwrap(),
+            "not an OpenRGB profile (bad magic)"
+        );
+        assert_eq!(
+            from_orp(&profile(MAX_VERSION + 1, &[]), &keymap)
+                .err()
+                .unwrap(),
+            format!("unsupported OpenRGB profile version {}", MAX_VERSION + 1)
+        );
+        assert_eq!(
+            from_orp(&profile(VERSION, &[]), &keymap).err().unwrap(),
+            "no keyboard in the OpenRGB profile"
+        );
+        let logo = device(VERSION, "Logo", &[("Logo", Rgb(0, 0, 0))]);
+        assert_eq!(
+            from_orp(&profile(VERSION, &[logo]), &keymap).err().unwrap(),
+            "no keyboard in the OpenRGB profile"
+        );
+        let mut tiny = profile(VERSION, &[]);
+        put_u32(&mut tiny, 3);
+        assert_eq!(
+            from_orp(&tiny, &keymap).err().unwrap(),
+            "bad device description size 3"
+        );
+    }
+
+    #[test]
+    fn truncated_profiles_are_errors() {
+        let keymap = Keymap::ansi();
+        let data = to_orp(&CustomConfig::new(), &keymap);
+        for len in (MAGIC.len()..data.len()).step_by(7) {
+            assert!(from_orp(&data[..len], &keymap).is_err(), "{} bytes", len);
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use super::{CustomConfig, Rgb, NUM_KEYS};
+use crate::keymap::Keymap;
+
+/// `key r g b`, or `None` for a blank line / comment
+fn parse_line(line: &str, keymap: &Keymap) -> Result<Option<(usize, Rgb)>, String> {
+    let line = match line.find('#') {
+        Some(i) => &line[..i],
+        None => line,
+    };
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let (name, channels) = match fields.split_first() {
+        None => return Ok(None),
+        Some((name, channels)) => (*name, channels),
+    };
+
+    let key = keymap
+        .index(name)
+        .ok_or_else(|| format!("unknown key '{}'", name))?;
+    if channels.len() != 3 {
+        return Err(format!("expected `{} R G B`, got '{}'", name, line.trim()));
+    }
+    let channel = |s: &str| {
+        s.parse::<u8>()
+            .map_err(|_| format!("'{}' isn't a color channel (0 - 255)", s))
+    };
+    let rgb = Rgb(
+        channel(channels[0])?,
+        channel(channels[1])?,
+        channel(channels[2])?,
+    );
+    Ok(Some((key, rgb)))
+}
+
+/// Like `from_text`, but carries on past bad lines, returning what it could
+/// make of the rest and a message for each bad line (for `validate`).
+pub fn from_text_checked(text: &str, keymap: &Keymap) -> (CustomConfig, Vec<String>) {
+    let mut cfg = CustomConfig::new();
+    let mut problems = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        match parse_line(line, keymap) {
+            Ok(Some((key, rgb))) => cfg.set_key(key, rgb),
+            Ok(None) => {}
+            Err(e) => problems.push(format!("line {}: {}", i + 1, e)),
+        }
+    }
+    (cfg, problems)
+}
+
+/// Compiles a plain text profile to a custom config: one key per line, as its
+/// name (or raw offset) followed by its red, green and blue (0 - 255), for
+/// generating from shell scripts and awk. `#` starts a comment.
+///
+/// ```text
+/// # a red escape key
+/// esc 255 0 0
+/// w   0 255 136
+/// ```
+///
+/// Keys that aren't mentioned are left off.
+pub fn from_text(text: &str, keymap: &Keymap) -> Result<CustomConfig, String> {
+    let (cfg, problems) = from_text_checked(text, keymap);
+    match problems.into_iter().next() {
+        Some(problem) => Err(problem),
+        None => Ok(cfg),
+    }
+}
+
+/// Emits a plain text profile, in config order. Keys that are off are skipped,
+/// and keys without a name in `keymap` are written as raw offsets.
+pub fn to_text(cfg: &CustomConfig, keymap: &Keymap) -> String {
+    let mut text = String::from("# key r g b\n");
+    for key in 0..NUM_KEYS {
+        let Rgb(r, g, b) = cfg.get_key(key);
+        if (r, g, b) != (0, 0, 0) {
+            let name = match keymap.name(key) {
+                Some(name) => name.to_string(),
+                None => key.to_string(),
+            };
+            text.push_str(&format!("{:<12} {:>3} {:>3} {:>3}\n", name, r, g, b));
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_keys_comments_and_raw_offsets() {
+        let keymap = Keymap::ansi();
+        let text = "# a red escape key\n\nesc 255 0 0\n   w   0 255 136  # trailing\n13 1 2 3\n";
+        let cfg = from_text(text, &keymap).unwrap();
+        assert_eq!(cfg.get_key(keymap.index("esc").unwrap()), Rgb(255, 0, 0));
+        assert_eq!(cfg.get_key(keymap.index("w").unwrap()), Rgb(0, 255, 136));
+        assert_eq!(cfg.get_key(13), Rgb(1, 2, 3));
+        assert_eq!(cfg.get_key(keymap.index("a").unwrap()), Rgb(0, 0, 0));
+    }
+
+    #[test]
+    fn round_trips() {
+        let keymap = Keymap::ansi();
+        let mut cfg = CustomConfig::new();
+        cfg.set_key(keymap.index("esc").unwrap(), Rgb(255, 0, 0));
+        cfg.set_key(keymap.index("space").unwrap(), Rgb(9, 99, 199));
+        // unnamed, so written as an offset
+        cfg.set_key(13, Rgb(1, 2, 3));
+
+        let text = to_text(&cfg, &keymap);
+        assert!(
+            text.lines().any(|l| l == "13             1   2   3"),
+            "{}",
+            text
+        );
+        let parsed = from_text(&text, &keymap).unwrap();
+        assert!(parsed.diff(&cfg).is_empty());
+        // keys that are off aren't written
+        assert_eq!(text.lines().count(), 4);
+    }
+
+    #[test]
+    fn bad_lines_are_reported_by_number() {
+        let keymap = Keymap::ansi();
+        let text = "esc 255 0 0\nnope 1 2 3\nw 1 2\na 1 2 256\ns 0 0 0 0\n";
+        let (cfg, problems) = from_text_checked(text, &keymap);
+        assert_eq!(
+            problems,
+            vec![
+                "line 2: unknown key 'nope'",
+                "line 3: expected `w R G B`, got 'w 1 2'",
+                "line 4: '256' isn't a color channel (0 - 255)",
+                "line 5: expected `s R G B`, got 's 0 0 0 0'",
+            ]
+        );
+        // good lines still count
+        assert_eq!(cfg.get_key(keymap.index("esc").unwrap()), Rgb(255, 0, 0));
+
+        assert_eq!(
+            from_text(text, &keymap).err().unwrap(),
+            "line 2: unknown key 'nope'"
+        );
+        assert_eq!(
+            from_text("128 1 2 3", &keymap).err().unwrap(),
+            "line 1: unknown key '128'"
+        );
+    }
+}
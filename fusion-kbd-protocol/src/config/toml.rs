@@ -0,0 +1,50 @@
+use std::str::FromStr;
+
+use super::{CustomConfig, Rgb, NUM_KEYS};
+use crate::keymap::Keymap;
+
+/// Compiles a TOML profile to a custom config. Like JSON profiles (see
+/// `json`), it's a single table of key names to colors:
+///
+/// ```toml
+/// esc = "#ff0000"
+/// w = "white"
+/// ```
+///
+/// Keys that aren't mentioned are left off.
+pub fn from_toml(text: &str, keymap: &Keymap) -> Result<CustomConfig, String> {
+    let keys: toml::Table = text.parse().map_err(|e: toml::de::Error| e.to_string())?;
+
+    let mut cfg = CustomConfig::new();
+    for (name, color) in keys.iter() {
+        let key = match keymap.index(name) {
+            Some(key) => key,
+            None => return Err(format!("unknown key '{}'", name)),
+        };
+        let color = match color.as_str() {
+            Some(color) => Rgb::from_str(color)?,
+            None => return Err(format!("color for '{}' must be a string", name)),
+        };
+        cfg.set_key(key, color);
+    }
+
+    Ok(cfg)
+}
+
+/// Emits a TOML profile, in config order. Keys that are off are skipped, and
+/// keys without a name in `keymap` are written as raw offsets.
+pub fn to_toml(cfg: &CustomConfig, keymap: &Keymap) -> String {
+    let mut keys = toml::Table::new();
+    for key in 0..NUM_KEYS {
+        let color = cfg.get_key(key);
+        if color != Rgb(0, 0, 0) {
+            let name = match keymap.name(key) {
+                Some(name) => name.to_string(),
+                None => key.to_string(),
+            };
+            keys.insert(name, color.to_string().into());
+        }
+    }
+
+    keys.to_string()
+}
@@ -0,0 +1,276 @@
+//! Checks config files more thoroughly than `decode` does, collecting every
+//! problem (rather than just the first) with enough detail to find it, e.g:
+//! which key, at what offset.
+//!
+//! Errors are files that can't be loaded at all. Warnings are files that load,
+//! but probably don't do what was intended (lit keys that aren't on the
+//! keyboard, a container made for a different layout, ...).
+
+use std::fmt;
+use std::str::FromStr;
+
+use super::container::Container;
+use super::{decode, CustomConfig, Format, Rgb, NUM_KEYS};
+use super::{openrgb, text};
+use crate::keymap::Keymap;
+use crate::models::{self, FALLBACK};
+use crate::protocol::key_offset;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.severity {
+            Severity::Error => write!(f, "error: {}", self.message),
+            Severity::Warning => write!(f, "warning: {}", self.message),
+        }
+    }
+}
+
+fn error(message: String) -> Problem {
+    Problem {
+        severity: Severity::Error,
+        message,
+    }
+}
+
+fn warning(message: String) -> Problem {
+    Problem {
+        severity: Severity::Warning,
+        message,
+    }
+}
+
+/// `key`, as it's referred to in messages
+fn describe(key: usize, keymap: &Keymap) -> String {
+    match keymap.name(key) {
+        Some(name) => format!("key {} ('{}', offset {})", key, name, key_offset(key)),
+        None => format!("key {} (offset {})", key, key_offset(key)),
+    }
+}
+
+/// Checks a raw config. The first byte of each key isn't understood, but
+/// every known-good config has it at 0x00 or 0xff.
+fn check_config(cfg: &CustomConfig, keymap: &Keymap) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let data = cfg.as_bytes();
+    for key in 0..cfg.num_keys() {
+        let flags = data[key_offset(key)];
+        if flags != 0x00 && flags != 0xff {
+            problems.push(warning(format!(
+                "{} has an unexpected first byte 0x{:02x} (expected 0x00 or 0xff)",
+                describe(key, keymap),
+                flags
+            )));
+        }
+    }
+
+    // filling every key (gaps included) is fine, anything else lit in a gap
+    // was probably meant for another layout
+    let uniform = (0..cfg.num_keys()).all(|key| cfg.get_key(key) == cfg.get_key(0));
+    let stray: Vec<String> = (0..cfg.num_keys())
+        .filter(|&key| keymap.name(key).is_none() && cfg.get_key(key) != Rgb(0, 0, 0))
+        .map(|key| key.to_string())
+        .collect();
+    if !uniform && !stray.is_empty() {
+        problems.push(warning(format!(
+            "key{} {} {} lit, but there's no key there on the {} layout",
+            if stray.len() == 1 { "" } else { "s" },
+            stray.join(", "),
+            if stray.len() == 1 { "is" } else { "are" },
+            keymap.layout()
+        )));
+    }
+    problems
+}
+
+fn check_binary(data: &[u8], keymap: &Keymap) -> Vec<Problem> {
+    let cfg = match super::from_raw(data) {
+        Ok(cfg) => cfg,
+        Err(mut message) => {
+            if Container::detect(data) {
+                message.push_str(" (it looks like a profile container, which should end in .fkp)");
+            }
+            return vec![error(message)];
+        }
+    };
+
+    let mut problems = Vec::new();
+    if data.len() < FALLBACK.config_len() {
+        problems.push(warning(format!(
+            "only {} of {} bytes, the rest of the keys will be off",
+            data.len(),
+            FALLBACK.config_len()
+        )));
+    }
+    problems.extend(check_config(&cfg, keymap));
+    problems
+}
+
+fn check_container(data: &[u8], keymap: &Keymap) -> Vec<Problem> {
+    let container = match Container::from_bytes(data) {
+        Ok(container) => container,
+        Err(e) => return vec![error(e)],
+    };
+
+    let mut problems = Vec::new();
+    if models::by_name(&container.model).is_none() {
+        let names: Vec<&str> = models::names().collect();
+        problems.push(warning(format!(
+            "made for a '{}' keyboard, which isn't a known model (expected one of: {})",
+            container.model,
+            names.join(", ")
+        )));
+    }
+    if container.layout != keymap.layout() {
+        problems.push(warning(format!(
+            "made with the '{}' layout, but the '{}' layout is in use",
+            container.layout,
+            keymap.layout()
+        )));
+    }
+    problems.extend(check_config(&container.config, keymap));
+    problems
+}
+
+/// Checks named-key profile `entries`: each key's name, and its color (or,
+/// if it isn't a string, what was given instead).
+fn check_profile<'a>(
+    entries: impl Iterator<Item = (&'a str, Result<&'a str, String>)>,
+    keymap: &Keymap,
+) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    // which name set each key, to catch e.g: "esc" and "11" both being given
+    let mut seen: Vec<Option<&str>> = vec![None; NUM_KEYS];
+    for (name, color) in entries {
+        match color {
+            Ok(color) => {
+                if let Err(e) = Rgb::from_str(color) {
+                    problems.push(error(format!("'{}': {}", name, e)));
+                }
+            }
+            Err(other) => problems.push(error(format!(
+                "the color for '{}' must be a string, not {}",
+                name, other
+            ))),
+        }
+
+        let key = match keymap.index(name) {
+            Some(key) => key,
+            None => {
+                problems.push(error(format!(
+                    "unknown key '{}' (not on the {} layout)",
+                    name,
+                    keymap.layout()
+                )));
+                continue;
+            }
+        };
+        match seen[key] {
+            Some(other) => problems.push(warning(format!(
+                "'{}' and '{}' are both {}, so only one of them takes effect",
+                other,
+                name,
+                describe(key, keymap)
+            ))),
+            None => seen[key] = Some(name),
+        }
+        if keymap.name(key).is_none() {
+            problems.push(warning(format!(
+                "'{}' is {}, but there's no key there on the {} layout",
+                name,
+                describe(key, keymap),
+                keymap.layout()
+            )));
+        }
+    }
+    problems
+}
+
+fn check_json(data: &[u8], keymap: &Keymap) -> Vec<Problem> {
+    let value: serde_json::Value = match std::str::from_utf8(data)
+        .map_err(|e| e.to_string())
+        .and_then(|text| serde_json::from_str(text).map_err(|e| e.to_string()))
+    {
+        Ok(value) => value,
+        Err(e) => return vec![error(e)],
+    };
+    let keys = match value.as_object() {
+        Some(keys) => keys,
+        None => return vec![error("profile must be a JSON object".to_string())],
+    };
+
+    let entries = keys
+        .iter()
+        .map(|(name, color)| (name.as_str(), color.as_str().ok_or(color.to_string())));
+    check_profile(entries, keymap)
+}
+
+fn check_toml(data: &[u8], keymap: &Keymap) -> Vec<Problem> {
+    let keys: toml::Table = match std::str::from_utf8(data)
+        .map_err(|e| e.to_string())
+        .and_then(|text| text.parse().map_err(|e: toml::de::Error| e.to_string()))
+    {
+        Ok(keys) => keys,
+        Err(e) => return vec![error(e)],
+    };
+
+    let entries = keys
+        .iter()
+        .map(|(name, color)| (name.as_str(), color.as_str().ok_or(color.to_string())));
+    check_profile(entries, keymap)
+}
+
+fn check_openrgb(data: &[u8], keymap: &Keymap) -> Vec<Problem> {
+    match openrgb::from_orp_checked(data, keymap) {
+        Ok((_, skipped)) => skipped
+            .into_iter()
+            .map(|led| {
+                warning(format!(
+                    "LED {} isn't a key in the {} layout",
+                    led,
+                    keymap.layout()
+                ))
+            })
+            .collect(),
+        Err(e) => vec![error(e)],
+    }
+}
+
+fn check_text(data: &[u8], keymap: &Keymap) -> Vec<Problem> {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(e) => return vec![error(e.to_string())],
+    };
+    let (cfg, problems) = text::from_text_checked(text, keymap);
+    let mut problems: Vec<Problem> = problems.into_iter().map(error).collect();
+    problems.extend(check_config(&cfg, keymap));
+    problems
+}
+
+/// Every problem with an in-memory config file. No problems means it loads,
+/// and looks sane.
+pub fn validate(data: &[u8], format: Format, keymap: &Keymap) -> Vec<Problem> {
+    match format {
+        Format::Binary => check_binary(data, keymap),
+        Format::Container => check_container(data, keymap),
+        Format::Json => check_json(data, keymap),
+        Format::Toml => check_toml(data, keymap),
+        Format::Png => match decode(data, format, keymap) {
+            Ok(_) => Vec::new(),
+            Err(e) => vec![error(e)],
+        },
+        Format::OpenRgb => check_openrgb(data, keymap),
+        Format::Text => check_text(data, keymap),
+    }
+}
@@ -0,0 +1,93 @@
+//! Color correction, applied to custom configs right before they're uploaded.
+
+use crate::config::{CustomConfig, Rgb, NUM_KEYS};
+
+/// supported white point range, in kelvin
+pub const MIN_TEMPERATURE: u32 = 1000;
+pub const MAX_TEMPERATURE: u32 = 10000;
+
+#[derive(Debug, Clone)]
+pub struct Correction {
+    /// shift the white point to this color temperature (in kelvin), e.g: 3500
+    /// for a warm, redshift-style backlight. 6600 is roughly neutral.
+    pub temperature: Option<u32>,
+    /// The LEDs are much brighter than expected at low values, so channels
+    /// are raised to this power (after scaling to 0 - 1). 1.0 leaves them as-is.
+    pub gamma: f32,
+    /// per-channel (R, G, B) multipliers, to even out LEDs that are brighter in
+    /// one channel than the others
+    pub scale: (f32, f32, f32),
+}
+
+impl Default for Correction {
+    fn default() -> Correction {
+        Correction {
+            temperature: None,
+            gamma: 1.0,
+            scale: (1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl Correction {
+    pub fn rgb(&self, color: Rgb) -> Rgb {
+        let (wr, wg, wb) = self.temperature.map_or((1.0, 1.0, 1.0), white_point);
+        let (sr, sg, sb) = self.scale;
+
+        let channel = |c: u8, s: f32| {
+            let c = (c as f32 / 255.0 * s).clamp(0.0, 1.0);
+            (c.powf(self.gamma) * 255.0).round() as u8
+        };
+        Rgb(
+            channel(color.0, wr * sr),
+            channel(color.1, wg * sg),
+            channel(color.2, wb * sb),
+        )
+    }
+
+    pub fn apply(&self, cfg: &CustomConfig) -> CustomConfig {
+        let mut out = cfg.clone();
+        for key in 0..NUM_KEYS {
+            out.set_key(key, self.rgb(cfg.get_key(key)));
+        }
+        out
+    }
+}
+
+/// Per-channel scale factors (0 - 1) for a white point of `kelvin`, using
+/// Tanner Helland's fit of the blackbody curve.
+pub fn white_point(kelvin: u32) -> (f32, f32, f32) {
+    let t = kelvin.clamp(MIN_TEMPERATURE, MAX_TEMPERATURE) as f32 / 100.0;
+
+    let r = if t <= 66.0 {
+        255.0
+    } else {
+        329.698_73 * (t - 60.0).powf(-0.133_204_76)
+    };
+    let g = if t <= 66.0 {
+        99.470_8 * t.ln() - 161.119_57
+    } else {
+        288.122_16 * (t - 60.0).powf(-0.075_514_846)
+    };
+    let b = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.517_73 * (t - 10.0).ln() - 305.044_8
+    };
+
+    let unit = |c: f32| c.clamp(0.0, 255.0) / 255.0;
+    (unit(r), unit(g), unit(b))
+}
+
+/// parses a color temperature like `3500K` or `3500`
+pub fn parse_temperature(s: &str) -> Result<u32, String> {
+    match s.trim_end_matches(['K', 'k']).parse::<u32>() {
+        Ok(kelvin) if (MIN_TEMPERATURE..=MAX_TEMPERATURE).contains(&kelvin) => Ok(kelvin),
+        _ => Err(format!(
+            "temperature must be from {}K - {}K",
+            MIN_TEMPERATURE, MAX_TEMPERATURE
+        )),
+    }
+}
@@ -0,0 +1,1082 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::fs::OpenOptions;
+#[cfg(unix)]
+use std::io::Read;
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::io::IntoRawFd;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::panic;
+#[cfg(unix)]
+use std::process;
+#[cfg(unix)]
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Mutex, MutexGuard, Once};
+use std::thread;
+use std::time;
+
+use log::{debug, error, trace, warn};
+use strum::IntoEnumIterator;
+
+use super::config::CustomConfig;
+use super::models::{self, Model};
+use super::protocol::{
+    check_config_len, check_slot, Color, Direction, Header, Preset, Upload, CONFIG_IN_ENDPOINT,
+    CONFIG_OUT_ENDPOINT, KEY_ENDPOINT, LIGHTING_INTERFACE, MAX_BRIGHTNESS, MAX_SPEED, NUM_SLOTS,
+    REPORT_VALUE, REQUEST_GET_REPORT, REQUEST_SET_REPORT,
+};
+use super::state::{Lighting, State};
+
+pub const VID: u16 = 0x1044;
+pub const PID_AERO_15X: u16 = models::AERO_15X.pid;
+
+/// `InvalidParam` (logging why) if `slot` isn't a custom slot
+pub fn valid_slot(slot: u8) -> Result<(), libusb::Error> {
+    check_slot(slot).map_err(|e| {
+        error!("{}", e);
+        libusb::Error::InvalidParam
+    })
+}
+
+/// `InvalidParam` (logging why) if `len` bytes isn't a custom config for
+/// `model`
+pub fn valid_config_len(model: &Model, len: usize) -> Result<(), libusb::Error> {
+    check_config_len(model, len).map_err(|e| {
+        error!("{}", e);
+        libusb::Error::InvalidParam
+    })
+}
+
+/// USB ids to look for keyboards by
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ids {
+    pub vid: u16,
+    /// in order of preference
+    pub pids: Vec<u16>,
+    /// only the device at this `(bus, address)`, when more than one matches
+    pub at: Option<(u8, u8)>,
+}
+
+impl Default for Ids {
+    /// `VID`, and every known model's product id (see `models`), anywhere
+    fn default() -> Ids {
+        Ids {
+            vid: VID,
+            pids: models::MODELS.iter().map(|m| m.pid).collect(),
+            at: None,
+        }
+    }
+}
+
+impl fmt::Display for Ids {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ids: Vec<String> = self
+            .pids
+            .iter()
+            .map(|pid| format!("{:04x}:{:04x}", self.vid, pid))
+            .collect();
+        write!(f, "{}", ids.join(", "))?;
+        if let Some((bus, address)) = self.at {
+            write!(f, " at {:03}:{:03}", bus, address)?;
+        }
+        Ok(())
+    }
+}
+
+/// every device on the bus matching `ids`, in the order of `ids.pids`
+pub fn scan<'a>(
+    context: &'a libusb::Context,
+    ids: &Ids,
+) -> Result<Vec<libusb::Device<'a>>, libusb::Error> {
+    let mut found = Vec::new();
+    for device in context.devices()?.iter() {
+        let desc = device.device_descriptor()?;
+        if desc.vendor_id() != ids.vid {
+            continue;
+        }
+        if let Some(at) = ids.at {
+            if at != (device.bus_number(), device.address()) {
+                continue;
+            }
+        }
+        if let Some(rank) = ids.pids.iter().position(|&pid| pid == desc.product_id()) {
+            found.push((rank, device));
+        }
+    }
+    found.sort_by_key(|&(rank, _)| rank);
+    Ok(found.into_iter().map(|(_, device)| device).collect())
+}
+
+/// how long a transfer can take before the keyboard is given up on
+pub const DEFAULT_TIMEOUT: time::Duration = time::Duration::from_secs(1);
+
+/// how many times an upload chunk is retried before giving up on the upload
+const CHUNK_RETRIES: u32 = 4;
+/// wait before the first retry, doubling with each one after that
+const RETRY_BACKOFF: time::Duration = time::Duration::from_millis(10);
+
+/// errors that might go away if the transfer is tried again
+fn is_transient(e: &libusb::Error) -> bool {
+    matches!(
+        e,
+        libusb::Error::Timeout
+            | libusb::Error::Io
+            | libusb::Error::Pipe
+            | libusb::Error::Busy
+            | libusb::Error::Overflow
+            | libusb::Error::Interrupted
+    )
+}
+
+/// The handles of every open `FusionKBD` (boxed, so they stay put when the
+/// `FusionKBD` moves), for `release_on_exit`'s handlers to give back to the
+/// kernel. Transfers hold the lock too, so that never happens halfway through
+/// one.
+static OPEN: Mutex<Vec<OpenHandle>> = Mutex::new(Vec::new());
+
+struct OpenHandle(*mut libusb::DeviceHandle<'static>);
+
+// only ever touched with `OPEN` locked
+unsafe impl Send for OpenHandle {}
+
+fn lock_open() -> MutexGuard<'static, Vec<OpenHandle>> {
+    // a panic mid-transfer doesn't make the handle any less usable
+    OPEN.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Hands the claimed interfaces back to the kernel driver. Without this, the
+/// keyboard stops typing until it's replugged.
+fn release(handle: &mut libusb::DeviceHandle) {
+    let _ = handle.release_interface(0);
+    let _ = handle.release_interface(3);
+    // there's no kernel driver to detach (or give back) elsewhere, e.g: with
+    // WinUSB on Windows
+    #[cfg(unix)]
+    {
+        let _ = handle.attach_kernel_driver(0);
+        let _ = handle.attach_kernel_driver(3);
+    }
+}
+
+/// releases every open keyboard
+fn release_open(open: &mut Vec<OpenHandle>) {
+    for OpenHandle(handle) in open.drain(..) {
+        // safe: registered by `FusionKBD::open_device`, and unregistered
+        // (under the same lock) before it's freed
+        release(unsafe { &mut *handle });
+    }
+}
+
+/// write end of the pipe `on_signal` wakes the cleanup thread with
+#[cfg(unix)]
+static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+#[cfg(unix)]
+extern "C" fn on_signal(signal: libc::c_int) {
+    // only async-signal-safe calls in here, the cleanup thread does the rest
+    let byte = signal as u8;
+    unsafe {
+        libc::write(
+            SIGNAL_PIPE.load(Ordering::SeqCst),
+            &byte as *const u8 as *const libc::c_void,
+            1,
+        );
+    }
+}
+
+/// releases every open keyboard on SIGINT / SIGTERM, then exits
+#[cfg(unix)]
+fn release_on_signals() {
+    let (mut rx, tx) = match UnixStream::pair() {
+        Ok(pair) => pair,
+        Err(e) => {
+            warn!("couldn't install signal handlers: {}", e);
+            return;
+        }
+    };
+    SIGNAL_PIPE.store(tx.into_raw_fd(), Ordering::SeqCst);
+    thread::spawn(move || {
+        let mut signal = [0; 1];
+        if rx.read_exact(&mut signal).is_ok() {
+            // keep the lock, so nothing uses the keyboard after this
+            let mut open = lock_open();
+            release_open(&mut open);
+            process::exit(128 + signal[0] as i32);
+        }
+    });
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+/// Makes sure the keyboard gets handed back to the kernel driver however the
+/// process ends: on SIGINT / SIGTERM (which would otherwise kill it without
+/// running `Drop`, e.g: Ctrl-C mid-upload), and on panics when they abort
+/// rather than unwind. Call before opening a `FusionKBD`.
+pub fn release_on_exit() {
+    static INSTALLED: Once = Once::new();
+    INSTALLED.call_once(|| {
+        #[cfg(unix)]
+        release_on_signals();
+
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            hook(info);
+            // when panics unwind, dropping the `FusionKBD` takes care of it
+            // (and a panic on some other thread shouldn't pull the keyboard
+            // out from under the main one)
+            if cfg!(panic = "abort") {
+                if let Ok(mut open) = OPEN.try_lock() {
+                    release_open(&mut open);
+                }
+            }
+        }));
+    });
+}
+
+/// How far along an upload is, as given to `FusionKBD::set_progress` after
+/// each chunk (and once before the first).
+#[derive(Debug, Clone)]
+pub struct Progress {
+    pub slot: u8,
+    /// chunks written so far, out of `chunks`
+    pub done: usize,
+    pub chunks: usize,
+    /// bytes written so far
+    pub bytes: usize,
+    /// transfers that had to be retried so far (see `CHUNK_RETRIES`)
+    pub retries: u32,
+    /// since the upload started
+    pub elapsed: time::Duration,
+}
+
+/// see `FusionKBD::set_progress`
+type OnProgress = RefCell<Box<dyn FnMut(&Progress)>>;
+
+/// A log of every transfer (its direction, request, payload, result, and how
+/// long it took), e.g: for working out firmware quirks, or for protocol bug
+/// reports.
+pub struct PacketLog(RefCell<Box<dyn Write>>);
+
+impl PacketLog {
+    pub fn new(out: Box<dyn Write>) -> PacketLog {
+        PacketLog(RefCell::new(out))
+    }
+
+    /// logs to the end of the file at `path`, or to stderr for "-"
+    pub fn open(path: &str) -> io::Result<PacketLog> {
+        if path == "-" {
+            return Ok(PacketLog::new(Box::new(io::stderr())));
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(PacketLog::new(Box::new(file)))
+    }
+
+    /// `payload` is what was sent, or (when `direction` is "IN") what came
+    /// back
+    pub(crate) fn log(
+        &self,
+        direction: &str,
+        request: &str,
+        payload: &[u8],
+        result: Result<usize, &libusb::Error>,
+        started: time::Instant,
+    ) {
+        let elapsed = started.elapsed();
+        let payload: Vec<String> = payload.iter().map(|b| format!("{:02x}", b)).collect();
+        let result = match result {
+            Ok(n) => format!("{} bytes", n),
+            Err(e) => format!("failed: {}", e),
+        };
+        // best-effort, a full disk shouldn't break the keyboard
+        let _ = writeln!(
+            self.0.borrow_mut(),
+            "{:<3} {}: [{}] -> {} ({:?})",
+            direction,
+            request,
+            payload.join(" "),
+            result,
+            elapsed
+        );
+    }
+}
+
+/// the model with product id `pid`, or `models::FALLBACK` (with a warning)
+pub(crate) fn model_of(pid: u16) -> Model {
+    match models::by_pid(pid) {
+        Some(model) => *model,
+        None => {
+            warn!(
+                "Unknown keyboard ({:04x}), treating it like the {}",
+                pid,
+                models::FALLBACK.name
+            );
+            models::FALLBACK
+        }
+    }
+}
+
+/// what a keyboard of this `model` supports
+pub fn capabilities_of(model: &Model) -> Capabilities {
+    Capabilities {
+        model: model.name.to_string(),
+        presets: Preset::iter().collect(),
+        preset_colors: Color::iter().collect(),
+        per_key_rgb: true,
+        num_slots: NUM_SLOTS,
+        num_keys: model.num_keys,
+        matrix_rows: model.matrix_rows,
+        matrix_cols: model.matrix_cols,
+        config_len: model.config_len(),
+        max_brightness: MAX_BRIGHTNESS,
+        max_speed: MAX_SPEED,
+    }
+}
+
+/// What the opened keyboard supports
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    /// which of `models::MODELS` it is (or `models::FALLBACK`, if it isn't one)
+    pub model: String,
+    /// built-in lighting presets
+    pub presets: Vec<Preset>,
+    /// fixed colors the presets can be combined with
+    pub preset_colors: Vec<Color>,
+    /// whether custom slots take arbitrary per-key RGB values
+    pub per_key_rgb: bool,
+    pub num_slots: u8,
+    pub num_keys: usize,
+    pub matrix_rows: usize,
+    pub matrix_cols: usize,
+    /// bytes in a custom config (see `Model::config_len`)
+    pub config_len: usize,
+    pub max_brightness: u8,
+    pub max_speed: u8,
+}
+
+/// Operations on a keyboard. Implemented by `FusionKBD`, and by anything that
+/// forwards them to one (e.g: a client for a daemon holding the device).
+pub trait Keyboard {
+    /// features supported by the opened model
+    fn capabilities(&self) -> Capabilities;
+
+    /// switch lighting to built-in preset
+    fn set_preset(
+        &self,
+        preset: Preset,
+        speed: u8,
+        brightness: u8,
+        color: Color,
+    ) -> Result<(), libusb::Error>;
+
+    /// Like `set_preset`, going in `direction` (see `Direction`, which is
+    /// experimental). Errors with `NotSupported` on keyboards that can't pass
+    /// one on.
+    fn set_preset_direction(
+        &self,
+        _preset: Preset,
+        _speed: u8,
+        _brightness: u8,
+        _color: Color,
+        _direction: Direction,
+    ) -> Result<(), libusb::Error> {
+        Err(libusb::Error::NotSupported)
+    }
+
+    /// Reads custom slot `slot` back into `data`, which has to be
+    /// `capabilities().config_len` bytes (it's `InvalidParam` otherwise).
+    fn download_custom(&self, slot: u8, data: &mut [u8]) -> Result<(), libusb::Error>;
+
+    /// `download_custom`, into a config of the right size
+    fn download_config(&self, slot: u8) -> Result<CustomConfig, libusb::Error> {
+        let mut data = vec![0; self.capabilities().config_len];
+        self.download_custom(slot, &mut data)?;
+        Ok(CustomConfig::from_bytes(data))
+    }
+
+    /// upload custom lighting scheme to selected custom mode slot
+    fn upload_custom(&self, slot: u8, data: &[u8]) -> Result<(), libusb::Error>;
+
+    /// switch to custom lighting scheme in selected custom mode slot
+    fn set_custom(&self, slot: u8, brightness: u8) -> Result<(), libusb::Error>;
+
+    /// switch to whatever `state` describes (custom slots are shown as-is,
+    /// nothing is uploaded)
+    fn set_state(&self, state: &State) -> Result<(), libusb::Error> {
+        match state.lighting {
+            Lighting::Preset {
+                preset,
+                color,
+                speed,
+            } => self.set_preset(preset, speed, state.brightness, color),
+            Lighting::Custom { slot } => self.set_custom(slot, state.brightness),
+        }
+    }
+}
+
+/// What a `FusionKBD` sends its transfers through: the keyboard's
+/// `libusb::DeviceHandle`, or a stand-in for one (e.g: one that records what
+/// was sent, for tests). The methods are `DeviceHandle`'s.
+pub trait Transport {
+    fn read_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+        timeout: time::Duration,
+    ) -> Result<usize, libusb::Error>;
+
+    fn write_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &[u8],
+        timeout: time::Duration,
+    ) -> Result<usize, libusb::Error>;
+
+    fn read_interrupt(
+        &self,
+        endpoint: u8,
+        buf: &mut [u8],
+        timeout: time::Duration,
+    ) -> Result<usize, libusb::Error>;
+
+    fn write_interrupt(
+        &self,
+        endpoint: u8,
+        buf: &[u8],
+        timeout: time::Duration,
+    ) -> Result<usize, libusb::Error>;
+
+    /// how many string languages the device supports (see `FusionKBD::ping`)
+    fn read_languages(&self, timeout: time::Duration) -> Result<usize, libusb::Error>;
+
+    /// called when the `FusionKBD` is dropped
+    fn release(&mut self) {}
+}
+
+impl<'a> Transport for libusb::DeviceHandle<'a> {
+    fn read_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+        timeout: time::Duration,
+    ) -> Result<usize, libusb::Error> {
+        libusb::DeviceHandle::read_control(self, request_type, request, value, index, buf, timeout)
+    }
+
+    fn write_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &[u8],
+        timeout: time::Duration,
+    ) -> Result<usize, libusb::Error> {
+        libusb::DeviceHandle::write_control(self, request_type, request, value, index, buf, timeout)
+    }
+
+    fn read_interrupt(
+        &self,
+        endpoint: u8,
+        buf: &mut [u8],
+        timeout: time::Duration,
+    ) -> Result<usize, libusb::Error> {
+        libusb::DeviceHandle::read_interrupt(self, endpoint, buf, timeout)
+    }
+
+    fn write_interrupt(
+        &self,
+        endpoint: u8,
+        buf: &[u8],
+        timeout: time::Duration,
+    ) -> Result<usize, libusb::Error> {
+        libusb::DeviceHandle::write_interrupt(self, endpoint, buf, timeout)
+    }
+
+    fn read_languages(&self, timeout: time::Duration) -> Result<usize, libusb::Error> {
+        libusb::DeviceHandle::read_languages(self, timeout).map(|languages| languages.len())
+    }
+
+    fn release(&mut self) {
+        release(self);
+    }
+}
+
+pub struct FusionKBD<'a> {
+    /// a `libusb::DeviceHandle` (registered in `OPEN`) when it's a real
+    /// keyboard
+    handle: Box<dyn Transport + 'a>,
+    /// for every control / interrupt transfer (default: `DEFAULT_TIMEOUT`).
+    /// Zero waits forever.
+    timeout: time::Duration,
+    model: Model,
+    log: Option<PacketLog>,
+    progress: Option<OnProgress>,
+}
+
+impl<'a> FusionKBD<'a> {
+    /// opens (and claims) the first known keyboard
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(context: &'a libusb::Context) -> Result<Self, libusb::Error> {
+        FusionKBD::open(context, &Ids::default())
+    }
+
+    /// opens (and claims) the first keyboard matching `ids`
+    pub fn open(context: &'a libusb::Context, ids: &Ids) -> Result<Self, libusb::Error> {
+        match scan(context, ids)?.first() {
+            Some(device) => FusionKBD::open_device(device),
+            None => {
+                error!("No keyboard found! (looked for {})", ids);
+                Err(libusb::Error::NoDevice)
+            }
+        }
+    }
+
+    /// opens (and claims) `device`, e.g: one found by `scan`
+    pub fn open_device(device: &libusb::Device<'a>) -> Result<Self, libusb::Error> {
+        let model = model_of(device.device_descriptor()?.product_id());
+
+        let mut handle = match device.open() {
+            Ok(handle) => handle,
+            Err(e) => {
+                error!("Failed to open device! Are you running as root?");
+                return Err(e);
+            }
+        };
+
+        #[cfg(unix)]
+        for &interface in &[0, LIGHTING_INTERFACE as u8] {
+            match handle.kernel_driver_active(interface) {
+                Ok(true) => handle.detach_kernel_driver(interface)?,
+                Ok(false) => {}
+                // can't tell (e.g: on a platform without kernel drivers), so
+                // just try claiming it
+                Err(libusb::Error::NotSupported) => {}
+                Err(e) => {
+                    error!(
+                        "Couldn't tell whether a kernel driver has interface {} ({})",
+                        interface, e
+                    );
+                    return Err(e);
+                }
+            }
+        }
+
+        handle.claim_interface(0)?;
+        handle.claim_interface(3)?;
+
+        let mut handle = Box::new(handle);
+        let raw: *mut libusb::DeviceHandle<'a> = &mut *handle;
+        lock_open().push(OpenHandle(raw.cast()));
+
+        Ok(FusionKBD {
+            handle,
+            timeout: DEFAULT_TIMEOUT,
+            model,
+            log: None,
+            progress: None,
+        })
+    }
+
+    /// A `model` keyboard that sends everything through `transport` rather
+    /// than a device (e.g: to check what gets sent, without one).
+    pub fn with_transport(transport: Box<dyn Transport + 'a>, model: Model) -> Self {
+        FusionKBD {
+            handle: transport,
+            timeout: DEFAULT_TIMEOUT,
+            model,
+            log: None,
+            progress: None,
+        }
+    }
+
+    /// what's known about the opened keyboard
+    pub fn model(&self) -> &Model {
+        &self.model
+    }
+
+    /// sets the timeout for USB transfers (zero waits forever)
+    pub fn set_timeout(&mut self, timeout: time::Duration) {
+        self.timeout = timeout;
+    }
+
+    /// logs every transfer from now on
+    pub fn set_packet_log(&mut self, log: PacketLog) {
+        self.log = Some(log);
+    }
+
+    /// calls `progress` as each upload goes along (e.g: to draw a progress bar)
+    pub fn set_progress(&mut self, progress: impl FnMut(&Progress) + 'static) {
+        self.progress = Some(RefCell::new(Box::new(progress)));
+    }
+
+    fn report(&self, progress: &Progress) {
+        if let Some(ref report) = self.progress {
+            (report.borrow_mut())(progress);
+        }
+    }
+
+    fn log(
+        &self,
+        direction: &str,
+        request: &str,
+        payload: &[u8],
+        result: Result<usize, &libusb::Error>,
+        started: time::Instant,
+    ) {
+        if let Some(ref log) = self.log {
+            log.log(direction, request, payload, result, started);
+        }
+    }
+
+    /// checks if a keyboard is plugged in, without opening it (which usually
+    /// requires root)
+    pub fn is_connected(context: &libusb::Context) -> Result<bool, libusb::Error> {
+        Ok(!scan(context, &Ids::default())?.is_empty())
+    }
+
+    /// A harmless standard request (reading the supported string languages),
+    /// to check the keyboard answers at all.
+    pub fn ping(&self) -> Result<(), libusb::Error> {
+        let _open = lock_open();
+        let started = time::Instant::now();
+        let result = self.handle.read_languages(self.timeout);
+        self.log(
+            "IN",
+            "control GET_DESCRIPTOR (languages)",
+            &[],
+            result.as_ref().map(|languages| languages * 2),
+            started,
+        );
+        result.map(|_| ())
+    }
+
+    /// A raw control transfer (e.g: one replayed from a capture). IN ones
+    /// (`request_type & 0x80`) read into `data`, OUT ones send it.
+    pub fn control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        data: &mut [u8],
+    ) -> Result<usize, libusb::Error> {
+        let _open = lock_open();
+        let name = format!(
+            "control {:02x} {:02x} {:04x} {:04x}",
+            request_type, request, value, index
+        );
+        let started = time::Instant::now();
+        if request_type & 0x80 != 0 {
+            let result =
+                self.handle
+                    .read_control(request_type, request, value, index, data, self.timeout);
+            let read = *result.as_ref().unwrap_or(&0);
+            self.log(
+                "IN",
+                &name,
+                &data[..read],
+                result.as_ref().copied(),
+                started,
+            );
+            result
+        } else {
+            let result =
+                self.handle
+                    .write_control(request_type, request, value, index, data, self.timeout);
+            self.log("OUT", &name, data, result.as_ref().copied(), started);
+            result
+        }
+    }
+
+    /// A raw interrupt transfer on `endpoint`. IN ones (`endpoint & 0x80`)
+    /// read into `data`, OUT ones send it.
+    pub fn interrupt(&self, endpoint: u8, data: &mut [u8]) -> Result<usize, libusb::Error> {
+        let _open = lock_open();
+        let name = format!("interrupt {:#04x}", endpoint);
+        let started = time::Instant::now();
+        if endpoint & 0x80 != 0 {
+            let result = self.handle.read_interrupt(endpoint, data, self.timeout);
+            let read = *result.as_ref().unwrap_or(&0);
+            self.log(
+                "IN",
+                &name,
+                &data[..read],
+                result.as_ref().copied(),
+                started,
+            );
+            result
+        } else {
+            let result = self.handle.write_interrupt(endpoint, data, self.timeout);
+            self.log("OUT", &name, data, result.as_ref().copied(), started);
+            result
+        }
+    }
+
+    fn write_control_kbd(&self, header: &Header) -> Result<usize, libusb::Error> {
+        debug!("Sending {}", header);
+        let _open = lock_open();
+        let started = time::Instant::now();
+        let result = self.handle.write_control(
+            libusb::request_type(
+                libusb::Direction::Out,
+                libusb::RequestType::Class,
+                libusb::Recipient::Interface,
+            ),
+            REQUEST_SET_REPORT,
+            REPORT_VALUE,
+            LIGHTING_INTERFACE,
+            header.as_bytes(),
+            self.timeout,
+        );
+        self.log(
+            "OUT",
+            "control SET_REPORT",
+            header.as_bytes(),
+            result.as_ref().copied(),
+            started,
+        );
+        result
+    }
+
+    /// Writes one chunk of a custom config, retrying (with backoff) if it
+    /// fails or is only partly written, so a hiccup doesn't leave the slot
+    /// corrupted. Returns how many retries it took.
+    ///
+    /// The keyboard takes every interrupt transfer as a whole chunk, so a
+    /// retry always resends all of it (the rest on its own would be read as
+    /// a short chunk, and throw the following ones out of place).
+    fn write_chunk(&self, chunk: &[u8]) -> Result<u32, libusb::Error> {
+        let mut backoff = RETRY_BACKOFF;
+        for retry in 0..=CHUNK_RETRIES {
+            if retry > 0 {
+                thread::sleep(backoff);
+                backoff *= 2;
+            }
+            let _open = lock_open();
+            let started = time::Instant::now();
+            let result = self
+                .handle
+                .write_interrupt(CONFIG_OUT_ENDPOINT, chunk, self.timeout);
+            self.log(
+                "OUT",
+                "interrupt 0x06",
+                chunk,
+                result.as_ref().copied(),
+                started,
+            );
+            match result {
+                Ok(n) if n == chunk.len() => return Ok(retry),
+                Ok(n) => {
+                    debug!(
+                        "Interrupt transfer was short ({} of {} bytes), resending it",
+                        n,
+                        chunk.len()
+                    );
+                }
+                Err(ref e) if is_transient(e) && retry < CHUNK_RETRIES => {
+                    debug!("Interrupt transfer failed ({}), retrying", e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        // still short after every retry
+        Err(libusb::Error::Io)
+    }
+
+    pub fn get_key(&self) -> Option<char> {
+        let mut buf: [u8; 8] = [0; 8];
+        let _open = lock_open();
+        let started = time::Instant::now();
+        let result =
+            self.handle
+                .read_interrupt(KEY_ENDPOINT, &mut buf, time::Duration::from_millis(10));
+        let read = *result.as_ref().unwrap_or(&0);
+        self.log(
+            "IN",
+            "interrupt 0x81",
+            &buf[..read],
+            result.as_ref().copied(),
+            started,
+        );
+
+        // too lazy to actually implement usbhid translaton.
+        // maybe later?
+        // check out:
+        //   - https://bitvijays.github.io/LFC-Forensics.html#usb-keyboard
+        //   - google usb_hid_keys.h
+
+        if buf[2] != 0x00 {
+            Some('a')
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> Keyboard for FusionKBD<'a> {
+    fn capabilities(&self) -> Capabilities {
+        capabilities_of(&self.model)
+    }
+
+    fn set_preset(
+        &self,
+        preset: Preset,
+        speed: u8,
+        brightness: u8,
+        color: Color,
+    ) -> Result<(), libusb::Error> {
+        let header = Header::preset(preset, speed, brightness, color);
+        self.write_control_kbd(&header)?;
+
+        Ok(())
+    }
+
+    fn set_preset_direction(
+        &self,
+        preset: Preset,
+        speed: u8,
+        brightness: u8,
+        color: Color,
+        direction: Direction,
+    ) -> Result<(), libusb::Error> {
+        let header = Header::preset_direction(preset, speed, brightness, color, direction);
+        self.write_control_kbd(&header)?;
+
+        Ok(())
+    }
+
+    fn download_custom(&self, slot: u8, data: &mut [u8]) -> Result<(), libusb::Error> {
+        valid_slot(slot)?;
+        valid_config_len(&self.model, data.len())?;
+
+        self.write_control_kbd(&Header::read_config(slot))?;
+
+        let _open = lock_open();
+        let mut dummy = [0; 8];
+        let started = time::Instant::now();
+        let result = self.handle.read_control(
+            libusb::request_type(
+                libusb::Direction::In,
+                libusb::RequestType::Class,
+                libusb::Recipient::Interface,
+            ),
+            REQUEST_GET_REPORT,
+            REPORT_VALUE,
+            LIGHTING_INTERFACE,
+            &mut dummy, // dummy buffer
+            self.timeout,
+        );
+        let read = *result.as_ref().unwrap_or(&0);
+        self.log(
+            "IN",
+            "control GET_REPORT",
+            &dummy[..read],
+            result.as_ref().copied(),
+            started,
+        );
+        result?;
+
+        let chunk_size = self.model.chunk_size;
+        for i in 0..self.model.num_chunks {
+            let start = i * chunk_size;
+            let end = start + chunk_size;
+            let started = time::Instant::now();
+            let result =
+                self.handle
+                    .read_interrupt(CONFIG_IN_ENDPOINT, &mut data[start..end], self.timeout);
+            let read = *result.as_ref().unwrap_or(&0);
+            self.log(
+                "IN",
+                "interrupt 0x85",
+                &data[start..start + read],
+                result.as_ref().copied(),
+                started,
+            );
+            let tf = result?;
+            if tf != chunk_size {
+                warn!("Interrupt transfer {} was short: {} bytes", i, tf);
+            }
+            trace!("Interrupt transfer {}/{} ok", i + 1, self.model.num_chunks);
+        }
+        debug!(
+            "Read back slot {} ({} interrupt transfers)",
+            slot, self.model.num_chunks
+        );
+
+        Ok(())
+    }
+
+    fn upload_custom(&self, slot: u8, data: &[u8]) -> Result<(), libusb::Error> {
+        let upload = Upload::new(slot, data, &self.model).map_err(|e| {
+            error!("{}", e);
+            libusb::Error::InvalidParam
+        })?;
+        let num_chunks = upload.chunks.len();
+        self.write_control_kbd(&upload.header)?;
+
+        let mut progress = Progress {
+            slot,
+            done: 0,
+            chunks: num_chunks,
+            bytes: 0,
+            retries: 0,
+            elapsed: time::Duration::ZERO,
+        };
+        let started = time::Instant::now();
+        self.report(&progress);
+        for (i, chunk) in upload.chunks.iter().enumerate() {
+            match self.write_chunk(chunk) {
+                Ok(retries) => progress.retries += retries,
+                Err(e) => {
+                    error!("Interrupt transfer {} failed: {}", i, e);
+                    return Err(e);
+                }
+            }
+            trace!("Interrupt transfer {}/{} ok", i + 1, num_chunks);
+            progress.done = i + 1;
+            progress.bytes += chunk.len();
+            progress.elapsed = started.elapsed();
+            self.report(&progress);
+        }
+        debug!(
+            "Uploaded slot {} ({} interrupt transfers)",
+            slot, num_chunks
+        );
+
+        // will NOT automatically switch to the new mode!
+        // requires call to set_custom
+
+        Ok(())
+    }
+
+    fn set_custom(&self, slot: u8, brightness: u8) -> Result<(), libusb::Error> {
+        let header = Header::custom(slot, brightness).map_err(|e| {
+            error!("{}", e);
+            libusb::Error::InvalidParam
+        })?;
+        self.write_control_kbd(&header)?;
+
+        Ok(())
+    }
+}
+
+/// The same operations on several keyboards at once (e.g: an Aero's own
+/// keyboard, and an external one speaking the same protocol).
+pub struct Broadcast<K: Keyboard> {
+    kbds: Vec<K>,
+}
+
+impl<K: Keyboard> Broadcast<K> {
+    /// errors with `NoDevice` if `kbds` is empty
+    pub fn new(kbds: Vec<K>) -> Result<Broadcast<K>, libusb::Error> {
+        if kbds.is_empty() {
+            return Err(libusb::Error::NoDevice);
+        }
+        Ok(Broadcast { kbds })
+    }
+
+    /// Does `op` to every keyboard, carrying on past ones that fail (so one
+    /// bad keyboard doesn't hold up the rest). Returns the first error.
+    fn each(&self, op: impl Fn(&K) -> Result<(), libusb::Error>) -> Result<(), libusb::Error> {
+        let mut result = Ok(());
+        for (i, kbd) in self.kbds.iter().enumerate() {
+            if let Err(e) = op(kbd) {
+                error!("keyboard {} of {} failed: {}", i + 1, self.kbds.len(), e);
+                result = result.and(Err(e));
+            }
+        }
+        result
+    }
+}
+
+impl<K: Keyboard> Keyboard for Broadcast<K> {
+    /// What every keyboard supports: the presets and colors they all have, and
+    /// the lowest of their limits. If their models (or config sizes) differ,
+    /// `config_len` is 0, so custom configs are refused rather than being sent
+    /// to keyboards they weren't made for.
+    fn capabilities(&self) -> Capabilities {
+        let mut caps = self.kbds[0].capabilities();
+        for other in self.kbds[1..].iter().map(K::capabilities) {
+            caps.presets.retain(|p| other.presets.contains(p));
+            caps.preset_colors
+                .retain(|c| other.preset_colors.contains(c));
+            caps.per_key_rgb &= other.per_key_rgb;
+            caps.num_slots = caps.num_slots.min(other.num_slots);
+            caps.max_brightness = caps.max_brightness.min(other.max_brightness);
+            caps.max_speed = caps.max_speed.min(other.max_speed);
+            if other.model != caps.model || other.config_len != caps.config_len {
+                caps.config_len = 0;
+            }
+        }
+        caps
+    }
+
+    fn set_preset(
+        &self,
+        preset: Preset,
+        speed: u8,
+        brightness: u8,
+        color: Color,
+    ) -> Result<(), libusb::Error> {
+        self.each(|kbd| kbd.set_preset(preset, speed, brightness, color))
+    }
+
+    fn set_preset_direction(
+        &self,
+        preset: Preset,
+        speed: u8,
+        brightness: u8,
+        color: Color,
+        direction: Direction,
+    ) -> Result<(), libusb::Error> {
+        self.each(|kbd| kbd.set_preset_direction(preset, speed, brightness, color, direction))
+    }
+
+    /// Refused: each keyboard could have something different in `slot`, and
+    /// there's only room for one of them.
+    fn download_custom(&self, slot: u8, _data: &mut [u8]) -> Result<(), libusb::Error> {
+        error!(
+            "can't read slot {} back from {} keyboards at once (pick one of them instead)",
+            slot,
+            self.kbds.len()
+        );
+        Err(libusb::Error::NotSupported)
+    }
+
+    /// refused before anything's sent if the keyboards are different models
+    /// (see `capabilities`)
+    fn upload_custom(&self, slot: u8, data: &[u8]) -> Result<(), libusb::Error> {
+        if self.capabilities().config_len == 0 {
+            error!("these keyboards are different models, so they can't share a custom config");
+            return Err(libusb::Error::InvalidParam);
+        }
+        self.each(|kbd| kbd.upload_custom(slot, data))
+    }
+
+    fn set_custom(&self, slot: u8, brightness: u8) -> Result<(), libusb::Error> {
+        self.each(|kbd| kbd.set_custom(slot, brightness))
+    }
+}
+
+impl<'a> Drop for FusionKBD<'a> {
+    fn drop(&mut self) {
+        let mut open = lock_open();
+        let this = &mut *self.handle as *mut (dyn Transport + 'a) as *mut ();
+        open.retain(|&OpenHandle(handle)| handle.cast() != this);
+        self.handle.release();
+    }
+}
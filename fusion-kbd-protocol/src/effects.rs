@@ -0,0 +1,329 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::config::{key_position, CustomConfig, Rgb, MATRIX_COLS, MATRIX_ROWS, NUM_KEYS};
+#[cfg(feature = "usb")]
+use super::device::Keyboard;
+use super::keymap::Keymap;
+
+/// every key set to `color`
+pub fn solid(color: Rgb) -> CustomConfig {
+    let mut cfg = CustomConfig::new();
+    cfg.fill(color);
+    cfg
+}
+
+/// only `key` lit
+pub fn single_key(key: usize, color: Rgb) -> CustomConfig {
+    let mut cfg = CustomConfig::new();
+    cfg.set_key(key, color);
+    cfg
+}
+
+/// only the keys in matrix column `col` lit
+pub fn column(col: usize, color: Rgb) -> CustomConfig {
+    let mut cfg = CustomConfig::new();
+    for key in column_keys(col) {
+        cfg.set_key(key, color);
+    }
+    cfg
+}
+
+/// keys in matrix column `col`
+pub fn column_keys(col: usize) -> Vec<usize> {
+    (0..NUM_KEYS)
+        .filter(|&key| key_position(key).1 == col)
+        .collect()
+}
+
+/// sRGB channel -> linear light (0 - 1)
+pub(crate) fn to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// linear light (0 - 1) -> sRGB channel
+pub(crate) fn from_linear(c: f32) -> u8 {
+    let c = c.clamp(0.0, 1.0);
+    let c = if c <= 0.003_130_8 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    };
+    (c * 255.0).round() as u8
+}
+
+/// Mixes `a` and `b` (`t` = 0 is all `a`, 1 is all `b`). Mixing happens in
+/// linear light, since averaging raw sRGB values gives muddy, too-dark
+/// midpoints.
+pub fn blend(a: Rgb, b: Rgb, t: f32) -> Rgb {
+    let t = t.clamp(0.0, 1.0);
+    let mix = |a: u8, b: u8| from_linear(to_linear(a) * (1.0 - t) + to_linear(b) * t);
+    Rgb(mix(a.0, b.0), mix(a.1, b.1), mix(a.2, b.2))
+}
+
+/// `blend`s every key of two configs
+pub fn crossfade(a: &CustomConfig, b: &CustomConfig, t: f32) -> CustomConfig {
+    let mut cfg = CustomConfig::new();
+    for key in 0..NUM_KEYS {
+        cfg.set_key(key, blend(a.get_key(key), b.get_key(key), t));
+    }
+    cfg
+}
+
+/// `steps` frames crossfading from `a` to `b` over `duration`
+pub fn fade(
+    a: &CustomConfig,
+    b: &CustomConfig,
+    steps: u32,
+    duration: Duration,
+) -> Vec<(CustomConfig, Duration)> {
+    let steps = steps.max(1);
+    (1..=steps)
+        .map(|i| (crossfade(a, b, i as f32 / steps as f32), duration / steps))
+        .collect()
+}
+
+/// built-in animations (see `animation`)
+pub const ANIMATIONS: &[&str] = &["rainbow", "breathe", "scan"];
+
+/// fully saturated color at `hue` degrees
+pub fn hue(hue: f32) -> Rgb {
+    let h = hue.rem_euclid(360.0) / 60.0;
+    let x = ((1.0 - (h % 2.0 - 1.0).abs()) * 255.0) as u8;
+    match h as u32 {
+        0 => Rgb(0xff, x, 0),
+        1 => Rgb(x, 0xff, 0),
+        2 => Rgb(0, 0xff, x),
+        3 => Rgb(0, x, 0xff),
+        4 => Rgb(x, 0, 0xff),
+        _ => Rgb(0xff, 0, x),
+    }
+}
+
+/// Frames of one of the built-in `ANIMATIONS`, which loop seamlessly:
+///
+/// - `rainbow`: a rainbow scrolling from left to right (ignores `color`)
+/// - `breathe`: every key fading between off and `color`
+/// - `scan`: a single lit column sweeping across the keyboard
+pub fn animation(name: &str, color: Rgb) -> Option<Vec<(CustomConfig, Duration)>> {
+    let frames = match name {
+        "rainbow" => (0..MATRIX_COLS)
+            .map(|frame| {
+                let mut cfg = CustomConfig::new();
+                for key in 0..NUM_KEYS {
+                    let col = key_position(key).1 as f32 - frame as f32;
+                    cfg.set_key(key, hue(col * 360.0 / MATRIX_COLS as f32));
+                }
+                (cfg, Duration::from_millis(50))
+            })
+            .collect(),
+        "breathe" => (0..40u32)
+            .map(|frame| {
+                let level = if frame < 20 { frame } else { 40 - frame };
+                (
+                    solid(blend(Rgb(0, 0, 0), color, level as f32 / 20.0)),
+                    Duration::from_millis(50),
+                )
+            })
+            .collect(),
+        "scan" => (0..MATRIX_COLS)
+            .map(|col| (column(col, color), Duration::from_millis(60)))
+            .collect(),
+        _ => return None,
+    };
+    Some(frames)
+}
+
+/// starter configs (see `template`)
+pub const TEMPLATES: &[&str] = &["gradient", "rows", "checkerboard", "single-color"];
+
+/// One of the starter `TEMPLATES`, to edit into something else. Only keys in
+/// `keymap` are lit.
+///
+/// - `gradient`: fading from `colors[0]` on the left to `colors[1]` on the
+///   right (default: red to blue)
+/// - `rows`: each row the next of `colors`, from the top (default: the
+///   rainbow)
+/// - `checkerboard`: alternating `colors[0]` and `colors[1]` (default: white
+///   and off)
+/// - `single-color`: every key `colors[0]` (default: white)
+pub fn template(name: &str, colors: &[Rgb], keymap: &Keymap) -> Option<CustomConfig> {
+    let color = |i: usize, default: Rgb| colors.get(i).copied().unwrap_or(default);
+    let (white, off) = (Rgb(0xff, 0xff, 0xff), Rgb(0, 0, 0));
+    let rainbow: Vec<Rgb> = (0..MATRIX_ROWS)
+        .map(|row| hue(row as f32 * 360.0 / MATRIX_ROWS as f32))
+        .collect();
+
+    let at: Box<dyn Fn(usize, usize) -> Rgb> = match name {
+        "gradient" => {
+            let (a, b) = (color(0, Rgb(0xff, 0, 0)), color(1, Rgb(0, 0, 0xff)));
+            // from the leftmost key to the rightmost one
+            let cols: Vec<usize> = keymap.keys().map(|(_, key)| key_position(key).1).collect();
+            let left = cols.iter().copied().min().unwrap_or(0);
+            let right = cols.iter().copied().max().unwrap_or(0);
+            let width = (right - left).max(1) as f32;
+            Box::new(move |_, col| blend(a, b, col.saturating_sub(left) as f32 / width))
+        }
+        "rows" => {
+            let rows = if colors.is_empty() {
+                rainbow
+            } else {
+                colors.to_vec()
+            };
+            Box::new(move |row, _| rows[row % rows.len()])
+        }
+        "checkerboard" => {
+            let (a, b) = (color(0, white), color(1, off));
+            Box::new(move |row, col| if (row + col) % 2 == 0 { a } else { b })
+        }
+        "single-color" => {
+            let a = color(0, white);
+            Box::new(move |_, _| a)
+        }
+        _ => return None,
+    };
+
+    let mut cfg = CustomConfig::new();
+    for (_, key) in keymap.keys() {
+        let (row, col) = key_position(key);
+        cfg.set_key(key, at(row, col));
+    }
+    Some(cfg)
+}
+
+/// A small, fast PRNG (splitmix64). Good enough for scattering colors, and
+/// always gives the same sequence for the same seed (unlike `rand`, which
+/// makes no such promise across versions).
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// uniform in 0 - 1
+    fn unit(&mut self) -> f32 {
+        (self.next() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// Keys in `keymap` set to `base`, with each one (independently, with
+/// probability `density`) swapped for `accent` instead. The same `seed`
+/// always scatters the same keys.
+pub fn sparkle(base: Rgb, accent: Rgb, density: f32, seed: u64, keymap: &Keymap) -> CustomConfig {
+    let mut rng = Rng(seed);
+    let mut cfg = CustomConfig::new();
+    for key in (0..NUM_KEYS).filter(|&k| keymap.name(k).is_some()) {
+        let color = if rng.unit() < density { accent } else { base };
+        cfg.set_key(key, color);
+    }
+    cfg
+}
+
+/// A still rainbow over the keys in `keymap`: red on the leftmost column
+/// through to violet on the rightmost (or, `by_row`, from the top row to the
+/// bottom one).
+pub fn rainbow(by_row: bool, keymap: &Keymap) -> CustomConfig {
+    let pick = |key: usize| {
+        let (row, col) = key_position(key);
+        if by_row {
+            row
+        } else {
+            col
+        }
+    };
+    let lines: Vec<usize> = keymap.keys().map(|(_, key)| pick(key)).collect();
+    let first = lines.iter().copied().min().unwrap_or(0);
+    let last = lines.iter().copied().max().unwrap_or(0);
+    let span = (last - first).max(1) as f32;
+
+    let mut cfg = CustomConfig::new();
+    for (_, key) in keymap.keys() {
+        let t = (pick(key) - first) as f32 / span;
+        cfg.set_key(key, hue(t * 300.0));
+    }
+    cfg
+}
+
+/// Paces frames against absolute deadlines on a timeline starting when the
+/// clock is created, so time spent uploading frames doesn't accumulate as
+/// drift. Frames whose slot on the timeline has already passed should be
+/// skipped (see `is_late`), so a slow device drops frames instead of falling
+/// behind.
+pub struct FrameClock {
+    start: Instant,
+    /// where on the timeline the current frame starts
+    offset: Duration,
+}
+
+impl FrameClock {
+    pub fn new() -> FrameClock {
+        FrameClock {
+            start: Instant::now(),
+            offset: Duration::from_secs(0),
+        }
+    }
+
+    /// true if the current frame (lasting `duration`) would already be over
+    pub fn is_late(&self, duration: Duration) -> bool {
+        Instant::now() >= self.start + self.offset + duration
+    }
+
+    /// moves on to the next frame without waiting
+    pub fn skip(&mut self, duration: Duration) {
+        self.offset += duration;
+    }
+
+    /// sleeps until the current frame (lasting `duration`) is over, then moves
+    /// on to the next one
+    pub fn wait(&mut self, duration: Duration) {
+        self.offset += duration;
+        let deadline = self.start + self.offset;
+        thread::sleep(deadline.saturating_duration_since(Instant::now()));
+    }
+}
+
+impl Default for FrameClock {
+    fn default() -> FrameClock {
+        FrameClock::new()
+    }
+}
+
+#[cfg(feature = "usb")]
+/// Streams an animation to the keyboard by repeatedly uploading each frame to
+/// `slot` and switching to it. Plays `loops` times, or forever if `loops` is 0.
+/// Playback follows a `FrameClock`, so frames are dropped rather than letting
+/// the animation drift if uploads can't keep up.
+pub fn play(
+    kbd: &dyn Keyboard,
+    slot: u8,
+    brightness: u8,
+    frames: &[(CustomConfig, Duration)],
+    loops: u32,
+) -> Result<(), libusb::Error> {
+    let mut clock = FrameClock::new();
+    let mut played = 0;
+    while loops == 0 || played < loops {
+        for (cfg, delay) in frames.iter() {
+            if clock.is_late(*delay) {
+                clock.skip(*delay);
+                continue;
+            }
+            kbd.upload_custom(slot, cfg.as_bytes())?;
+            kbd.set_custom(slot, brightness)?;
+            clock.wait(*delay);
+        }
+        played += 1;
+    }
+
+    Ok(())
+}
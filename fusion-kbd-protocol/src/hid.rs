@@ -0,0 +1,193 @@
+//! A hidapi backend, for where libusb can't take the keyboard over (e.g: on
+//! Windows, where the keyboard belongs to the OS's HID driver, and there's no
+//! kernel driver to detach). Nothing gets claimed, so the keyboard keeps
+//! typing throughout.
+//!
+//! It sends what `FusionKBD` does, as HID reports on interface 3 (report id
+//! 0): feature reports stand in for the control transfers, and output /
+//! input reports for the interrupt ones.
+
+use std::time::Duration;
+
+use hidapi::{HidApi, HidDevice, HidError};
+use log::{debug, error, trace, warn};
+
+use crate::device::{
+    capabilities_of, model_of, valid_config_len, valid_slot, Capabilities, Ids, Keyboard,
+    DEFAULT_TIMEOUT,
+};
+use crate::models::Model;
+use crate::protocol::{Color, Direction, Header, Preset, Upload, LIGHTING_INTERFACE};
+
+/// hidapi's errors don't map onto libusb's, so they're logged instead
+fn hid_error(e: HidError) -> libusb::Error {
+    error!("HID error: {}", e);
+    libusb::Error::Io
+}
+
+pub struct HidKBD {
+    device: HidDevice,
+    /// for every report read (default: `DEFAULT_TIMEOUT`). Zero waits forever.
+    timeout: Duration,
+    model: Model,
+}
+
+impl HidKBD {
+    /// Opens the first keyboard matching `ids`. hidapi doesn't say which bus
+    /// devices are on, so `ids.at` isn't supported.
+    pub fn open(ids: &Ids) -> Result<HidKBD, libusb::Error> {
+        if ids.at.is_some() {
+            error!("--device isn't supported by the hidapi backend");
+            return Err(libusb::Error::NotSupported);
+        }
+
+        let api = HidApi::new().map_err(hid_error)?;
+        let info = api
+            .device_list()
+            .filter(|d| {
+                d.vendor_id() == ids.vid && d.interface_number() == LIGHTING_INTERFACE as i32
+            })
+            .filter_map(|d| Some((ids.pids.iter().position(|&p| p == d.product_id())?, d)))
+            .min_by_key(|&(rank, _)| rank)
+            .map(|(_, d)| d);
+        let info = match info {
+            Some(info) => info,
+            None => {
+                error!("No keyboard found! (looked for {})", ids);
+                return Err(libusb::Error::NoDevice);
+            }
+        };
+
+        let device = match api.open_path(info.path()) {
+            Ok(device) => device,
+            Err(e) => {
+                error!("Failed to open device! ({})", e);
+                return Err(libusb::Error::Access);
+            }
+        };
+        Ok(HidKBD {
+            device,
+            timeout: DEFAULT_TIMEOUT,
+            model: model_of(info.product_id()),
+        })
+    }
+
+    /// what's known about the opened keyboard
+    pub fn model(&self) -> &Model {
+        &self.model
+    }
+
+    /// sets the timeout for reads (zero waits forever)
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// `timeout`, the way hidapi takes it (-1 waits forever)
+    fn timeout_ms(&self) -> i32 {
+        if self.timeout.is_zero() {
+            -1
+        } else {
+            self.timeout.as_millis().min(i32::MAX as u128) as i32
+        }
+    }
+
+    fn send_header(&self, header: &Header) -> Result<(), libusb::Error> {
+        let mut report = vec![0];
+        report.extend_from_slice(header.as_bytes());
+        self.device.send_feature_report(&report).map_err(hid_error)
+    }
+}
+
+impl Keyboard for HidKBD {
+    fn capabilities(&self) -> Capabilities {
+        capabilities_of(&self.model)
+    }
+
+    fn set_preset(
+        &self,
+        preset: Preset,
+        speed: u8,
+        brightness: u8,
+        color: Color,
+    ) -> Result<(), libusb::Error> {
+        self.send_header(&Header::preset(preset, speed, brightness, color))
+    }
+
+    fn set_preset_direction(
+        &self,
+        preset: Preset,
+        speed: u8,
+        brightness: u8,
+        color: Color,
+        direction: Direction,
+    ) -> Result<(), libusb::Error> {
+        self.send_header(&Header::preset_direction(
+            preset, speed, brightness, color, direction,
+        ))
+    }
+
+    fn download_custom(&self, slot: u8, data: &mut [u8]) -> Result<(), libusb::Error> {
+        valid_slot(slot)?;
+        valid_config_len(&self.model, data.len())?;
+
+        self.send_header(&Header::read_config(slot))?;
+        // the same dummy read `FusionKBD` does
+        self.device
+            .get_feature_report(&mut [0; 9])
+            .map_err(hid_error)?;
+
+        let chunk_size = self.model.chunk_size;
+        for i in 0..self.model.num_chunks {
+            let start = i * chunk_size;
+            let end = start + chunk_size;
+            let n = self
+                .device
+                .read_timeout(&mut data[start..end], self.timeout_ms())
+                .map_err(hid_error)?;
+            if n == 0 {
+                error!("Input report {} timed out", i);
+                return Err(libusb::Error::Timeout);
+            }
+            if n != chunk_size {
+                warn!("Input report {} was short: {} bytes", i, n);
+            }
+            trace!("Input report {}/{} ok", i + 1, self.model.num_chunks);
+        }
+        debug!(
+            "Read back slot {} ({} input reports)",
+            slot, self.model.num_chunks
+        );
+
+        Ok(())
+    }
+
+    fn upload_custom(&self, slot: u8, data: &[u8]) -> Result<(), libusb::Error> {
+        let upload = Upload::new(slot, data, &self.model).map_err(|e| {
+            error!("{}", e);
+            libusb::Error::InvalidParam
+        })?;
+        let num_chunks = upload.chunks.len();
+        self.send_header(&upload.header)?;
+
+        for (i, chunk) in upload.chunks.iter().enumerate() {
+            let mut report = vec![0];
+            report.extend_from_slice(chunk);
+            if let Err(e) = self.device.write(&report) {
+                error!("Output report {} failed: {}", i, e);
+                return Err(libusb::Error::Io);
+            }
+            trace!("Output report {}/{} ok", i + 1, num_chunks);
+        }
+        debug!("Uploaded slot {} ({} output reports)", slot, num_chunks);
+
+        Ok(())
+    }
+
+    fn set_custom(&self, slot: u8, brightness: u8) -> Result<(), libusb::Error> {
+        let header = Header::custom(slot, brightness).map_err(|e| {
+            error!("{}", e);
+            libusb::Error::InvalidParam
+        })?;
+        self.send_header(&header)
+    }
+}
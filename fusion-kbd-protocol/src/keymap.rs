@@ -0,0 +1,248 @@
+//! Mapping between physical key names and their offset in custom configs.
+
+use std::path::Path;
+
+use crate::config::NUM_KEYS;
+
+/// key names and their offset in the custom config, on a US (ANSI) layout
+/// (see example-configs/keys.txt)
+static ANSI: &[(&str, usize)] = &[
+    ("lctrl", 6),
+    ("shift", 7),
+    ("caps", 8),
+    ("tab", 9),
+    ("`", 10),
+    ("esc", 11),
+    ("fn", 12),
+    ("a", 14),
+    ("q", 15),
+    ("1", 16),
+    ("f1", 17),
+    ("win", 18),
+    ("z", 19),
+    ("s", 20),
+    ("w", 21),
+    ("2", 22),
+    ("f2", 23),
+    ("lalt", 24),
+    ("x", 25),
+    ("d", 26),
+    ("e", 27),
+    ("3", 28),
+    ("f3", 29),
+    ("c", 31),
+    ("f", 32),
+    ("r", 33),
+    ("4", 34),
+    ("f4", 35),
+    ("v", 37),
+    ("g", 38),
+    ("t", 39),
+    ("5", 40),
+    ("f5", 41),
+    ("space", 42),
+    ("b", 43),
+    ("h", 44),
+    ("y", 45),
+    ("6", 46),
+    ("f6", 47),
+    ("n", 49),
+    ("j", 50),
+    ("u", 51),
+    ("7", 52),
+    ("f7", 53),
+    ("m", 55),
+    ("k", 56),
+    ("i", 57),
+    ("8", 58),
+    ("f8", 59),
+    ("ralt", 60),
+    (",", 61),
+    ("l", 62),
+    ("o", 63),
+    ("9", 64),
+    ("f9", 65),
+    ("menu", 66),
+    (".", 67),
+    (";", 68),
+    ("p", 69),
+    ("0", 70),
+    ("f10", 71),
+    ("rctrl", 72),
+    ("/", 73),
+    ("'", 74),
+    ("[", 75),
+    ("-", 76),
+    ("f11", 77),
+    ("]", 81),
+    ("=", 82),
+    ("f12", 83),
+    ("left", 84),
+    ("rshift", 85),
+    ("\\", 87),
+    ("pause", 89),
+    ("down", 90),
+    ("up", 91),
+    ("enter", 92),
+    ("backspace", 94),
+    ("del", 95),
+    ("right", 96),
+    ("num1", 97),
+    ("num4", 98),
+    ("num7", 99),
+    ("numlk", 100),
+    ("home", 101),
+    ("num0", 102),
+    ("num2", 103),
+    ("num5", 104),
+    ("num8", 105),
+    ("num/", 106),
+    ("pgup", 107),
+    ("num.", 108),
+    ("num3", 109),
+    ("num6", 110),
+    ("num9", 111),
+    ("num*", 112),
+    ("pgdn", 113),
+    ("numenter", 114),
+    ("num+", 116),
+    ("num-", 118),
+    ("end", 119),
+];
+
+/// ISO layouts have an extra key next to left shift, and a `#` key left of the
+/// (taller) enter key, which swallows the ANSI backslash.
+///
+/// These offsets are inferred from the gaps in the ANSI matrix, and haven't
+/// been checked against a real ISO keyboard yet.
+static ISO_EXTRA: &[(&str, usize)] = &[("iso\\", 13), ("#", 86)];
+static ISO_MISSING: &[&str] = &["\\"];
+
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    /// `"ansi"`, `"iso"`, or `"custom"` for keymaps loaded from a file
+    layout: String,
+    keys: Vec<(String, usize)>,
+}
+
+impl Keymap {
+    pub fn ansi() -> Keymap {
+        Keymap {
+            layout: "ansi".to_string(),
+            keys: ANSI.iter().map(|&(n, k)| (n.to_string(), k)).collect(),
+        }
+    }
+
+    pub fn iso() -> Keymap {
+        let mut keys: Vec<(String, usize)> = ANSI
+            .iter()
+            .filter(|(n, _)| !ISO_MISSING.contains(n))
+            .map(|&(n, k)| (n.to_string(), k))
+            .collect();
+        keys.extend(ISO_EXTRA.iter().map(|&(n, k)| (n.to_string(), k)));
+        keys.sort_by_key(|&(_, k)| k);
+        Keymap {
+            layout: "iso".to_string(),
+            keys,
+        }
+    }
+
+    /// Parses a keymap for other keyboard variants. The file has an optional
+    /// `base` layout to start from (`"ansi"` or `"iso"`, default: none), and a
+    /// `[keys]` table mapping names to offsets, which override the base:
+    ///
+    /// ```toml
+    /// base = "ansi"
+    ///
+    /// [keys]
+    /// esc = 11
+    /// "non-us-backslash" = 13
+    /// ```
+    pub fn from_toml(text: &str) -> Result<Keymap, String> {
+        let table: toml::Table = text.parse().map_err(|e: toml::de::Error| e.to_string())?;
+
+        let mut keymap = match table.get("base").map(|b| b.as_str()) {
+            None => Keymap {
+                layout: "custom".to_string(),
+                keys: Vec::new(),
+            },
+            Some(Some("ansi")) => Keymap::ansi(),
+            Some(Some("iso")) => Keymap::iso(),
+            Some(_) => return Err("`base` must be \"ansi\" or \"iso\"".to_string()),
+        };
+
+        let keys = match table.get("keys") {
+            Some(toml::Value::Table(keys)) => keys,
+            Some(_) => return Err("`keys` must be a table".to_string()),
+            None => return Ok(keymap),
+        };
+
+        for (name, key) in keys.iter() {
+            let key = match key.as_integer() {
+                Some(key) if key >= 0 && (key as usize) < NUM_KEYS => key as usize,
+                _ => {
+                    return Err(format!(
+                        "offset for '{}' must be a number from 0 - {}",
+                        name,
+                        NUM_KEYS - 1
+                    ))
+                }
+            };
+            keymap.keys.retain(|(n, k)| n != name && *k != key);
+            keymap.keys.push((name.clone(), key));
+        }
+        keymap.keys.sort_by_key(|&(_, k)| k);
+        keymap.layout = "custom".to_string();
+
+        Ok(keymap)
+    }
+
+    /// `"ansi"`, `"iso"`, or a path to a keymap TOML
+    pub fn load(name: &str) -> Result<Keymap, String> {
+        match name {
+            "ansi" => Ok(Keymap::ansi()),
+            "iso" => Ok(Keymap::iso()),
+            path => {
+                let text = std::fs::read_to_string(Path::new(path))
+                    .map_err(|e| format!("couldn't open '{}': {}", path, e))?;
+                Keymap::from_toml(&text)
+            }
+        }
+    }
+
+    pub fn layout(&self) -> &str {
+        &self.layout
+    }
+
+    /// looks up a key by name. Raw offsets (e.g: `"13"`) are accepted as well,
+    /// so unnamed keys can still be addressed.
+    pub fn index(&self, name: &str) -> Option<usize> {
+        if let Some(&(_, key)) = self.keys.iter().find(|(n, _)| n == name) {
+            return Some(key);
+        }
+
+        match name.parse::<usize>() {
+            Ok(key) if key < NUM_KEYS => Some(key),
+            _ => None,
+        }
+    }
+
+    /// name of the key at `key`, if it has one
+    pub fn name(&self, key: usize) -> Option<&str> {
+        self.keys
+            .iter()
+            .find(|(_, k)| *k == key)
+            .map(|(n, _)| n.as_str())
+    }
+
+    /// all named keys, in config order
+    pub fn keys(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.keys.iter().map(|(n, k)| (n.as_str(), *k))
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        Keymap::ansi()
+    }
+}
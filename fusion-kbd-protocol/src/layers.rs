@@ -0,0 +1,157 @@
+//! Compositing layers of lighting over a base config, so things drawn on top
+//! (indicators, overlays, reactive effects) don't have to overwrite it.
+//!
+//! Each layer is a full config, a per-key `Mask` saying how much of it shows
+//! through (0 - 1), and a `Blend` mode saying how it combines with what's
+//! under it. Layers are applied bottom to top. Like `effects::blend`, all the
+//! math happens in linear light.
+
+use std::fmt;
+use std::str::FromStr;
+
+use super::config::{CustomConfig, Rgb, NUM_KEYS};
+use super::effects::{from_linear, to_linear};
+
+pub const BLENDS: &[&str] = &["normal", "add", "multiply", "screen"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Blend {
+    /// the layer replaces what's under it
+    Normal,
+    /// the layer's light adds to what's under it
+    Add,
+    /// the layer tints what's under it (white leaves it alone)
+    Multiply,
+    /// like `Add`, but saturating gently rather than clipping
+    Screen,
+}
+
+impl Blend {
+    /// combines one linear light channel of `layer` with `base`
+    fn apply(self, base: f32, layer: f32) -> f32 {
+        match self {
+            Blend::Normal => layer,
+            Blend::Add => base + layer,
+            Blend::Multiply => base * layer,
+            Blend::Screen => 1.0 - (1.0 - base) * (1.0 - layer),
+        }
+    }
+}
+
+impl FromStr for Blend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Blend, String> {
+        match s {
+            "normal" => Ok(Blend::Normal),
+            "add" => Ok(Blend::Add),
+            "multiply" => Ok(Blend::Multiply),
+            "screen" => Ok(Blend::Screen),
+            _ => Err(format!(
+                "unknown blend mode '{}' (expected one of: {})",
+                s,
+                BLENDS.join(", ")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Blend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Blend::Normal => "normal",
+            Blend::Add => "add",
+            Blend::Multiply => "multiply",
+            Blend::Screen => "screen",
+        };
+        f.write_str(name)
+    }
+}
+
+/// how much of a layer shows through (0 - 1), per key
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mask([f32; NUM_KEYS]);
+
+impl Mask {
+    /// every key fully covered
+    pub fn all() -> Mask {
+        Mask([1.0; NUM_KEYS])
+    }
+
+    /// nothing covered
+    pub fn none() -> Mask {
+        Mask([0.0; NUM_KEYS])
+    }
+
+    /// only `keys` covered
+    pub fn keys(keys: &[usize]) -> Mask {
+        let mut mask = Mask::none();
+        for &key in keys {
+            mask.set(key, 1.0);
+        }
+        mask
+    }
+
+    pub fn get(&self, key: usize) -> f32 {
+        self.0[key]
+    }
+
+    pub fn set(&mut self, key: usize, opacity: f32) {
+        self.0[key] = opacity.clamp(0.0, 1.0);
+    }
+
+    /// whether the layer doesn't show at all
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|&o| o == 0.0)
+    }
+}
+
+#[derive(Clone)]
+pub struct Layer {
+    pub config: CustomConfig,
+    pub mask: Mask,
+    pub blend: Blend,
+}
+
+impl Layer {
+    /// `config` over every key, replacing what's under it
+    pub fn new(config: CustomConfig) -> Layer {
+        Layer {
+            config,
+            mask: Mask::all(),
+            blend: Blend::Normal,
+        }
+    }
+
+    /// the color `key` ends up, with this layer over `base`
+    fn over(&self, key: usize, base: Rgb) -> Rgb {
+        let opacity = self.mask.get(key);
+        if opacity == 0.0 {
+            return base;
+        }
+        let top = self.config.get_key(key);
+        let mix = |b: u8, l: u8| {
+            let b = to_linear(b);
+            let blended = self.blend.apply(b, to_linear(l)).clamp(0.0, 1.0);
+            from_linear(b * (1.0 - opacity) + blended * opacity)
+        };
+        Rgb(mix(base.0, top.0), mix(base.1, top.1), mix(base.2, top.2))
+    }
+}
+
+/// `layers` applied over `base`, bottom (first) to top (last)
+pub fn composite<'a>(
+    base: &CustomConfig,
+    layers: impl IntoIterator<Item = &'a Layer>,
+) -> CustomConfig {
+    let mut cfg = base.clone();
+    for layer in layers {
+        if layer.mask.is_empty() {
+            continue;
+        }
+        for key in 0..NUM_KEYS {
+            cfg.set_key(key, layer.over(key, cfg.get_key(key)));
+        }
+    }
+    cfg
+}
@@ -0,0 +1,51 @@
+//! Fusion RGB keyboard support.
+//!
+//! - `protocol`: wire format (headers, checksums, constants, how uploads are
+//!   split into transfers), with no USB dependency
+//! - `device`: talking to the keyboard over libusb
+//! - `hid`: talking to it over hidapi instead (`hid` feature)
+//! - `worker`: an async front for a keyboard, which runs on a thread of its own
+//! - `capture`: reading pcapng / pcap captures of USB traffic
+//! - `models`: known keyboard models, and how they differ
+//! - `colors`: CSS color names
+//! - `config`: custom lighting configs, and the file formats they're stored in
+//! - `correction`: white point / color correction applied before upload
+//! - `keymap`: key names <-> config offsets, for various layouts
+//! - `zones`: predefined groups of keys (wasd, numpad, ...)
+//! - `state`: description of what the keyboard is showing
+//! - `effects`: generators for custom lighting configs, and animation playback
+//! - `layers`: compositing lighting layers (with masks / blend modes) over a base
+//! - `preview`: renders custom configs to RGBA images, or for the terminal
+//! - `transform`: variations of configs (mirrored, shifted, hue rotated, ...)
+//! - `themes`: built-in themes (color schemes, pride flags, ...) for any layout
+//!
+//! Everything that touches the USB stack sits behind the (default) `usb`
+//! feature. Without it, the crate builds for `wasm32-unknown-unknown`, so
+//! e.g: a browser-based layout editor can reuse the same encoding logic.
+
+pub mod capture;
+pub mod colors;
+pub mod config;
+pub mod correction;
+#[cfg(feature = "usb")]
+pub mod device;
+pub mod effects;
+#[cfg(feature = "hid")]
+pub mod hid;
+pub mod keymap;
+pub mod layers;
+pub mod models;
+pub mod preview;
+pub mod protocol;
+pub mod state;
+pub mod themes;
+pub mod transform;
+#[cfg(feature = "usb")]
+pub mod worker;
+pub mod zones;
+
+pub use config::{key_position, CustomConfig, Rgb, MATRIX_COLS, NUM_KEYS};
+#[cfg(feature = "usb")]
+pub use device::{Capabilities, FusionKBD, Keyboard};
+pub use keymap::Keymap;
+pub use protocol::{Color, Preset};
@@ -0,0 +1,96 @@
+//! Known keyboard models: how they're identified, and where they differ (the
+//! number of LEDs, how custom configs are split into packets, and the layout
+//! they usually ship with).
+//!
+//! Only the AERO 15X's keyboard has been confirmed so far, so it's the only
+//! entry. Other Aero / Aorus keyboards can still be opened with `--pid`, and
+//! are treated like `FALLBACK` (with a warning). Once one's been checked
+//! (e.g: with `devices`, `custom get` and a comparison against a capture),
+//! add an entry for it here, with its own LED count and packet layout.
+//!
+//! Which model a laptop has can also be told from its DMI product name (see
+//! `detect`), e.g: to pick its layout before the keyboard's been opened.
+
+use std::fs;
+
+use crate::config::{MATRIX_COLS, MATRIX_ROWS, NUM_KEYS};
+use crate::protocol::{CHUNK_SIZE, NUM_CHUNKS};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Model {
+    pub name: &'static str,
+    /// USB product id (the vendor id is always `device::VID`)
+    pub pid: u16,
+    pub num_keys: usize,
+    pub matrix_rows: usize,
+    pub matrix_cols: usize,
+    /// custom configs are sent (and read back) as this many packets...
+    pub num_chunks: usize,
+    /// ...of this many bytes each
+    pub chunk_size: usize,
+    /// the keymap it usually ships with (see `Keymap::load`)
+    pub layout: &'static str,
+    /// DMI product names of the laptops it's in start with one of these
+    /// (lowercased, without spaces or dashes)
+    pub dmi: &'static [&'static str],
+}
+
+impl Model {
+    /// bytes in one of its custom configs
+    pub fn config_len(&self) -> usize {
+        self.num_chunks * self.chunk_size
+    }
+}
+
+pub const AERO_15X: Model = Model {
+    name: "aero-15x",
+    pid: 0x7a39,
+    num_keys: NUM_KEYS,
+    matrix_rows: MATRIX_ROWS,
+    matrix_cols: MATRIX_COLS,
+    num_chunks: NUM_CHUNKS,
+    chunk_size: CHUNK_SIZE,
+    layout: "ansi",
+    dmi: &["aero15x"],
+};
+
+/// every known model
+pub static MODELS: &[Model] = &[AERO_15X];
+
+/// what's assumed of keyboards that aren't in `MODELS` (e.g: ones found with
+/// `--pid`)
+pub const FALLBACK: Model = AERO_15X;
+
+pub fn by_pid(pid: u16) -> Option<&'static Model> {
+    MODELS.iter().find(|m| m.pid == pid)
+}
+
+pub fn by_name(name: &str) -> Option<&'static Model> {
+    MODELS.iter().find(|m| m.name == name)
+}
+
+/// names of all the known models
+pub fn names() -> impl Iterator<Item = &'static str> {
+    MODELS.iter().map(|m| m.name)
+}
+
+/// where Linux puts the DMI product name
+pub static DMI_PRODUCT_NAME: &str = "/sys/class/dmi/id/product_name";
+
+/// the model in a laptop with this DMI `product_name` (e.g: "AERO 15X v8")
+pub fn by_product_name(product_name: &str) -> Option<&'static Model> {
+    let normalized: String = product_name
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .flat_map(char::to_lowercase)
+        .collect();
+    MODELS
+        .iter()
+        .find(|m| m.dmi.iter().any(|prefix| normalized.starts_with(prefix)))
+}
+
+/// The model in this laptop, going by its DMI product name. `None` if it isn't
+/// a known one (or the name can't be read).
+pub fn detect() -> Option<&'static Model> {
+    by_product_name(&fs::read_to_string(DMI_PRODUCT_NAME).ok()?)
+}
@@ -0,0 +1,127 @@
+use std::io::Write;
+use std::time::Duration;
+
+use crate::config::{key_position, CustomConfig, Rgb, MATRIX_COLS, MATRIX_ROWS, NUM_KEYS};
+use crate::keymap::Keymap;
+
+/// outline drawn around each key, so unlit keys are still visible
+const OUTLINE: Rgb = Rgb(0x40, 0x40, 0x40);
+
+/// An RGBA image of a custom config
+pub struct Preview {
+    pub width: usize,
+    pub height: usize,
+    /// RGBA, row-major, 4 bytes per pixel
+    pub pixels: Vec<u8>,
+}
+
+/// Draws a config as a grid of `key_size` pixel squares laid out like the
+/// lighting matrix. Only keys with a name in `keymap` are drawn, everything
+/// else is left transparent.
+pub fn render(cfg: &CustomConfig, keymap: &Keymap, key_size: usize) -> Preview {
+    let key_size = key_size.max(3);
+    let gap = (key_size / 8).max(1);
+    let pitch = key_size + gap;
+
+    let width = MATRIX_COLS * pitch + gap;
+    let height = MATRIX_ROWS * pitch + gap;
+    let mut pixels = vec![0; width * height * 4];
+
+    for key in (0..NUM_KEYS).filter(|&k| keymap.name(k).is_some()) {
+        let (row, col) = key_position(key);
+        let (x0, y0) = (gap + col * pitch, gap + row * pitch);
+        let color = cfg.get_key(key);
+
+        for y in 0..key_size {
+            for x in 0..key_size {
+                let edge = x == 0 || y == 0 || x == key_size - 1 || y == key_size - 1;
+                let Rgb(r, g, b) = if edge { OUTLINE } else { color };
+
+                let i = ((y0 + y) * width + x0 + x) * 4;
+                pixels[i..i + 4].copy_from_slice(&[r, g, b, 0xff]);
+            }
+        }
+    }
+
+    Preview {
+        width,
+        height,
+        pixels,
+    }
+}
+
+/// A short (at most 3 character) label for `key`, to fit in a terminal cell
+pub fn label(keymap: &Keymap, key: usize) -> String {
+    let name = keymap.name(key).unwrap_or("?");
+    let name = match name.strip_prefix("num") {
+        Some(rest) if !rest.is_empty() => format!("n{}", rest),
+        _ => name.to_string(),
+    };
+    name.chars().take(3).collect()
+}
+
+/// The ANSI escape for a terminal cell lit `color` (needs a truecolor
+/// terminal), with dark text on light colors and light text on dark ones.
+pub fn ansi_style(color: Rgb) -> String {
+    let Rgb(r, g, b) = color;
+    let luma = 0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32;
+    let text = if luma > 128.0 { 30 } else { 97 };
+    format!("\x1b[48;2;{};{};{};{}m", r, g, b, text)
+}
+
+/// Draws a config for the terminal, laid out like `render` does, with each
+/// key a labelled cell in its color (see `ansi_style`). Rows are separated by
+/// blank lines, and every line ends in `newline`.
+pub fn ansi(cfg: &CustomConfig, keymap: &Keymap, newline: &str) -> String {
+    let mut grid = vec![vec![None; MATRIX_COLS]; MATRIX_ROWS];
+    for key in (0..NUM_KEYS).filter(|&k| keymap.name(k).is_some()) {
+        let (row, col) = key_position(key);
+        grid[row][col] = Some(key);
+    }
+
+    let mut out = String::new();
+    for (i, row) in grid.iter().enumerate() {
+        if i > 0 {
+            out.push_str(newline);
+        }
+        let line: String = row
+            .iter()
+            .map(|key| match *key {
+                Some(key) => format!(
+                    "{}{:^3}\x1b[0m ",
+                    ansi_style(cfg.get_key(key)),
+                    label(keymap, key)
+                ),
+                None => "    ".to_string(),
+            })
+            .collect();
+        out.push_str(line.trim_end());
+        out.push_str(newline);
+    }
+    out
+}
+
+/// Encodes previews as a looping animated GIF, each frame shown for its
+/// `Duration` (rounded to the GIF's 10ms resolution). All frames must be the
+/// same size.
+pub fn write_gif<W: Write>(frames: &[(Preview, Duration)], out: W) -> Result<(), String> {
+    let (width, height) = match frames.first() {
+        Some((p, _)) => (p.width as u16, p.height as u16),
+        None => return Err("animation has no frames".to_string()),
+    };
+
+    let mut encoder = gif::Encoder::new(out, width, height, &[]).map_err(|e| e.to_string())?;
+    encoder
+        .set_repeat(gif::Repeat::Infinite)
+        .map_err(|e| e.to_string())?;
+
+    for (preview, delay) in frames {
+        let mut pixels = preview.pixels.clone();
+        let mut frame = gif::Frame::from_rgba_speed(width, height, &mut pixels, 10);
+        frame.delay = ((delay.as_millis() + 5) / 10).max(1) as u16;
+        frame.dispose = gif::DisposalMethod::Background;
+        encoder.write_frame(&frame).map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,432 @@
+use std::fmt;
+use std::str::FromStr;
+
+use strum::IntoEnumIterator;
+use strum_macros::*;
+
+use crate::config::Rgb;
+use crate::models::Model;
+use crate::state::{Lighting, State};
+
+#[derive(Display, EnumIter, EnumString, PartialEq, Clone, Copy, Debug)]
+#[strum(serialize_all = "snake_case")]
+pub enum Preset {
+    Static = 0x01,
+    Breathing = 0x02,
+    Wave = 0x03,
+    FadeOnKeypress = 0x04,
+    Marquee = 0x05,
+    Ripple = 0x06,
+    FlashOnKeypress = 0x07,
+    Neon = 0x08,
+    RainbowMarquee = 0x09,
+    Raindrop = 0x0a,
+    CircleMarquee = 0x0b,
+    Hedge = 0x0c,
+    Rotate = 0x0d,
+}
+
+#[derive(Display, EnumIter, EnumString, PartialEq, Clone, Copy, Debug)]
+#[strum(serialize_all = "snake_case")]
+pub enum Color {
+    #[strum(serialize = "rand", serialize = "rainbow", serialize = "cycle")]
+    Rand = 0x00,
+    Red = 0x01,
+    Green = 0x02,
+    Yellow = 0x03,
+    Blue = 0x04,
+    Orange = 0x05,
+    Purple = 0x06,
+    White = 0x07,
+}
+
+/// Which way a directional preset goes (see `Preset::takes_direction`).
+/// Experimental: the values are the ones similar ITE firmware uses, which
+/// hasn't been confirmed for every model. Without one, presets go the
+/// firmware's default way.
+#[derive(Display, EnumIter, EnumString, PartialEq, Clone, Copy, Debug)]
+#[strum(serialize_all = "snake_case")]
+pub enum Direction {
+    Right = 0x01,
+    Left = 0x02,
+    Up = 0x03,
+    Down = 0x04,
+}
+
+impl Preset {
+    /// whether it can be given a `Direction`
+    pub fn takes_direction(self) -> bool {
+        self == Preset::Wave || self == Preset::Marquee || self == Preset::Raindrop
+    }
+
+    /// whether its speed changes anything (`Static` doesn't move)
+    pub fn takes_speed(self) -> bool {
+        self != Preset::Static
+    }
+
+    /// whether it's shown in the color it's given (`Wave` and `Neon` bring
+    /// their own colors)
+    pub fn takes_color(self) -> bool {
+        self != Preset::Wave && self != Preset::Neon
+    }
+
+    /// What's meaningless about giving this preset `speed` / `color` (`None`
+    /// for ones that weren't given), e.g: a speed for `static`.
+    pub fn unused_params(self, speed: Option<u8>, color: Option<Color>) -> Vec<String> {
+        let mut unused = Vec::new();
+        if speed.is_some() && !self.takes_speed() {
+            unused.push(format!("`{}` doesn't take a speed (it doesn't move)", self));
+        }
+        if color.is_some() && !self.takes_color() {
+            unused.push(format!(
+                "`{}` doesn't take a color (it brings its own)",
+                self
+            ));
+        }
+        unused
+    }
+}
+
+impl Color {
+    /// Approximate RGB value of a preset color, as it shows up on the LEDs.
+    /// `Rand` cycles through colors, so it doesn't have one.
+    pub fn to_rgb(self) -> Option<Rgb> {
+        let rgb = match self {
+            Color::Rand => return None,
+            Color::Red => Rgb(0xff, 0x00, 0x00),
+            Color::Green => Rgb(0x00, 0xff, 0x00),
+            Color::Yellow => Rgb(0xff, 0xff, 0x00),
+            Color::Blue => Rgb(0x00, 0x00, 0xff),
+            Color::Orange => Rgb(0xff, 0x80, 0x00),
+            Color::Purple => Rgb(0x80, 0x00, 0xff),
+            Color::White => Rgb(0xff, 0xff, 0xff),
+        };
+        Some(rgb)
+    }
+
+    /// The preset color closest in hue to `rgb`. Washed-out colors (grays,
+    /// pastels, ...) map to `White`, since presets can't be desaturated.
+    pub fn nearest(rgb: Rgb) -> Color {
+        // (hue in degrees, saturation from 0 - 1)
+        let hsv = |Rgb(r, g, b): Rgb| {
+            let (r, g, b) = (r as f32, g as f32, b as f32);
+            let max = r.max(g).max(b);
+            let delta = max - r.min(g).min(b);
+            if delta == 0.0 {
+                return (0.0, 0.0);
+            }
+            let hue = if max == r {
+                (g - b) / delta
+            } else if max == g {
+                (b - r) / delta + 2.0
+            } else {
+                (r - g) / delta + 4.0
+            };
+            ((hue * 60.0).rem_euclid(360.0), delta / max)
+        };
+
+        let (hue, saturation) = hsv(rgb);
+        if saturation < 0.35 {
+            return Color::White;
+        }
+
+        let distance = |c: &Color| {
+            let d = (hsv(c.to_rgb().unwrap()).0 - hue).abs();
+            d.min(360.0 - d)
+        };
+        Color::iter()
+            .filter(|&c| c != Color::Rand && c != Color::White)
+            .min_by(|a, b| distance(a).partial_cmp(&distance(b)).unwrap())
+            .unwrap()
+    }
+
+    /// Parses a preset color name, or maps any other color (see `Rgb`'s
+    /// `FromStr`) to the nearest preset color.
+    pub fn lookup(s: &str) -> Result<Color, String> {
+        match Color::from_str(s) {
+            Ok(c) => Ok(c),
+            Err(_) => Rgb::from_str(s).map(Color::nearest),
+        }
+    }
+}
+
+/// number of custom lighting slots
+pub const NUM_SLOTS: u8 = 5;
+/// brightness ranges from 0 - 50
+pub const MAX_BRIGHTNESS: u8 = 50;
+/// preset speed ranges from 0 - 10
+pub const MAX_SPEED: u8 = 10;
+/// the AERO 15X's custom configs are sent as 8 interrupt transfers of 64
+/// bytes (other models' are described by their `Model`)
+pub const CHUNK_SIZE: usize = 64;
+pub const NUM_CHUNKS: usize = 8;
+/// custom slots are selected as modes 0x33..0x37
+pub const CUSTOM_MODE_BASE: u8 = 0x33;
+/// each key takes 4 bytes of a custom config: [?, R, G, B]
+pub const BYTES_PER_KEY: usize = 4;
+
+/// the interface lighting headers are sent to (as `wIndex`)
+pub const LIGHTING_INTERFACE: u16 = 3;
+/// HID class requests headers are sent / acknowledged with
+pub const REQUEST_SET_REPORT: u8 = 0x09;
+pub const REQUEST_GET_REPORT: u8 = 0x01;
+/// `wValue` of both: feature report, id 0
+pub const REPORT_VALUE: u16 = 0x0300;
+/// interrupt endpoints custom configs are written to, and read back from
+pub const CONFIG_OUT_ENDPOINT: u8 = 0x06;
+pub const CONFIG_IN_ENDPOINT: u8 = 0x85;
+/// where key presses come in
+pub const KEY_ENDPOINT: u8 = 0x81;
+
+/// why `slot` isn't a custom slot, if it isn't one
+pub fn check_slot(slot: u8) -> Result<(), String> {
+    if slot < NUM_SLOTS {
+        return Ok(());
+    }
+    Err(format!(
+        "there's no custom slot {} (they go from 0 - {})",
+        slot,
+        NUM_SLOTS - 1
+    ))
+}
+
+/// why `len` bytes isn't a custom config for `model`, if it isn't one
+pub fn check_config_len(model: &Model, len: usize) -> Result<(), String> {
+    if len == model.config_len() {
+        return Ok(());
+    }
+    Err(format!(
+        "Custom configs for the {} are {} bytes (not {})",
+        model.name,
+        model.config_len(),
+        len
+    ))
+}
+
+/// where `key`'s 4 bytes start in a custom config
+pub fn key_offset(key: usize) -> usize {
+    key * BYTES_PER_KEY
+}
+
+#[repr(C, packed)]
+pub struct Header {
+    kind: u8,         // Kind of the control transfer
+    reserved: u8,     // ??
+    mode: u8,         // mode or config slot
+    speed_length: u8, // Speed or length of usb packets to follow
+    brightness: u8,   // Brightness. 0 to 50
+    color: u8,        // Predefined color
+    direction: u8,    // Direction of directional presets, or 0
+    checksum: u8,
+}
+
+impl Header {
+    /// creates valid header (computes checksum)
+    pub fn new(kind: u8, mode: u8, speed_length: u8, brightness: u8, color: u8) -> Header {
+        Header::with_direction(kind, mode, speed_length, brightness, color, 0)
+    }
+
+    fn with_direction(
+        kind: u8,
+        mode: u8,
+        speed_length: u8,
+        brightness: u8,
+        color: u8,
+        direction: u8,
+    ) -> Header {
+        let mut header = Header {
+            kind,
+            mode,
+            speed_length,
+            brightness,
+            color,
+            reserved: 0,
+            direction,
+            checksum: 0,
+        };
+
+        // calculate checksum byte
+        header.checksum = !(header
+            .as_bytes()
+            .iter()
+            .take(7)
+            .fold(0, |sum, x| sum.wrapping_add(*x)));
+
+        header
+    }
+
+    /// switches to a built-in preset
+    pub fn preset(preset: Preset, speed: u8, brightness: u8, color: Color) -> Header {
+        Header::new(KIND_PRESET, preset as u8, speed, brightness, color as u8)
+    }
+
+    /// switches to a built-in preset, going in `direction` (see `Direction`)
+    pub fn preset_direction(
+        preset: Preset,
+        speed: u8,
+        brightness: u8,
+        color: Color,
+        direction: Direction,
+    ) -> Header {
+        Header::with_direction(
+            KIND_PRESET,
+            preset as u8,
+            speed,
+            brightness,
+            color as u8,
+            direction as u8,
+        )
+    }
+
+    /// switches to custom `slot`. Errors if there's no such slot.
+    pub fn custom(slot: u8, brightness: u8) -> Result<Header, String> {
+        check_slot(slot)?;
+        Ok(Header::new(
+            KIND_PRESET,
+            CUSTOM_MODE_BASE + slot,
+            0,
+            brightness,
+            0,
+        ))
+    }
+
+    /// announces a custom config for `slot`, in `num_chunks` interrupt
+    /// transfers
+    pub fn custom_config(slot: u8, num_chunks: usize) -> Header {
+        Header::new(KIND_CUSTOM_CONFIG, slot, num_chunks as u8, 0, 0)
+    }
+
+    /// asks for `slot`'s custom config back
+    pub fn read_config(slot: u8) -> Header {
+        Header::new(KIND_READ_CONFIG, slot, 0, 0, 0)
+    }
+
+    /// the header in `bytes`, if they are one (with a valid checksum)
+    pub fn from_bytes(bytes: &[u8]) -> Option<Header> {
+        if bytes.len() != std::mem::size_of::<Self>() {
+            return None;
+        }
+        let header =
+            Header::with_direction(bytes[0], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6]);
+        if &header.as_bytes()[..] == bytes {
+            Some(header)
+        } else {
+            None
+        }
+    }
+
+    /// what the keyboard shows after this header, if it switches lighting
+    pub fn state(&self) -> Option<State> {
+        if self.kind != KIND_PRESET {
+            return None;
+        }
+        let mode = self.mode;
+        let lighting = if (CUSTOM_MODE_BASE..CUSTOM_MODE_BASE + NUM_SLOTS).contains(&mode) {
+            Lighting::Custom {
+                slot: mode - CUSTOM_MODE_BASE,
+            }
+        } else {
+            Lighting::Preset {
+                preset: Preset::iter().find(|&p| p as u8 == mode)?,
+                color: Color::iter().find(|&c| c as u8 == self.color)?,
+                speed: self.speed_length,
+            }
+        };
+        Some(State {
+            lighting,
+            brightness: self.brightness,
+        })
+    }
+
+    /// the slot, and number of interrupt transfers to follow, if this
+    /// announces a custom config
+    pub fn custom_config_slot(&self) -> Option<(u8, usize)> {
+        if self.kind == KIND_CUSTOM_CONFIG && self.mode < NUM_SLOTS {
+            Some((self.mode, self.speed_length as usize))
+        } else {
+            None
+        }
+    }
+
+    /// used when sending over-the-wire with libusb
+    pub fn as_bytes(&self) -> &[u8; std::mem::size_of::<Self>()] {
+        unsafe { &*(self as *const Header as *const [u8; 8]) }
+    }
+}
+
+/// Everything sent to upload a custom config: the header announcing it, then
+/// the config split into the interrupt transfers that follow.
+pub struct Upload {
+    pub header: Header,
+    pub chunks: Vec<Vec<u8>>,
+}
+
+impl Upload {
+    /// The transfers uploading `data` to `slot` on a `model` keyboard, or why
+    /// it can't be (the wrong size, or a slot that doesn't exist).
+    pub fn new(slot: u8, data: &[u8], model: &Model) -> Result<Upload, String> {
+        check_slot(slot)?;
+        check_config_len(model, data.len())?;
+        Ok(Upload {
+            header: Header::custom_config(slot, model.num_chunks),
+            chunks: data.chunks(model.chunk_size).map(<[u8]>::to_vec).collect(),
+        })
+    }
+}
+
+impl fmt::Display for Header {
+    /// what the header asks for, e.g: "preset static, speed 0, brightness 16,
+    /// color white"
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (kind, mode, speed_length, brightness, color, direction) = (
+            self.kind,
+            self.mode,
+            self.speed_length,
+            self.brightness,
+            self.color,
+            self.direction,
+        );
+        if kind == KIND_PRESET {
+            if (CUSTOM_MODE_BASE..CUSTOM_MODE_BASE + NUM_SLOTS).contains(&mode) {
+                return write!(
+                    f,
+                    "custom slot {}, brightness {}",
+                    mode - CUSTOM_MODE_BASE,
+                    brightness
+                );
+            }
+            let preset = Preset::iter()
+                .find(|&p| p as u8 == mode)
+                .map_or_else(|| format!("{:#04x}", mode), |p| p.to_string());
+            let color = Color::iter()
+                .find(|&c| c as u8 == color)
+                .map_or_else(|| format!("{:#04x}", color), |c| c.to_string());
+            write!(
+                f,
+                "preset {}, speed {}, brightness {}, color {}",
+                preset, speed_length, brightness, color
+            )?;
+            if direction != 0 {
+                let direction = Direction::iter()
+                    .find(|&d| d as u8 == direction)
+                    .map_or_else(|| format!("{:#04x}", direction), |d| d.to_string());
+                write!(f, ", direction {}", direction)?;
+            }
+            Ok(())
+        } else if kind == KIND_CUSTOM_CONFIG {
+            write!(
+                f,
+                "upload to custom slot {}, {} packets",
+                mode, speed_length
+            )
+        } else if kind == KIND_READ_CONFIG {
+            write!(f, "read back custom slot {}", mode)
+        } else {
+            write!(f, "unknown kind {:#04x}", kind)
+        }
+    }
+}
+
+pub static KIND_PRESET: u8 = 0x08;
+pub static KIND_CUSTOM_CONFIG: u8 = 0x12;
+pub static KIND_READ_CONFIG: u8 = 0x92;
@@ -0,0 +1,85 @@
+//! Description of what the keyboard is currently showing.
+
+use std::str::FromStr;
+
+use serde_json::{json, Value};
+
+use crate::protocol::{Color, Preset};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Lighting {
+    Preset {
+        preset: Preset,
+        color: Color,
+        speed: u8,
+    },
+    Custom {
+        slot: u8,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct State {
+    pub lighting: Lighting,
+    pub brightness: u8,
+}
+
+impl State {
+    /// e.g: `{"mode": "custom", "slot": 2, "brightness": 16}`
+    pub fn to_json(&self) -> Value {
+        match self.lighting {
+            Lighting::Preset {
+                preset,
+                color,
+                speed,
+            } => json!({
+                "mode": "preset",
+                "preset": preset.to_string(),
+                "color": color.to_string(),
+                "speed": speed,
+                "brightness": self.brightness,
+            }),
+            Lighting::Custom { slot } => json!({
+                "mode": "custom",
+                "slot": slot,
+                "brightness": self.brightness,
+            }),
+        }
+    }
+
+    pub fn from_json(value: &Value) -> Result<State, String> {
+        let field = |name: &str| -> Result<&Value, String> {
+            value
+                .get(name)
+                .ok_or_else(|| format!("missing field '{}'", name))
+        };
+        let number = |name: &str| -> Result<u8, String> {
+            match field(name)?.as_u64() {
+                Some(n) if n <= 0xff => Ok(n as u8),
+                _ => Err(format!("'{}' must be a number", name)),
+            }
+        };
+        let string = |name: &str| -> Result<&str, String> {
+            field(name)?
+                .as_str()
+                .ok_or_else(|| format!("'{}' must be a string", name))
+        };
+
+        let lighting = match string("mode")? {
+            "preset" => Lighting::Preset {
+                preset: Preset::from_str(string("preset")?).map_err(|e| e.to_string())?,
+                color: Color::from_str(string("color")?).map_err(|e| e.to_string())?,
+                speed: number("speed")?,
+            },
+            "custom" => Lighting::Custom {
+                slot: number("slot")?,
+            },
+            mode => return Err(format!("unknown mode '{}'", mode)),
+        };
+
+        Ok(State {
+            lighting,
+            brightness: number("brightness")?,
+        })
+    }
+}
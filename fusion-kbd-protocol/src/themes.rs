@@ -0,0 +1,236 @@
+//! Built-in themes: color schemes, pride flags and game palettes, generated
+//! for whatever keymap is in use (from its zones, see `zones`), rather than
+//! stored as configs for one particular layout.
+
+use crate::config::{key_position, CustomConfig, Rgb, MATRIX_ROWS};
+use crate::keymap::Keymap;
+use crate::zones;
+
+enum Style {
+    /// every key `base`, then each zone (or key, by name) painted over it,
+    /// in order
+    Zones {
+        base: Rgb,
+        zones: &'static [(&'static str, Rgb)],
+    },
+    /// horizontal stripes, top to bottom, stretched over the rows
+    Stripes(&'static [Rgb]),
+}
+
+static THEMES: &[(&str, Style)] = &[
+    // color schemes
+    (
+        "nord",
+        Style::Zones {
+            base: Rgb(0x81, 0xa1, 0xc1),
+            zones: &[
+                ("function", Rgb(0x88, 0xc0, 0xd0)),
+                ("modifiers", Rgb(0x5e, 0x81, 0xac)),
+                ("wasd", Rgb(0xeb, 0xcb, 0x8b)),
+                ("arrows", Rgb(0xeb, 0xcb, 0x8b)),
+                ("esc", Rgb(0xbf, 0x61, 0x6a)),
+            ],
+        },
+    ),
+    (
+        "dracula",
+        Style::Zones {
+            base: Rgb(0xbd, 0x93, 0xf9),
+            zones: &[
+                ("function", Rgb(0xff, 0x79, 0xc6)),
+                ("modifiers", Rgb(0x62, 0x72, 0xa4)),
+                ("wasd", Rgb(0x50, 0xfa, 0x7b)),
+                ("arrows", Rgb(0x8b, 0xe9, 0xfd)),
+                ("esc", Rgb(0xff, 0x55, 0x55)),
+            ],
+        },
+    ),
+    (
+        "gruvbox",
+        Style::Zones {
+            base: Rgb(0xd7, 0x99, 0x21),
+            zones: &[
+                ("function", Rgb(0xfe, 0x80, 0x19)),
+                ("modifiers", Rgb(0x45, 0x85, 0x88)),
+                ("wasd", Rgb(0xb8, 0xbb, 0x26)),
+                ("arrows", Rgb(0x83, 0xa5, 0x98)),
+                ("esc", Rgb(0xfb, 0x49, 0x34)),
+            ],
+        },
+    ),
+    (
+        "solarized",
+        Style::Zones {
+            base: Rgb(0x26, 0x8b, 0xd2),
+            zones: &[
+                ("function", Rgb(0x2a, 0xa1, 0x98)),
+                ("modifiers", Rgb(0x6c, 0x71, 0xc4)),
+                ("wasd", Rgb(0xb5, 0x89, 0x00)),
+                ("arrows", Rgb(0x85, 0x99, 0x00)),
+                ("esc", Rgb(0xdc, 0x32, 0x2f)),
+            ],
+        },
+    ),
+    (
+        "catppuccin",
+        Style::Zones {
+            base: Rgb(0xcb, 0xa6, 0xf7),
+            zones: &[
+                ("function", Rgb(0x89, 0xb4, 0xfa)),
+                ("modifiers", Rgb(0xf5, 0xc2, 0xe7)),
+                ("wasd", Rgb(0xa6, 0xe3, 0xa1)),
+                ("arrows", Rgb(0x94, 0xe2, 0xd5)),
+                ("esc", Rgb(0xf3, 0x8b, 0xa8)),
+            ],
+        },
+    ),
+    // pride flags
+    (
+        "pride",
+        Style::Stripes(&[
+            Rgb(0xe4, 0x03, 0x03),
+            Rgb(0xff, 0x8c, 0x00),
+            Rgb(0xff, 0xed, 0x00),
+            Rgb(0x00, 0x80, 0x26),
+            Rgb(0x00, 0x4d, 0xff),
+            Rgb(0x75, 0x07, 0x87),
+        ]),
+    ),
+    (
+        "trans",
+        Style::Stripes(&[
+            Rgb(0x5b, 0xce, 0xfa),
+            Rgb(0xf5, 0xa9, 0xb8),
+            Rgb(0xff, 0xff, 0xff),
+            Rgb(0xf5, 0xa9, 0xb8),
+            Rgb(0x5b, 0xce, 0xfa),
+        ]),
+    ),
+    (
+        "bi",
+        Style::Stripes(&[
+            Rgb(0xd6, 0x02, 0x70),
+            Rgb(0xd6, 0x02, 0x70),
+            Rgb(0x9b, 0x4f, 0x96),
+            Rgb(0x00, 0x38, 0xa8),
+            Rgb(0x00, 0x38, 0xa8),
+        ]),
+    ),
+    (
+        "pan",
+        Style::Stripes(&[
+            Rgb(0xff, 0x21, 0x8c),
+            Rgb(0xff, 0xd8, 0x00),
+            Rgb(0x21, 0xb1, 0xff),
+        ]),
+    ),
+    (
+        "lesbian",
+        Style::Stripes(&[
+            Rgb(0xd5, 0x2d, 0x00),
+            Rgb(0xff, 0x9a, 0x56),
+            Rgb(0xff, 0xff, 0xff),
+            Rgb(0xd3, 0x62, 0xa4),
+            Rgb(0xa3, 0x02, 0x62),
+        ]),
+    ),
+    (
+        "nonbinary",
+        Style::Stripes(&[
+            Rgb(0xfc, 0xf4, 0x34),
+            Rgb(0xff, 0xff, 0xff),
+            Rgb(0x9c, 0x59, 0xd1),
+            Rgb(0x2c, 0x2c, 0x2c),
+        ]),
+    ),
+    (
+        "ace",
+        Style::Stripes(&[
+            Rgb(0x00, 0x00, 0x00),
+            Rgb(0xa3, 0xa3, 0xa3),
+            Rgb(0xff, 0xff, 0xff),
+            Rgb(0x80, 0x00, 0x80),
+        ]),
+    ),
+    // game palettes
+    (
+        "doom",
+        Style::Zones {
+            base: Rgb(0xb0, 0x00, 0x00),
+            zones: &[
+                ("function", Rgb(0xff, 0x40, 0x00)),
+                ("wasd", Rgb(0xff, 0xae, 0x00)),
+                ("arrows", Rgb(0xff, 0xae, 0x00)),
+            ],
+        },
+    ),
+    (
+        "minecraft",
+        Style::Zones {
+            base: Rgb(0x5a, 0x8f, 0x29),
+            zones: &[
+                ("function", Rgb(0x86, 0x60, 0x43)),
+                ("modifiers", Rgb(0x7f, 0x7f, 0x7f)),
+                ("wasd", Rgb(0x6e, 0xcb, 0xff)),
+                ("arrows", Rgb(0xff, 0xd7, 0x00)),
+            ],
+        },
+    ),
+    (
+        "portal",
+        Style::Zones {
+            base: Rgb(0xff, 0xff, 0xff),
+            zones: &[
+                ("left", Rgb(0x00, 0xa2, 0xff)),
+                ("right", Rgb(0xff, 0x9a, 0x00)),
+            ],
+        },
+    ),
+    (
+        "cyberpunk",
+        Style::Zones {
+            base: Rgb(0xfc, 0xee, 0x0a),
+            zones: &[
+                ("function", Rgb(0x00, 0xf0, 0xff)),
+                ("modifiers", Rgb(0xff, 0x00, 0x3c)),
+                ("wasd", Rgb(0x00, 0xf0, 0xff)),
+                ("arrows", Rgb(0x00, 0xf0, 0xff)),
+            ],
+        },
+    ),
+];
+
+/// names of all the built-in themes
+pub fn names() -> impl Iterator<Item = &'static str> {
+    THEMES.iter().map(|&(name, _)| name)
+}
+
+/// The built-in theme called `name`, drawn on the keys in `keymap`, or `None`
+/// if there's no such theme.
+pub fn theme(name: &str, keymap: &Keymap) -> Option<CustomConfig> {
+    let style = &THEMES.iter().find(|&&(n, _)| n == name)?.1;
+
+    let mut cfg = CustomConfig::new();
+    match *style {
+        Style::Zones { base, zones } => {
+            for (_, key) in keymap.keys() {
+                cfg.set_key(key, base);
+            }
+            for &(zone, color) in zones {
+                let keys = zones::keys(zone, keymap)
+                    .or_else(|| keymap.index(zone).map(|key| vec![key]))
+                    .unwrap_or_default();
+                for key in keys {
+                    cfg.set_key(key, color);
+                }
+            }
+        }
+        Style::Stripes(stripes) => {
+            for (_, key) in keymap.keys() {
+                let row = key_position(key).0;
+                cfg.set_key(key, stripes[row * stripes.len() / MATRIX_ROWS]);
+            }
+        }
+    }
+    Some(cfg)
+}
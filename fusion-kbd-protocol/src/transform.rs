@@ -0,0 +1,111 @@
+//! Operations that make variations of an existing config: mirrored, shifted
+//! along the rows, hue rotated, or with its brightness inverted.
+//!
+//! Only colors are touched. The (unknown) first byte of each key stays where
+//! it was.
+
+use crate::config::{key_position, CustomConfig, Rgb, MATRIX_ROWS, NUM_KEYS};
+use crate::keymap::Keymap;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transform {
+    /// flip each row left to right
+    Mirror,
+    /// move colors this many keys to the right (left, if negative) along
+    /// each row, wrapping around at the ends
+    Shift(isize),
+    /// rotate hues by this many degrees
+    Hue(f32),
+    /// swap light for dark, keeping each key's hue
+    Invert,
+}
+
+/// the named keys on each row of the lighting matrix, left to right
+fn rows(keymap: &Keymap) -> Vec<Vec<usize>> {
+    let mut rows = vec![Vec::new(); MATRIX_ROWS];
+    for key in (0..NUM_KEYS).filter(|&k| keymap.name(k).is_some()) {
+        let (row, col) = key_position(key);
+        rows[row].push((col, key));
+    }
+    rows.into_iter()
+        .map(|mut row| {
+            row.sort();
+            row.into_iter().map(|(_, key)| key).collect()
+        })
+        .collect()
+}
+
+/// hue (0 - 360), saturation and value (0 - 1)
+fn to_hsv(Rgb(r, g, b): Rgb) -> (f32, f32, f32) {
+    let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+    (hue, saturation, max)
+}
+
+fn from_hsv(hue: f32, saturation: f32, value: f32) -> Rgb {
+    let hue = hue.rem_euclid(360.0) / 60.0;
+    let chroma = value * saturation;
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = value - chroma;
+    let channel = |c: f32| ((c + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+    Rgb(channel(r), channel(g), channel(b))
+}
+
+impl Transform {
+    /// `cfg`, transformed. Only keys with a name in `keymap` are touched, so
+    /// e.g: mirroring a row doesn't move colors into the gaps of the matrix.
+    pub fn apply(&self, cfg: &CustomConfig, keymap: &Keymap) -> CustomConfig {
+        let mut out = cfg.clone();
+        match *self {
+            Transform::Mirror => {
+                for row in rows(keymap) {
+                    for (&key, &from) in row.iter().zip(row.iter().rev()) {
+                        out.set_key(key, cfg.get_key(from));
+                    }
+                }
+            }
+            Transform::Shift(by) => {
+                for row in rows(keymap) {
+                    let n = row.len() as isize;
+                    for (i, &key) in row.iter().enumerate() {
+                        let from = row[(i as isize - by).rem_euclid(n) as usize];
+                        out.set_key(key, cfg.get_key(from));
+                    }
+                }
+            }
+            Transform::Hue(degrees) => {
+                for (_, key) in keymap.keys() {
+                    let (hue, saturation, value) = to_hsv(cfg.get_key(key));
+                    out.set_key(key, from_hsv(hue + degrees, saturation, value));
+                }
+            }
+            Transform::Invert => {
+                for (_, key) in keymap.keys() {
+                    let (hue, saturation, value) = to_hsv(cfg.get_key(key));
+                    out.set_key(key, from_hsv(hue, saturation, 1.0 - value));
+                }
+            }
+        }
+        out
+    }
+}
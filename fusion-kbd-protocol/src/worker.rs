@@ -0,0 +1,225 @@
+//! An async front for a keyboard: `AsyncKBD` owns the device on a worker
+//! thread of its own, and its `async fn`s queue operations for it, so async
+//! code (e.g: several tasks in a tokio runtime) can share the keyboard without
+//! blocking an executor thread on libusb.
+//!
+//! It doesn't depend on any particular runtime: replies come back through a
+//! plain `Future` that wakes whoever is polling it. Operations run one at a
+//! time, in the order they were queued. Dropping a future doesn't cancel its
+//! operation, it just discards the result.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use super::device::{Capabilities, FusionKBD, Ids, Keyboard};
+use super::protocol::{Color, Direction, Preset};
+use super::state::State;
+
+type Job = Box<dyn FnOnce(&dyn Keyboard) + Send>;
+
+struct Slot<T> {
+    value: Option<T>,
+    waker: Option<Waker>,
+    /// the worker dropped its end without sending anything
+    closed: bool,
+}
+
+/// What the worker thread sends a result back through
+struct Sender<T>(Arc<Mutex<Slot<T>>>);
+
+impl<T> Sender<T> {
+    fn send(self, value: T) {
+        let mut slot = self.0.lock().unwrap();
+        slot.value = Some(value);
+        if let Some(waker) = slot.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut slot = self.0.lock().unwrap();
+        slot.closed = true;
+        if let Some(waker) = slot.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// A result the worker thread hasn't sent yet. Resolves to `None` if it never
+/// will (i.e: the thread is gone).
+struct Reply<T>(Arc<Mutex<Slot<T>>>);
+
+impl<T> Future for Reply<T> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        let mut slot = self.0.lock().unwrap();
+        if let Some(value) = slot.value.take() {
+            return Poll::Ready(Some(value));
+        }
+        if slot.closed {
+            return Poll::Ready(None);
+        }
+        slot.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+fn reply<T>() -> (Sender<T>, Reply<T>) {
+    let slot = Arc::new(Mutex::new(Slot {
+        value: None,
+        waker: None,
+        closed: false,
+    }));
+    (Sender(slot.clone()), Reply(slot))
+}
+
+/// A keyboard on a worker thread (see the module docs). The thread stops,
+/// releasing the keyboard, once this is dropped and it has finished whatever
+/// was already queued.
+pub struct AsyncKBD {
+    jobs: Option<mpsc::Sender<Job>>,
+    capabilities: Capabilities,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl AsyncKBD {
+    /// opens (and claims) the first keyboard matching `ids`, on a new worker
+    /// thread
+    pub async fn open(ids: Ids) -> Result<AsyncKBD, libusb::Error> {
+        AsyncKBD::spawn(move |run| {
+            let context = libusb::Context::new()?;
+            let kbd = FusionKBD::open(&context, &ids)?;
+            run(&kbd);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Starts a worker thread that calls `make` to get a keyboard (which is
+    /// how to use something other than `FusionKBD::open`, e.g: the hidapi
+    /// backend, or a `FusionKBD` with a packet log). `make` is given the
+    /// thread's main loop, which it should call with the keyboard, and return
+    /// from afterwards. That way the keyboard can borrow from `make`'s stack,
+    /// like a `FusionKBD` does its libusb context.
+    pub async fn spawn<F>(make: F) -> Result<AsyncKBD, libusb::Error>
+    where
+        F: FnOnce(&mut dyn FnMut(&dyn Keyboard)) -> Result<(), libusb::Error> + Send + 'static,
+    {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let (opened, reply) = reply::<Result<Capabilities, libusb::Error>>();
+        let thread = thread::Builder::new()
+            .name("fusion-kbd".to_string())
+            .spawn(move || {
+                let mut opened = Some(opened);
+                let result = make(&mut |kbd: &dyn Keyboard| {
+                    if let Some(opened) = opened.take() {
+                        opened.send(Ok(kbd.capabilities()));
+                    }
+                    for job in queue.iter() {
+                        job(kbd);
+                    }
+                });
+                if let (Err(e), Some(opened)) = (result, opened.take()) {
+                    opened.send(Err(e));
+                }
+            })
+            .map_err(|_| libusb::Error::Other)?;
+
+        match reply.await {
+            Some(Ok(capabilities)) => Ok(AsyncKBD {
+                jobs: Some(jobs),
+                capabilities,
+                thread: Some(thread),
+            }),
+            Some(Err(e)) => Err(e),
+            // `make` returned without opening anything, or panicked
+            None => Err(libusb::Error::Other),
+        }
+    }
+
+    /// Runs `f` with the keyboard, on the worker thread. Errors with
+    /// `NoDevice` if the thread has stopped (e.g: a previous `f` panicked).
+    pub async fn run<T, F>(&self, f: F) -> Result<T, libusb::Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&dyn Keyboard) -> T + Send + 'static,
+    {
+        let (sender, reply) = reply();
+        let job: Job = Box::new(move |kbd| sender.send(f(kbd)));
+        let jobs = self.jobs.as_ref().ok_or(libusb::Error::NoDevice)?;
+        jobs.send(job).map_err(|_| libusb::Error::NoDevice)?;
+        reply.await.ok_or(libusb::Error::NoDevice)
+    }
+
+    /// features supported by the opened model (known up front, so this
+    /// doesn't wait on anything)
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities.clone()
+    }
+
+    /// switch lighting to built-in preset
+    pub async fn set_preset(
+        &self,
+        preset: Preset,
+        speed: u8,
+        brightness: u8,
+        color: Color,
+    ) -> Result<(), libusb::Error> {
+        self.run(move |kbd| kbd.set_preset(preset, speed, brightness, color))
+            .await?
+    }
+
+    /// like `set_preset`, going in `direction` (see
+    /// `Keyboard::set_preset_direction`)
+    pub async fn set_preset_direction(
+        &self,
+        preset: Preset,
+        speed: u8,
+        brightness: u8,
+        color: Color,
+        direction: Direction,
+    ) -> Result<(), libusb::Error> {
+        self.run(move |kbd| kbd.set_preset_direction(preset, speed, brightness, color, direction))
+            .await?
+    }
+
+    /// download the config in selected custom mode slot (as many bytes as
+    /// `capabilities().config_len`)
+    pub async fn download_custom(&self, slot: u8) -> Result<Vec<u8>, libusb::Error> {
+        self.run(move |kbd| kbd.download_config(slot).map(|cfg| cfg.as_bytes().to_vec()))
+            .await?
+    }
+
+    /// upload custom lighting scheme to selected custom mode slot
+    pub async fn upload_custom(&self, slot: u8, data: Vec<u8>) -> Result<(), libusb::Error> {
+        self.run(move |kbd| kbd.upload_custom(slot, &data)).await?
+    }
+
+    /// switch to custom lighting scheme in selected custom mode slot
+    pub async fn set_custom(&self, slot: u8, brightness: u8) -> Result<(), libusb::Error> {
+        self.run(move |kbd| kbd.set_custom(slot, brightness))
+            .await?
+    }
+
+    /// switch to whatever `state` describes (see `Keyboard::set_state`)
+    pub async fn set_state(&self, state: State) -> Result<(), libusb::Error> {
+        self.run(move |kbd| kbd.set_state(&state)).await?
+    }
+}
+
+impl Drop for AsyncKBD {
+    fn drop(&mut self) {
+        // hanging up ends the worker's loop
+        self.jobs = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
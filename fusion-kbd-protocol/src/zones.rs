@@ -0,0 +1,63 @@
+//! Predefined groups of keys, so common regions can be colored in one go.
+
+use crate::config::{key_position, MATRIX_COLS, NUM_KEYS};
+use crate::keymap::Keymap;
+
+/// names of all the predefined zones
+pub static ZONES: &[&str] = &[
+    "all",
+    "function",
+    "wasd",
+    "arrows",
+    "numpad",
+    "modifiers",
+    "left",
+    "center",
+    "right",
+];
+
+static FUNCTION: &[&str] = &[
+    "esc", "f1", "f2", "f3", "f4", "f5", "f6", "f7", "f8", "f9", "f10", "f11", "f12",
+];
+static WASD: &[&str] = &["w", "a", "s", "d"];
+static ARROWS: &[&str] = &["up", "down", "left", "right"];
+static MODIFIERS: &[&str] = &[
+    "lctrl", "rctrl", "shift", "rshift", "lalt", "ralt", "win", "fn", "caps", "menu",
+];
+
+/// Keys in the zone called `name`, or `None` if there's no such zone.
+///
+/// Only keys with a name in `keymap` are included, so the zones follow
+/// whatever layout is in use.
+pub fn keys(name: &str, keymap: &Keymap) -> Option<Vec<usize>> {
+    let by_name =
+        |names: &[&str]| -> Vec<usize> { names.iter().filter_map(|n| keymap.index(n)).collect() };
+    let by_third = |third: usize| -> Vec<usize> {
+        keymap
+            .keys()
+            .map(|(_, k)| k)
+            .filter(|&k| key_position(k).1 * 3 / MATRIX_COLS == third)
+            .collect()
+    };
+
+    let keys = match name {
+        "all" => (0..NUM_KEYS)
+            .filter(|&k| keymap.name(k).is_some())
+            .collect(),
+        "function" => by_name(FUNCTION),
+        "wasd" => by_name(WASD),
+        "arrows" => by_name(ARROWS),
+        "numpad" => keymap
+            .keys()
+            .filter(|(n, _)| n.starts_with("num"))
+            .map(|(_, k)| k)
+            .collect(),
+        "modifiers" => by_name(MODIFIERS),
+        "left" => by_third(0),
+        "center" => by_third(1),
+        "right" => by_third(2),
+        _ => return None,
+    };
+
+    Some(keys)
+}
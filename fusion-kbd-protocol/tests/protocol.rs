@@ -0,0 +1,191 @@
+//! Tests for the wire protocol: properties every header has to satisfy (over
+//! inputs generated by proptest), and how configs are split up for uploads.
+//!
+//! Only uses the USB-free parts of the crate, so it also runs with
+//! `--no-default-features`. The exact bytes `FusionKBD` sends are checked in
+//! `transfers.rs`.
+
+use fusion_kbd_protocol::models::{self, Model, AERO_15X};
+use fusion_kbd_protocol::protocol::{
+    Direction, Header, Upload, KIND_CUSTOM_CONFIG, KIND_PRESET, KIND_READ_CONFIG, NUM_SLOTS,
+};
+use fusion_kbd_protocol::state::{Lighting, State};
+use fusion_kbd_protocol::{Color, Preset};
+use proptest::prelude::*;
+use strum::IntoEnumIterator;
+
+fn wire_sum(header: &Header) -> u8 {
+    header
+        .as_bytes()
+        .iter()
+        .fold(0, |sum: u8, b| sum.wrapping_add(*b))
+}
+
+fn presets() -> impl Strategy<Value = Preset> {
+    prop::sample::select(Preset::iter().collect::<Vec<_>>())
+}
+
+fn colors() -> impl Strategy<Value = Color> {
+    prop::sample::select(Color::iter().collect::<Vec<_>>())
+}
+
+/// a known model, and a config that's the right size for it
+fn configs() -> impl Strategy<Value = (Model, Vec<u8>)> {
+    prop::sample::select(models::MODELS.to_vec()).prop_flat_map(|model| {
+        (
+            Just(model),
+            prop::collection::vec(any::<u8>(), model.config_len()),
+        )
+    })
+}
+
+proptest! {
+    #[test]
+    fn checksum_makes_header_sum_to_ff(
+        kind in any::<u8>(),
+        mode in any::<u8>(),
+        speed_length in any::<u8>(),
+        brightness in any::<u8>(),
+        color in any::<u8>(),
+    ) {
+        let header = Header::new(kind, mode, speed_length, brightness, color);
+        let bytes = header.as_bytes();
+        prop_assert_eq!(wire_sum(&header), 0xff, "{:02x?}", bytes);
+        prop_assert_eq!(
+            [bytes[0], bytes[2], bytes[3], bytes[4], bytes[5]],
+            [kind, mode, speed_length, brightness, color]
+        );
+        // the reserved bytes are always sent as zero
+        prop_assert_eq!((bytes[1], bytes[6]), (0, 0));
+    }
+
+    #[test]
+    fn headers_round_trip_through_bytes(
+        kind in any::<u8>(),
+        mode in any::<u8>(),
+        speed_length in any::<u8>(),
+        brightness in any::<u8>(),
+        color in any::<u8>(),
+    ) {
+        let header = Header::new(kind, mode, speed_length, brightness, color);
+        let parsed = Header::from_bytes(header.as_bytes());
+        prop_assert_eq!(
+            parsed.as_ref().map(|h| *h.as_bytes()),
+            Some(*header.as_bytes())
+        );
+    }
+
+    #[test]
+    fn single_bit_errors_are_rejected(
+        kind in any::<u8>(),
+        mode in any::<u8>(),
+        speed_length in any::<u8>(),
+        brightness in any::<u8>(),
+        color in any::<u8>(),
+        byte in 0..8usize,
+        bit in 0..8u32,
+    ) {
+        let header = Header::new(kind, mode, speed_length, brightness, color);
+        let mut bytes = *header.as_bytes();
+        bytes[byte] ^= 1 << bit;
+        prop_assert!(
+            Header::from_bytes(&bytes).is_none(),
+            "{:02x?} (byte {}, bit {} flipped) was accepted",
+            bytes,
+            byte,
+            bit
+        );
+    }
+
+    #[test]
+    fn lighting_headers_describe_their_state(
+        preset in presets(),
+        color in colors(),
+        speed in any::<u8>(),
+        brightness in any::<u8>(),
+        slot in 0..NUM_SLOTS,
+    ) {
+        let header = Header::preset(preset, speed, brightness, color);
+        prop_assert_eq!(
+            header.state(),
+            Some(State {
+                lighting: Lighting::Preset {
+                    preset,
+                    color,
+                    speed
+                },
+                brightness,
+            })
+        );
+        prop_assert!(header.custom_config_slot().is_none());
+
+        prop_assert_eq!(
+            Header::custom(slot, brightness).unwrap().state(),
+            Some(State {
+                lighting: Lighting::Custom { slot },
+                brightness,
+            })
+        );
+        prop_assert!(Header::read_config(slot).state().is_none());
+    }
+
+    #[test]
+    fn uploads_split_configs_into_chunks((model, data) in configs(), slot in 0..NUM_SLOTS) {
+        let upload = Upload::new(slot, &data, &model).unwrap();
+        prop_assert_eq!(
+            upload.header.custom_config_slot(),
+            Some((slot, model.num_chunks))
+        );
+        prop_assert_eq!(upload.chunks.len(), model.num_chunks);
+        prop_assert!(upload.chunks.iter().all(|c| c.len() == model.chunk_size));
+        prop_assert_eq!(upload.chunks.concat(), data);
+    }
+
+    #[test]
+    fn uploads_of_the_wrong_size_are_refused(
+        data in prop::collection::vec(any::<u8>(), 0..2 * AERO_15X.config_len()),
+        slot in 0..NUM_SLOTS,
+    ) {
+        prop_assert_eq!(
+            Upload::new(slot, &data, &AERO_15X).is_ok(),
+            data.len() == AERO_15X.config_len()
+        );
+    }
+}
+
+#[test]
+fn headers_of_the_wrong_length_are_rejected() {
+    let header = Header::custom(0, 50).unwrap();
+    let bytes = header.as_bytes();
+    assert!(Header::from_bytes(&bytes[..7]).is_none());
+    assert!(Header::from_bytes(&[&bytes[..], &[0]].concat()).is_none());
+    assert!(Header::from_bytes(&[]).is_none());
+}
+
+#[test]
+fn slots_that_dont_exist_are_refused() {
+    let data = [0; 512];
+    for slot in NUM_SLOTS..=u8::MAX {
+        assert!(Header::custom(slot, 50).is_err());
+        assert!(Upload::new(slot, &data, &AERO_15X).is_err());
+    }
+}
+
+#[test]
+fn headers_with_a_direction_parse() {
+    // e.g: in captures of the Windows software
+    let header = Header::preset_direction(Preset::Wave, 5, 50, Color::Red, Direction::Left);
+    let parsed = Header::from_bytes(header.as_bytes()).expect("a valid header");
+    assert_eq!(
+        parsed.to_string(),
+        "preset wave, speed 5, brightness 50, color red, direction left"
+    );
+}
+
+#[test]
+fn header_kinds() {
+    assert_eq!(
+        (KIND_PRESET, KIND_CUSTOM_CONFIG, KIND_READ_CONFIG),
+        (0x08, 0x12, 0x92)
+    );
+}
@@ -0,0 +1,299 @@
+//! Golden transfers: exactly what `FusionKBD` sends for `set_preset` /
+//! `set_custom` / `upload_custom` / `download_custom`, recorded through a
+//! stand-in `Transport`, so a refactor of the USB backend can't quietly change
+//! what goes over the wire (or how an upload is split up).
+
+#![cfg(feature = "usb")]
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::Duration;
+
+use fusion_kbd_protocol::device::{Broadcast, Transport};
+use fusion_kbd_protocol::models::{Model, AERO_15X};
+use fusion_kbd_protocol::protocol::Direction;
+use fusion_kbd_protocol::{Color, FusionKBD, Keyboard, Preset};
+
+/// One transfer, as the keyboard sees it. Control ones are (request type,
+/// request, value, index, ...).
+#[derive(Debug, Clone, PartialEq)]
+enum Sent {
+    Control(u8, u8, u16, u16, Vec<u8>),
+    /// a control transfer reading (up to) this many bytes
+    ControlIn(u8, u8, u16, u16, usize),
+    Interrupt(u8, Vec<u8>),
+    /// an interrupt transfer reading (up to) this many bytes
+    InterruptIn(u8, usize),
+}
+
+use Sent::*;
+
+/// Records every transfer. Interrupt reads are answered from `reply`, and
+/// interrupt write number `short_write` (counting from 0) only takes half its
+/// data.
+#[derive(Clone, Default)]
+struct Recorder {
+    sent: Rc<RefCell<Vec<Sent>>>,
+    reply: Rc<RefCell<Vec<u8>>>,
+    short_write: Option<usize>,
+    writes: Rc<Cell<usize>>,
+}
+
+impl Recorder {
+    fn keyboard(&self) -> FusionKBD<'static> {
+        FusionKBD::with_transport(Box::new(self.clone()), AERO_15X)
+    }
+
+    fn sent(&self) -> Vec<Sent> {
+        self.sent.borrow_mut().drain(..).collect()
+    }
+}
+
+impl Transport for Recorder {
+    fn read_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+        _timeout: Duration,
+    ) -> Result<usize, libusb::Error> {
+        let sent = ControlIn(request_type, request, value, index, buf.len());
+        self.sent.borrow_mut().push(sent);
+        Ok(buf.len())
+    }
+
+    fn write_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &[u8],
+        _timeout: Duration,
+    ) -> Result<usize, libusb::Error> {
+        let sent = Control(request_type, request, value, index, buf.to_vec());
+        self.sent.borrow_mut().push(sent);
+        Ok(buf.len())
+    }
+
+    fn read_interrupt(
+        &self,
+        endpoint: u8,
+        buf: &mut [u8],
+        _timeout: Duration,
+    ) -> Result<usize, libusb::Error> {
+        self.sent
+            .borrow_mut()
+            .push(InterruptIn(endpoint, buf.len()));
+        let mut reply = self.reply.borrow_mut();
+        let n = buf.len().min(reply.len());
+        buf[..n].copy_from_slice(&reply[..n]);
+        reply.drain(..n);
+        Ok(n)
+    }
+
+    fn write_interrupt(
+        &self,
+        endpoint: u8,
+        buf: &[u8],
+        _timeout: Duration,
+    ) -> Result<usize, libusb::Error> {
+        self.sent
+            .borrow_mut()
+            .push(Interrupt(endpoint, buf.to_vec()));
+        let n = self.writes.get();
+        self.writes.set(n + 1);
+        match self.short_write {
+            Some(short) if short == n => Ok(buf.len() / 2),
+            _ => Ok(buf.len()),
+        }
+    }
+
+    fn read_languages(&self, _timeout: Duration) -> Result<usize, libusb::Error> {
+        Ok(1)
+    }
+}
+
+/// a SET_REPORT to the lighting interface, i.e: a header
+fn set_report(header: [u8; 8]) -> Sent {
+    Control(0x21, 0x09, 0x0300, 3, header.to_vec())
+}
+
+#[test]
+fn set_preset_transfers() {
+    let recorder = Recorder::default();
+    let kbd = recorder.keyboard();
+    let cases = [
+        (
+            Preset::Static,
+            0,
+            50,
+            Color::White,
+            [0x08, 0x00, 0x01, 0x00, 0x32, 0x07, 0x00, 0xbd],
+        ),
+        (
+            Preset::Wave,
+            5,
+            50,
+            Color::Red,
+            [0x08, 0x00, 0x03, 0x05, 0x32, 0x01, 0x00, 0xbc],
+        ),
+        (
+            Preset::Breathing,
+            10,
+            0,
+            Color::Rand,
+            [0x08, 0x00, 0x02, 0x0a, 0x00, 0x00, 0x00, 0xeb],
+        ),
+        (
+            Preset::Rotate,
+            1,
+            25,
+            Color::Purple,
+            [0x08, 0x00, 0x0d, 0x01, 0x19, 0x06, 0x00, 0xca],
+        ),
+    ];
+    for &(preset, speed, brightness, color, header) in cases.iter() {
+        kbd.set_preset(preset, speed, brightness, color).unwrap();
+        assert_eq!(recorder.sent(), [set_report(header)], "{}", preset);
+    }
+
+    kbd.set_preset_direction(Preset::Wave, 5, 50, Color::Red, Direction::Left)
+        .unwrap();
+    assert_eq!(
+        recorder.sent(),
+        [set_report([0x08, 0x00, 0x03, 0x05, 0x32, 0x01, 0x02, 0xba])]
+    );
+}
+
+#[test]
+fn set_custom_transfers() {
+    let recorder = Recorder::default();
+    let kbd = recorder.keyboard();
+    let cases = [
+        (0, 50, [0x08, 0x00, 0x33, 0x00, 0x32, 0x00, 0x00, 0x92]),
+        (2, 25, [0x08, 0x00, 0x35, 0x00, 0x19, 0x00, 0x00, 0xa9]),
+        (4, 0, [0x08, 0x00, 0x37, 0x00, 0x00, 0x00, 0x00, 0xc0]),
+    ];
+    for &(slot, brightness, header) in cases.iter() {
+        kbd.set_custom(slot, brightness).unwrap();
+        assert_eq!(recorder.sent(), [set_report(header)], "slot {}", slot);
+    }
+
+    // refused before anything's sent
+    assert!(matches!(
+        kbd.set_custom(5, 50),
+        Err(libusb::Error::InvalidParam)
+    ));
+    assert_eq!(recorder.sent(), []);
+}
+
+#[test]
+fn upload_custom_transfers() {
+    let recorder = Recorder::default();
+    let kbd = recorder.keyboard();
+    let data: Vec<u8> = (0..512).map(|i| i as u8).collect();
+    kbd.upload_custom(1, &data).unwrap();
+    // then the header that shows it, which ends every upload the CLI does
+    kbd.set_custom(1, 50).unwrap();
+
+    let mut expected = vec![set_report([0x12, 0x00, 0x01, 0x08, 0x00, 0x00, 0x00, 0xe4])];
+    // the config itself, split at every 64 bytes
+    for i in 0..8 {
+        expected.push(Interrupt(0x06, data[i * 64..(i + 1) * 64].to_vec()));
+    }
+    expected.push(set_report([0x08, 0x00, 0x34, 0x00, 0x32, 0x00, 0x00, 0x91]));
+    assert_eq!(recorder.sent(), expected);
+
+    // the wrong size is refused before anything's sent
+    assert!(matches!(
+        kbd.upload_custom(1, &data[..511]),
+        Err(libusb::Error::InvalidParam)
+    ));
+    assert!(matches!(
+        kbd.upload_custom(5, &data),
+        Err(libusb::Error::InvalidParam)
+    ));
+    assert_eq!(recorder.sent(), []);
+}
+
+#[test]
+fn short_writes_resend_the_whole_chunk() {
+    // the third chunk only gets half way the first time
+    let recorder = Recorder {
+        short_write: Some(2),
+        ..Recorder::default()
+    };
+    let data: Vec<u8> = (0..512).map(|i| i as u8).collect();
+    let chunk = |i: usize| Interrupt(0x06, data[i * 64..(i + 1) * 64].to_vec());
+    recorder.keyboard().upload_custom(4, &data).unwrap();
+
+    let mut expected = vec![set_report([0x12, 0x00, 0x04, 0x08, 0x00, 0x00, 0x00, 0xe1])];
+    expected.extend([0, 1, 2, 2, 3, 4, 5, 6, 7].iter().map(|&i| chunk(i)));
+    assert_eq!(recorder.sent(), expected);
+}
+
+#[test]
+fn download_custom_transfers() {
+    let recorder = Recorder::default();
+    let config: Vec<u8> = (0..512).map(|i| (i * 7) as u8).collect();
+    *recorder.reply.borrow_mut() = config.clone();
+    let kbd = recorder.keyboard();
+
+    let mut data = [0; 512];
+    kbd.download_custom(3, &mut data).unwrap();
+    assert_eq!(data[..], config[..]);
+
+    let mut expected = vec![
+        set_report([0x92, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x6a]),
+        ControlIn(0xa1, 0x01, 0x0300, 3, 8),
+    ];
+    expected.extend((0..8).map(|_| InterruptIn(0x85, 64)));
+    assert_eq!(recorder.sent(), expected);
+
+    assert!(matches!(
+        kbd.download_custom(3, &mut [0; 256]),
+        Err(libusb::Error::InvalidParam)
+    ));
+    assert_eq!(recorder.sent(), []);
+}
+
+#[test]
+fn broadcasts_go_to_every_keyboard() {
+    let (a, b) = (Recorder::default(), Recorder::default());
+    let kbd = Broadcast::new(vec![a.keyboard(), b.keyboard()]).unwrap();
+    let data: Vec<u8> = (0..512).map(|i| i as u8).collect();
+    kbd.upload_custom(2, &data).unwrap();
+    let sent = a.sent();
+    assert_eq!(sent.len(), 9);
+    assert_eq!(b.sent(), sent);
+
+    // each one could have something different in the slot
+    assert!(matches!(
+        kbd.download_custom(2, &mut [0; 512]),
+        Err(libusb::Error::NotSupported)
+    ));
+    assert_eq!(a.sent(), []);
+
+    // another model's configs aren't laid out the same
+    let other = Model {
+        name: "other",
+        num_chunks: 4,
+        ..AERO_15X
+    };
+    let c = Recorder::default();
+    let kbd = Broadcast::new(vec![
+        a.keyboard(),
+        FusionKBD::with_transport(Box::new(c.clone()), other),
+    ])
+    .unwrap();
+    assert_eq!(kbd.capabilities().config_len, 0);
+    assert!(matches!(
+        kbd.upload_custom(2, &data),
+        Err(libusb::Error::InvalidParam)
+    ));
+    assert_eq!(a.sent(), []);
+    assert_eq!(c.sent(), []);
+}